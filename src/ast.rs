@@ -21,7 +21,7 @@
 ///    patterns are _not sparse_ in the input.
 use crate::arena::Arena;
 use crate::builtins::Function;
-use crate::common::{Either, Stage};
+use crate::common::{Either, FileSpec, Stage};
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Unop {
@@ -54,11 +54,18 @@ pub enum Pattern<'a, 'b, I> {
 pub struct Prog<'a, 'b, I> {
     // FS
     pub field_sep: Option<&'b str>,
+    // FIELDWIDTHS
+    pub field_widths: Option<&'b str>,
     pub prelude_vardecs: Vec<(I, &'a Expr<'a, 'b, I>)>,
     // OFS
     pub output_sep: Option<&'b str>,
     // ORS
     pub output_record_sep: Option<&'b str>,
+    // The column names read from the input's header row, if --header was passed.
+    pub header: Option<Vec<&'b str>>,
+    // Whether the input is JSON Lines (--input-format jsonl), where $"path" refers to the value at
+    // a key path of each record.
+    pub key_paths: bool,
     pub decs: Vec<FunDec<'a, 'b, I>>,
     pub begin: Option<&'a Stmt<'a, 'b, I>>,
     pub prepare: Option<&'a Stmt<'a, 'b, I>>,
@@ -86,6 +93,13 @@ impl<'a, 'b, I: From<&'b str> + Clone> Prog<'a, 'b, I> {
                 arena.alloc_v(StrLit(sep)),
             )))));
         }
+        // Desugar --field-widths flag
+        if let Some(widths) = self.field_widths {
+            begin.push(arena.alloc_v(Expr(arena.alloc_v(Assign(
+                arena.alloc_v(Var("FIELDWIDTHS".into())),
+                arena.alloc_v(StrLit(widths)),
+            )))));
+        }
         // Support "output csv/tsv" mode
         if let Some(sep) = self.output_sep {
             begin.push(arena.alloc_v(Expr(arena.alloc_v(Assign(
@@ -99,6 +113,18 @@ impl<'a, 'b, I: From<&'b str> + Clone> Prog<'a, 'b, I> {
                 arena.alloc_v(StrLit(sep)),
             )))));
         }
+        // Support --header: FIELDS maps column names to their indexes.
+        if let Some(header) = &self.header {
+            for (i, name) in header.iter().enumerate() {
+                begin.push(arena.alloc_v(Expr(arena.alloc_v(Assign(
+                    arena.alloc_v(Index(
+                        arena.alloc_v(Var("FIELDS".into())),
+                        arena.alloc_v(StrLit(name)),
+                    )),
+                    arena.alloc_v(ILit(i as i64 + 1)),
+                )))));
+            }
+        }
         // Desugar -v flags
         for (ident, exp) in self.prelude_vardecs.iter() {
             begin.push(arena.alloc_v(Expr(
@@ -296,6 +322,10 @@ pub enum Expr<'a, 'b, I> {
         into: Option<&'a Expr<'a, 'b, I>>,
         from: Option<&'a Expr<'a, 'b, I>>,
     },
+    GetlineCmd {
+        cmd: &'a Expr<'a, 'b, I>,
+        into: Option<&'a Expr<'a, 'b, I>>,
+    },
     ReadStdin,
     // Used for comma patterns
     Cond(usize),
@@ -310,13 +340,13 @@ pub enum Stmt<'a, 'b, I> {
     Block(Vec<&'a Stmt<'a, 'b, I>>),
     Print(
         Vec<&'a Expr<'a, 'b, I>>,
-        Option<(&'a Expr<'a, 'b, I>, bool /*append*/)>,
+        Option<(&'a Expr<'a, 'b, I>, FileSpec)>,
     ),
     // Unlike print, printf must have at least one argument.
     Printf(
         &'a Expr<'a, 'b, I>,
         Vec<&'a Expr<'a, 'b, I>>,
-        Option<(&'a Expr<'a, 'b, I>, bool /*append*/)>,
+        Option<(&'a Expr<'a, 'b, I>, FileSpec)>,
     ),
     If(
         &'a Expr<'a, 'b, I>,
@@ -343,4 +373,6 @@ pub enum Stmt<'a, 'b, I> {
     Next,
     NextFile,
     Return(Option<&'a Expr<'a, 'b, I>>),
+    // Exit with an optional exit code. Exits from the main loop or BEGIN still run END.
+    Exit(Option<&'a Expr<'a, 'b, I>>),
 }
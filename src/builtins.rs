@@ -4,7 +4,7 @@ use crate::ast;
 use crate::common::Either;
 use crate::common::{NodeIx, Result};
 use crate::compile;
-use crate::runtime::{Int, IntMap, Str};
+use crate::runtime::{self, Int, IntMap, Str, StrMap};
 use crate::types::{self, SmallVec};
 #[cfg(feature = "llvm_backend")]
 use llvm_sys::{core::*, prelude::*};
@@ -21,32 +21,55 @@ pub enum Function {
     Print,
     PrintStdout,
     Close,
+    System,
     ReadErr,
     Nextline,
+    ReadErrCmd,
+    NextlineCmd,
     ReadErrStdin,
     NextlineStdin,
     ReadLineStdinFused,
     NextFile,
+    Exit,
+    Exiting,
     Setcol,
+    RebuiltRecord,
     Split,
+    PatSplit,
     Length,
     Contains,
     Delete,
+    Clear,
+    Asort,
+    Asorti,
     Match,
+    MatchCaptures,
     SubstrIndex,
     Sub,
     GSub,
+    GenSub,
     EscapeCSV,
+    ToUpper,
+    ToLower,
+    ToOutputStr,
+    ToJSON,
     EscapeTSV,
+    EscapeJSON,
     JoinCols,
     JoinCSV,
     JoinTSV,
+    JoinJSON,
     Substr,
     ToInt,
     HexToInt,
     Rand,
     Srand,
     ReseedRng,
+    Systime,
+    Strftime,
+    Mktime,
+    ParseTime,
+    JSONPath,
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -249,12 +272,15 @@ impl FloatFunc {
 static_map!(
     FUNCTIONS<&'static str, Function>,
     ["close", Function::Close],
+    ["system", Function::System],
     ["print", Function::Print],
     ["split", Function::Split],
+    ["patsplit", Function::PatSplit],
     ["length", Function::Length],
     ["match", Function::Match],
     ["sub", Function::Sub],
     ["gsub", Function::GSub],
+    ["gensub", Function::GenSub],
     ["substr", Function::Substr],
     ["int", Function::ToInt],
     ["hex", Function::HexToInt],
@@ -277,11 +303,22 @@ static_map!(
     ["join_fields", Function::JoinCols],
     ["join_csv", Function::JoinCSV],
     ["join_tsv", Function::JoinTSV],
+    ["join_json", Function::JoinJSON],
     ["escape_csv", Function::EscapeCSV],
     ["escape_tsv", Function::EscapeTSV],
+    ["escape_json", Function::EscapeJSON],
+    ["toupper", Function::ToUpper],
+    ["tolower", Function::ToLower],
     ["rand", Function::Rand],
     ["srand", Function::Srand],
-    ["index", Function::SubstrIndex]
+    ["index", Function::SubstrIndex],
+    ["asort", Function::Asort],
+    ["asorti", Function::Asorti],
+    ["systime", Function::Systime],
+    ["strftime", Function::Strftime],
+    ["mktime", Function::Mktime],
+    ["parse_time", Function::ParseTime],
+    ["json", Function::JSONPath]
 );
 
 impl<'a> TryFrom<&'a str> for Function {
@@ -318,6 +355,54 @@ impl Function {
                 );
                 ctx.nw.add_dep(arg1, args[1], Constraint::Flows(()));
             }
+            // patsplit stores both fields and separators under integer keys.
+            Function::PatSplit => {
+                for arg in [args[1], args[3]].iter() {
+                    let arr = ctx.constant(
+                        Map {
+                            key: BaseTy::Int,
+                            val: BaseTy::Str,
+                        }
+                        .abs(),
+                    );
+                    ctx.nw.add_dep(arr, *arg, Constraint::Flows(()));
+                }
+            }
+            // Capture groups are stored under integer keys alongside the `n SUBSEP "start"` and
+            // `n SUBSEP "length"` entries, so the map always has string keys: a lookup like
+            // `arr[n, "start"]` does not by itself constrain the key type.
+            Function::MatchCaptures => {
+                let arg2 = ctx.constant(
+                    Map {
+                        key: BaseTy::Str,
+                        val: BaseTy::Str,
+                    }
+                    .abs(),
+                );
+                ctx.nw.add_dep(arg2, args[2], Constraint::Flows(()));
+            }
+            // The destination of asort and asorti is keyed by integers (or by their string
+            // representations, if it has string keys) and holds the values (or keys) of the source
+            // map. We unify the element types of the two maps, as we do not convert between them.
+            Function::Asort | Function::Asorti => {
+                let (src, dst) = (args[0], args[1]);
+                ctx.constrain_as_map(src);
+                let int_keys = ctx.constant(Some(Map {
+                    key: Some(BaseTy::Int),
+                    val: None,
+                }));
+                ctx.nw.add_dep(int_keys, dst, Constraint::Flows(()));
+                let elt = ctx.fresh_var();
+                if let Function::Asort = self {
+                    ctx.nw.add_dep(src, elt, Constraint::Val(()));
+                    ctx.nw.add_dep(elt, src, Constraint::ValIn(()));
+                } else {
+                    ctx.nw.add_dep(src, elt, Constraint::Key(()));
+                    ctx.nw.add_dep(elt, src, Constraint::KeyIn(()));
+                }
+                ctx.nw.add_dep(dst, elt, Constraint::Val(()));
+                ctx.nw.add_dep(elt, dst, Constraint::ValIn(()));
+            }
             Function::Contains => {
                 let arr = args[0];
                 let query = args[1];
@@ -403,6 +488,31 @@ impl Function {
                 MapStrInt | MapStrStr | MapStrFloat => (smallvec![incoming[0], Str], Int),
                 _ => return err!("invalid input spec fo Delete: {:?}", &incoming[..]),
             },
+            Clear => match incoming[0] {
+                MapIntInt | MapIntStr | MapIntFloat | MapStrInt | MapStrStr | MapStrFloat
+                | Null => (smallvec![incoming[0]], Int),
+                _ => return err!("invalid input spec for Clear: {:?}", &incoming[..]),
+            },
+            Asort | Asorti => {
+                let elt = match incoming[0] {
+                    MapIntInt | MapIntStr | MapIntFloat | MapStrInt | MapStrStr | MapStrFloat => {
+                        if let Asort = self {
+                            incoming[0].val()?
+                        } else {
+                            incoming[0].key()?
+                        }
+                    }
+                    _ => return err!("invalid input spec for {}: {:?}", self, &incoming[..]),
+                };
+                match incoming[1] {
+                    MapIntInt | MapIntStr | MapIntFloat | MapStrInt | MapStrStr | MapStrFloat
+                        if incoming[1].val()? == elt =>
+                    {
+                        (smallvec![incoming[0], incoming[1]], Int)
+                    }
+                    _ => return err!("invalid input spec for {}: {:?}", self, &incoming[..]),
+                }
+            }
             Srand => (smallvec![Int], Int),
             HexToInt => (smallvec![Str], Int),
             ReseedRng => (smallvec![], Int),
@@ -421,17 +531,34 @@ impl Function {
             }
             Print => (smallvec![Str, Str, Int], Int),
             PrintStdout => (smallvec![Str], Int),
-            Nextline => (smallvec![Str], Str),
-            ReadErr => (smallvec![Str], Int),
-            NextFile | ReadLineStdinFused => (smallvec![], Int),
+            Nextline | NextlineCmd => (smallvec![Str], Str),
+            ReadErr | ReadErrCmd => (smallvec![Str], Int),
+            NextFile | ReadLineStdinFused | Exiting | RebuiltRecord => (smallvec![], Int),
+            // A bare `exit` statement does not set the exit code.
+            Exit => match incoming.len() {
+                0 => (smallvec![], Int),
+                1 => (smallvec![Int], Int),
+                n => return err!("exit expected at most 1 input but got {}", n),
+            },
             NextlineStdin => (smallvec![], Str),
             ReadErrStdin => (smallvec![], Int),
             // irrelevant return type
             Setcol => (smallvec![Int, Str], Int),
             Length => (smallvec![incoming[0]], Int),
-            Close => (smallvec![Str], Str),
+            Close | System => (smallvec![Str], Int),
             Sub | GSub => (smallvec![Str, Str, Str], Int),
-            EscapeCSV | EscapeTSV => (smallvec![Str], Str),
+            GenSub => (smallvec![Str; 4], Str),
+            EscapeCSV | EscapeTSV | EscapeJSON | ToUpper | ToLower => (smallvec![Str], Str),
+            ToOutputStr => match incoming[0] {
+                Null | Int | Float | Str => (smallvec![incoming[0]], Str),
+                _ => return err!("invalid input type for print: {:?}", &incoming[..]),
+            },
+            // Numbers are printed as JSON numbers, everything else as a JSON string.
+            ToJSON => match incoming[0] {
+                Int | Float => (smallvec![incoming[0]], Str),
+                Null | Str => (smallvec![Str], Str),
+                _ => return err!("invalid input type for print: {:?}", &incoming[..]),
+            },
             Substr => (smallvec![Str, Int, Int], Str),
             Match => (smallvec![Str, Str], Int),
             // Split's second input can be a map of either type
@@ -442,22 +569,44 @@ impl Function {
                     return err!("invalid input spec for split: {:?}", &incoming[..]);
                 }
             }
+            PatSplit => match (incoming[1], incoming[3]) {
+                (MapIntStr, MapIntStr) => (smallvec![Str, MapIntStr, Str, MapIntStr], Int),
+                _ => return err!("invalid input spec for patsplit: {:?}", &incoming[..]),
+            },
+            // As with split, the capture groups can be stored in a map of either type
+            MatchCaptures => {
+                if let MapIntStr | MapStrStr = incoming[2] {
+                    (smallvec![Str, Str, incoming[2]], Int)
+                } else {
+                    return err!("invalid input spec for match: {:?}", &incoming[..]);
+                }
+            }
             JoinCols => (smallvec![Int, Int, Str], Str),
-            JoinCSV | JoinTSV => (smallvec![Int, Int], Str),
+            JoinCSV | JoinTSV | JoinJSON => (smallvec![Int, Int], Str),
+            Systime => (smallvec![], Int),
+            Strftime => (smallvec![Str, Int, Int], Str),
+            Mktime => (smallvec![Str], Int),
+            ParseTime => (smallvec![Str, Str], Int),
+            JSONPath => (smallvec![Str, Str], Str),
         })
     }
 
     pub(crate) fn arity(&self) -> Option<usize> {
         use Function::*;
         Some(match self {
+            Exit => return None,
             FloatFunc(ff) => ff.arity(),
             IntFunc(bw) => bw.arity(),
-            Rand | ReseedRng | ReadErrStdin | NextlineStdin | NextFile | ReadLineStdinFused => 0,
-            Srand | HexToInt | ToInt | EscapeCSV | EscapeTSV | Close | Length | ReadErr
-            | Nextline | PrintStdout | Unop(_) => 1,
+            Rand | ReseedRng | ReadErrStdin | NextlineStdin | NextFile | ReadLineStdinFused
+            | Exiting | RebuiltRecord | Systime => 0,
+            Srand | HexToInt | ToInt | EscapeCSV | EscapeTSV | EscapeJSON | ToUpper | ToLower
+            | ToOutputStr | ToJSON | Close | System | Length | Clear | ReadErr | ReadErrCmd
+            | Nextline | NextlineCmd | PrintStdout | Mktime | Unop(_) => 1,
             SubstrIndex | Match | Setcol | Binop(_) => 2,
-            JoinCSV | JoinTSV | Delete | Contains => 2,
-            JoinCols | Substr | Sub | GSub | Print | Split => 3,
+            JoinCSV | JoinTSV | JoinJSON | Delete | Contains | ParseTime | JSONPath | Asort
+            | Asorti => 2,
+            JoinCols | Substr | Sub | GSub | Print | Split | MatchCaptures | Strftime => 3,
+            GenSub | PatSplit => 4,
         })
     }
 
@@ -490,13 +639,16 @@ impl Function {
             Rand | Binop(Div) | Binop(Pow) => Ok(Scalar(BaseTy::Float).abs()),
             Setcol | Print | PrintStdout => Ok(Scalar(BaseTy::Null).abs()),
             SubstrIndex | Srand | ReseedRng | Unop(Not) | Binop(IsMatch) | Binop(LT)
-            | Binop(GT) | Binop(LTE) | Binop(GTE) | Binop(EQ) | Length | Split | ReadErr
-            | ReadErrStdin | Contains | Delete | Match | Sub | GSub | ToInt | HexToInt => {
-                Ok(Scalar(BaseTy::Int).abs())
+            | Binop(GT) | Binop(LTE) | Binop(GTE) | Binop(EQ) | Length | Split | PatSplit
+            | ReadErr | ReadErrCmd | ReadErrStdin | Contains | Delete | Clear | Asort | Asorti
+            | Match | MatchCaptures | Sub | GSub | ToInt | HexToInt | Exiting | RebuiltRecord
+            | Close | System | Systime | Mktime | ParseTime => Ok(Scalar(BaseTy::Int).abs()),
+            JoinCSV | JoinTSV | JoinJSON | JoinCols | EscapeCSV | EscapeTSV | EscapeJSON
+            | ToUpper | ToLower | JSONPath | ToOutputStr | ToJSON | Substr | GenSub | Strftime
+            | Unop(Column) | Binop(Concat) | Nextline | NextlineCmd | NextlineStdin => {
+                Ok(Scalar(BaseTy::Str).abs())
             }
-            JoinCSV | JoinTSV | JoinCols | EscapeCSV | EscapeTSV | Substr | Unop(Column)
-            | Binop(Concat) | Nextline | NextlineStdin => Ok(Scalar(BaseTy::Str).abs()),
-            NextFile | ReadLineStdinFused | Close => Ok(None),
+            NextFile | ReadLineStdinFused | Exit => Ok(None),
         }
     }
 }
@@ -516,15 +668,26 @@ pub(crate) enum Variable {
     ORS = 10,
     FNR = 11,
     PID = 12,
+    ENVIRON = 13,
+    SUBSEP = 14,
+    CONVFMT = 15,
+    OFMT = 16,
+    #[allow(non_camel_case_types)]
+    SORTED_IN = 17,
+    RT = 18,
+    FIELDWIDTHS = 19,
+    FPAT = 20,
 }
 
 impl From<Variable> for compile::Ty {
     fn from(v: Variable) -> compile::Ty {
         use Variable::*;
         match v {
-            FS | OFS | ORS | RS | FILENAME => compile::Ty::Str,
+            FS | OFS | ORS | RS | RT | FIELDWIDTHS | FPAT | FILENAME | SUBSEP | CONVFMT | OFMT
+            | SORTED_IN => compile::Ty::Str,
             PID | ARGC | NF | NR | FNR | RSTART | RLENGTH => compile::Ty::Int,
             ARGV => compile::Ty::MapIntStr,
+            ENVIRON => compile::Ty::MapStrStr,
         }
     }
 }
@@ -536,13 +699,37 @@ pub(crate) struct Variables<'a> {
     pub ofs: Str<'a>,
     pub ors: Str<'a>,
     pub rs: Str<'a>,
+    // The text that terminated the last record read.
+    pub rt: Str<'a>,
+    // Column widths used to split fixed-width input.
+    pub fieldwidths: Str<'a>,
+    // A pattern matching the fields of each record.
+    pub fpat: Str<'a>,
     pub nf: Int,
     pub nr: Int,
     pub fnr: Int,
     pub filename: Str<'a>,
+    pub subsep: Str<'a>,
+    pub convfmt: Str<'a>,
+    pub ofmt: Str<'a>,
+    pub sorted_in: Str<'a>,
+    pub sort_order: runtime::sort::SortOrder,
     pub rstart: Int,
     pub rlength: Int,
     pub pid: Int,
+    pub environ: StrMap<'a, Str<'a>>,
+}
+
+/// The contents of the process environment, used to initialize `ENVIRON`.
+fn environ<'a>() -> StrMap<'a, Str<'a>> {
+    std::env::vars_os()
+        .map(|(k, v)| {
+            (
+                Str::from(k.to_string_lossy().into_owned()),
+                Str::from(v.to_string_lossy().into_owned()),
+            )
+        })
+        .collect()
 }
 
 impl<'a> Default for Variables<'a> {
@@ -554,13 +741,22 @@ impl<'a> Default for Variables<'a> {
             ofs: " ".into(),
             ors: "\n".into(),
             rs: "\n".into(),
+            rt: Default::default(),
+            fieldwidths: Default::default(),
+            fpat: Default::default(),
             nr: 0,
             fnr: 0,
             nf: 0,
             filename: Default::default(),
+            subsep: "\x1c".into(),
+            convfmt: runtime::DEFAULT_NUM_FMT.into(),
+            ofmt: runtime::DEFAULT_NUM_FMT.into(),
+            sorted_in: Default::default(),
+            sort_order: Default::default(),
             rstart: 0,
             pid: 0,
             rlength: -1,
+            environ: environ(),
         }
     }
 }
@@ -575,7 +771,8 @@ impl<'a> Variables<'a> {
             RSTART => self.rstart,
             RLENGTH => self.rlength,
             PID => self.pid,
-            ORS | OFS | FS | RS | FILENAME | ARGV => return err!("var {} not an int", var),
+            ORS | OFS | FS | RS | RT | FIELDWIDTHS | FPAT | FILENAME | SUBSEP | CONVFMT | OFMT
+            | SORTED_IN | ARGV | ENVIRON => return err!("var {} not an int", var),
         })
     }
 
@@ -589,7 +786,8 @@ impl<'a> Variables<'a> {
             RSTART => self.rstart = i,
             RLENGTH => self.rlength = i,
             PID => self.pid = i,
-            ORS | OFS | FS | RS | FILENAME | ARGV => return err!("var {} not an int", var),
+            ORS | OFS | FS | RS | RT | FIELDWIDTHS | FPAT | FILENAME | SUBSEP | CONVFMT | OFMT
+            | SORTED_IN | ARGV | ENVIRON => return err!("var {} not an int", var),
         })
     }
 
@@ -600,8 +798,15 @@ impl<'a> Variables<'a> {
             OFS => self.ofs.clone(),
             ORS => self.ors.clone(),
             RS => self.rs.clone(),
+            RT => self.rt.clone(),
+            FIELDWIDTHS => self.fieldwidths.clone(),
+            FPAT => self.fpat.clone(),
             FILENAME => self.filename.clone(),
-            PID | ARGC | ARGV | NF | NR | FNR | RSTART | RLENGTH => {
+            SUBSEP => self.subsep.clone(),
+            CONVFMT => self.convfmt.clone(),
+            OFMT => self.ofmt.clone(),
+            SORTED_IN => self.sorted_in.clone(),
+            PID | ARGC | ARGV | ENVIRON | NF | NR | FNR | RSTART | RLENGTH => {
                 return err!("var {} not a string", var)
             }
         })
@@ -614,8 +819,18 @@ impl<'a> Variables<'a> {
             OFS => self.ofs = s,
             ORS => self.ors = s,
             RS => self.rs = s,
+            RT => self.rt = s,
+            FIELDWIDTHS => self.fieldwidths = s,
+            FPAT => self.fpat = s,
             FILENAME => self.filename = s,
-            PID | ARGC | ARGV | NF | NR | FNR | RSTART | RLENGTH => {
+            SUBSEP => self.subsep = s,
+            CONVFMT => self.convfmt = s,
+            OFMT => self.ofmt = s,
+            SORTED_IN => {
+                self.sort_order = runtime::sort::SortOrder::parse(&s)?;
+                self.sorted_in = s;
+            }
+            PID | ARGC | ARGV | ENVIRON | NF | NR | FNR | RSTART | RLENGTH => {
                 return err!("var {} not a string", var)
             }
         })
@@ -625,8 +840,9 @@ impl<'a> Variables<'a> {
         use Variable::*;
         match var {
             ARGV => Ok(self.argv.clone()),
-            PID | ORS | OFS | ARGC | NF | NR | FNR | FS | RS | FILENAME | RSTART | RLENGTH => {
-                err!("var {} is not a map", var)
+            PID | ORS | OFS | ARGC | ENVIRON | NF | NR | FNR | FS | RS | FILENAME | SUBSEP
+            | CONVFMT | OFMT | SORTED_IN | RT | FIELDWIDTHS | FPAT | RSTART | RLENGTH => {
+                err!("var {} is not an int map", var)
             }
         }
     }
@@ -635,8 +851,31 @@ impl<'a> Variables<'a> {
         use Variable::*;
         match var {
             ARGV => Ok(self.argv = m),
-            PID | ORS | OFS | ARGC | NF | NR | FNR | FS | RS | FILENAME | RSTART | RLENGTH => {
-                err!("var {} is not a map", var)
+            PID | ORS | OFS | ARGC | ENVIRON | NF | NR | FNR | FS | RS | FILENAME | SUBSEP
+            | CONVFMT | OFMT | SORTED_IN | RT | FIELDWIDTHS | FPAT | RSTART | RLENGTH => {
+                err!("var {} is not an int map", var)
+            }
+        }
+    }
+
+    pub fn load_strmap(&self, var: Variable) -> Result<StrMap<'a, Str<'a>>> {
+        use Variable::*;
+        match var {
+            ENVIRON => Ok(self.environ.clone()),
+            PID | ORS | OFS | ARGC | ARGV | NF | NR | FNR | FS | RS | FILENAME | SUBSEP
+            | CONVFMT | OFMT | SORTED_IN | RT | FIELDWIDTHS | FPAT | RSTART | RLENGTH => {
+                err!("var {} is not a string map", var)
+            }
+        }
+    }
+
+    pub fn store_strmap(&mut self, var: Variable, m: StrMap<'a, Str<'a>>) -> Result<()> {
+        use Variable::*;
+        match var {
+            ENVIRON => Ok(self.environ = m),
+            PID | ORS | OFS | ARGC | ARGV | NF | NR | FNR | FS | RS | FILENAME | SUBSEP
+            | CONVFMT | OFMT | SORTED_IN | RT | FIELDWIDTHS | FPAT | RSTART | RLENGTH => {
+                err!("var {} is not a string map", var)
             }
         }
     }
@@ -674,7 +913,12 @@ impl Variable {
                 key: types::BaseTy::Int,
                 val: types::BaseTy::Str,
             },
-            ORS | OFS | FS | RS | FILENAME => types::TVar::Scalar(types::BaseTy::Str),
+            ENVIRON => types::TVar::Map {
+                key: types::BaseTy::Str,
+                val: types::BaseTy::Str,
+            },
+            ORS | OFS | FS | RS | RT | FIELDWIDTHS | FPAT | FILENAME | SUBSEP | CONVFMT | OFMT
+            | SORTED_IN => types::TVar::Scalar(types::BaseTy::Str),
         }
     }
 }
@@ -707,6 +951,14 @@ impl<'a> TryFrom<usize> for Variable {
             10 => Ok(ORS),
             11 => Ok(FNR),
             12 => Ok(PID),
+            13 => Ok(ENVIRON),
+            14 => Ok(SUBSEP),
+            15 => Ok(CONVFMT),
+            16 => Ok(OFMT),
+            17 => Ok(SORTED_IN),
+            18 => Ok(RT),
+            19 => Ok(FIELDWIDTHS),
+            20 => Ok(FPAT),
             _ => Err(()),
         }
     }
@@ -726,5 +978,13 @@ static_map!(
     ["FILENAME", Variable::FILENAME],
     ["RSTART", Variable::RSTART],
     ["RLENGTH", Variable::RLENGTH],
-    ["PID", Variable::PID]
+    ["PID", Variable::PID],
+    ["ENVIRON", Variable::ENVIRON],
+    ["SUBSEP", Variable::SUBSEP],
+    ["CONVFMT", Variable::CONVFMT],
+    ["OFMT", Variable::OFMT],
+    ["SORTED_IN", Variable::SORTED_IN],
+    ["RT", Variable::RT],
+    ["FIELDWIDTHS", Variable::FIELDWIDTHS],
+    ["FPAT", Variable::FPAT]
 );
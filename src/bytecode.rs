@@ -2,7 +2,7 @@ use std::hash::{Hash, Hasher};
 use std::marker::PhantomData;
 
 use crate::builtins::{Bitwise, FloatFunc, Variable};
-use crate::common::NumTy;
+use crate::common::{FileSpec, NumTy};
 use crate::compile::{self, Ty};
 use crate::interp::{index, index_mut, Storage};
 use crate::runtime::{self, Float, Int, Str, UniqueStr};
@@ -69,6 +69,10 @@ pub(crate) enum Instr<'a> {
     // Conversions
     IntToStr(Reg<Str<'a>>, Reg<Int>),
     FloatToStr(Reg<Str<'a>>, Reg<Float>),
+    // Like FloatToStr, but uses OFMT rather than CONVFMT.
+    FloatToOutputStr(Reg<Str<'a>>, Reg<Float>),
+    // Like FloatToOutputStr, but infinities and NaN become null.
+    FloatToJSON(Reg<Str<'a>>, Reg<Float>),
     StrToInt(Reg<Int>, Reg<Str<'a>>),
     HexStrToInt(Reg<Int>, Reg<Str<'a>>),
     FloatToInt(Reg<Int>, Reg<Float>),
@@ -108,10 +112,40 @@ pub(crate) enum Instr<'a> {
     ),
     ReseedRng(/* previous seed */ Reg<Int>),
 
+    // Time
+    Systime(Reg<Int>),
+    Strftime(
+        Reg<Str<'a>>,
+        /*fmt*/ Reg<Str<'a>>,
+        /*timestamp*/ Reg<Int>,
+        /*utc*/ Reg<Int>,
+    ),
+    Mktime(Reg<Int>, Reg<Str<'a>>),
+    ParseTime(Reg<Int>, Reg<Str<'a>>, /*fmt*/ Reg<Str<'a>>),
+    JSONPath(Reg<Str<'a>>, /*path*/ Reg<Str<'a>>, Reg<Str<'a>>),
+
     // String processing
     Concat(Reg<Str<'a>>, Reg<Str<'a>>, Reg<Str<'a>>),
     IsMatch(Reg<Int>, Reg<Str<'a>>, Reg<Str<'a>>),
     Match(Reg<Int>, Reg<Str<'a>>, Reg<Str<'a>>),
+    // Variants of Match, SubstrIndex, LenStr and Substr that use character rather than byte
+    // offsets (see `--chars`).
+    MatchChars(Reg<Int>, Reg<Str<'a>>, Reg<Str<'a>>),
+    SubstrIndexChars(Reg<Int>, Reg<Str<'a>>, Reg<Str<'a>>),
+    LenStrChars(Reg<Int>, Reg<Str<'a>>),
+    SubstrChars(Reg<Str<'a>>, Reg<Str<'a>>, Reg<Int>, Reg<Int>),
+    MatchCapturesInt(
+        Reg<Int>,
+        Reg<Str<'a>>,
+        Reg<Str<'a>>,
+        Reg<runtime::IntMap<Str<'a>>>,
+    ),
+    MatchCapturesStr(
+        Reg<Int>,
+        Reg<Str<'a>>,
+        Reg<Str<'a>>,
+        Reg<runtime::StrMap<'a, Str<'a>>>,
+    ),
     // index(s, t) returns index of substring t in s, 0 if it does not appear.
     SubstrIndex(Reg<Int>, Reg<Str<'a>>, Reg<Str<'a>>),
     LenStr(Reg<Int>, Reg<Str<'a>>),
@@ -127,8 +161,18 @@ pub(crate) enum Instr<'a> {
         /*for*/ Reg<Str<'a>>,
         /*in*/ Reg<Str<'a>>,
     ),
+    GenSub(
+        Reg<Str<'a>>,
+        /*pat*/ Reg<Str<'a>>,
+        /*for*/ Reg<Str<'a>>,
+        /*how*/ Reg<Str<'a>>,
+        /*in*/ Reg<Str<'a>>,
+    ),
     EscapeCSV(Reg<Str<'a>>, Reg<Str<'a>>),
     EscapeTSV(Reg<Str<'a>>, Reg<Str<'a>>),
+    EscapeJSON(Reg<Str<'a>>, Reg<Str<'a>>),
+    ToUpper(Reg<Str<'a>>, Reg<Str<'a>>),
+    ToLower(Reg<Str<'a>>, Reg<Str<'a>>),
     Substr(Reg<Str<'a>>, Reg<Str<'a>>, Reg<Int>, Reg<Int>),
 
     // Comparison
@@ -151,6 +195,9 @@ pub(crate) enum Instr<'a> {
     // Columns
     SetColumn(Reg<Int> /* dst column */, Reg<Str<'a>>),
     GetColumn(Reg<Str<'a>>, Reg<Int>),
+    // Check if $0 was rebuilt from the fields of the line after one of them was assigned, in which
+    // case it is already escaped for output.
+    RebuiltRecord(Reg<Int>),
     JoinCSV(
         Reg<Str<'a>>, /* dst */
         Reg<Int>,     /* start col */
@@ -161,6 +208,11 @@ pub(crate) enum Instr<'a> {
         Reg<Int>,     /* start col */
         Reg<Int>,     /* end col */
     ),
+    JoinJSON(
+        Reg<Str<'a>>, /* dst */
+        Reg<Int>,     /* start col */
+        Reg<Int>,     /* end col */
+    ),
     JoinColumns(
         Reg<Str<'a>>, /* dst */
         Reg<Int>,     /* start col */
@@ -171,12 +223,19 @@ pub(crate) enum Instr<'a> {
     // File reading.
     ReadErr(Reg<Int>, Reg<Str<'a>>),
     NextLine(Reg<Str<'a>>, Reg<Str<'a>>),
+    // Reading from the output of a command.
+    ReadErrCmd(Reg<Int>, Reg<Str<'a>>),
+    NextLineCmd(Reg<Str<'a>>, Reg<Str<'a>>),
     ReadErrStdin(Reg<Int>),
     NextLineStdin(Reg<Str<'a>>),
     // Fetches line directly into $0.
     NextLineStdinFused(),
     // Advances early to the next file in our sequence
     NextFile(),
+    // Stop reading input and run the END block (if there is one), with an optional exit code.
+    Exit(Option<Reg<Int>>),
+    // Check if `exit` has been called, used after calls to functions that may exit.
+    Exiting(Reg<Int>),
 
     // Split
     SplitInt(
@@ -191,13 +250,20 @@ pub(crate) enum Instr<'a> {
         Reg<runtime::StrMap<'a, Str<'a>>>,
         Reg<Str<'a>>,
     ),
+    PatSplit {
+        flds: Reg<Int>,
+        to_split: Reg<Str<'a>>,
+        arr: Reg<runtime::IntMap<Str<'a>>>,
+        pat: Reg<Str<'a>>,
+        seps: Reg<runtime::IntMap<Str<'a>>>,
+    },
     Sprintf {
         dst: Reg<Str<'a>>,
         fmt: Reg<Str<'a>>,
         args: Vec<(NumTy, Ty)>,
     },
     Printf {
-        output: Option<(Reg<Str<'a>>, bool)>,
+        output: Option<(Reg<Str<'a>>, FileSpec)>,
         fmt: Reg<Str<'a>>,
         args: Vec<(NumTy, Ty)>,
     },
@@ -205,9 +271,10 @@ pub(crate) enum Instr<'a> {
     Print(
         Reg<Str<'a>>, /*text*/
         Reg<Str<'a>>, /*output*/
-        bool,         /*append*/
+        FileSpec,
     ),
-    Close(Reg<Str<'a>>),
+    Close(Reg<Int>, Reg<Str<'a>>),
+    RunCmd(Reg<Int>, Reg<Str<'a>>),
 
     // Map operations
     Lookup {
@@ -227,6 +294,26 @@ pub(crate) enum Instr<'a> {
         map: NumTy,
         key: NumTy,
     },
+    Clear {
+        map_ty: Ty,
+        map: NumTy,
+    },
+    // Store the values (or keys, for Asorti) of `src` in sorted order in `dst`, under the keys 1
+    // through `res`.
+    Asort {
+        src_ty: Ty,
+        src: NumTy,
+        dst_ty: Ty,
+        dst: NumTy,
+        res: NumTy,
+    },
+    Asorti {
+        src_ty: Ty,
+        src: NumTy,
+        dst_ty: Ty,
+        dst: NumTy,
+        res: NumTy,
+    },
     Len {
         map_ty: Ty,
         dst: NumTy,
@@ -260,6 +347,8 @@ pub(crate) enum Instr<'a> {
     StoreVarInt(Variable, Reg<Int>),
     LoadVarIntMap(Reg<runtime::IntMap<Str<'a>>>, Variable),
     StoreVarIntMap(Variable, Reg<runtime::IntMap<Str<'a>>>),
+    LoadVarStrMap(Reg<runtime::StrMap<'a, Str<'a>>>, Variable),
+    StoreVarStrMap(Variable, Reg<runtime::StrMap<'a, Str<'a>>>),
 
     LoadSlot {
         ty: Ty,
@@ -384,7 +473,7 @@ impl<'a> Instr<'a> {
                 sr.accum(&mut f);
                 ir.accum(&mut f)
             }
-            FloatToStr(sr, fr) => {
+            FloatToStr(sr, fr) | FloatToOutputStr(sr, fr) | FloatToJSON(sr, fr) => {
                 sr.accum(&mut f);
                 fr.accum(&mut f);
             }
@@ -484,6 +573,27 @@ impl<'a> Instr<'a> {
                 y.accum(&mut f);
             }
             Rand(res) => res.accum(&mut f),
+            Systime(res) => res.accum(&mut f),
+            Strftime(res, fmt, ts, utc) => {
+                res.accum(&mut f);
+                fmt.accum(&mut f);
+                ts.accum(&mut f);
+                utc.accum(&mut f);
+            }
+            Mktime(res, s) => {
+                res.accum(&mut f);
+                s.accum(&mut f);
+            }
+            ParseTime(res, s, fmt) => {
+                res.accum(&mut f);
+                s.accum(&mut f);
+                fmt.accum(&mut f);
+            }
+            JSONPath(res, path, s) => {
+                res.accum(&mut f);
+                path.accum(&mut f);
+                s.accum(&mut f);
+            }
             Srand(res, seed) => {
                 res.accum(&mut f);
                 seed.accum(&mut f)
@@ -494,7 +604,7 @@ impl<'a> Instr<'a> {
                 l.accum(&mut f);
                 r.accum(&mut f);
             }
-            Match(res, l, r) => {
+            Match(res, l, r) | MatchChars(res, l, r) => {
                 res.accum(&mut f);
                 l.accum(&mut f);
                 r.accum(&mut f);
@@ -504,12 +614,12 @@ impl<'a> Instr<'a> {
                 l.accum(&mut f);
                 r.accum(&mut f);
             }
-            SubstrIndex(res, s, t) => {
+            SubstrIndex(res, s, t) | SubstrIndexChars(res, s, t) => {
                 res.accum(&mut f);
                 s.accum(&mut f);
                 t.accum(&mut f);
             }
-            LenStr(res, s) => {
+            LenStr(res, s) | LenStrChars(res, s) => {
                 res.accum(&mut f);
                 s.accum(&mut f)
             }
@@ -519,11 +629,22 @@ impl<'a> Instr<'a> {
                 s.accum(&mut f);
                 in_s.accum(&mut f);
             }
-            EscapeCSV(res, s) | EscapeTSV(res, s) => {
+            GenSub(res, pat, s, how, in_s) => {
                 res.accum(&mut f);
+                pat.accum(&mut f);
                 s.accum(&mut f);
+                how.accum(&mut f);
+                in_s.accum(&mut f);
             }
-            Substr(res, base, l, r) => {
+            EscapeCSV(res, s)
+            | EscapeTSV(res, s)
+            | EscapeJSON(res, s)
+            | ToUpper(res, s)
+            | ToLower(res, s) => {
+                res.accum(&mut f);
+                s.accum(&mut f);
+            }
+            Substr(res, base, l, r) | SubstrChars(res, base, l, r) => {
                 res.accum(&mut f);
                 base.accum(&mut f);
                 l.accum(&mut f);
@@ -612,7 +733,7 @@ impl<'a> Instr<'a> {
                 dst.accum(&mut f);
                 src.accum(&mut f)
             }
-            JoinCSV(dst, start, end) | JoinTSV(dst, start, end) => {
+            JoinCSV(dst, start, end) | JoinTSV(dst, start, end) | JoinJSON(dst, start, end) => {
                 dst.accum(&mut f);
                 start.accum(&mut f);
                 end.accum(&mut f);
@@ -623,6 +744,18 @@ impl<'a> Instr<'a> {
                 end.accum(&mut f);
                 sep.accum(&mut f);
             }
+            MatchCapturesInt(res, l, r, arr) => {
+                res.accum(&mut f);
+                l.accum(&mut f);
+                r.accum(&mut f);
+                arr.accum(&mut f);
+            }
+            MatchCapturesStr(res, l, r, arr) => {
+                res.accum(&mut f);
+                l.accum(&mut f);
+                r.accum(&mut f);
+                arr.accum(&mut f);
+            }
             SplitInt(flds, to_split, arr, pat) => {
                 flds.accum(&mut f);
                 to_split.accum(&mut f);
@@ -635,6 +768,19 @@ impl<'a> Instr<'a> {
                 arr.accum(&mut f);
                 pat.accum(&mut f);
             }
+            PatSplit {
+                flds,
+                to_split,
+                arr,
+                pat,
+                seps,
+            } => {
+                flds.accum(&mut f);
+                to_split.accum(&mut f);
+                arr.accum(&mut f);
+                pat.accum(&mut f);
+                seps.accum(&mut f);
+            }
             Sprintf { dst, fmt, args } => {
                 dst.accum(&mut f);
                 fmt.accum(&mut f);
@@ -652,11 +798,18 @@ impl<'a> Instr<'a> {
                 }
             }
             PrintStdout(txt) => txt.accum(&mut f),
-            Print(txt, out, _append) => {
+            Print(txt, out, _spec) => {
                 txt.accum(&mut f);
                 out.accum(&mut f)
             }
-            Close(file) => file.accum(&mut f),
+            Close(dst, file) => {
+                dst.accum(&mut f);
+                file.accum(&mut f)
+            }
+            RunCmd(dst, cmd) => {
+                dst.accum(&mut f);
+                cmd.accum(&mut f)
+            }
             Lookup {
                 map_ty,
                 dst,
@@ -684,6 +837,25 @@ impl<'a> Instr<'a> {
                 f(*key, k);
                 f(*map, *map_ty);
             }
+            Clear { map_ty, map } => f(*map, *map_ty),
+            Asort {
+                src_ty,
+                src,
+                dst_ty,
+                dst,
+                res,
+            }
+            | Asorti {
+                src_ty,
+                src,
+                dst_ty,
+                dst,
+                res,
+            } => {
+                f(*res, Ty::Int);
+                f(*src, *src_ty);
+                f(*dst, *dst_ty);
+            }
             Len { map_ty, map, dst } => {
                 f(*dst, Ty::Int);
                 f(*map, *map_ty);
@@ -708,6 +880,8 @@ impl<'a> Instr<'a> {
             StoreVarInt(_var, src) => src.accum(&mut f),
             LoadVarIntMap(dst, _var) => dst.accum(&mut f),
             StoreVarIntMap(_var, src) => src.accum(&mut f),
+            LoadVarStrMap(dst, _var) => dst.accum(&mut f),
+            StoreVarStrMap(_var, src) => src.accum(&mut f),
 
             LoadSlot { ty, dst, .. } => f(*dst, *ty),
             StoreSlot { ty, src, .. } => f(*src, *ty),
@@ -733,8 +907,22 @@ impl<'a> Instr<'a> {
                 dst.accum(&mut f);
                 file.accum(&mut f)
             }
+            ReadErrCmd(dst, cmd) => {
+                dst.accum(&mut f);
+                cmd.accum(&mut f)
+            }
+            NextLineCmd(dst, cmd) => {
+                dst.accum(&mut f);
+                cmd.accum(&mut f)
+            }
             ReadErrStdin(dst) => dst.accum(&mut f),
             NextLineStdin(dst) => dst.accum(&mut f),
+            Exit(code) => {
+                if let Some(code) = code {
+                    code.accum(&mut f)
+                }
+            }
+            Exiting(dst) | RebuiltRecord(dst) => dst.accum(&mut f),
             JmpIf(cond, _lbl) => cond.accum(&mut f),
             Push(ty, reg) => f(*reg, *ty),
             Pop(ty, reg) => f(*reg, *ty),
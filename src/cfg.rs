@@ -1,7 +1,7 @@
 use crate::arena;
 use crate::ast::{self, Expr, Stmt, Unop};
 use crate::builtins::{self, IsSprintf};
-use crate::common::{Either, Graph, NodeIx, NumTy, Result, Stage};
+use crate::common::{Either, FileSpec, Graph, NodeIx, NumTy, Result, Stage};
 use crate::dom;
 
 use hashbrown::{HashMap, HashSet};
@@ -135,6 +135,7 @@ impl Ident {
 pub enum Escaper {
     CSV,
     TSV,
+    JSON,
     Identity,
 }
 
@@ -185,7 +186,7 @@ pub(crate) enum PrimStmt<'a> {
     Printf(
         /*spec*/ PrimVal<'a>,
         /* args */ SmallVec<PrimVal<'a>>,
-        /* output */ Option<(PrimVal<'a>, /* append */ bool)>,
+        /* output */ Option<(PrimVal<'a>, FileSpec)>,
     ),
 }
 
@@ -275,6 +276,8 @@ pub(crate) struct ProgramContext<'a, I> {
     // table at construction time (in the func_table passed to View).
     pub funcs: Vec<Function<'a, I>>,
     main_offset: Stage<usize>,
+    // Whether length, substr, index and match count characters rather than bytes (--chars).
+    pub chars: bool,
 }
 
 impl<'a, I> ProgramContext<'a, I> {
@@ -284,6 +287,10 @@ impl<'a, I> ProgramContext<'a, I> {
     pub fn main_offsets(&self) -> impl Iterator<Item = usize> + '_ {
         self.main_offset.iter().cloned()
     }
+    /// The key paths of JSON Lines input that the program reads, indexed by column - 1.
+    pub fn key_paths(&self) -> &[String] {
+        self.shared.key_paths.as_deref().unwrap_or(&[])
+    }
 }
 
 impl<'a> ProgramContext<'a, &'a str> {
@@ -310,6 +317,8 @@ pub enum SepAssign<'a> {
     Potential {
         field_sep: Option<&'a str>,
         record_sep: Option<&'a str>,
+        field_widths: Option<&'a str>,
+        field_pattern: Option<&'a str>,
     },
     Unsure,
 }
@@ -349,15 +358,45 @@ where
             Stage::Par { begin, .. } => begin,
         }
     }
-    pub fn analyze_sep_assignments(&self) -> SepAssign<'a> {
+    pub fn analyze_sep_assignments(&self) -> Result<SepAssign<'a>> {
+        let res = self.guess_seps();
+        // Unlike FS and RS, we cannot fall back to reading FIELDWIDTHS or FPAT for each record,
+        // so we refuse to run programs that assign them in ways we cannot resolve up front.
+        if let SepAssign::Unsure = res {
+            for var in &[builtins::Variable::FIELDWIDTHS, builtins::Variable::FPAT] {
+                if self.assigns(*var) {
+                    return err!(
+                        "{} can only be assigned a string literal at the start of the BEGIN \
+                         block, in programs that assign FS, RS, FIELDWIDTHS and FPAT only there \
+                         and do not call getline in BEGIN",
+                        var
+                    );
+                }
+            }
+        }
+        Ok(res)
+    }
+
+    // Whether the program assigns to the builtin variable `var`.
+    fn assigns(&self, var: builtins::Variable) -> bool {
+        self.funcs
+            .iter()
+            .any(|f| f.vars.get(&Some(var)).map_or(false, |v| v.len() > 0))
+    }
+
+    fn guess_seps(&self) -> SepAssign<'a> {
         let mut field_sep = None;
         let mut record_sep = None;
+        let mut field_widths = None;
+        let mut field_pattern = None;
         let mut has_getline = false;
         for (i, f) in self.funcs.iter().enumerate() {
             if Some(i) == self.begin_offset() {
                 for (bi, sep) in [
                     (builtins::Variable::FS, &mut field_sep),
                     (builtins::Variable::RS, &mut record_sep),
+                    (builtins::Variable::FIELDWIDTHS, &mut field_widths),
+                    (builtins::Variable::FPAT, &mut field_pattern),
                 ]
                 .iter_mut()
                 {
@@ -373,7 +412,7 @@ where
                         if bb != 0 {
                             return SepAssign::Unsure;
                         }
-                        // FS/RS assigned to a non-string-literal value.
+                        // FS/RS/FIELDWIDTHS/FPAT assigned to a non-string-literal value.
                         if v.is_none() {
                             return SepAssign::Unsure;
                         }
@@ -390,21 +429,35 @@ where
                     has_getline = true;
                 }
             } else {
-                for bi in [builtins::Variable::FS, builtins::Variable::RS].iter() {
+                for bi in [
+                    builtins::Variable::FS,
+                    builtins::Variable::RS,
+                    builtins::Variable::FIELDWIDTHS,
+                    builtins::Variable::FPAT,
+                ]
+                .iter()
+                {
                     if f.vars.get(&Some(*bi)).is_some() {
                         return SepAssign::Unsure;
                     }
                 }
             }
         }
-        // We called getline() _and_ assigned to FS/RS in the begin block; let's bail out just to
-        // be safe.
-        if has_getline && (field_sep.is_some() || record_sep.is_some()) {
+        // We called getline() _and_ assigned to FS/RS/FIELDWIDTHS/FPAT in the begin block; let's
+        // bail out just to be safe.
+        if has_getline
+            && (field_sep.is_some()
+                || record_sep.is_some()
+                || field_widths.is_some()
+                || field_pattern.is_some())
+        {
             return SepAssign::Unsure;
         }
         SepAssign::Potential {
             field_sep,
             record_sep,
+            field_widths,
+            field_pattern,
         }
     }
 
@@ -430,6 +483,14 @@ where
             max: 1, // 0 reserved for assigning to "unused" var for side-effecting operations
             conds: Default::default(),
             esc,
+            udf_exits: p.decs.iter().any(|d| contains_exit(d.body)),
+            header: p.header.as_ref().map(|h| {
+                h.iter()
+                    .enumerate()
+                    .map(|(i, name)| (String::from(*name), i as i64 + 1))
+                    .collect()
+            }),
+            key_paths: if p.key_paths { Some(Vec::new()) } else { None },
         };
         let mut func_table: HashMap<FunctionName<I>, NumTy> = Default::default();
         let mut funcs: Vec<Function<'a, I>> = Default::default();
@@ -474,6 +535,7 @@ where
         }
         // Now that we have all the functions in place, it's time to fill them up and convert them
         // to SSA.
+        let header = p.header.as_deref().unwrap_or(&[]);
         macro_rules! fill {
             ($stmt: expr, $name:expr) => {
                 if let Some(s) = $stmt {
@@ -483,6 +545,7 @@ where
                         ctx: &mut shared,
                         f: &mut func,
                         func_table: &func_table,
+                        header,
                     }
                     .fill(s)?;
                     func_table.insert($name, offset as NumTy);
@@ -502,6 +565,7 @@ where
                 ctx: &mut shared,
                 f: funcs.get_mut(f as usize).unwrap(),
                 func_table: &func_table,
+                header,
             }
             .fill(fundec.body)?;
         }
@@ -538,6 +602,7 @@ where
             shared,
             funcs,
             main_offset,
+            chars: false,
         })
     }
 }
@@ -546,6 +611,9 @@ struct View<'a, 'b, I> {
     ctx: &'a mut GlobalContext<I>,
     f: &'a mut Function<'b, I>,
     func_table: &'a HashMap<FunctionName<I>, NumTy>,
+    // With --header, the names of the columns in the header row. These key the objects printed
+    // with JSON output.
+    header: &'a [&'b str],
 }
 
 #[derive(Debug)]
@@ -566,6 +634,14 @@ struct GlobalContext<I> {
     max: NumTy,
     conds: HashMap<usize, Ident>,
     esc: Escaper,
+    // Whether any user-defined function contains an `exit` statement. If so, every call to a
+    // user-defined function is followed by a check of whether the program is exiting.
+    udf_exits: bool,
+    // With --header, the index of each column name in the header row.
+    header: Option<HashMap<String, i64>>,
+    // With JSON Lines input, the key path read into each column, in the order in which the paths
+    // first appear in the program.
+    key_paths: Option<Vec<String>>,
 }
 
 impl<I> GlobalContext<I> {
@@ -612,6 +688,9 @@ pub(crate) struct Function<'a, I> {
     //
     // NB: We only support doing this from main.
     toplevel_header: Option<NodeIx>,
+    // Blocks that `exit` before the toplevel loop has been created (i.e. from BEGIN). These are
+    // connected to the loop's header once it exists, so that END still runs.
+    pending_exits: SmallVec<NodeIx>,
 
     // Variable assignments, used to extract fast paths for splitting.
     // None indicates a call to `getline`.
@@ -640,6 +719,7 @@ impl<'a, I> Function<'a, I> {
             exit,
             loop_ctx: Default::default(),
             toplevel_header: None,
+            pending_exits: Default::default(),
             vars: Default::default(),
             dt: Default::default(),
             df: Default::default(),
@@ -651,6 +731,23 @@ pub(crate) fn is_unused(i: Ident) -> bool {
     i.low == 0
 }
 
+fn contains_exit<'a, 'b, I>(stmt: &Stmt<'a, 'b, I>) -> bool {
+    use Stmt::*;
+    match stmt {
+        Exit(_) => true,
+        Block(stmts) => stmts.iter().any(|s| contains_exit(s)),
+        If(_, t, f) => contains_exit(t) || f.map(contains_exit).unwrap_or(false),
+        For(init, _, update, body) => {
+            init.map(contains_exit).unwrap_or(false)
+                || update.map(contains_exit).unwrap_or(false)
+                || contains_exit(body)
+        }
+        DoWhile(_, body) | While(_, _, body) | ForEach(_, _, body) => contains_exit(body),
+        StartCond(_) | EndCond(_) | LastCond(_) | Expr(_) | Print(..) | Printf(..) | Break
+        | Continue | Next | NextFile | Return(_) => false,
+    }
+}
+
 fn record_ident(
     defsites: &mut HashMap<Ident, HashSet<NodeIx>>,
     orig: &mut HashMap<NodeIx, HashSet<Ident>>,
@@ -778,10 +875,10 @@ where
                     arg_vs.push(arg_v);
                     current_open = next;
                 }
-                let out_v = if let Some((out, append)) = out {
+                let out_v = if let Some((out, spec)) = out {
                     let (next, out_v) = self.convert_val(out, current_open)?;
                     current_open = next;
-                    Some((out_v, *append))
+                    Some((out_v, *spec))
                 } else {
                     None
                 };
@@ -800,9 +897,9 @@ where
                     )?;
                     PrimVal::Var(ors)
                 };
-                let (next, out) = if let Some((o, append)) = out {
+                let (next, out) = if let Some((o, spec)) = out {
                     let (next, e) = self.convert_val(o, current_open)?;
-                    (next, Some((e, append)))
+                    (next, Some((e, spec)))
                 } else {
                     (current_open, None)
                 };
@@ -814,10 +911,10 @@ where
                 macro_rules! print_stmt {
                     ($v:expr) => {{
                         let _v = $v;
-                        let v = if let Some((o, append)) = &out {
+                        let v = if let Some((o, spec)) = &out {
                             PrimExpr::CallBuiltin(
                                 builtins::Function::Print,
-                                smallvec![_v, o.clone(), PrimVal::ILit(**append as i64)],
+                                smallvec![_v, o.clone(), PrimVal::ILit(**spec as i64)],
                             )
                         } else {
                             PrimExpr::CallBuiltin(builtins::Function::PrintStdout, smallvec![_v])
@@ -831,7 +928,22 @@ where
                         print_stmt!(escaped)
                     }};
                 };
-                if vs.len() == 0 {
+                macro_rules! print_stmt_output {
+                    ($v:expr) => {{
+                        let output = self.output_str($v, current_open)?;
+                        print_stmt_escaped!(output)
+                    }};
+                }
+                let json = matches!(self.ctx.esc, Escaper::JSON);
+                // Outside of JSON output, print $0 is the same as print.
+                let record = vs.len() == 0
+                    || (!json
+                        && vs.len() == 1
+                        && matches!(
+                            vs[0],
+                            ast::Expr::Unop(ast::Unop::Column, ast::Expr::ILit(0))
+                        ));
+                if record && (!json || self.ctx.key_paths.is_some()) {
                     // 0 args: print $0
                     let tmp = self.fresh_local();
                     self.add_stmt(
@@ -844,7 +956,101 @@ where
                             ),
                         ),
                     )?;
-                    print_stmt_escaped!(PrimVal::Var(tmp))?;
+                    if json || matches!(self.ctx.esc, Escaper::Identity) {
+                        // With JSON Lines input and JSON output, $0 is already a JSON value.
+                        print_stmt!(PrimVal::Var(tmp))?;
+                    } else {
+                        // $0 is escaped like any other value, unless the reader rebuilt it from
+                        // its escaped fields: CSV and TSV readers always do so, and other readers
+                        // after a field is assigned.
+                        let rebuilt = self.to_val(
+                            PrimExpr::CallBuiltin(builtins::Function::RebuiltRecord, smallvec![]),
+                            current_open,
+                        )?;
+                        let as_is = self.f.cfg.add_node(Default::default());
+                        let escape = self.f.cfg.add_node(Default::default());
+                        let next = self.f.cfg.add_node(Default::default());
+                        self.f
+                            .cfg
+                            .add_edge(current_open, as_is, Transition::new(rebuilt));
+                        self.f
+                            .cfg
+                            .add_edge(current_open, escape, Transition::null());
+                        self.seal(current_open);
+                        current_open = as_is;
+                        print_stmt!(PrimVal::Var(tmp))?;
+                        current_open = escape;
+                        print_stmt_escaped!(PrimVal::Var(tmp))?;
+                        self.f.cfg.add_edge(as_is, next, Transition::null());
+                        self.f.cfg.add_edge(escape, next, Transition::null());
+                        current_open = next;
+                    }
+                    print_stmt!(ors)?;
+                    current_open
+                } else if json && vs.len() == 0 && self.header.len() == 0 {
+                    // With JSON output, print with no arguments prints every column as an
+                    // object: join_json(1, NF)
+                    let nf = self.fresh_local();
+                    self.add_stmt(
+                        current_open,
+                        PrimStmt::AsgnVar(nf, PrimExpr::LoadBuiltin(builtins::Variable::NF)),
+                    )?;
+                    let tmp = self.fresh_local();
+                    self.add_stmt(
+                        current_open,
+                        PrimStmt::AsgnVar(
+                            tmp,
+                            PrimExpr::CallBuiltin(
+                                builtins::Function::JoinJSON,
+                                smallvec![PrimVal::ILit(1), PrimVal::Var(nf)],
+                            ),
+                        ),
+                    )?;
+                    print_stmt!(PrimVal::Var(tmp))?;
+                    print_stmt!(ors)?;
+                    current_open
+                } else if json && vs.len() == 1 && self.is_json_object(vs[0]) {
+                    // print join_json(i, j) prints the object as it is.
+                    let (next, v) = self.convert_val(vs[0], current_open)?;
+                    current_open = next;
+                    print_stmt!(v)?;
+                    print_stmt!(ors)?;
+                    current_open
+                } else if json {
+                    // With JSON output, each print emits one object. Values are keyed by the
+                    // name of the column they read, if it has one, and by their position among
+                    // the arguments otherwise. Numbers are written as JSON numbers and objects
+                    // from join_json as they are. With --header, print with no arguments prints
+                    // each column of the header.
+                    let n = if vs.len() == 0 {
+                        self.header.len()
+                    } else {
+                        vs.len()
+                    };
+                    for i in 0..n {
+                        let ix = ast::Expr::ILit(i as i64 + 1);
+                        let col = ast::Expr::Unop(ast::Unop::Column, &ix);
+                        let (key, e) = match vs.get(i) {
+                            Some(e) => (self.json_key(e), *e),
+                            None => (Some(self.header[i]), &col),
+                        };
+                        let key = key.map_or(PrimVal::ILit(i as i64 + 1), PrimVal::StrLit);
+                        let (next, v) = self.convert_val(e, current_open)?;
+                        current_open = next;
+                        let v = if self.is_json_object(e) {
+                            v
+                        } else {
+                            self.to_val(
+                                PrimExpr::CallBuiltin(builtins::Function::ToJSON, smallvec![v]),
+                                current_open,
+                            )?
+                        };
+                        print_stmt!(PrimVal::StrLit(if i == 0 { "{" } else { "," }))?;
+                        print_stmt_escaped!(key)?;
+                        print_stmt!(PrimVal::StrLit(":"))?;
+                        print_stmt!(v)?;
+                    }
+                    print_stmt!(PrimVal::StrLit("}"))?;
                     print_stmt!(ors)?;
                     current_open
                 } else {
@@ -863,7 +1069,7 @@ where
                     for (i, v) in vs.iter().enumerate() {
                         let (next, v) = self.convert_val(*v, current_open)?;
                         current_open = next;
-                        print_stmt_escaped!(v)?;
+                        print_stmt_output!(v)?;
                         if i != vs.len() - 1 {
                             print_stmt!(fs.clone())?;
                         } else {
@@ -1001,6 +1207,23 @@ where
                 self.seal(current_open);
                 current_open
             }
+            Exit(code) => {
+                let (current_open, args) = if let Some(code) = code {
+                    let (next, v) = self.convert_val(code, current_open)?;
+                    (next, smallvec![v])
+                } else {
+                    (current_open, smallvec![])
+                };
+                self.add_stmt(
+                    current_open,
+                    PrimStmt::AsgnVar(
+                        Ident::unused(),
+                        PrimExpr::CallBuiltin(builtins::Function::Exit, args),
+                    ),
+                )?;
+                self.do_exit(current_open);
+                current_open
+            }
         })
     }
 
@@ -1025,6 +1248,16 @@ where
                 let id = self.get_cond(*cond);
                 PrimExpr::Val(PrimVal::Var(id))
             }
+            Unop(ast::Unop::Column, e) => {
+                let (next, v) = self.convert_column(e, current_open)?;
+                return Ok((
+                    next,
+                    PrimExpr::CallBuiltin(
+                        builtins::Function::Unop(ast::Unop::Column),
+                        smallvec![v],
+                    ),
+                ));
+            }
             Unop(op, e) => {
                 let (next, v) = self.convert_val(e, current_open)?;
                 return Ok((
@@ -1218,6 +1451,40 @@ where
                     }
                 };
             }
+            GetlineCmd { cmd, into } => {
+                self.f
+                    .vars
+                    .entry(None)
+                    .or_insert_with(Vec::new)
+                    .push((current_open.index(), None));
+                // As with Getline, we desugar:
+                //   cmd | getline var
+                // to
+                //   tmp = cmd
+                //   var = nextline_cmd(tmp)
+                //   readerr_cmd(tmp)
+                // Where `var` defaults to $0. `cmd` is only evaluated once, as it may have side
+                // effects (and `var` may be a variable that `cmd` reads).
+                use builtins::Function::{NextlineCmd, ReadErrCmd};
+                let into = into.unwrap_or(&Unop(ast::Unop::Column, &ast::Expr::ILit(0)));
+                let (next, cmd_v) = self.convert_val(cmd, current_open)?;
+                let tmp = self.fresh_local();
+                self.add_stmt(next, PrimStmt::AsgnVar(tmp, PrimExpr::Val(cmd_v)))?;
+                let line = PrimExpr::CallBuiltin(NextlineCmd, smallvec![PrimVal::Var(tmp)]);
+                let (next, _) = match into {
+                    Index(arr, ix) => self.do_assign_index(
+                        arr,
+                        ix,
+                        |_, _, _, open| Ok((open, line.clone())),
+                        next,
+                    )?,
+                    _ => self.do_assign(into, |_| line, next)?,
+                };
+                return Ok((
+                    next,
+                    PrimExpr::CallBuiltin(ReadErrCmd, smallvec![PrimVal::Var(tmp)]),
+                ));
+            }
         };
         Ok((current_open, res_expr))
     }
@@ -1271,6 +1538,9 @@ where
             Var(i) => Ok((
                 current_open,
                 if let Ok(b) = builtins::Variable::try_from(i.clone()) {
+                    if let (builtins::Variable::NF, Some(_)) = (b, &self.ctx.key_paths) {
+                        return err!("NF of JSON Lines input cannot be assigned to");
+                    }
                     // We collect some data on which builtins are assigned to, and if they are
                     // assigned to a string literal. This is used for triggering some fast paths
                     // for field splitting; it is not as precise as the method for inferring used
@@ -1294,7 +1564,15 @@ where
             )),
             Unop(ast::Unop::Column, n) => {
                 use {ast::Unop::*, builtins::Function};
-                let (next, v) = self.convert_val(n, current_open)?;
+                // $0 of JSON Lines input is only ever the raw line: it is not rebuilt from the
+                // key paths that the program reads, so those cannot be assigned to.
+                if self.ctx.key_paths.is_some() && !matches!(n, ILit(0)) {
+                    return err!(
+                        "columns of JSON Lines input cannot be assigned to; assign a new \
+                         object to $0 instead"
+                    );
+                }
+                let (next, v) = self.convert_column(n, current_open)?;
                 let res = PrimExpr::CallBuiltin(Function::Unop(Column), smallvec![v.clone()]);
                 let res_v = self.to_val(res.clone(), next)?;
                 let to_v = self.to_val(to(&res_v), next)?;
@@ -1381,6 +1659,46 @@ where
         Ok(next)
     }
 
+    // Convert the operand of `$`. With --header, a string literal operand names a column of the
+    // header row. With JSON Lines input, it is a key path, and each distinct path is given the
+    // next column. Either way, we resolve it to the column's index here so that field projection
+    // still applies.
+    fn convert_column<'c>(
+        &mut self,
+        expr: &'c Expr<'c, 'b, I>,
+        current_open: NodeIx,
+    ) -> Result<(NodeIx, PrimVal<'b>)> {
+        if let (Some(header), Expr::StrLit(name)) = (&self.ctx.header, expr) {
+            return match header.get(*name) {
+                Some(ix) => Ok((current_open, PrimVal::ILit(*ix))),
+                None => err!("column {:?} does not appear in the input header", name),
+            };
+        }
+        if let Some(paths) = &mut self.ctx.key_paths {
+            // Columns are numbered by the order their paths appear in the script, so a column
+            // number or a path that is only known at runtime does not name anything useful.
+            let path = match expr {
+                Expr::StrLit(path) => path,
+                Expr::ILit(0) => return self.convert_val(expr, current_open),
+                _ => {
+                    return err!(
+                        "columns of JSON Lines input must be $0 or a key path given as a string \
+                         literal, like $\"user.id\"; use json(path) to look up other paths"
+                    )
+                }
+            };
+            let ix = match paths.iter().position(|p| p == path) {
+                Some(ix) => ix,
+                None => {
+                    paths.push(String::from(*path));
+                    paths.len() - 1
+                }
+            };
+            return Ok((current_open, PrimVal::ILit(ix as i64 + 1)));
+        }
+        self.convert_val(expr, current_open)
+    }
+
     fn convert_val<'c>(
         &mut self,
         expr: &'c Expr<'c, 'b, I>,
@@ -1445,6 +1763,54 @@ where
         }
     }
 
+    // Handles control flow for `exit` statements. The runtime stops reading input once `exit` has
+    // been called, so from within the toplevel loop we jump to the loop header, which will then
+    // fall through to the END block. Within a function, or after the toplevel loop, we return
+    // immediately.
+    fn do_exit(&mut self, current_open: NodeIx) {
+        let in_toplevel = match (self.f.toplevel_header, self.f.loop_ctx.first()) {
+            (Some(header), Some((h, _))) => header == *h,
+            _ => false,
+        };
+        if let FunctionName::Named(_) = self.f.name {
+            self.f
+                .cfg
+                .add_edge(current_open, self.f.exit, Transition::null());
+        } else if in_toplevel {
+            let header = self.f.toplevel_header.unwrap();
+            self.f
+                .cfg
+                .add_edge(current_open, header, Transition::null());
+        } else if self.f.toplevel_header.is_some() {
+            self.f
+                .cfg
+                .add_edge(current_open, self.f.exit, Transition::null());
+        } else {
+            // We have not reached the toplevel loop yet. If there is no loop at all, `finish`
+            // will connect this block to the exit node.
+            self.f.pending_exits.push(current_open);
+        }
+        self.seal(current_open);
+    }
+
+    // Calls to user-defined functions may exit. When that is possible, we check whether we are
+    // exiting after the call returns, and follow the same control flow as `exit` if we are.
+    fn check_exit(&mut self, current_open: NodeIx) -> Result<NodeIx> {
+        let exiting = self.to_val(
+            PrimExpr::CallBuiltin(builtins::Function::Exiting, smallvec![]),
+            current_open,
+        )?;
+        let exit_block = self.f.cfg.add_node(Default::default());
+        let next = self.f.cfg.add_node(Default::default());
+        self.f
+            .cfg
+            .add_edge(current_open, exit_block, Transition::new(exiting));
+        self.f.cfg.add_edge(current_open, next, Transition::null());
+        self.seal(current_open);
+        self.do_exit(exit_block);
+        Ok(next)
+    }
+
     fn make_loop<'c>(
         &mut self,
         body: &'c Stmt<'c, 'b, I>,
@@ -1464,6 +1830,9 @@ where
         self.f.loop_ctx.push((h, f));
         if is_toplevel {
             self.f.toplevel_header = Some(h);
+            for bb in std::mem::replace(&mut self.f.pending_exits, Default::default()) {
+                self.f.cfg.add_edge(bb, h, Transition::null());
+            }
         }
 
         // The body is a standalone graph.
@@ -1552,7 +1921,13 @@ where
                         .entry(None)
                         .or_insert_with(Vec::new)
                         .push((current_open.index(), None));
-                    Ok((open, PrimExpr::CallUDF(*i, prim_args)))
+                    if self.ctx.udf_exits {
+                        let res = self.to_val(PrimExpr::CallUDF(*i, prim_args), open)?;
+                        let next = self.check_exit(open)?;
+                        Ok((next, PrimExpr::Val(res)))
+                    } else {
+                        Ok((open, PrimExpr::CallUDF(*i, prim_args)))
+                    }
                 } else {
                     err!("Call to unknown function \"{}\"", fname)
                 };
@@ -1572,12 +1947,26 @@ where
                     prim_args.push(PrimVal::Var(fs));
                 }
 
-                // join_fields(start, end) => join_{c,t}sv (if in csv/tsv output mode)
+                // patsplit(s, arr, re) => patsplit(s, arr, re, seps), where seps is a fresh
+                // local that is never read.
+                if bi == builtins::Function::PatSplit {
+                    match args.len() {
+                        4 => {}
+                        3 => {
+                            let seps = self.fresh_local();
+                            prim_args.push(PrimVal::Var(seps));
+                        }
+                        n => return err!("{} takes either 3 or 4 arguments, we got {}", bi, n),
+                    }
+                }
+
+                // join_fields(start, end) => join_{c,t}sv/join_json (if in csv/tsv/json output mode)
                 // join_fields(start, end) => join_fields(start, end, OFS) (otherwise)
                 if bi == builtins::Function::JoinCols && args.len() == 2 {
                     match self.ctx.esc {
                         Escaper::CSV => bi = builtins::Function::JoinCSV,
                         Escaper::TSV => bi = builtins::Function::JoinTSV,
+                        Escaper::JSON => bi = builtins::Function::JoinJSON,
                         Escaper::Identity => {
                             let fs = self.fresh_local();
                             self.add_stmt(
@@ -1600,6 +1989,70 @@ where
                     prim_args.push(PrimVal::ILit(i64::max_value()));
                 }
 
+                // gensub(re, repl, how) => gensub(re, repl, how, $0). Unlike sub and gsub, the
+                // target is not modified, so no assignment is needed.
+                if bi == builtins::Function::GenSub {
+                    match args.len() {
+                        4 => {}
+                        3 => {
+                            let e = &Expr::Unop(ast::Unop::Column, &Expr::ILit(0));
+                            let (next, v) = self.convert_val(e, open)?;
+                            open = next;
+                            prim_args.push(v);
+                        }
+                        n => return err!("{} takes either 3 or 4 arguments, we got {}", bi, n),
+                    }
+                }
+
+                // strftime() => strftime(DEFAULT_FORMAT, systime(), 0)
+                // strftime(fmt) => strftime(fmt, systime(), 0)
+                // strftime(fmt, ts) => strftime(fmt, ts, 0)
+                if bi == builtins::Function::Strftime {
+                    if args.len() == 0 {
+                        prim_args.push(PrimVal::StrLit(crate::runtime::time::DEFAULT_FORMAT));
+                    }
+                    if args.len() < 2 {
+                        let now = self.fresh_local();
+                        self.add_stmt(
+                            open,
+                            PrimStmt::AsgnVar(
+                                now.clone(),
+                                PrimExpr::CallBuiltin(builtins::Function::Systime, smallvec![]),
+                            ),
+                        )?;
+                        prim_args.push(PrimVal::Var(now));
+                    }
+                    if args.len() < 3 {
+                        prim_args.push(PrimVal::ILit(0));
+                    }
+                }
+
+                // parse_time(s) => parse_time(s, ""), where an empty format means ISO-8601.
+                if bi == builtins::Function::ParseTime && args.len() == 1 {
+                    prim_args.push(PrimVal::StrLit(""));
+                }
+
+                // json(path) => json(path, $0)
+                if bi == builtins::Function::JSONPath && args.len() == 1 {
+                    let e = &Expr::Unop(ast::Unop::Column, &Expr::ILit(0));
+                    let (next, v) = self.convert_val(e, open)?;
+                    open = next;
+                    prim_args.push(v);
+                }
+
+                // asort(m) => asort(m, m), and likewise for asorti.
+                if let builtins::Function::Asort | builtins::Function::Asorti = bi {
+                    if args.len() == 1 {
+                        let src = prim_args[0].clone();
+                        prim_args.push(src);
+                    }
+                }
+
+                // match(s, re, arr) => the variant of match that also stores capture groups
+                if bi == builtins::Function::Match && args.len() == 3 {
+                    bi = builtins::Function::MatchCaptures;
+                }
+
                 // srand() => the special "reseed rng" function
                 if bi == builtins::Function::Srand && args.len() == 0 {
                     bi = builtins::Function::ReseedRng;
@@ -1669,11 +2122,55 @@ where
         }
     }
 
+    // Numbers passed to print are converted to strings using OFMT, rather than CONVFMT.
+    fn output_str(&mut self, v: PrimVal<'b>, current_open: NodeIx) -> Result<PrimVal<'b>> {
+        if let PrimVal::ILit(_) | PrimVal::StrLit(_) = v {
+            return Ok(v);
+        }
+        let e = PrimExpr::CallBuiltin(builtins::Function::ToOutputStr, smallvec![v]);
+        self.to_val(e, current_open)
+    }
+
+    // The name of the column that `expr` reads, if it has one: a column of the header row, or a
+    // key path of JSON Lines input.
+    fn json_key<'c>(&self, expr: &'c Expr<'c, 'b, I>) -> Option<&'b str> {
+        match expr {
+            Expr::Unop(ast::Unop::Column, Expr::StrLit(name))
+                if self.ctx.header.is_some() || self.ctx.key_paths.is_some() =>
+            {
+                Some(name)
+            }
+            Expr::Unop(ast::Unop::Column, Expr::ILit(ix)) if *ix > 0 => {
+                self.header.get(*ix as usize - 1).cloned()
+            }
+            _ => None,
+        }
+    }
+
+    // Is `expr` a call to join_json? With JSON output, join_fields(i, j) is join_json(i, j).
+    fn is_json_object<'c>(&self, expr: &'c Expr<'c, 'b, I>) -> bool {
+        use builtins::Function::{JoinCols, JoinJSON};
+        let (fname, args) = match expr {
+            Expr::Call(fname, args) => (fname, args),
+            _ => return false,
+        };
+        let bi = match fname {
+            Either::Left(fname) => builtins::Function::try_from(fname.clone()).ok(),
+            Either::Right(bi) => Some(*bi),
+        };
+        match bi {
+            Some(JoinJSON) => true,
+            Some(JoinCols) => args.len() == 2,
+            _ => false,
+        }
+    }
+
     fn escape(&mut self, v: PrimVal<'b>, current_open: NodeIx) -> Result<PrimVal<'b>> {
         let builtin = match self.ctx.esc {
             Escaper::CSV => builtins::Function::EscapeCSV,
 
             Escaper::TSV => builtins::Function::EscapeTSV,
+            Escaper::JSON => builtins::Function::EscapeJSON,
             Escaper::Identity => return Ok(v),
         };
         let e = PrimExpr::CallBuiltin(builtin, smallvec![v]);
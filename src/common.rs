@@ -48,6 +48,29 @@ impl ExecutionStrategy {
     }
 }
 
+/// The destination of a `print` or `printf` statement with an output redirect.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FileSpec {
+    /// `print > file`
+    Trunc = 0,
+    /// `print >> file`
+    Append = 1,
+    /// `print | command`
+    Cmd = 2,
+}
+
+impl FileSpec {
+    pub(crate) fn from_int(i: i64) -> Option<FileSpec> {
+        use FileSpec::*;
+        match i {
+            0 => Some(Trunc),
+            1 => Some(Append),
+            2 => Some(Cmd),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum Stage<T> {
     Main(T),
@@ -1,7 +1,7 @@
 use crate::builtins;
 use crate::bytecode;
 use crate::cfg::{self, is_unused, Function, Ident, PrimExpr, PrimStmt, PrimVal, ProgramContext};
-use crate::common::{Either, Graph, NodeIx, NumTy, Result, Stage, WorkList};
+use crate::common::{Either, FileSpec, Graph, NodeIx, NumTy, Result, Stage, WorkList};
 use crate::cross_stage;
 #[cfg(feature = "llvm_backend")]
 use crate::llvm;
@@ -166,7 +166,7 @@ pub(crate) fn run_llvm<'a>(
     reader: impl llvm::IntoRuntime,
     ff: impl runtime::writers::FileFactory,
     cfg: llvm::Config,
-) -> Result<()> {
+) -> Result<i32 /* exit code */> {
     use crate::llvm::Generator;
     let mut typer = Typer::init_from_ctx(ctx)?;
     let used_fields = typer.used_fields.clone();
@@ -271,6 +271,8 @@ pub(crate) struct Typer<'a> {
 
     // For projection pushdown
     used_fields: FieldSet,
+    // Whether string functions and printf count characters rather than bytes (--chars).
+    pub chars: bool,
     // Not used for bytecode generation.
     callgraph: Graph<HashSet<(NumTy, Ty)>, ()>,
 
@@ -358,6 +360,8 @@ struct View<'a, 'b> {
     local_globals: &'b HashSet<NumTy>,
     arity: &'b HashMap<NumTy, NumTy>,
     func_info: &'b Vec<FuncInfo>,
+    // Whether string functions use character rather than byte offsets (--chars).
+    chars: bool,
     // The current basic block being filled; It'll be swaped into `frame.cfg` as we translate a
     // given function cfg.
     stream: &'b mut VecDeque<Instr<'a>>,
@@ -449,6 +453,7 @@ impl<'a> Typer<'a> {
             reader,
             ff,
             &self.used_fields,
+            self.chars,
         ))
     }
     fn to_bytecode(&mut self) -> Result<Vec<Vec<LL<'a>>>> {
@@ -622,6 +627,7 @@ impl<'a> Typer<'a> {
         // and global variables.
 
         let mut gen = Typer::default();
+        gen.chars = pc.chars;
         let types::TypeInfo { var_tys, func_tys } = types::get_types(pc)?;
         let local_globals = pc.local_globals();
         macro_rules! init_entry {
@@ -693,6 +699,7 @@ impl<'a> Typer<'a> {
                 arity: &gen.arity,
                 local_globals: &gen.local_globals,
                 func_info: &gen.func_info,
+                chars: gen.chars,
                 stream: &mut stream,
             }
             .process_function(&pc.funcs[src_func])?;
@@ -722,6 +729,7 @@ impl<'a> Typer<'a> {
                         Either::Left(LL::GetColumn(_dst, col_reg)) => ufa.add_col(*col_reg),
                         Either::Left(LL::JoinCSV(_, start, end))
                         | Either::Left(LL::JoinTSV(_, start, end))
+                        | Either::Left(LL::JoinJSON(_, start, end))
                         | Either::Left(LL::JoinColumns(_, start, end, _)) => {
                             ufa.add_join(*start, *end);
                         }
@@ -1253,7 +1261,9 @@ impl<'a, 'b> View<'a, 'b> {
                     }
                 }
             }
+            Match if self.chars => gen_op!(Match, [Str, MatchChars]),
             Match => gen_op!(Match, [Str, Match]),
+            SubstrIndex if self.chars => gen_op!(SubstrIndex, [Str, SubstrIndexChars]),
             SubstrIndex => gen_op!(SubstrIndex, [Str, SubstrIndex]),
             Contains => {
                 if res_reg != UNUSED {
@@ -1281,6 +1291,12 @@ impl<'a, 'b> View<'a, 'b> {
                 }
             }
             Nextline => self.pushl(LL::NextLine(res_reg.into(), conv_regs[0].into())),
+            ReadErrCmd => {
+                if res_reg != UNUSED {
+                    self.pushl(LL::ReadErrCmd(res_reg.into(), conv_regs[0].into()))
+                }
+            }
+            NextlineCmd => self.pushl(LL::NextLineCmd(res_reg.into(), conv_regs[0].into())),
             ReadErrStdin => {
                 if res_reg != UNUSED {
                     self.pushl(LL::ReadErrStdin(res_reg.into()))
@@ -1289,7 +1305,18 @@ impl<'a, 'b> View<'a, 'b> {
             NextlineStdin => self.pushl(LL::NextLineStdin(res_reg.into())),
             ReadLineStdinFused => self.pushl(LL::NextLineStdinFused()),
             NextFile => self.pushl(LL::NextFile()),
+            Exit => self.pushl(LL::Exit(conv_regs.get(0).map(|r| (*r).into()))),
+            Exiting => {
+                if res_reg != UNUSED {
+                    self.pushl(LL::Exiting(res_reg.into()))
+                }
+            }
             Setcol => self.pushl(LL::SetColumn(conv_regs[0].into(), conv_regs[1].into())),
+            RebuiltRecord => {
+                if res_reg != UNUSED {
+                    self.pushl(LL::RebuiltRecord(res_reg.into()))
+                }
+            }
             Sub => {
                 if res_reg == UNUSED {
                     res_reg = self.regs.stats.reg_of_ty(res_ty);
@@ -1312,6 +1339,17 @@ impl<'a, 'b> View<'a, 'b> {
                     conv_regs[2].into(),
                 ))
             }
+            GenSub => {
+                if res_reg != UNUSED {
+                    self.pushl(LL::GenSub(
+                        res_reg.into(),
+                        conv_regs[0].into(),
+                        conv_regs[1].into(),
+                        conv_regs[2].into(),
+                        conv_regs[3].into(),
+                    ))
+                }
+            }
             EscapeCSV => {
                 if res_reg != UNUSED {
                     self.pushl(LL::EscapeCSV(res_reg.into(), conv_regs[0].into()))
@@ -1322,9 +1360,49 @@ impl<'a, 'b> View<'a, 'b> {
                     self.pushl(LL::EscapeTSV(res_reg.into(), conv_regs[0].into()))
                 }
             }
+            EscapeJSON => {
+                if res_reg != UNUSED {
+                    self.pushl(LL::EscapeJSON(res_reg.into(), conv_regs[0].into()))
+                }
+            }
+            ToUpper => {
+                if res_reg != UNUSED {
+                    self.pushl(LL::ToUpper(res_reg.into(), conv_regs[0].into()))
+                }
+            }
+            ToLower => {
+                if res_reg != UNUSED {
+                    self.pushl(LL::ToLower(res_reg.into(), conv_regs[0].into()))
+                }
+            }
+            ToOutputStr => {
+                if res_reg != UNUSED {
+                    if let Ty::Float = conv_tys[0] {
+                        self.pushl(LL::FloatToOutputStr(res_reg.into(), conv_regs[0].into()))
+                    } else {
+                        self.convert(res_reg, Ty::Str, conv_regs[0], conv_tys[0])?
+                    }
+                }
+            }
+            ToJSON => {
+                if res_reg != UNUSED {
+                    match conv_tys[0] {
+                        Ty::Float => {
+                            self.pushl(LL::FloatToJSON(res_reg.into(), conv_regs[0].into()))
+                        }
+                        Ty::Int => self.convert(res_reg, Ty::Str, conv_regs[0], conv_tys[0])?,
+                        _ => self.pushl(LL::EscapeJSON(res_reg.into(), conv_regs[0].into())),
+                    }
+                }
+            }
             Substr => {
                 if res_reg != UNUSED {
-                    self.pushl(LL::Substr(
+                    let substr = if self.chars {
+                        LL::SubstrChars
+                    } else {
+                        LL::Substr
+                    };
+                    self.pushl(substr(
                         res_reg.into(),
                         conv_regs[0].into(),
                         conv_regs[1].into(),
@@ -1338,6 +1416,44 @@ impl<'a, 'b> View<'a, 'b> {
                     self.pushl(LL::HexStrToInt(res_reg.into(), conv_regs[0].into()))
                 }
             }
+            Systime => {
+                if res_reg != UNUSED {
+                    self.pushl(LL::Systime(res_reg.into()))
+                }
+            }
+            Strftime => {
+                if res_reg != UNUSED {
+                    self.pushl(LL::Strftime(
+                        res_reg.into(),
+                        conv_regs[0].into(),
+                        conv_regs[1].into(),
+                        conv_regs[2].into(),
+                    ))
+                }
+            }
+            Mktime => {
+                if res_reg != UNUSED {
+                    self.pushl(LL::Mktime(res_reg.into(), conv_regs[0].into()))
+                }
+            }
+            ParseTime => {
+                if res_reg != UNUSED {
+                    self.pushl(LL::ParseTime(
+                        res_reg.into(),
+                        conv_regs[0].into(),
+                        conv_regs[1].into(),
+                    ))
+                }
+            }
+            JSONPath => {
+                if res_reg != UNUSED {
+                    self.pushl(LL::JSONPath(
+                        res_reg.into(),
+                        conv_regs[0].into(),
+                        conv_regs[1].into(),
+                    ))
+                }
+            }
             Rand => {
                 if res_reg == UNUSED {
                     res_reg = self.regs.stats.reg_of_ty(res_ty);
@@ -1378,6 +1494,40 @@ impl<'a, 'b> View<'a, 'b> {
                     return err!("invalid input types to split: {:?}", &conv_tys[..]);
                 })
             }
+            PatSplit => {
+                if res_reg == UNUSED {
+                    res_reg = self.regs.stats.reg_of_ty(res_ty);
+                }
+                self.pushl(LL::PatSplit {
+                    flds: res_reg.into(),
+                    to_split: conv_regs[0].into(),
+                    arr: conv_regs[1].into(),
+                    pat: conv_regs[2].into(),
+                    seps: conv_regs[3].into(),
+                })
+            }
+            MatchCaptures => {
+                if res_reg == UNUSED {
+                    res_reg = self.regs.stats.reg_of_ty(res_ty);
+                }
+                self.pushl(if conv_tys[2] == Ty::MapIntStr {
+                    LL::MatchCapturesInt(
+                        res_reg.into(),
+                        conv_regs[0].into(),
+                        conv_regs[1].into(),
+                        conv_regs[2].into(),
+                    )
+                } else if conv_tys[2] == Ty::MapStrStr {
+                    LL::MatchCapturesStr(
+                        res_reg.into(),
+                        conv_regs[0].into(),
+                        conv_regs[1].into(),
+                        conv_regs[2].into(),
+                    )
+                } else {
+                    return err!("invalid input types to match: {:?}", &conv_tys[..]);
+                })
+            }
             Length => {
                 if res_reg != UNUSED {
                     self.pushl(match conv_tys[0] {
@@ -1392,6 +1542,9 @@ impl<'a, 'b> View<'a, 'b> {
                             map: conv_regs[0],
                             dst: res_reg.into(),
                         },
+                        Ty::Str if self.chars => {
+                            LL::LenStrChars(res_reg.into(), conv_regs[0].into())
+                        }
                         Ty::Str => LL::LenStr(res_reg.into(), conv_regs[0].into()),
                         _ => return err!("invalid input type for length: {:?}", &conv_tys[..]),
                     })
@@ -1399,12 +1552,15 @@ impl<'a, 'b> View<'a, 'b> {
             }
             Print => {
                 // XXX this imports a specific assumption on how the PrimStmt is generated, we may
-                // want to make the bool parameter to Print dynamic.
-                if let cfg::PrimVal::ILit(i) = &args[2] {
-                    self.pushl(LL::Print(conv_regs[0].into(), conv_regs[1].into(), *i != 0));
+                // want to make the FileSpec parameter to Print dynamic.
+                if let Some(spec) = match &args[2] {
+                    cfg::PrimVal::ILit(i) => FileSpec::from_int(*i),
+                    _ => None,
+                } {
+                    self.pushl(LL::Print(conv_regs[0].into(), conv_regs[1].into(), spec));
                     return Ok(());
                 } else {
-                    return err!("must pass constant append parameter to print");
+                    return err!("must pass constant output spec parameter to print");
                 }
             }
             PrintStdout => {
@@ -1425,12 +1581,59 @@ impl<'a, 'b> View<'a, 'b> {
                 }),
                 _ => return err!("incorrect parameter types for Delete: {:?}", &conv_tys[..]),
             },
+            Clear => match &conv_tys[0] {
+                Ty::Null => {}
+                Ty::MapIntInt
+                | Ty::MapIntStr
+                | Ty::MapIntFloat
+                | Ty::MapStrInt
+                | Ty::MapStrStr
+                | Ty::MapStrFloat => self.pushl(LL::Clear {
+                    map_ty: conv_tys[0],
+                    map: conv_regs[0],
+                }),
+                _ => return err!("incorrect parameter types for Clear: {:?}", &conv_tys[..]),
+            },
+            Asort | Asorti => {
+                if res_reg == UNUSED {
+                    res_reg = self.regs.stats.reg_of_ty(res_ty);
+                }
+                let (src_ty, src, dst_ty, dst, res) = (
+                    conv_tys[0],
+                    conv_regs[0],
+                    conv_tys[1],
+                    conv_regs[1],
+                    res_reg,
+                );
+                self.pushl(if let Asort = bf {
+                    LL::Asort {
+                        src_ty,
+                        src,
+                        dst_ty,
+                        dst,
+                        res,
+                    }
+                } else {
+                    LL::Asorti {
+                        src_ty,
+                        src,
+                        dst_ty,
+                        dst,
+                        res,
+                    }
+                })
+            }
             Close => {
-                self.pushl(LL::Close(conv_regs[0].into()));
-                assert_eq!(res_ty, Ty::Str);
-                if res_reg != UNUSED {
-                    self.pushl(LL::StoreConstStr(res_reg.into(), Default::default()));
+                if res_reg == UNUSED {
+                    res_reg = self.regs.stats.reg_of_ty(res_ty);
+                }
+                self.pushl(LL::Close(res_reg.into(), conv_regs[0].into()))
+            }
+            System => {
+                if res_reg == UNUSED {
+                    res_reg = self.regs.stats.reg_of_ty(res_ty);
                 }
+                self.pushl(LL::RunCmd(res_reg.into(), conv_regs[0].into()))
             }
             JoinCSV => {
                 if res_reg != UNUSED {
@@ -1450,6 +1653,15 @@ impl<'a, 'b> View<'a, 'b> {
                     ))
                 }
             }
+            JoinJSON => {
+                if res_reg != UNUSED {
+                    self.pushl(LL::JoinJSON(
+                        res_reg.into(),
+                        conv_regs[0].into(),
+                        conv_regs[1].into(),
+                    ))
+                }
+            }
             JoinCols => {
                 if res_reg != UNUSED {
                     self.pushl(LL::JoinColumns(
@@ -1642,6 +1854,7 @@ impl<'a, 'b> View<'a, 'b> {
                     Ty::Str => LL::LoadVarStr(target_reg.into(), *bv),
                     Ty::Int => LL::LoadVarInt(target_reg.into(), *bv),
                     Ty::MapIntStr => LL::LoadVarIntMap(target_reg.into(), *bv),
+                    Ty::MapStrStr => LL::LoadVarStrMap(target_reg.into(), *bv),
                     _ => unreachable!(),
                 });
                 self.convert(dst_reg, dst_ty, target_reg, target_ty)?
@@ -1691,6 +1904,7 @@ impl<'a, 'b> View<'a, 'b> {
                 self.pushl(match ty {
                     Str => LL::StoreVarStr(*v, reg.into()),
                     MapIntStr => LL::StoreVarIntMap(*v, reg.into()),
+                    MapStrStr => LL::StoreVarStrMap(*v, reg.into()),
                     Int => LL::StoreVarInt(*v, reg.into()),
                     _ => return err!("unexpected type for variable {} : {:?}", v, ty),
                 });
@@ -1710,10 +1924,10 @@ impl<'a, 'b> View<'a, 'b> {
                 for a in args {
                     arg_regs.push(self.get_reg(a)?);
                 }
-                let out_reg = if let Some((out, append)) = out {
+                let out_reg = if let Some((out, spec)) = out {
                     let (mut out_reg, out_ty) = self.get_reg(out)?;
                     out_reg = self.ensure_ty(out_reg, out_ty, Ty::Str)?;
-                    Some((out_reg.into(), *append))
+                    Some((out_reg.into(), *spec))
                 } else {
                     None
                 };
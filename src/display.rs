@@ -2,6 +2,7 @@
 use crate::ast::{Binop, Unop};
 use crate::builtins::{Function, Variable};
 use crate::cfg::{BasicBlock, Ident, PrimExpr, PrimStmt, PrimVal, Transition};
+use crate::common::FileSpec;
 use crate::lexer;
 use std::fmt::{self, Display, Formatter};
 
@@ -50,8 +51,13 @@ impl<'a> Display for PrimStmt<'a> {
                     }
                 }
                 write!(f, ")")?;
-                if let Some((out, ap)) = out {
-                    write!(f, " {} {}", out, if *ap { ">>" } else { ">" })?;
+                if let Some((out, spec)) = out {
+                    let redirect = match spec {
+                        FileSpec::Trunc => ">",
+                        FileSpec::Append => ">>",
+                        FileSpec::Cmd => "|",
+                    };
+                    write!(f, " {} {}", out, redirect)?;
                 }
                 Ok(())
             }
@@ -129,24 +135,47 @@ impl Display for Function {
             PrintStdout => write!(f, "print(stdout)"),
             ReadErr => write!(f, "hasline"),
             Nextline => write!(f, "nextline"),
+            ReadErrCmd => write!(f, "hasline_cmd"),
+            NextlineCmd => write!(f, "nextline_cmd"),
             ReadErrStdin => write!(f, "hasline(stdin)"),
             NextlineStdin => write!(f, "nextline(stdin)"),
             ReadLineStdinFused => write!(f, "stdin-fused"),
             NextFile => write!(f, "nextfile"),
+            Exit => write!(f, "exit"),
+            Exiting => write!(f, "exiting"),
             Setcol => write!(f, "$="),
+            RebuiltRecord => write!(f, "rebuilt-record"),
             Split => write!(f, "split"),
+            PatSplit => write!(f, "patsplit"),
             Length => write!(f, "length"),
             Contains => write!(f, "contains"),
             Delete => write!(f, "delete"),
+            Clear => write!(f, "clear"),
+            Asort => write!(f, "asort"),
+            Asorti => write!(f, "asorti"),
             Close => write!(f, "close"),
+            System => write!(f, "system"),
             Match => write!(f, "match"),
+            MatchCaptures => write!(f, "match"),
             SubstrIndex => write!(f, "index"),
             Sub => write!(f, "sub"),
             GSub => write!(f, "gsub"),
+            GenSub => write!(f, "gensub"),
+            Systime => write!(f, "systime"),
+            Strftime => write!(f, "strftime"),
+            Mktime => write!(f, "mktime"),
+            ParseTime => write!(f, "parse_time"),
+            JSONPath => write!(f, "json"),
             EscapeCSV => write!(f, "escape_csv"),
+            ToUpper => write!(f, "toupper"),
+            ToLower => write!(f, "tolower"),
+            ToOutputStr => write!(f, "to_output_str"),
+            ToJSON => write!(f, "to_json"),
             EscapeTSV => write!(f, "escape_tsv"),
+            EscapeJSON => write!(f, "escape_json"),
             JoinCSV => write!(f, "join_csv"),
             JoinTSV => write!(f, "join_tsv"),
+            JoinJSON => write!(f, "join_json"),
             JoinCols => write!(f, "join_fields"),
             Substr => write!(f, "substr"),
             ToInt => write!(f, "int"),
@@ -178,6 +207,14 @@ impl Display for Variable {
                 RSTART => "RSTART",
                 RLENGTH => "RLENGTH",
                 PID => "PID",
+                ENVIRON => "ENVIRON",
+                SUBSEP => "SUBSEP",
+                CONVFMT => "CONVFMT",
+                OFMT => "OFMT",
+                SORTED_IN => "SORTED_IN",
+                RT => "RT",
+                FIELDWIDTHS => "FIELDWIDTHS",
+                FPAT => "FPAT",
             }
         )
     }
@@ -301,6 +338,7 @@ impl<'a> Display for lexer::Tok<'a> {
             COLON => ":",
 
             Append => ">>",
+            Pipe => "|",
 
             Dollar => "$",
             Semi => ";",
@@ -310,6 +348,7 @@ impl<'a> Display for lexer::Tok<'a> {
             Delete => "delete",
             Function => "function",
             Return => "return",
+            Exit => "exit",
 
             Ident(s) => return write!(fmt, "identifier({})", s),
             StrLit(s) => return write!(fmt, "{:?}", s),
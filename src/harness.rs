@@ -14,6 +14,7 @@ use crate::{
         splitter::{
             batch::{CSVReader, InputFormat},
             regex::RegexSplitter,
+            FieldSplit, FieldSplitReader,
         },
         writers::testing::FakeFs,
         ChainedReader,
@@ -59,10 +60,11 @@ cfg_if! {
 
         fn simulate_stdin_csv(
             ifmt: InputFormat,
+            esc: Escaper,
             inp: impl Into<String>,
             strat: ExecutionStrategy,
         ) -> impl llvm::IntoRuntime + runtime::LineReader {
-            CSVReader::new(split_stdin(inp.into()), ifmt, runtime::CHUNK_SIZE, /*check_utf8=*/ true,strat)
+            CSVReader::new(split_stdin(inp.into()), ifmt, esc, runtime::CHUNK_SIZE, /*check_utf8=*/ true,strat)
         }
 
         fn simulate_stdin_regex(
@@ -73,6 +75,18 @@ cfg_if! {
             })
         }
 
+        fn simulate_stdin_field_split(
+            field_split: FieldSplit,
+            inp: impl Into<String>,
+        ) -> impl llvm::IntoRuntime + runtime::LineReader {
+            FieldSplitReader::new(
+                simulate_stdin(inp, |reader, name| {
+                    RegexSplitter::new(reader, runtime::CHUNK_SIZE, name, /*check_utf8=*/false)
+                }),
+                field_split,
+            )
+        }
+
         fn simulate_stdin_whitespace(
             inp: impl Into<String>,
         ) -> impl llvm::IntoRuntime + runtime::LineReader {
@@ -103,10 +117,16 @@ cfg_if! {
                     cfg::SepAssign::Potential {
                         field_sep,
                         record_sep,
+                        field_widths,
+                        field_pattern,
                     } => {
+                        let field_split = FieldSplit::new(field_widths, field_pattern, false)?;
                         let field_sep = field_sep.unwrap_or(" ");
                         let record_sep = record_sep.unwrap_or("\n");
-                        if field_sep.len() == 1 && record_sep.len() == 1 {
+                        if let Some(field_split) = field_split {
+                            let $id = simulate_stdin_field_split(field_split, $inp);
+                            $body
+                        } else if field_sep.len() == 1 && record_sep.len() == 1 {
                             if field_sep == " " && record_sep == "\n" {
                                 let $id = simulate_stdin_whitespace($inp);
                                 $body
@@ -136,10 +156,11 @@ cfg_if! {
 
         fn simulate_stdin_csv(
             ifmt: InputFormat,
+            esc: Escaper,
             inp: impl Into<String>,
             strat: ExecutionStrategy,
         ) -> impl runtime::LineReader {
-            CSVReader::new(split_stdin(inp.into()), ifmt, runtime::CHUNK_SIZE, /*check_utf8=*/false, strat)
+            CSVReader::new(split_stdin(inp.into()), ifmt, esc, runtime::CHUNK_SIZE, /*check_utf8=*/false, strat)
         }
 
         fn simulate_stdin_regex(
@@ -149,6 +170,18 @@ cfg_if! {
                 RegexSplitter::new(reader, runtime::CHUNK_SIZE, name, /*check_utf8=*/true)
             })
         }
+
+        fn simulate_stdin_field_split(
+            field_split: FieldSplit,
+            inp: impl Into<String>,
+        ) -> impl runtime::LineReader {
+            FieldSplitReader::new(
+                simulate_stdin(inp, |reader, name| {
+                    RegexSplitter::new(reader, runtime::CHUNK_SIZE, name, /*check_utf8=*/true)
+                }),
+                field_split,
+            )
+        }
     }
 }
 
@@ -210,7 +243,7 @@ cfg_if! {
             let a = Arena::default();
             let stmt = parse_program(prog, &a, esc, strat)?;
             let mut ctx = cfg::ProgramContext::from_prog(&a, stmt, esc)?;
-            let sep_analysis = ctx.analyze_sep_assignments();
+            let sep_analysis = ctx.analyze_sep_assignments()?;
             if _PRINT_DEBUG_INFO {
                 let mut buf = Vec::<u8>::new();
                 ctx.dbg_print(&mut buf).unwrap();
@@ -220,7 +253,7 @@ cfg_if! {
             if let Some(ifmt) = ifmt {
                 compile::run_llvm(
                     &mut ctx,
-                    simulate_stdin_csv(ifmt, stdin, strat),
+                    simulate_stdin_csv(ifmt, esc, stdin, strat),
                     fake_fs.clone(),
                     llvm::Config {
                         opt_level: LLVM_CONFIG.opt_level,
@@ -266,6 +299,38 @@ pub(crate) fn parse_program<'a, 'inp, 'outer>(
     a: &'a Arena<'outer>,
     esc: Escaper,
     strat: ExecutionStrategy,
+) -> Result<Prog<'a>> {
+    parse_program_with_header(prog, a, esc, strat, None)
+}
+
+// Parse `prog` as though it were run with --header, with `header` as the input's header row.
+pub(crate) fn parse_program_with_header<'a, 'inp, 'outer>(
+    prog: &'inp str,
+    a: &'a Arena<'outer>,
+    esc: Escaper,
+    strat: ExecutionStrategy,
+    header: Option<Vec<&'a str>>,
+) -> Result<Prog<'a>> {
+    parse_program_inner(prog, a, esc, strat, header, /*key_paths=*/ false)
+}
+
+// Parse `prog` as though it were run with --input-format jsonl.
+pub(crate) fn parse_program_jsonl<'a, 'inp, 'outer>(
+    prog: &'inp str,
+    a: &'a Arena<'outer>,
+    esc: Escaper,
+    strat: ExecutionStrategy,
+) -> Result<Prog<'a>> {
+    parse_program_inner(prog, a, esc, strat, None, /*key_paths=*/ true)
+}
+
+fn parse_program_inner<'a, 'inp, 'outer>(
+    prog: &'inp str,
+    a: &'a Arena<'outer>,
+    esc: Escaper,
+    strat: ExecutionStrategy,
+    header: Option<Vec<&'a str>>,
+    key_paths: bool,
 ) -> Result<Prog<'a>> {
     let prog = a.alloc_str(prog);
     let lexer = lexer::Tokenizer::new(prog);
@@ -276,8 +341,10 @@ pub(crate) fn parse_program<'a, 'inp, 'outer>(
             match esc {
                 Escaper::CSV => program.output_sep = Some(","),
                 Escaper::TSV => program.output_sep = Some("\t"),
-                Escaper::Identity => {}
+                Escaper::JSON | Escaper::Identity => {}
             };
+            program.header = header;
+            program.key_paths = key_paths;
             Ok(a.alloc_v(program))
         }
         Err(e) => {
@@ -356,7 +423,22 @@ pub(crate) fn run_prog<'a>(
                 if let Some(ifmt) = ifmt {
                     let mut $interp = compile::bytecode(
                         &mut ctx,
-                        simulate_stdin_csv(ifmt, stdin, strat),
+                        simulate_stdin_csv(ifmt, esc, stdin, strat),
+                        fake_fs.clone(),
+                        strat.num_workers(),
+                    )?;
+                    $body
+                } else if let Some(field_split) = match ctx.analyze_sep_assignments()? {
+                    cfg::SepAssign::Potential {
+                        field_widths,
+                        field_pattern,
+                        ..
+                    } => FieldSplit::new(field_widths, field_pattern, false)?,
+                    cfg::SepAssign::Unsure => None,
+                } {
+                    let mut $interp = compile::bytecode(
+                        &mut ctx,
+                        simulate_stdin_field_split(field_split, stdin),
                         fake_fs.clone(),
                         strat.num_workers(),
                     )?;
@@ -403,8 +485,16 @@ pub(crate) fn run_prog<'a>(
 mod tests {
     extern crate test;
     use super::*;
+    use crate::runtime::splitter::batch::EscapeStyle;
     use test::{black_box, Bencher};
 
+    // The time functions use the local timezone; pin it so test output does not depend on the
+    // host.
+    fn pin_timezone() {
+        static PIN: std::sync::Once = std::sync::Once::new();
+        PIN.call_once(|| std::env::set_var("TZ", "UTC"));
+    }
+
     macro_rules! test_program_parallel {
         ($desc:ident, $strat:tt, $e:expr, $in:expr, $out:expr) => {
             mod $desc {
@@ -479,6 +569,7 @@ mod tests {
                 use super::*;
                 #[test]
                 fn bytecode() {
+                    pin_timezone();
                     let a = Arena::default();
                     let out = run_program(&a, $e, $inp, $esc, $csv, ExecutionStrategy::Serial);
                     match out {
@@ -504,6 +595,7 @@ mod tests {
                 #[cfg(feature = "llvm_backend")]
                 #[test]
                 fn llvm() {
+                    pin_timezone();
                     match run_llvm($e, $inp, $esc, $csv, ExecutionStrategy::Serial) {
                         Ok(out) => assert_eq!(
                             out, $out,
@@ -561,6 +653,295 @@ mod tests {
         assert_eq!(s1, used_fields(p1).unwrap());
     }
 
+    #[test]
+    fn header_columns() {
+        let a = Arena::default();
+        let prog = parse_program_with_header(
+            r#"{ print $"name", $FIELDS["age"] }
+            END { print FIELDS["name"], FIELDS["age"] }"#,
+            &a,
+            Escaper::Identity,
+            ExecutionStrategy::Serial,
+            Some(vec!["name", "age"]),
+        )
+        .unwrap();
+        let (out, _, _) = run_prog(
+            &a,
+            prog,
+            "bob,30\n\"al,ice\",25\n",
+            Escaper::Identity,
+            Some(InputFormat::CSV),
+            ExecutionStrategy::Serial,
+        )
+        .unwrap();
+        assert_eq!(out, "bob 30\nal,ice 25\n1 2\n");
+
+        let a = Arena::default();
+        let esc = Escaper::Identity;
+        let header = Some(vec!["name", "age", "city"]);
+        let prog = r#"{ print $"city", $"name" }"#;
+        let prog =
+            parse_program_with_header(prog, &a, esc, ExecutionStrategy::Serial, header).unwrap();
+        let mut ctx = cfg::ProgramContext::from_prog(&a, prog, esc).unwrap();
+        let mut fs = FieldSet::singleton(1);
+        fs.set(3);
+        assert_eq!(fs, compile::used_fields(&mut ctx).unwrap());
+
+        let prog = parse_program_with_header(
+            r#"{ print $"zip" }"#,
+            &a,
+            esc,
+            ExecutionStrategy::Serial,
+            Some(vec!["name"]),
+        )
+        .unwrap();
+        assert!(cfg::ProgramContext::from_prog(&a, prog, esc).is_err());
+
+        // With JSON output, printed columns are keyed by their names.
+        let a = Arena::default();
+        let esc = Escaper::JSON;
+        let prog = parse_program_with_header(
+            r#"{ print; print $"age", $1, NR }"#,
+            &a,
+            esc,
+            ExecutionStrategy::Serial,
+            Some(vec!["name", "age"]),
+        )
+        .unwrap();
+        let (out, _, _) = run_prog(
+            &a,
+            prog,
+            "bob,30\n",
+            esc,
+            Some(InputFormat::CSV),
+            ExecutionStrategy::Serial,
+        )
+        .unwrap();
+        assert_eq!(
+            out,
+            "{\"name\":\"bob\",\"age\":\"30\"}\n{\"age\":\"30\",\"name\":\"bob\",\"3\":1}\n"
+        );
+
+        // So are a rebuilt $0 and the result of join_json, with columns past the end of the header
+        // keyed by index.
+        let prog = parse_program_with_header(
+            r#"{ $2 = 31; $3 = "x"; printf "%s\n%s\n", $0, join_json(2, 3) }"#,
+            &a,
+            esc,
+            ExecutionStrategy::Serial,
+            Some(vec!["name", "age"]),
+        )
+        .unwrap();
+        let mut ctx = cfg::ProgramContext::from_prog(&a, prog, esc).unwrap();
+        let reader = CSVReader::new(
+            std::iter::once((io::Cursor::new("bob,30\n"), String::from("-"))),
+            InputFormat::CSV,
+            esc,
+            runtime::CHUNK_SIZE,
+            /*check_utf8=*/ false,
+            ExecutionStrategy::Serial,
+        )
+        .header(vec!["name".into(), "age".into()]);
+        let fake_fs = FakeFs::default();
+        {
+            let mut interp = compile::bytecode(&mut ctx, reader, fake_fs.clone(), 1).unwrap();
+            interp.run().unwrap();
+        }
+        let out = String::from_utf8(fake_fs.stdout.read_data()).unwrap();
+        assert_eq!(
+            out,
+            "{\"name\":\"bob\",\"age\":\"31\",\"3\":\"x\"}\n{\"age\":\"31\",\"3\":\"x\"}\n"
+        );
+    }
+
+    #[test]
+    fn csv_strict_header() {
+        use crate::runtime::splitter::batch::{HeaderLens, OnError, SkipHeader};
+        let input = "name,n\na,1\n\"b\"x,2\nc,3\n";
+        for strat in &[ExecutionStrategy::Serial, ExecutionStrategy::ShardPerFile] {
+            let strat = *strat;
+            let run = |prog: &str, on_error: OnError| -> Result<(String, FakeFs)> {
+                let a = Arena::default();
+                let esc = Escaper::Identity;
+                let prog =
+                    parse_program_with_header(prog, &a, esc, strat, Some(vec!["name", "n"]))?;
+                let mut ctx = cfg::ProgramContext::from_prog(&a, prog, esc)?;
+                let ifmt = InputFormat {
+                    header: true,
+                    strict: true,
+                    ..InputFormat::CSV
+                };
+                let lens = HeaderLens::new(1);
+                let open = move || Ok(io::Cursor::new(input));
+                let reader = CSVReader::new(
+                    std::iter::once((
+                        SkipHeader::new(open, ifmt, lens.clone(), 0),
+                        String::from("fake-stdin"),
+                    )),
+                    ifmt,
+                    esc,
+                    runtime::CHUNK_SIZE,
+                    /*check_utf8=*/ false,
+                    strat,
+                )
+                .strict(Some(on_error), lens);
+                let fs = FakeFs::default();
+                {
+                    let mut interp =
+                        compile::bytecode(&mut ctx, reader, fs.clone(), strat.num_workers())?;
+                    interp.run()?;
+                }
+                let out = String::from_utf8(fs.stdout.read_data()).unwrap();
+                Ok((out, fs))
+            };
+            let sum = r#"{ n += $"n" } END { print n }"#;
+
+            // Byte offsets count the header row; record numbers do not.
+            let msg = match run(sum, OnError::Abort) {
+                Ok(_) => panic!("expected a malformed record error"),
+                Err(e) => format!("{}", e),
+            };
+            assert!(
+                msg.contains("fake-stdin: record 2 at byte 11: text after the closing quote"),
+                "{}",
+                msg
+            );
+
+            let (out, _) = run(sum, OnError::Skip).unwrap();
+            assert_eq!(out, "4.0\n");
+
+            // Rejected records are written through the program's output files, so the program
+            // can append to the same file.
+            let prog = r#"{ n += $"n" } END { print "sum", n > "rejects" }"#;
+            let (out, fs) = run(prog, OnError::Reject("rejects".into())).unwrap();
+            assert_eq!(out, "");
+            let rejected = fs.get_handle("rejects").unwrap().read_data();
+            assert_eq!(String::from_utf8(rejected).unwrap(), "\"b\"x,2\nsum 4.0\n");
+        }
+    }
+
+    #[test]
+    fn json_key_paths() {
+        use crate::runtime::splitter::json::JSONReader;
+        let a = Arena::default();
+        let esc = Escaper::Identity;
+        let prog = parse_program_jsonl(
+            r#"{ print $"user.name", $"tags.1", NF; n = n $"user.id" }
+            END { print n, json("a.b", "{\"a\": {\"b\": [true]}}") }"#,
+            &a,
+            esc,
+            ExecutionStrategy::Serial,
+        )
+        .unwrap();
+        let mut ctx = cfg::ProgramContext::from_prog(&a, prog, esc).unwrap();
+        assert_eq!(ctx.key_paths(), &["user.name", "tags.1", "user.id"]);
+        let input = concat!(
+            r#"{"user": {"id": 1, "name": "Ann \"A\""}, "tags": ["x", "y"]}"#,
+            "
+",
+            r#"{"tags": [], "user": {"name": "Bob", "id": 2}}"#,
+            "
+"
+        );
+        let reader = JSONReader::new(
+            std::iter::once((io::Cursor::new(input), String::from("-"))),
+            ctx.key_paths().to_vec(),
+            runtime::CHUNK_SIZE,
+            /*check_utf8=*/ false,
+            ExecutionStrategy::Serial,
+        );
+        let fake_fs = FakeFs::default();
+        {
+            let mut interp = compile::bytecode(&mut ctx, reader, fake_fs.clone(), 1).unwrap();
+            interp.run().unwrap();
+        }
+        let out = String::from_utf8(fake_fs.stdout.read_data()).unwrap();
+        assert_eq!(out, "Ann \"A\" y 3\nBob  3\n12 [true]\n");
+
+        // Only the paths that are used are decoded. `f` is never called, so "a" is not used.
+        let prog = parse_program_jsonl(
+            r#"function f() { return $"a"; }
+            { print $"b.c", json("d") }"#,
+            &a,
+            esc,
+            ExecutionStrategy::Serial,
+        )
+        .unwrap();
+        let mut ctx = cfg::ProgramContext::from_prog(&a, prog, esc).unwrap();
+        assert_eq!(ctx.key_paths(), &["a", "b.c"]);
+        let mut fs = FieldSet::singleton(0);
+        fs.set(2);
+        assert_eq!(fs, compile::used_fields(&mut ctx).unwrap());
+
+        // Columns can only be named by literal paths; other paths go through json(). Columns and
+        // NF cannot be assigned to, as $0 could not be rebuilt from the paths the program reads.
+        for prog in &[
+            r#"{ print $1 }"#,
+            r#"{ p = "user.id"; print $p }"#,
+            r#"{ $NF = 1 }"#,
+            r#"{ $"a.b" = "x" }"#,
+            r#"{ sub(/x/, "y", $"a") }"#,
+            r#"{ NF = 1 }"#,
+        ] {
+            let prog = parse_program_jsonl(prog, &a, esc, ExecutionStrategy::Serial).unwrap();
+            assert!(cfg::ProgramContext::from_prog(&a, prog, esc).is_err());
+        }
+
+        // With JSON output, $0 is printed as it is, and printed columns are keyed by key path. $0
+        // can still be assigned to.
+        let esc = Escaper::JSON;
+        let prog = parse_program_jsonl(
+            r#"{ print; print $"a.b", NR; $0 = "{\"a\": {\"b\": \"x\"}}"; print $"a.b"; print }"#,
+            &a,
+            esc,
+            ExecutionStrategy::Serial,
+        )
+        .unwrap();
+        let mut ctx = cfg::ProgramContext::from_prog(&a, prog, esc).unwrap();
+        let reader = JSONReader::new(
+            std::iter::once((
+                io::Cursor::new(r#"{"a": {"b": 1}, "c": 2}"#),
+                String::from("-"),
+            )),
+            ctx.key_paths().to_vec(),
+            runtime::CHUNK_SIZE,
+            /*check_utf8=*/ false,
+            ExecutionStrategy::Serial,
+        );
+        let fake_fs = FakeFs::default();
+        {
+            let mut interp = compile::bytecode(&mut ctx, reader, fake_fs.clone(), 1).unwrap();
+            interp.run().unwrap();
+        }
+        let out = String::from_utf8(fake_fs.stdout.read_data()).unwrap();
+        assert_eq!(
+            out,
+            "{\"a\": {\"b\": 1}, \"c\": 2}\n{\"a.b\":\"1\",\"2\":1}\n{\"a.b\":\"x\"}\n{\"a\": {\"b\": \"x\"}}\n"
+        );
+    }
+
+    #[test]
+    fn char_semantics() {
+        let prog = r#"BEGIN {
+            s = "héllo wörld"
+            print length(s), substr(s, 2, 4), index(s, "wö"), match(s, /ö.l/), RSTART, RLENGTH
+            print length("hello"), substr("hello", 2, 4), index("hello", "l")
+        }"#;
+        let a = Arena::default();
+        let esc = Escaper::Identity;
+        let prog = parse_program(prog, &a, esc, ExecutionStrategy::Serial).unwrap();
+        let mut ctx = cfg::ProgramContext::from_prog(&a, prog, esc).unwrap();
+        ctx.chars = true;
+        let fake_fs = FakeFs::default();
+        {
+            let mut interp =
+                compile::bytecode(&mut ctx, simulate_stdin_regex(""), fake_fs.clone(), 1).unwrap();
+            interp.run().unwrap();
+        }
+        let out = String::from_utf8(fake_fs.stdout.read_data()).unwrap();
+        assert_eq!(out, "11 éll 7 8 8 3\n5 ell 3\n");
+    }
+
     test_program_parallel!(
         parallel_aggs,
         ShardPerFile,
@@ -636,6 +1017,108 @@ r#"hi,there
         @out_fmt Escaper::TSV
     );
 
+    test_program!(
+        basic_json_render,
+        r#"BEGIN { print "hi", 1; print "quote\"in field", "and a\ttab\n"; print ""; }"#,
+        r#"{"1":"hi","2":1}
+{"1":"quote\"in field","2":"and a\ttab\n"}
+{"1":""}
+"#,
+        @out_fmt Escaper::JSON
+    );
+
+    test_program!(
+        json_empty_record,
+        r#"{ print }"#,
+        "{\"1\":\"a\",\"2\":\"b\"}\n{}\n{\"1\":\"c\"}\n",
+        @input "a b\n\nc\n",
+        @types [],
+        @out_fmt Escaper::JSON,
+        @csv None
+    );
+
+    test_program!(
+        json_escape_builtins,
+        r#"{ print escape_json($2), join_json(2, NF), join_json(3, 2) }"#,
+        "\"b\\\"\" {\"2\":\"b\\\"\",\"3\":\"\\\\c\"} {}\n",
+        @input "a b\" \\c"
+    );
+
+    test_program!(
+        paragraph_mode,
+        r#"BEGIN { RS = ""; FS = ":" } { print NR": "$1, $3, NF; }"#,
+        "1: name 3 3\n2: name 3 3\n",
+        @input "\n\nname:a\n3\n\n\n\nname:b\n3\n\n"
+    );
+
+    test_program!(
+        record_terminator,
+        r#"BEGIN { RS = "[0-9]+" } { print $0 "<" RT ">"; } END { print "x" RT "x"; }"#,
+        "a<1>\nb<22>\nc<>\nxx\n",
+        @input "a1b22c"
+    );
+
+    test_program!(
+        record_terminator_bytes,
+        r#"{ print $0 "<" RT ">"; }"#,
+        "a<\n>\nb<>\n",
+        @input "a\nb"
+    );
+
+    test_program!(
+        field_widths,
+        r#"BEGIN { FIELDWIDTHS = "3 2:4 *" } { print NF, $1, $2, $3; }"#,
+        "3 abc fghi jklmn\n2 123 67 \n",
+        @input "abcdefghijklmn\n1234567\n"
+    );
+
+    test_program!(
+        field_widths_assign,
+        r#"BEGIN { FIELDWIDTHS = "2 2 2" } { $2 = "XX"; print; print $3; }"#,
+        "aa XX cc\ncc\n",
+        @input "aabbcc\n"
+    );
+
+    #[test]
+    fn field_widths_unknown() {
+        // FIELDWIDTHS assignments we cannot resolve before reading input are rejected rather than
+        // ignored.
+        for prog in &[
+            r#"{ FIELDWIDTHS = "2 4"; print $1 }"#,
+            r#"BEGIN { FIELDWIDTHS = "2 4"; FS = x } { print $1 }"#,
+            r#"{ FPAT = "[a-z]+"; print $1 }"#,
+        ] {
+            let a = Arena::default();
+            let res = run_program(
+                &a,
+                prog,
+                "abcdef\n",
+                Escaper::Identity,
+                None,
+                ExecutionStrategy::Serial,
+            );
+            assert!(res.is_err(), "{}", prog);
+        }
+    }
+
+    test_program!(
+        field_pattern,
+        r#"BEGIN { FPAT = "([^,]*)|(\"[^\"]+\")" } { print NF, $2, $3; }"#,
+        "4 \"b,c\" \n2 y \n",
+        @input "a,\"b,c\",,d\nx,y\n"
+    );
+
+    test_program!(
+        patsplit,
+        r#"BEGIN {
+    n = patsplit("<a1b22c>", arr, "[0-9]+", seps);
+    print n, arr[1], arr[2], seps[0], seps[1], seps[2];
+    m = patsplit("7 8 9", arr, "[0-9]");
+    print m, length(arr), arr[3];
+}"#,
+        "2 1 22 <a b c>\n3 3 9\n"
+    );
+
     test_program!(
         basic_multi_file,
         // test some OFS/ORS behavior for good measure
@@ -681,6 +1164,130 @@ is the second file
 it has one more line"#
     );
 
+    test_program!(
+        exit_in_main,
+        r#"NR == 3 { exit; } { print $0; } END { print "end", NR; }"#,
+        "1\n2\nend 3\n",
+        @input "1\n2\n3\n4\n5"
+    );
+    test_program!(
+        exit_in_begin,
+        r#"BEGIN { print "begin"; exit 2; }
+        { print $0; }
+        END { print "end", NR; }"#,
+        "begin\nend 0\n",
+        @input "1\n2\n3"
+    );
+    test_program!(
+        exit_in_end,
+        r#"END { print "end"; if (NR > 1) { exit; }; print "unreachable"; }"#,
+        "end\n",
+        @input "1\n2\n3"
+    );
+    test_program!(
+        exit_in_function,
+        r#"function check(x) { if (x > 1) { exit 1; }; return x; }
+        { print check($1); }
+        END { print "end", NR; }"#,
+        "0\n1\nend 3\n",
+        @input "0\n1\n2\n3"
+    );
+    test_program_parallel!(
+        exit_parallel,
+        ShardPerFile,
+        r#"BEGIN { exit; } { n++; } END { print "end", n+0; }"#,
+        "1\n2<<<FILE BREAK>>>3\n4",
+        "end 0\n"
+    );
+
+    test_program!(
+        getline_cmd,
+        r#"function cmd() { n++; return "echo " n; }
+BEGIN {
+    "echo hello" | getline x;
+    while (("printf \"a\nb\n\"" | getline) > 0) print "got " $0;
+    print x, close("echo hello"), close("not open");
+    cmd() | getline arr[n];
+    print n, arr[1];
+}"#,
+        "got a\ngot b\nhello 0 -1\n1 1\n"
+    );
+
+    test_program!(
+        print_cmd,
+        r#"BEGIN {
+    print "b" | "sort";
+    print "a" | "sort";
+    print "close", close("sort");
+    printf "%s\n", "done" | "cat";
+    close("cat");
+    print "status", close("exit 3");
+    print "x" | "cat > /dev/null; exit 3";
+    print "status", close("cat > /dev/null; exit 3");
+}"#,
+        "a\nb\nclose 0\ndone\nstatus -1\nstatus 3\n"
+    );
+
+    test_program!(
+        system_status,
+        r#"BEGIN {
+    print "first";
+    r = system("echo second; exit 4");
+    print "third", r;
+    print "status", system("kill -9 $$");
+}"#,
+        "first\nsecond\nthird 4\nstatus 265\n"
+    );
+
+    test_program!(
+        environ_basic,
+        r#"BEGIN {
+    print ("PATH" in ENVIRON);
+    ENVIRON["FRAWK_TEST"] = "set";
+    print ENVIRON["FRAWK_TEST"];
+}"#,
+        "1\nset\n"
+    );
+
+    test_program!(
+        multi_dim_subscripts,
+        r#"{ c[$1, $2]++; }
+        END {
+            if (("a", "x") in c) print "yes";
+            if (!(("b", "x") in c)) print "no";
+            print c["a", "x"];
+            delete c["a", "x"];
+            print length(c);
+            SUBSEP = ":";
+            for (k in c) print k;
+            m[1, 2] = 3;
+            for (k in m) print k, m[k];
+        }"#,
+        "yes\nno\n2\n1\nb\x1cy\n1:2 3\n",
+        @input "a x\nb y\na x\n",
+        @types [c :: MapStrInt, m :: MapStrInt]
+    );
+
+    test_program!(
+        case_conversion,
+        r#"{ print toupper($1), tolower($2); }"#,
+        "HELLO world\nÉCOLE straße\n",
+        @input "hello WORLD\nécole STRAßE\n"
+    );
+
+    test_program!(
+        convfmt_ofmt,
+        r#"BEGIN {
+    x = 1 / 4;
+    print x;
+    OFMT = "%.2f";
+    print 1 / 3, 6 / 2, (1 / 3) "";
+    CONVFMT = "%.3f";
+    print (1 / 3) "", 2.0 "";
+}"#,
+        "0.25\n0.33 3 0.3333333333333333\n0.333 2\n"
+    );
+
     test_program_csv!(
         csv_no_escaping,
         r#"function max(x, y) { return x<y?y:x; }
@@ -703,6 +1310,84 @@ it has one more line"#
           "1,2\t,3\"4\n",
           @input r#"help,"1,2\t,3""4",5"#
     );
+    test_program!(
+        csv_dialect,
+        r#"{ print NF, $1, $2 }"#,
+        "2 a|b c\\td\n2 it's x\n",
+        @input "# header comment\n'a|b'|c\\td\n#|x\n'it''s'|x\n",
+        @types [],
+        @out_fmt Escaper::Identity,
+        @csv Some(InputFormat {
+            delimiter: b'|',
+            quote: Some(b'\''),
+            escape: EscapeStyle::Doubled,
+            comment: Some(b'#'),
+            header: false,
+            strict: false,
+        })
+    );
+    test_program!(
+        csv_set_columns,
+        r#"{ $3 = toupper($3); print; NF = 2; print; $4 = "x"; print; print NF }"#,
+        "a,b,C\na,b\na,b,,x\n4\n\"x,y\",z,\"W\"\"\"\n\"x,y\",z\n\"x,y\",z,,x\n4\n",
+        @input "a,b,c\n\"x,y\",z,\"w\"\"\"\n",
+        @types [],
+        @out_fmt Escaper::CSV,
+        @csv Some(InputFormat::CSV)
+    );
+    test_program!(
+        csv_conditional_assign,
+        r#"{ if ($2 > 10) $2 = $2 + 1; print; print $0 }"#,
+        "bob,31.0,\"NY, NY\"\nbob,31.0,\"NY, NY\"\nal,4,LA\nal,4,LA\n",
+        @input "bob,30,\"NY, NY\"\nal,4,LA\n",
+        @types [],
+        @out_fmt Escaper::CSV,
+        @csv Some(InputFormat::CSV)
+    );
+    test_program!(
+        csv_set_projected_column,
+        r#"{ $3 = "x"; print $1; print }"#,
+        "a\na,b,x,d\n",
+        @input "a,b,c,d\n",
+        @types [],
+        @out_fmt Escaper::CSV,
+        @csv Some(InputFormat::CSV)
+    );
+    test_program!(
+        csv_output_plain_input,
+        r#"{ print; $0 = "a,b"; print; print $0; $2 = "c"; print }"#,
+        "x y\n\"a,b\"\n\"a,b\"\n\"a,c\"\n",
+        @input "x y\n",
+        @types [],
+        @out_fmt Escaper::CSV,
+        @csv None
+    );
+    test_program!(
+        json_render_fields,
+        r#"{ print; print $2, NR, NR / 2, log(0); $2 = "\"q\""; printf "%s\n", $0;
+             print join_fields(1, 1); print join_fields(2, 2), NR }"#,
+        r#"{"1":"a","2":"b,c"}
+{"1":"b,c","2":1,"3":0.5,"4":null}
+{"1":"a","2":"\"q\""}
+{"1":"a"}
+{"1":{"2":"\"q\""},"2":1}
+{"1":""}
+{"1":"","2":2,"3":1.0,"4":null}
+{"1":"","2":"\"q\""}
+{"1":""}
+{"1":{"2":"\"q\""},"2":2}
+"#,
+        @input "a,\"b,c\"\n\n",
+        @types [],
+        @out_fmt Escaper::JSON,
+        @csv Some(InputFormat::CSV)
+    );
+    test_program!(
+        nf_assign,
+        r#"{ NF = 2; print; NF = 3; $3 = "c"; print NF, $0 }"#,
+        "1 2\n3 1 2 c\n",
+        @input "1 2 3 4"
+    );
     test_program_tsv!(
         tsv_escaping,
         r#"{ print $1,$2,$3; }"#,
@@ -937,6 +1622,60 @@ for (k in m) {
         @types [m :: MapStrInt]
     );
 
+    test_program!(
+        map_clear,
+        r#"function reset(a) { delete a; }
+        BEGIN {
+            m[0] = 1;
+            m[1] = 2;
+            reset(m);
+            print length(m), (0 in m);
+            m[2] = 3;
+            for (k in m) { print k, m[k]; }
+        }"#,
+        "0 0\n2 3\n",
+        @input "",
+        @types [m :: MapIntInt]
+    );
+
+    test_program!(
+        map_clear_per_file,
+        r#"FNR == 1 { delete seen } !seen[$0]++"#,
+        "a\nb\na\nc\n",
+        @input "a\nb\na\n<<<FILE BREAK>>>a\nc\n",
+        @types [seen :: MapStrInt]
+    );
+
+    test_program!(
+        asort_asorti,
+        r#"BEGIN {
+        m["b"] = 3; m["a"] = 10; m["c"] = 1;
+        n = asort(m, vals)
+        for (i = 1; i <= n; i++) printf "%s ", vals[i];
+        n = asorti(m, keys)
+        for (i = 1; i <= n; i++) printf "%s ", keys[i];
+        print n
+        asort(m)
+        print m[1], m[2], m[3], ("a" in m)
+        }"#,
+        "1 3 10 a b c 3\n1 3 10 0\n",
+        @input "",
+        @types [vals :: MapIntInt, keys :: MapIntStr, m :: MapStrInt]
+    );
+
+    test_program!(
+        sorted_in,
+        r#"BEGIN {
+        m[10] = "x"; m[9] = "y"; m[100] = "z";
+        SORTED_IN = "@ind_num_asc"; for (k in m) printf "%s ", k;
+        SORTED_IN = "@ind_num_desc"; for (k in m) printf "%s ", k;
+        SORTED_IN = "@ind_str_asc"; for (k in m) printf "%s ", k;
+        SORTED_IN = "@ind_str_desc"; for (k in m) printf "%s ", k;
+        print SORTED_IN
+        }"#,
+        "9 10 100 100 10 9 10 100 9 9 100 10 @ind_str_desc\n"
+    );
+
     test_program!(
         lengths,
         r#" BEGIN {
@@ -1058,6 +1797,14 @@ for (k in m) {
         "232      hello 00\n\n\n232      hello 00 2.56 320\n\n\n"
     );
 
+    test_program!(
+        printf_extended,
+        r#"BEGIN { printf("%i %u %X %#x %E %G 100%%\n", 42.7, 3, 255, 255, 12345.5, 0.0001);
+        printf("[%*d][%-*d][%.*f]\n", 5, 42, 4, 7, 2, 3.14159);
+        s = sprintf("%2$s %1$s %3$'d", "world", "hello", 1234567); print s; }"#,
+        "42 3 FF 0xff 1.234550E+04 0.0001 100%\n[   42][7   ][3.14]\nhello world 1,234,567\n"
+    );
+
     test_program!(
         comma_patterns,
         r#"
@@ -1107,6 +1854,42 @@ this as well"#
         "5 5 2\n0 0 -1\n"
     );
 
+    test_program!(
+        match_captures,
+        r#"{
+        if (match($0, /([a-z]+)@([a-z.]+)/, m)) {
+            print m[0], m[1], m[2];
+        } else {
+            print "none", length(m);
+        }
+        }"#,
+        "bob@example.com bob example.com\nnone 0\n",
+        @input "to: bob@example.com\nnothing here\n",
+        @types [m :: MapStrStr]
+    );
+
+    test_program!(
+        match_captures_offsets,
+        r#"BEGIN {
+        print match("key=value", /([a-z]+)=(x)?([a-z]+)/, m);
+        print m[3], m[3, "start"], m[3, "length"], ((2, "start") in m);
+        }"#,
+        "1\nvalue 5 5 0\n",
+        @input "",
+        @types [m :: MapStrStr]
+    );
+
+    test_program!(
+        match_captures_offsets_only,
+        r#"BEGIN {
+        match("key=value", /([a-z]+)=(x)?([a-z]+)/, m);
+        print m[3, "start"], m[3, "length"], m[1, "start"];
+        }"#,
+        "5 5 1\n",
+        @input "",
+        @types [m :: MapStrStr]
+    );
+
     test_program!(degenerate_map, r#"BEGIN { print m[1]; }"#, "\n");
 
     test_program!(
@@ -1157,6 +1940,41 @@ this as well"#
         @input "snow ball"
     );
 
+    test_program!(
+        gensub_backrefs,
+        r#"{
+        print gensub(/([a-z]+)=([0-9]+)/, "\\2:\\1", "g");
+        print gensub(/[0-9]+/, "<&>", 2);
+        print gensub(/a/, "\\&", "G", $2);
+        print;
+        }"#,
+        "1:a 22:ab\na=1 ab=<22>\n&b=22\na=1 ab=22\n",
+        @input "a=1 ab=22"
+    );
+
+    test_program!(
+        time_functions,
+        r#"BEGIN {
+        print strftime("%Y-%m-%d %H:%M:%S", 951827696);
+        print strftime("%H:%M", 3600, 1);
+        print mktime("2020 06 01 12 30 00"), mktime("2020 13 01 00 00 00"), mktime("junk");
+        print parse_time("2020-06-01T14:30:00.5+02:00"), parse_time("2020-06-01 12:30:00");
+        print parse_time("01/06/2020 12:30", "%d/%m/%Y %H:%M"), parse_time("2020-13-01");
+        print (systime() > 1600000000);
+        }"#,
+        "2000-02-29 12:34:56\n01:00\n1591014600 1609459200 -1\n1591014600 1591014600\n1591014600 -1\n1\n"
+    );
+
+    test_program!(
+        json_builtin,
+        r#"{
+        print json("id"), json("tags.0"), json("tags"), "[" json("missing") "]";
+        print json("a.b", "{\"a\": {\"b\": 1}}"), json("", "[1, 2] "), "[" json("x", "{") "]";
+        }"#,
+        "7 é\t [\"\\u00e9\\t\", 2] []\n1 [1, 2] []\n",
+        @input r#"{"id": 7, "tags": ["\u00e9\t", 2]}"#
+    );
+
     test_program!(
         substrings,
         r#"BEGIN {
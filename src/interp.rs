@@ -11,7 +11,12 @@ use hashbrown::HashMap;
 use rand::{self, rngs::StdRng, Rng, SeedableRng};
 
 use std::cmp;
+use std::hash::Hash;
 use std::mem;
+use std::sync::{
+    atomic::{AtomicBool, AtomicI64, Ordering},
+    Arc,
+};
 
 type ClassicReader = runtime::splitter::regex::RegexSplitter<Box<dyn std::io::Read>>;
 
@@ -30,6 +35,17 @@ pub(crate) struct Core<'a> {
     pub rng: StdRng,
     pub current_seed: u64,
     pub slots: Slots,
+    pub exit: Arc<ExitStatus>,
+    // Whether printf widths count characters rather than bytes (--chars).
+    pub chars: bool,
+}
+
+/// ExitStatus records calls to `exit`. It is shared between a Core and all of the worker Cores
+/// created from it, so that an `exit` in any thread stops every thread from reading more input.
+#[derive(Default)]
+pub(crate) struct ExitStatus {
+    exiting: AtomicBool,
+    code: AtomicI64,
 }
 
 impl<'a> Drop for Core<'a> {
@@ -150,16 +166,38 @@ impl<'a> Core<'a> {
         let fs: UniqueStr<'a> = self.vars.fs.clone().into();
         let ofs: UniqueStr<'a> = self.vars.ofs.clone().into();
         let rs: UniqueStr<'a> = self.vars.rs.clone().into();
+        let rt: UniqueStr<'a> = self.vars.rt.clone().into();
+        let fieldwidths: UniqueStr<'a> = self.vars.fieldwidths.clone().into();
+        let fpat: UniqueStr<'a> = self.vars.fpat.clone().into();
         let ors: UniqueStr<'a> = self.vars.ors.clone().into();
         let filename: UniqueStr<'a> = self.vars.filename.clone().into();
+        let subsep: UniqueStr<'a> = self.vars.subsep.clone().into();
+        let convfmt: UniqueStr<'a> = self.vars.convfmt.clone().into();
+        let ofmt: UniqueStr<'a> = self.vars.ofmt.clone().into();
+        let sorted_in: UniqueStr<'a> = self.vars.sorted_in.clone().into();
+        let sort_order = self.vars.sort_order;
+        let environ: HashMap<UniqueStr<'a>, UniqueStr<'a>> = self.vars.environ.iter(|i| {
+            i.map(|(k, v)| (k.clone().into(), v.clone().into()))
+                .collect()
+        });
         let slots = self.slots.clone();
+        let exit = self.exit.clone();
+        let chars = self.chars;
         move || {
             let vars = Variables {
                 fs: fs.into_str(),
                 ofs: ofs.into_str(),
                 ors: ors.into_str(),
                 rs: rs.into_str(),
+                rt: rt.into_str(),
+                fieldwidths: fieldwidths.into_str(),
+                fpat: fpat.into_str(),
                 filename: filename.into_str(),
+                subsep: subsep.into_str(),
+                convfmt: convfmt.into_str(),
+                ofmt: ofmt.into_str(),
+                sorted_in: sorted_in.into_str(),
+                sort_order,
                 pid,
                 nf: 0,
                 nr: 0,
@@ -168,6 +206,10 @@ impl<'a> Core<'a> {
                 rlength: 0,
                 argc: 0,
                 argv: Default::default(),
+                environ: environ
+                    .into_iter()
+                    .map(|(k, v)| (k.into_str(), v.into_str()))
+                    .collect(),
             };
             Core {
                 vars,
@@ -176,6 +218,8 @@ impl<'a> Core<'a> {
                 rng: rand::rngs::StdRng::seed_from_u64(seed),
                 current_seed: seed,
                 slots,
+                exit,
+                chars,
             }
         }
     }
@@ -188,6 +232,8 @@ impl<'a> Core<'a> {
             rng: rand::rngs::StdRng::seed_from_u64(seed),
             current_seed: seed,
             slots: Default::default(),
+            exit: Default::default(),
+            chars: false,
         }
     }
 
@@ -203,6 +249,23 @@ impl<'a> Core<'a> {
         self.vars.nr = self.vars.nr.agg(nr);
     }
 
+    /// Begin exiting the program. A `code` of None (i.e. a bare `exit` statement) keeps the exit
+    /// code from any previous call.
+    pub fn exit(&mut self, code: Option<Int>) {
+        if let Some(code) = code {
+            self.exit.code.store(code, Ordering::Relaxed);
+        }
+        self.exit.exiting.store(true, Ordering::Release);
+    }
+
+    pub fn exiting(&self) -> bool {
+        self.exit.exiting.load(Ordering::Acquire)
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        self.exit.code.load(Ordering::Relaxed) as i32
+    }
+
     pub fn reseed(&mut self, seed: u64) -> u64 /* old seed */ {
         self.rng = StdRng::seed_from_u64(seed);
         let old_seed = self.current_seed;
@@ -218,6 +281,43 @@ impl<'a> Core<'a> {
         self.regexes.regex_match_loc(&mut self.vars, pat, s)
     }
 
+    // Like `match_regex`, but RSTART and RLENGTH are character offsets.
+    pub fn match_regex_chars(&mut self, s: &Str<'a>, pat: &Str<'a>) -> Result<Int> {
+        let start = self.match_regex(s, pat)?;
+        if start <= 0 {
+            return Ok(start);
+        }
+        let (byte_start, byte_len) = ((start - 1) as usize, self.vars.rlength as usize);
+        let (start, len) = s.with_bytes(|bs| {
+            let start = runtime::utf8::char_offset(bs, byte_start);
+            let end = runtime::utf8::char_offset(bs, byte_start + byte_len);
+            (start as Int + 1, (end - start) as Int)
+        });
+        self.vars.rstart = start;
+        self.vars.rlength = len;
+        Ok(start)
+    }
+
+    pub fn match_regex_intmap(
+        &mut self,
+        s: &Str<'a>,
+        pat: &Str<'a>,
+        m: &runtime::IntMap<Str<'a>>,
+    ) -> Result<Int> {
+        self.regexes
+            .regex_match_loc_intmap(&mut self.vars, pat, s, m)
+    }
+
+    pub fn match_regex_strmap(
+        &mut self,
+        s: &Str<'a>,
+        pat: &Str<'a>,
+        m: &runtime::StrMap<'a, Str<'a>>,
+    ) -> Result<Int> {
+        self.regexes
+            .regex_match_loc_strmap(&mut self.vars, pat, s, m)
+    }
+
     pub fn is_match_regex(&mut self, s: &Str<'a>, pat: &Str<'a>) -> Result<bool> {
         self.regexes.is_regex_match(pat, s)
     }
@@ -438,8 +538,12 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
         stdin: LR,
         ff: impl runtime::writers::FileFactory,
         used_fields: &FieldSet,
+        chars: bool,
     ) -> Self {
         use compile::Ty::*;
+        let mut core = Core::new(ff);
+        core.chars = chars;
+        let read_files = runtime::FileRead::new(stdin, used_fields, &core.write_files);
         Interp {
             main_func,
             num_workers,
@@ -448,10 +552,10 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
             floats: default_of(regs(Float)),
             ints: default_of(regs(Int)),
             strs: default_of(regs(Str)),
-            core: Core::new(ff),
+            core,
 
             line: Default::default(),
-            read_files: runtime::FileRead::new(stdin, used_fields),
+            read_files,
 
             maps_int_float: default_of(regs(MapIntFloat)),
             maps_int_int: default_of(regs(MapIntInt)),
@@ -597,11 +701,13 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
         Ok(())
     }
 
-    pub(crate) fn run(&mut self) -> Result<()> {
+    /// Run the program, returning its exit code.
+    pub(crate) fn run(&mut self) -> Result<i32> {
         match self.main_func {
             Stage::Main(_) => self.run_serial(),
             Stage::Par { .. } => self.run_parallel(),
-        }
+        }?;
+        Ok(self.core.exit_code())
     }
 
     pub(crate) fn run_at(&mut self, mut cur_fn: usize) -> Result<()> {
@@ -636,7 +742,17 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
                         *self.get_mut(sr) = s;
                     }
                     FloatToStr(sr, fr) => {
-                        let s = runtime::convert::<_, Str>(*self.get(*fr));
+                        let s = runtime::float_to_str_fmt(*self.get(*fr), &self.core.vars.convfmt)?;
+                        let sr = *sr;
+                        *self.get_mut(sr) = s;
+                    }
+                    FloatToOutputStr(sr, fr) => {
+                        let s = runtime::float_to_str_fmt(*self.get(*fr), &self.core.vars.ofmt)?;
+                        let sr = *sr;
+                        *self.get_mut(sr) = s;
+                    }
+                    FloatToJSON(sr, fr) => {
+                        let s = runtime::float_to_json(*self.get(*fr), &self.core.vars.ofmt)?;
                         let sr = *sr;
                         *self.get_mut(sr) = s;
                     }
@@ -768,6 +884,29 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
                         let dst = *dst;
                         *self.get_mut(dst) = bw.eval2(ix, iy);
                     }
+                    Systime(res) => {
+                        *index_mut(&mut self.ints, res) = runtime::time::systime();
+                    }
+                    Strftime(res, fmt, ts, utc) => {
+                        *index_mut(&mut self.strs, res) = runtime::time::strftime(
+                            index(&self.strs, fmt),
+                            *index(&self.ints, ts),
+                            *index(&self.ints, utc) != 0,
+                        )
+                        .upcast();
+                    }
+                    Mktime(res, s) => {
+                        *index_mut(&mut self.ints, res) =
+                            runtime::time::mktime(index(&self.strs, s));
+                    }
+                    ParseTime(res, s, fmt) => {
+                        *index_mut(&mut self.ints, res) =
+                            runtime::time::parse_time(index(&self.strs, s), index(&self.strs, fmt));
+                    }
+                    JSONPath(res, path, s) => {
+                        *index_mut(&mut self.strs, res) =
+                            runtime::json_path(index(&self.strs, path), index(&self.strs, s));
+                    }
                     Rand(dst) => {
                         let res: f64 = self.core.rng.gen_range(0.0, 1.0);
                         *index_mut(&mut self.floats, dst) = res;
@@ -790,6 +929,25 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
                             .core
                             .match_regex(index(&self.strs, l), index(&self.strs, r))?;
                     }
+                    MatchChars(res, l, r) => {
+                        *index_mut(&mut self.ints, res) = self
+                            .core
+                            .match_regex_chars(index(&self.strs, l), index(&self.strs, r))?;
+                    }
+                    MatchCapturesInt(res, l, r, arr) => {
+                        *index_mut(&mut self.ints, res) = self.core.match_regex_intmap(
+                            index(&self.strs, l),
+                            index(&self.strs, r),
+                            index(&self.maps_int_str, arr),
+                        )?;
+                    }
+                    MatchCapturesStr(res, l, r, arr) => {
+                        *index_mut(&mut self.ints, res) = self.core.match_regex_strmap(
+                            index(&self.strs, l),
+                            index(&self.strs, r),
+                            index(&self.maps_str_str, arr),
+                        )?;
+                    }
                     IsMatch(res, l, r) => {
                         *index_mut(&mut self.ints, res) = self
                             .core
@@ -802,6 +960,18 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
                         let t = index(&self.strs, t);
                         *self.get_mut(res) = runtime::string_search::index_substr(t, s);
                     }
+                    SubstrIndexChars(res, s, t) => {
+                        let res = *res;
+                        let s = index(&self.strs, s);
+                        let t = index(&self.strs, t);
+                        *self.get_mut(res) = runtime::string_search::index_substr_chars(t, s);
+                    }
+                    LenStrChars(res, s) => {
+                        let res = *res;
+                        let s = *s;
+                        let len = self.get(s).char_len();
+                        *self.get_mut(res) = len as Int;
+                    }
                     LenStr(res, s) => {
                         let res = *res;
                         let s = *s;
@@ -834,6 +1004,18 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
                         *index_mut(&mut self.strs, in_s) = subbed;
                         *index_mut(&mut self.ints, res) = subs_made;
                     }
+                    GenSub(res, pat, s, how, in_s) => {
+                        let subbed = {
+                            let pat = index(&self.strs, pat);
+                            let s = index(&self.strs, s);
+                            let how = index(&self.strs, how);
+                            let in_s = index(&self.strs, in_s);
+                            self.core
+                                .regexes
+                                .with_regex(pat, |re| in_s.gensub(re, s, how))?
+                        };
+                        *index_mut(&mut self.strs, res) = subbed;
+                    }
                     EscapeCSV(res, s) => {
                         *index_mut(&mut self.strs, res) = {
                             let s = index(&self.strs, s);
@@ -846,6 +1028,18 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
                             runtime::escape_tsv(s)
                         };
                     }
+                    EscapeJSON(res, s) => {
+                        *index_mut(&mut self.strs, res) = {
+                            let s = index(&self.strs, s);
+                            runtime::escape_json(s)
+                        };
+                    }
+                    ToUpper(res, s) => {
+                        *index_mut(&mut self.strs, res) = index(&self.strs, s).to_upper();
+                    }
+                    ToLower(res, s) => {
+                        *index_mut(&mut self.strs, res) = index(&self.strs, s).to_lower();
+                    }
                     Substr(res, base, l, r) => {
                         let base = index(&self.strs, base);
                         let len = base.len();
@@ -853,6 +1047,12 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
                         let r = cmp::min(len as Int, *index(&self.ints, r)) as usize;
                         *index_mut(&mut self.strs, res) = base.slice(l, r);
                     }
+                    SubstrChars(res, base, l, r) => {
+                        let base = index(&self.strs, base);
+                        let l = cmp::max(0, -1 + *index(&self.ints, l)) as usize;
+                        let r = cmp::max(0, *index(&self.ints, r)) as usize;
+                        *index_mut(&mut self.strs, res) = base.char_slice(l, r);
+                    }
                     LTFloat(res, l, r) => {
                         let res = *res;
                         let l = *self.get(*l);
@@ -960,6 +1160,10 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
                         )?;
                         *self.get_mut(dst) = res;
                     }
+                    RebuiltRecord(dst) => {
+                        let dst = *dst;
+                        *self.get_mut(dst) = self.line.rebuilt() as Int;
+                    }
                     JoinCSV(dst, start, end) => {
                         let nf = self.line.nf(&self.core.vars.fs, &mut self.core.regexes)?;
                         *index_mut(&mut self.strs, dst) = {
@@ -980,6 +1184,14 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
                             })?
                         };
                     }
+                    JoinJSON(dst, start, end) => {
+                        let nf = self.line.nf(&self.core.vars.fs, &mut self.core.regexes)?;
+                        *index_mut(&mut self.strs, dst) = {
+                            let start = *index(&self.ints, start);
+                            let end = *index(&self.ints, end);
+                            runtime::join_json(&mut self.line, start, end, nf)?
+                        };
+                    }
                     JoinColumns(dst, start, end, sep) => {
                         let nf = self.line.nf(&self.core.vars.fs, &mut self.core.regexes)?;
                         *index_mut(&mut self.strs, dst) = {
@@ -1015,6 +1227,24 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
                         let flds = *flds;
                         *self.get_mut(flds) = res;
                     }
+                    PatSplit {
+                        flds,
+                        to_split,
+                        arr,
+                        pat,
+                        seps,
+                    } => {
+                        let to_split = index(&self.strs, to_split);
+                        let arr = index(&self.maps_int_str, arr);
+                        let pat = index(&self.strs, pat);
+                        let seps = index(&self.maps_int_str, seps);
+                        let res = self
+                            .core
+                            .regexes
+                            .patsplit_intmap(&pat, &to_split, &arr, &seps)?;
+                        let flds = *flds;
+                        *self.get_mut(flds) = res;
+                    }
                     PrintStdout(txt) => {
                         let txt = index(&self.strs, txt);
                         // Why do this? We want to exit cleanly when output is closed. We use this
@@ -1023,10 +1253,10 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
                             return Ok(());
                         }
                     }
-                    Print(txt, out, append) => {
+                    Print(txt, out, spec) => {
                         let txt = index(&self.strs, txt);
                         let out = index(&self.strs, out);
-                        self.core.write_files.write_str(out, txt, *append)?
+                        self.core.write_files.write_str(out, txt, *spec)?
                     }
                     Sprintf { dst, fmt, args } => {
                         debug_assert_eq!(scratch.len(), 0);
@@ -1036,8 +1266,10 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
                         use runtime::str_impl::DynamicBuf;
                         let fmt_str = index(&self.strs, fmt);
                         let mut buf = DynamicBuf::new(0);
-                        fmt_str
-                            .with_bytes(|bs| runtime::printf::printf(&mut buf, bs, &scratch[..]))?;
+                        let chars = self.core.chars;
+                        fmt_str.with_bytes(|bs| {
+                            runtime::printf::printf(&mut buf, bs, &scratch[..], chars)
+                        })?;
                         scratch.clear();
                         let res = unsafe { buf.into_str() };
                         let dst = *dst;
@@ -1049,29 +1281,43 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
                             scratch.push(self.format_arg(*a)?);
                         }
                         let fmt_str = index(&self.strs, fmt);
-                        let res = if let Some((out_path_reg, append)) = output {
+                        let res = if let Some((out_path_reg, spec)) = output {
                             let out_path = index(&self.strs, out_path_reg);
                             self.core.write_files.printf(
-                                Some((out_path, *append)),
+                                Some((out_path, *spec)),
                                 fmt_str,
                                 &scratch[..],
+                                self.core.chars,
                             )
                         } else {
                             // print to stdout.
-                            self.core.write_files.printf(None, fmt_str, &scratch[..])
+                            self.core.write_files.printf(
+                                None,
+                                fmt_str,
+                                &scratch[..],
+                                self.core.chars,
+                            )
                         };
                         if res.is_err() {
                             return Ok(());
                         }
                         scratch.clear();
                     }
-                    Close(file) => {
+                    Close(dst, file) => {
+                        let dst = *dst;
                         let file = index(&self.strs, file);
-                        // NB this may create an unused entry in write_files. It would not be
-                        // terribly difficult to optimize the close path to include an existence
-                        // check first.
-                        self.core.write_files.close(file)?;
-                        self.read_files.close(file);
+                        let res = runtime::close_file(
+                            &mut self.core.write_files,
+                            &mut self.read_files,
+                            file,
+                        )?;
+                        *self.get_mut(dst) = res;
+                    }
+                    RunCmd(dst, cmd) => {
+                        let dst = *dst;
+                        let cmd = index(&self.strs, cmd);
+                        let res = self.core.write_files.system(cmd)?;
+                        *self.get_mut(dst) = res;
                     }
                     Lookup {
                         map_ty,
@@ -1086,6 +1332,21 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
                         key,
                     } => self.contains(*map_ty, *dst, *map, *key),
                     Delete { map_ty, map, key } => self.delete(*map_ty, *map, *key),
+                    Clear { map_ty, map } => self.clear(*map_ty, *map),
+                    Asort {
+                        src_ty,
+                        src,
+                        dst_ty,
+                        dst,
+                        res,
+                    } => self.asort(*src_ty, *src, *dst_ty, *dst, *res),
+                    Asorti {
+                        src_ty,
+                        src,
+                        dst_ty,
+                        dst,
+                        res,
+                    } => self.asorti(*src_ty, *src, *dst_ty, *dst, *res),
                     Len { map_ty, map, dst } => self.len(*map_ty, *map, *dst),
                     Store {
                         map_ty,
@@ -1117,6 +1378,10 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
                     StoreVarInt(var, src) => {
                         let src = *src;
                         let s = *self.get(src);
+                        if let NF = *var {
+                            self.line
+                                .set_nf(s, &self.core.vars.fs, &mut self.core.regexes)?;
+                        }
                         self.core.vars.store_int(*var, s)?;
                     }
                     LoadVarIntMap(dst, var) => {
@@ -1129,6 +1394,16 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
                         let s = self.get(src).clone();
                         self.core.vars.store_intmap(*var, s)?;
                     }
+                    LoadVarStrMap(dst, var) => {
+                        let arr = self.core.vars.load_strmap(*var)?;
+                        let dst = *dst;
+                        *self.get_mut(dst) = arr;
+                    }
+                    StoreVarStrMap(var, src) => {
+                        let src = *src;
+                        let s = self.get(src).clone();
+                        self.core.vars.store_strmap(*var, s)?;
+                    }
 
                     IterBegin { map_ty, map, dst } => self.iter_begin(*map_ty, *map, *dst),
                     IterHasNext { iter_ty, dst, iter } => self.iter_has_next(*iter_ty, *dst, *iter),
@@ -1151,7 +1426,25 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
                         let file = index(&self.strs, file);
                         match self.core.regexes.get_line(
                             file,
-                            &self.core.vars.rs,
+                            &mut self.core.vars,
+                            &mut self.read_files,
+                        ) {
+                            Ok(l) => *self.get_mut(dst) = l,
+                            Err(_) => *self.get_mut(dst) = "".into(),
+                        };
+                    }
+                    ReadErrCmd(dst, cmd) => {
+                        let dst = *dst;
+                        let cmd = index(&self.strs, cmd);
+                        let res = self.read_files.read_err_cmd(cmd)?;
+                        *self.get_mut(dst) = res;
+                    }
+                    NextLineCmd(dst, cmd) => {
+                        let dst = *dst;
+                        let cmd = index(&self.strs, cmd);
+                        match self.core.regexes.get_line_cmd(
+                            cmd,
+                            &mut self.core.vars,
                             &mut self.read_files,
                         ) {
                             Ok(l) => *self.get_mut(dst) = l,
@@ -1160,7 +1453,13 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
                     }
                     ReadErrStdin(dst) => {
                         let dst = *dst;
-                        let res = self.read_files.read_err_stdin();
+                        // Once we are exiting, the main loop should behave as though the input is
+                        // exhausted.
+                        let res = if self.core.exiting() {
+                            0
+                        } else {
+                            self.read_files.read_err_stdin()
+                        };
                         *self.get_mut(dst) = res;
                     }
                     NextLineStdin(dst) => {
@@ -1168,15 +1467,18 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
                         let (changed, res) = self
                             .core
                             .regexes
-                            .get_line_stdin(&self.core.vars.rs, &mut self.read_files)?;
+                            .get_line_stdin(&mut self.core.vars, &mut self.read_files)?;
                         if changed {
                             self.reset_file_vars();
                         }
                         *self.get_mut(dst) = res;
                     }
                     NextLineStdinFused() => {
+                        if self.core.exiting() {
+                            break cur + 1;
+                        }
                         let changed = self.core.regexes.get_line_stdin_reuse(
-                            &self.core.vars.rs,
+                            &mut self.core.vars,
                             &mut self.read_files,
                             &mut self.line,
                         )?;
@@ -1188,6 +1490,14 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
                         self.read_files.next_file()?;
                         self.reset_file_vars();
                     }
+                    Exit(code) => {
+                        let code = code.map(|c| *self.get(c));
+                        self.core.exit(code);
+                    }
+                    Exiting(dst) => {
+                        let dst = *dst;
+                        *self.get_mut(dst) = self.core.exiting() as Int;
+                    }
                     JmpIf(cond, lbl) => {
                         let cond = *cond;
                         if *self.get(cond) != 0 {
@@ -1289,6 +1599,74 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
             self.get(map).delete(self.get(key))
         });
     }
+    fn clear(&mut self, map_ty: Ty, map: NumTy) {
+        map_regs!(map_ty, map, self.get(map).clear());
+    }
+    fn asort(&mut self, src_ty: Ty, src: NumTy, dst_ty: Ty, dst: NumTy, res: NumTy) {
+        let n = match (src_ty, dst_ty) {
+            (Ty::MapIntInt, Ty::MapIntInt) => self.asort_regs::<Int, Int, Int>(src, dst),
+            (Ty::MapIntInt, Ty::MapStrInt) => self.asort_regs::<Int, Int, Str<'a>>(src, dst),
+            (Ty::MapIntFloat, Ty::MapIntFloat) => self.asort_regs::<Int, Float, Int>(src, dst),
+            (Ty::MapIntFloat, Ty::MapStrFloat) => self.asort_regs::<Int, Float, Str<'a>>(src, dst),
+            (Ty::MapIntStr, Ty::MapIntStr) => self.asort_regs::<Int, Str<'a>, Int>(src, dst),
+            (Ty::MapIntStr, Ty::MapStrStr) => self.asort_regs::<Int, Str<'a>, Str<'a>>(src, dst),
+            (Ty::MapStrInt, Ty::MapIntInt) => self.asort_regs::<Str<'a>, Int, Int>(src, dst),
+            (Ty::MapStrInt, Ty::MapStrInt) => self.asort_regs::<Str<'a>, Int, Str<'a>>(src, dst),
+            (Ty::MapStrFloat, Ty::MapIntFloat) => self.asort_regs::<Str<'a>, Float, Int>(src, dst),
+            (Ty::MapStrFloat, Ty::MapStrFloat) => {
+                self.asort_regs::<Str<'a>, Float, Str<'a>>(src, dst)
+            }
+            (Ty::MapStrStr, Ty::MapIntStr) => self.asort_regs::<Str<'a>, Str<'a>, Int>(src, dst),
+            (Ty::MapStrStr, Ty::MapStrStr) => {
+                self.asort_regs::<Str<'a>, Str<'a>, Str<'a>>(src, dst)
+            }
+            _ => panic!("invalid types for asort: {:?} {:?}", src_ty, dst_ty),
+        };
+        *index_mut(&mut self.ints, &res.into()) = n;
+    }
+    fn asorti(&mut self, src_ty: Ty, src: NumTy, dst_ty: Ty, dst: NumTy, res: NumTy) {
+        let n = match (src_ty, dst_ty) {
+            (Ty::MapIntInt, Ty::MapIntInt) => self.asorti_regs::<Int, Int, Int>(src, dst),
+            (Ty::MapIntInt, Ty::MapStrInt) => self.asorti_regs::<Int, Int, Str<'a>>(src, dst),
+            (Ty::MapIntFloat, Ty::MapIntInt) => self.asorti_regs::<Int, Float, Int>(src, dst),
+            (Ty::MapIntFloat, Ty::MapStrInt) => self.asorti_regs::<Int, Float, Str<'a>>(src, dst),
+            (Ty::MapIntStr, Ty::MapIntInt) => self.asorti_regs::<Int, Str<'a>, Int>(src, dst),
+            (Ty::MapIntStr, Ty::MapStrInt) => self.asorti_regs::<Int, Str<'a>, Str<'a>>(src, dst),
+            (Ty::MapStrInt, Ty::MapIntStr) => self.asorti_regs::<Str<'a>, Int, Int>(src, dst),
+            (Ty::MapStrInt, Ty::MapStrStr) => self.asorti_regs::<Str<'a>, Int, Str<'a>>(src, dst),
+            (Ty::MapStrFloat, Ty::MapIntStr) => self.asorti_regs::<Str<'a>, Float, Int>(src, dst),
+            (Ty::MapStrFloat, Ty::MapStrStr) => {
+                self.asorti_regs::<Str<'a>, Float, Str<'a>>(src, dst)
+            }
+            (Ty::MapStrStr, Ty::MapIntStr) => self.asorti_regs::<Str<'a>, Str<'a>, Int>(src, dst),
+            (Ty::MapStrStr, Ty::MapStrStr) => {
+                self.asorti_regs::<Str<'a>, Str<'a>, Str<'a>>(src, dst)
+            }
+            _ => panic!("invalid types for asorti: {:?} {:?}", src_ty, dst_ty),
+        };
+        *index_mut(&mut self.ints, &res.into()) = n;
+    }
+    fn asort_regs<K, V, D>(&self, src: NumTy, dst: NumTy) -> Int
+    where
+        Self: Get<runtime::SharedMap<K, V>> + Get<runtime::SharedMap<D, V>>,
+        K: Hash + Eq,
+        V: runtime::sort::SortKey,
+        D: Hash + Eq + From<Int>,
+    {
+        let src: Reg<runtime::SharedMap<K, V>> = src.into();
+        let dst: Reg<runtime::SharedMap<D, V>> = dst.into();
+        runtime::sort::asort(self.get(src), self.get(dst))
+    }
+    fn asorti_regs<K, V, D>(&self, src: NumTy, dst: NumTy) -> Int
+    where
+        Self: Get<runtime::SharedMap<K, V>> + Get<runtime::SharedMap<D, K>>,
+        K: Hash + Eq + runtime::sort::SortKey,
+        D: Hash + Eq + From<Int>,
+    {
+        let src: Reg<runtime::SharedMap<K, V>> = src.into();
+        let dst: Reg<runtime::SharedMap<D, K>> = dst.into();
+        runtime::sort::asorti(self.get(src), self.get(dst))
+    }
     fn store_map(&mut self, map_ty: Ty, map: NumTy, key: NumTy, val: NumTy) {
         map_regs!(map_ty, map, key, val, {
             let k = self.get(key).clone();
@@ -1303,8 +1681,9 @@ impl<'a, LR: LineReader> Interp<'a, LR> {
     fn iter_begin(&mut self, map_ty: Ty, map: NumTy, dst: NumTy) {
         let _k = 0u32;
         let _v = 0u32;
+        let order = self.core.vars.sort_order;
         map_regs!(map_ty, map, _k, _v, dst, {
-            let iter = self.get(map).to_iter();
+            let iter = self.get(map).to_iter(order);
             *self.get_mut(dst) = iter;
         })
     }
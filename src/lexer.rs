@@ -80,6 +80,7 @@ pub enum Tok<'a> {
     COLON,
 
     Append, // >>
+    Pipe,   // |
 
     Dollar,
     Semi,
@@ -89,6 +90,7 @@ pub enum Tok<'a> {
     Delete,
     Function,
     Return,
+    Exit,
 
     Ident(&'a str),
     StrLit(&'a str),
@@ -149,6 +151,7 @@ static_map!(
     ["++", Tok::Incr],
     [">=", Tok::GTE],
     [">>", Tok::Append],
+    ["|", Tok::Pipe],
     [";", Tok::Semi],
     ["\n", Tok::Newline],
     ["\r\n", Tok::Newline],
@@ -164,6 +167,7 @@ static_map!(
     ["delete", Tok::Delete],
     ["function", Tok::Function],
     ["return", Tok::Return],
+    ["exit", Tok::Exit],
     ["$", Tok::Dollar]
 );
 
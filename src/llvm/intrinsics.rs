@@ -1,6 +1,6 @@
 use super::attr::{self, FunctionAttr};
 use crate::builtins::Variable;
-use crate::common::Either;
+use crate::common::{Either, FileSpec};
 use crate::compile::Ty;
 use crate::libc::c_void;
 use crate::pushdown::FieldSet;
@@ -10,7 +10,9 @@ use crate::runtime::{
     splitter::{
         batch::{ByteReader, CSVReader, WhitespaceOffsets},
         chunk::{ChunkProducer, OffsetChunk},
+        json::JSONReader,
         regex::RegexSplitter,
+        FieldSplitReader,
     },
     ChainedReader, FileRead, Float, Int, IntMap, Line, LineReader, Str, StrMap,
 };
@@ -88,6 +90,8 @@ macro_rules! with_input {
             $crate::llvm::intrinsics::InputData::V2($p) => $body,
             $crate::llvm::intrinsics::InputData::V3($p) => $body,
             $crate::llvm::intrinsics::InputData::V4($p) => $body,
+            $crate::llvm::intrinsics::InputData::V5($p) => $body,
+            $crate::llvm::intrinsics::InputData::V6($p) => $body,
         }
     };
 }
@@ -98,6 +102,8 @@ pub(crate) enum InputData {
     V2(InputTuple<ByteReader<Box<dyn ChunkProducer<Chunk = OffsetChunk<WhitespaceOffsets>>>>>),
     V3(InputTuple<ByteReader<Box<dyn ChunkProducer<Chunk = OffsetChunk>>>>),
     V4(InputTuple<ChainedReader<RegexSplitter<Box<dyn io::Read + Send>>>>),
+    V5(InputTuple<FieldSplitReader<ChainedReader<RegexSplitter<Box<dyn io::Read + Send>>>>>),
+    V6(InputTuple<JSONReader<Box<dyn ChunkProducer<Chunk = OffsetChunk>>>>),
 }
 
 pub(crate) trait IntoRuntime {
@@ -116,13 +122,14 @@ macro_rules! impl_into_runtime {
                 ff: impl runtime::writers::FileFactory,
                 used_fields: &FieldSet,
             ) -> Runtime<'a> {
+                let core = crate::interp::Core::new(ff);
                 Runtime {
                     concurrent: false,
                     input_data: InputData::$var((
                         Default::default(),
-                        FileRead::new(self, used_fields),
+                        FileRead::new(self, used_fields, &core.write_files),
                     )),
-                    core: crate::interp::Core::new(ff),
+                    core,
                 }
             }
         }
@@ -142,6 +149,11 @@ impl_into_runtime!(
 );
 impl_into_runtime!(ByteReader<Box<dyn ChunkProducer<Chunk = OffsetChunk>>>, V3);
 impl_into_runtime!(ChainedReader<RegexSplitter<Box<dyn io::Read + Send>>>, V4);
+impl_into_runtime!(
+    FieldSplitReader<ChainedReader<RegexSplitter<Box<dyn io::Read + Send>>>>,
+    V5
+);
+impl_into_runtime!(JSONReader<Box<dyn ChunkProducer<Chunk = OffsetChunk>>>, V6);
 
 pub(crate) struct Runtime<'a> {
     pub(crate) core: crate::interp::Core<'a>,
@@ -270,43 +282,68 @@ pub(crate) unsafe fn register(module: LLVMModuleRef, ctx: LLVMContextRef) -> Int
         drop_str_slow(str_ref_ty, int_ty);
         ref_map(map_ty);
         [ReadOnly] int_to_str(int_ty) -> str_ty;
-        [ReadOnly] float_to_str(float_ty) -> str_ty;
+        float_to_str(rt_ty, float_ty) -> str_ty;
+        float_to_output_str(rt_ty, float_ty) -> str_ty;
+        float_to_json(rt_ty, float_ty) -> str_ty;
         [ReadOnly] str_to_int(str_ref_ty) -> int_ty;
         [ReadOnly] hex_str_to_int(str_ref_ty) -> int_ty;
         [ReadOnly] str_to_float(str_ref_ty) -> float_ty;
         [ReadOnly] str_len(str_ref_ty) -> int_ty;
+        [ReadOnly] str_len_chars(str_ref_ty) -> int_ty;
         concat(str_ref_ty, str_ref_ty) -> str_ty;
         [ReadOnly] match_pat(rt_ty, str_ref_ty, str_ref_ty) -> int_ty;
         [ReadOnly] match_pat_loc(rt_ty, str_ref_ty, str_ref_ty) -> int_ty;
+        [ReadOnly] match_pat_loc_chars(rt_ty, str_ref_ty, str_ref_ty) -> int_ty;
+        match_captures_int(rt_ty, str_ref_ty, str_ref_ty, map_ty) -> int_ty;
+        match_captures_str(rt_ty, str_ref_ty, str_ref_ty, map_ty) -> int_ty;
         [ReadOnly] substr_index(str_ref_ty, str_ref_ty) -> int_ty;
+        [ReadOnly] substr_index_chars(str_ref_ty, str_ref_ty) -> int_ty;
         subst_first(rt_ty, str_ref_ty, str_ref_ty, str_ref_ty) -> int_ty;
         subst_all(rt_ty, str_ref_ty, str_ref_ty, str_ref_ty) -> int_ty;
+        gensub(rt_ty, str_ref_ty, str_ref_ty, str_ref_ty, str_ref_ty) -> str_ty;
         escape_csv(str_ref_ty) -> str_ty;
         escape_tsv(str_ref_ty) -> str_ty;
+        escape_json(str_ref_ty) -> str_ty;
+        to_upper(str_ref_ty) -> str_ty;
+        to_lower(str_ref_ty) -> str_ty;
         substr(str_ref_ty, int_ty, int_ty) -> str_ty;
+        substr_chars(str_ref_ty, int_ty, int_ty) -> str_ty;
         [ReadOnly] get_col(rt_ty, int_ty) -> str_ty;
+        [ReadOnly] rebuilt_record(rt_ty) -> int_ty;
         [ReadOnly] join_csv(rt_ty, int_ty, int_ty) -> str_ty;
         [ReadOnly] join_tsv(rt_ty, int_ty, int_ty) -> str_ty;
+        [ReadOnly] join_json(rt_ty, int_ty, int_ty) -> str_ty;
         [ReadOnly] join_cols(rt_ty, int_ty, int_ty, str_ref_ty) -> str_ty;
         set_col(rt_ty, int_ty, str_ref_ty);
         split_int(rt_ty, str_ref_ty, map_ty, str_ref_ty) -> int_ty;
         split_str(rt_ty, str_ref_ty, map_ty, str_ref_ty) -> int_ty;
+        patsplit(rt_ty, str_ref_ty, map_ty, str_ref_ty, map_ty) -> int_ty;
         rand_float(rt_ty) -> float_ty;
         seed_rng(rt_ty, int_ty) -> int_ty;
         reseed_rng(rt_ty) -> int_ty;
+        systime() -> int_ty;
+        _frawk_strftime(str_ref_ty, int_ty, int_ty) -> str_ty;
+        _frawk_mktime(str_ref_ty) -> int_ty;
+        [ReadOnly] parse_time(str_ref_ty, str_ref_ty) -> int_ty;
+        json_path(str_ref_ty, str_ref_ty) -> str_ty;
 
         print_stdout(rt_ty, str_ref_ty);
         print(rt_ty, str_ref_ty, str_ref_ty, int_ty);
         sprintf_impl(rt_ty, str_ref_ty, fmt_args_ty, fmt_tys_ty, int_ty) -> str_ty;
         printf_impl_file(rt_ty, str_ref_ty, fmt_args_ty, fmt_tys_ty, int_ty, str_ref_ty, int_ty);
         printf_impl_stdout(rt_ty, str_ref_ty, fmt_args_ty, fmt_tys_ty, int_ty);
-        close_file(rt_ty, str_ref_ty);
+        close_file(rt_ty, str_ref_ty) -> int_ty;
+        run_system(rt_ty, str_ref_ty) -> int_ty;
         read_err(rt_ty, str_ref_ty) -> int_ty;
+        read_err_cmd(rt_ty, str_ref_ty) -> int_ty;
         read_err_stdin(rt_ty) -> int_ty;
         next_line(rt_ty, str_ref_ty) -> str_ty;
+        next_line_cmd(rt_ty, str_ref_ty) -> str_ty;
         next_line_stdin(rt_ty) -> str_ty;
         next_line_stdin_fused(rt_ty);
         next_file(rt_ty);
+        exit_stmt(rt_ty, int_ty, int_ty);
+        exiting(rt_ty) -> int_ty;
 
         [ReadOnly, ArgmemOnly] _frawk_atan(float_ty) -> float_ty;
         [ReadOnly, ArgmemOnly] _frawk_atan2(float_ty, float_ty) -> float_ty;
@@ -317,6 +354,8 @@ pub(crate) unsafe fn register(module: LLVMModuleRef, ctx: LLVMContextRef) -> Int
         store_var_int(rt_ty, int_ty, int_ty);
         [ReadOnly] load_var_intmap(rt_ty, int_ty) -> map_ty;
         store_var_intmap(rt_ty, int_ty, map_ty);
+        [ReadOnly] load_var_strmap(rt_ty, int_ty) -> map_ty;
+        store_var_strmap(rt_ty, int_ty, map_ty);
 
         [ReadOnly] str_lt(str_ref_ty, str_ref_ty) -> int_ty;
         [ReadOnly] str_gt(str_ref_ty, str_ref_ty) -> int_ty;
@@ -328,57 +367,75 @@ pub(crate) unsafe fn register(module: LLVMModuleRef, ctx: LLVMContextRef) -> Int
         drop_iter_str(iter_str_ty, int_ty);
 
         alloc_intint() -> map_ty;
-        iter_intint(map_ty) -> iter_int_ty;
+        iter_intint(rt_ty, map_ty) -> iter_int_ty;
         [ReadOnly] len_intint(map_ty) -> int_ty;
         [ReadOnly] lookup_intint(map_ty, int_ty) -> int_ty;
         [ReadOnly] contains_intint(map_ty, int_ty) -> int_ty;
         insert_intint(map_ty, int_ty, int_ty);
         delete_intint(map_ty, int_ty);
+        clear_intint(map_ty);
+        asort_intint(map_ty, map_ty, int_ty) -> int_ty;
+        asorti_intint(map_ty, map_ty, int_ty) -> int_ty;
         drop_intint(map_ty);
 
         alloc_intfloat() -> map_ty;
-        iter_intfloat(map_ty) -> iter_int_ty;
+        iter_intfloat(rt_ty, map_ty) -> iter_int_ty;
         [ReadOnly] len_intfloat(map_ty) -> int_ty;
         [ReadOnly] lookup_intfloat(map_ty, int_ty) -> float_ty;
         [ReadOnly] contains_intfloat(map_ty, int_ty) -> int_ty;
         insert_intfloat(map_ty, int_ty, float_ty);
         delete_intfloat(map_ty, int_ty);
+        clear_intfloat(map_ty);
+        asort_intfloat(map_ty, map_ty, int_ty) -> int_ty;
+        asorti_intfloat(map_ty, map_ty, int_ty) -> int_ty;
         drop_intfloat(map_ty);
 
         alloc_intstr() -> map_ty;
-        iter_intstr(map_ty) -> iter_int_ty;
+        iter_intstr(rt_ty, map_ty) -> iter_int_ty;
         [ReadOnly] len_intstr(map_ty) -> int_ty;
         [ReadOnly] lookup_intstr(map_ty, int_ty) -> str_ty;
         [ReadOnly] contains_intstr(map_ty, int_ty) -> int_ty;
         insert_intstr(map_ty, int_ty, str_ref_ty);
         delete_intstr(map_ty, int_ty);
+        clear_intstr(map_ty);
+        asort_intstr(map_ty, map_ty, int_ty) -> int_ty;
+        asorti_intstr(map_ty, map_ty, int_ty) -> int_ty;
         drop_intstr(map_ty);
 
         alloc_strint() -> map_ty;
-        iter_strint(map_ty) -> iter_str_ty;
+        iter_strint(rt_ty, map_ty) -> iter_str_ty;
         [ReadOnly] len_strint(map_ty) -> int_ty;
         [ReadOnly] lookup_strint(map_ty, str_ref_ty) -> int_ty;
         [ReadOnly] contains_strint(map_ty, str_ref_ty) -> int_ty;
         insert_strint(map_ty, str_ref_ty, int_ty);
         delete_strint(map_ty, str_ref_ty);
+        clear_strint(map_ty);
+        asort_strint(map_ty, map_ty, int_ty) -> int_ty;
+        asorti_strint(map_ty, map_ty, int_ty) -> int_ty;
         drop_strint(map_ty);
 
         alloc_strfloat() -> map_ty;
-        iter_strfloat(map_ty) -> iter_str_ty;
+        iter_strfloat(rt_ty, map_ty) -> iter_str_ty;
         [ReadOnly] len_strfloat(map_ty) -> int_ty;
         [ReadOnly] lookup_strfloat(map_ty, str_ref_ty) -> float_ty;
         [ReadOnly] contains_strfloat(map_ty, str_ref_ty) -> int_ty;
         insert_strfloat(map_ty, str_ref_ty, float_ty);
         delete_strfloat(map_ty, str_ref_ty);
+        clear_strfloat(map_ty);
+        asort_strfloat(map_ty, map_ty, int_ty) -> int_ty;
+        asorti_strfloat(map_ty, map_ty, int_ty) -> int_ty;
         drop_strfloat(map_ty);
 
         alloc_strstr() -> map_ty;
-        iter_strstr(map_ty) -> iter_str_ty;
+        iter_strstr(rt_ty, map_ty) -> iter_str_ty;
         [ReadOnly] len_strstr(map_ty) -> int_ty;
         [ReadOnly] lookup_strstr(map_ty, str_ref_ty) -> str_ty;
         [ReadOnly] contains_strstr(map_ty, str_ref_ty) -> int_ty;
         insert_strstr(map_ty, str_ref_ty, str_ref_ty);
         delete_strstr(map_ty, str_ref_ty);
+        clear_strstr(map_ty);
+        asort_strstr(map_ty, map_ty, int_ty) -> int_ty;
+        asorti_strstr(map_ty, map_ty, int_ty) -> int_ty;
         drop_strstr(map_ty);
 
         load_slot_int(rt_ty, int_ty) -> int_ty;
@@ -435,9 +492,26 @@ pub unsafe extern "C" fn read_err(runtime: *mut c_void, file: *mut c_void) -> In
     res
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn read_err_cmd(runtime: *mut c_void, cmd: *mut c_void) -> Int {
+    let runtime = &mut *(runtime as *mut Runtime);
+    let res = try_abort!(
+        runtime,
+        with_input!(&mut runtime.input_data, |(_, read_files)| {
+            read_files.read_err_cmd(&*(cmd as *mut Str))
+        }),
+        "unexpected error when reading error status of command:"
+    );
+    res
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn read_err_stdin(runtime: *mut c_void) -> Int {
     let runtime = &mut *(runtime as *mut Runtime);
+    // Once we are exiting, the main loop should behave as though the input is exhausted.
+    if runtime.core.exiting() {
+        return 0;
+    }
     with_input!(&mut runtime.input_data, |(_, read_files)| read_files
         .read_err_stdin())
 }
@@ -445,13 +519,16 @@ pub unsafe extern "C" fn read_err_stdin(runtime: *mut c_void) -> Int {
 #[no_mangle]
 pub unsafe extern "C" fn next_line_stdin_fused(runtime: *mut c_void) {
     let runtime = &mut *(runtime as *mut Runtime);
+    if runtime.core.exiting() {
+        return;
+    }
     let changed = try_abort!(
         runtime,
         with_input!(&mut runtime.input_data, |(line, read_files)| {
             runtime
                 .core
                 .regexes
-                .get_line_stdin_reuse(&runtime.core.vars.rs, read_files, line)
+                .get_line_stdin_reuse(&mut runtime.core.vars, read_files, line)
         }),
         "unexpected error when reading line from stdin:"
     );
@@ -471,6 +548,20 @@ pub unsafe extern "C" fn next_file(runtime: *mut c_void) {
     );
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn exit_stmt(runtime: *mut c_void, code: Int, has_code: Int) {
+    let runtime = &mut *(runtime as *mut Runtime);
+    runtime
+        .core
+        .exit(if has_code != 0 { Some(code) } else { None });
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn exiting(runtime: *mut c_void) -> Int {
+    let runtime = &mut *(runtime as *mut Runtime);
+    runtime.core.exiting() as Int
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn next_line_stdin(runtime: *mut c_void) -> U128 {
     let runtime = &mut *(runtime as *mut Runtime);
@@ -480,7 +571,7 @@ pub unsafe extern "C" fn next_line_stdin(runtime: *mut c_void) -> U128 {
             runtime
                 .core
                 .regexes
-                .get_line_stdin(&runtime.core.vars.rs, read_files)
+                .get_line_stdin(&mut runtime.core.vars, read_files)
         }),
         "unexpected error when reading line from stdin:"
     );
@@ -498,7 +589,23 @@ pub unsafe extern "C" fn next_line(runtime: *mut c_void, file: *mut c_void) -> U
         runtime
             .core
             .regexes
-            .get_line(file, &runtime.core.vars.rs, read_files)
+            .get_line(file, &mut runtime.core.vars, read_files)
+    });
+    match res {
+        Ok(res) => mem::transmute::<Str, U128>(res),
+        Err(_) => mem::transmute::<Str, U128>("".into()),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn next_line_cmd(runtime: *mut c_void, cmd: *mut c_void) -> U128 {
+    let runtime = &mut *(runtime as *mut Runtime);
+    let cmd = &*(cmd as *mut Str);
+    let res = with_input!(&mut runtime.input_data, |(_, read_files)| {
+        runtime
+            .core
+            .regexes
+            .get_line_cmd(cmd, &mut runtime.core.vars, read_files)
     });
     match res {
         Ok(res) => mem::transmute::<Str, U128>(res),
@@ -520,17 +627,16 @@ pub unsafe extern "C" fn print(
     runtime: *mut c_void,
     txt: *mut c_void,
     out: *mut c_void,
-    append: Int,
+    spec: Int,
 ) {
     let runtime = &mut *(runtime as *mut Runtime);
     let txt = &*(txt as *mut Str);
     let out = &*(out as *mut Str);
-    if runtime
-        .core
-        .write_files
-        .write_str(out, txt, append != 0)
-        .is_err()
-    {
+    let spec = match FileSpec::from_int(spec) {
+        Some(spec) => spec,
+        None => fail!(runtime, "invalid output spec passed to print: {}", spec),
+    };
+    if runtime.core.write_files.write_str(out, txt, spec).is_err() {
         exit!(runtime);
     }
 }
@@ -583,6 +689,31 @@ pub unsafe extern "C" fn split_int(
     res
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn patsplit(
+    runtime: *mut c_void,
+    to_split: *mut c_void,
+    into_arr: *mut c_void,
+    pat: *mut c_void,
+    seps: *mut c_void,
+) -> Int {
+    let runtime = &mut *(runtime as *mut Runtime);
+    let into_arr = mem::transmute::<*mut c_void, IntMap<Str>>(into_arr);
+    let seps = mem::transmute::<*mut c_void, IntMap<Str>>(seps);
+    let to_split = &*(to_split as *mut Str);
+    let pat = &*(pat as *mut Str);
+    let res = match runtime
+        .core
+        .regexes
+        .patsplit_intmap(&pat, &to_split, &into_arr, &seps)
+    {
+        Ok(res) => res,
+        Err(e) => fail!(runtime, "failed to split string: {}", e),
+    };
+    mem::forget((into_arr, seps, to_split, pat));
+    res
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn get_col(runtime: *mut c_void, col: Int) -> U128 {
     let runtime = &mut *(runtime as *mut Runtime);
@@ -601,6 +732,12 @@ pub unsafe extern "C" fn get_col(runtime: *mut c_void, col: Int) -> U128 {
     mem::transmute::<Str, U128>(res)
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn rebuilt_record(runtime: *mut c_void) -> Int {
+    let runtime = &mut *(runtime as *mut Runtime);
+    with_input!(&mut runtime.input_data, |(line, _)| line.rebuilt()) as Int
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn join_csv(runtime: *mut c_void, start: Int, end: Int) -> U128 {
     let sep: Str<'static> = ",".into();
@@ -639,6 +776,24 @@ pub unsafe extern "C" fn join_tsv(runtime: *mut c_void, start: Int, end: Int) ->
     mem::transmute::<Str, U128>(res)
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn join_json(runtime: *mut c_void, start: Int, end: Int) -> U128 {
+    let runtime = &mut *(runtime as *mut Runtime);
+    let res = try_abort!(
+        runtime,
+        with_input!(&mut runtime.input_data, |(line, _)| {
+            let nf = try_abort!(
+                runtime,
+                line.nf(&runtime.core.vars.fs, &mut runtime.core.regexes),
+                "nf:"
+            );
+            runtime::join_json(line, start, end, nf)
+        }),
+        "join_json:"
+    );
+    mem::transmute::<Str, U128>(res)
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn join_cols(
     runtime: *mut c_void,
@@ -684,6 +839,14 @@ pub unsafe extern "C" fn str_len(s: *mut c_void) -> usize {
     res
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn str_len_chars(s: *mut c_void) -> usize {
+    let s = &*(s as *mut Str);
+    let res = s.char_len();
+    mem::forget(s);
+    res
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn concat(s1: *mut c_void, s2: *mut c_void) -> U128 {
     let s1 = &*(s1 as *mut Str);
@@ -724,6 +887,64 @@ pub unsafe extern "C" fn match_pat_loc(
     res as Int
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn match_pat_loc_chars(
+    runtime: *mut c_void,
+    s: *mut c_void,
+    pat: *mut c_void,
+) -> Int {
+    let runtime = runtime as *mut Runtime;
+    let s = &*(s as *mut Str);
+    let pat = &*(pat as *mut Str);
+    let res = try_abort!(
+        runtime,
+        (*runtime).core.match_regex_chars(s, pat),
+        "match_pat_loc_chars:"
+    );
+    mem::forget((s, pat));
+    res as Int
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn match_captures_int(
+    runtime: *mut c_void,
+    s: *mut c_void,
+    pat: *mut c_void,
+    arr: *mut c_void,
+) -> Int {
+    let runtime = runtime as *mut Runtime;
+    let s = &*(s as *mut Str);
+    let pat = &*(pat as *mut Str);
+    let arr = mem::transmute::<*mut c_void, IntMap<Str>>(arr);
+    let res = try_abort!(
+        runtime,
+        (*runtime).core.match_regex_intmap(s, pat, &arr),
+        "match_captures_int:"
+    );
+    mem::forget((s, pat, arr));
+    res as Int
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn match_captures_str(
+    runtime: *mut c_void,
+    s: *mut c_void,
+    pat: *mut c_void,
+    arr: *mut c_void,
+) -> Int {
+    let runtime = runtime as *mut Runtime;
+    let s = &*(s as *mut Str);
+    let pat = &*(pat as *mut Str);
+    let arr = mem::transmute::<*mut c_void, StrMap<Str>>(arr);
+    let res = try_abort!(
+        runtime,
+        (*runtime).core.match_regex_strmap(s, pat, &arr),
+        "match_captures_str:"
+    );
+    mem::forget((s, pat, arr));
+    res as Int
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn substr_index(s: *mut U128, t: *mut U128) -> Int {
     let s = &*(s as *mut Str);
@@ -731,6 +952,13 @@ pub unsafe extern "C" fn substr_index(s: *mut U128, t: *mut U128) -> Int {
     runtime::string_search::index_substr(/*needle*/ t, /*haystack*/ s)
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn substr_index_chars(s: *mut U128, t: *mut U128) -> Int {
+    let s = &*(s as *mut Str);
+    let t = &*(t as *mut Str);
+    runtime::string_search::index_substr_chars(/*needle*/ t, /*haystack*/ s)
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn subst_first(
     runtime: *mut c_void,
@@ -775,6 +1003,29 @@ pub unsafe extern "C" fn subst_all(
     nsubs
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn gensub(
+    runtime: *mut c_void,
+    pat: *mut U128,
+    s: *mut U128,
+    how: *mut U128,
+    in_s: *mut U128,
+) -> U128 {
+    let runtime = &mut *(runtime as *mut Runtime);
+    let s = &*(s as *mut Str);
+    let pat = &*(pat as *mut Str);
+    let how = &*(how as *mut Str);
+    let in_s = &*(in_s as *mut Str);
+    let subbed = try_abort!(
+        runtime,
+        runtime
+            .core
+            .regexes
+            .with_regex(pat, |re| in_s.gensub(re, s, how))
+    );
+    mem::transmute::<Str, U128>(subbed)
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn escape_csv(s: *mut U128) -> U128 {
     mem::transmute::<Str, U128>(runtime::escape_csv(&*(s as *mut Str)))
@@ -785,6 +1036,21 @@ pub unsafe extern "C" fn escape_tsv(s: *mut U128) -> U128 {
     mem::transmute::<Str, U128>(runtime::escape_tsv(&*(s as *mut Str)))
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn escape_json(s: *mut U128) -> U128 {
+    mem::transmute::<Str, U128>(runtime::escape_json(&*(s as *mut Str)))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn to_upper(s: *mut U128) -> U128 {
+    mem::transmute::<Str, U128>((&*(s as *mut Str)).to_upper())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn to_lower(s: *mut U128) -> U128 {
+    mem::transmute::<Str, U128>((&*(s as *mut Str)).to_lower())
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn substr(base: *mut U128, l: Int, r: Int) -> U128 {
     use std::cmp::{max, min};
@@ -795,6 +1061,15 @@ pub unsafe extern "C" fn substr(base: *mut U128, l: Int, r: Int) -> U128 {
     mem::transmute::<Str, U128>(base.slice(l, r))
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn substr_chars(base: *mut U128, l: Int, r: Int) -> U128 {
+    use std::cmp::max;
+    let base = &*(base as *mut Str);
+    let l = max(0, l - 1) as usize;
+    let r = max(0, r) as usize;
+    mem::transmute::<Str, U128>(base.char_slice(l, r))
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn ref_str(s: *mut c_void) {
     mem::forget((&*(s as *mut Str)).clone())
@@ -826,8 +1101,24 @@ pub unsafe extern "C" fn int_to_str(i: Int) -> U128 {
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn float_to_str(f: Float) -> U128 {
-    mem::transmute::<Str, U128>(runtime::convert::<Float, Str>(f))
+pub unsafe extern "C" fn float_to_str(rt: *mut c_void, f: Float) -> U128 {
+    let rt = &*(rt as *mut Runtime);
+    let res = try_abort!(rt, runtime::float_to_str_fmt(f, &rt.core.vars.convfmt));
+    mem::transmute::<Str, U128>(res)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn float_to_output_str(rt: *mut c_void, f: Float) -> U128 {
+    let rt = &*(rt as *mut Runtime);
+    let res = try_abort!(rt, runtime::float_to_str_fmt(f, &rt.core.vars.ofmt));
+    mem::transmute::<Str, U128>(res)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn float_to_json(rt: *mut c_void, f: Float) -> U128 {
+    let rt = &*(rt as *mut Runtime);
+    let res = try_abort!(rt, runtime::float_to_json(f, &rt.core.vars.ofmt));
+    mem::transmute::<Str, U128>(res)
 }
 
 // TODO: these next few mem::forgets don't seem necessary.
@@ -900,6 +1191,15 @@ pub unsafe extern "C" fn load_var_int(rt: *mut c_void, var: usize) -> Int {
 pub unsafe extern "C" fn store_var_int(rt: *mut c_void, var: usize, i: Int) {
     let runtime = &mut *(rt as *mut Runtime);
     if let Ok(var) = Variable::try_from(var) {
+        if let Variable::NF = var {
+            if let Err(e) = with_input!(&mut runtime.input_data, |(line, _)| line.set_nf(
+                i,
+                &runtime.core.vars.fs,
+                &mut runtime.core.regexes
+            )) {
+                fail!(runtime, "set_nf: {}", e);
+            }
+        }
         try_abort!(runtime, runtime.core.vars.store_int(var, i));
     } else {
         fail!(runtime, "invalid variable code={}", var)
@@ -929,6 +1229,29 @@ pub unsafe extern "C" fn store_var_intmap(rt: *mut c_void, var: usize, map: *mut
     }
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn load_var_strmap(rt: *mut c_void, var: usize) -> *mut c_void {
+    let runtime = &*(rt as *mut Runtime);
+    if let Ok(var) = Variable::try_from(var) {
+        let res = try_abort!(runtime, runtime.core.vars.load_strmap(var));
+        mem::transmute::<StrMap<_>, *mut c_void>(res)
+    } else {
+        fail!(runtime, "invalid variable code={}", var)
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn store_var_strmap(rt: *mut c_void, var: usize, map: *mut c_void) {
+    let runtime = &mut *(rt as *mut Runtime);
+    if let Ok(var) = Variable::try_from(var) {
+        let map = mem::transmute::<*mut c_void, StrMap<Str>>(map);
+        try_abort!(runtime, runtime.core.vars.store_strmap(var, map.clone()));
+        mem::forget(map);
+    } else {
+        fail!(runtime, "invalid variable code={}", var)
+    }
+}
+
 macro_rules! str_compare_inner {
     ($name:ident, $op:tt) => {
         #[no_mangle]
@@ -1002,17 +1325,28 @@ pub unsafe extern "C" fn printf_impl_file(
     tys: *mut u32,
     num_args: Int,
     output: *mut U128,
-    append: Int,
+    out_spec: Int,
 ) {
-    let output_wrapped = Some((&*(output as *mut Str), append != 0));
+    let fspec = match FileSpec::from_int(out_spec) {
+        Some(fspec) => fspec,
+        None => fail!(
+            &mut *(rt as *mut Runtime),
+            "invalid output spec passed to printf: {}",
+            out_spec
+        ),
+    };
+    let output_wrapped = Some((&*(output as *mut Str), fspec));
     let format_args = wrap_args(&mut *(rt as *mut _), args, tys, num_args);
     let rt = rt as *mut Runtime;
+    let chars = (*rt).core.chars;
     try_abort!(
         rt,
-        (*rt)
-            .core
-            .write_files
-            .printf(output_wrapped, &*(spec as *mut Str), &format_args[..],)
+        (*rt).core.write_files.printf(
+            output_wrapped,
+            &*(spec as *mut Str),
+            &format_args[..],
+            chars
+        )
     )
 }
 
@@ -1026,10 +1360,11 @@ pub unsafe extern "C" fn sprintf_impl(
 ) -> U128 {
     use runtime::str_impl::DynamicBuf;
     let mut buf = DynamicBuf::new(0);
-    let rt = &mut *(rt as *mut _);
+    let rt = &mut *(rt as *mut Runtime);
+    let chars = rt.core.chars;
     let format_args = wrap_args(rt, args, tys, num_args);
     let spec = &*(spec as *mut Str);
-    if let Err(e) = spec.with_bytes(|bs| printf(&mut buf, bs, &format_args[..])) {
+    if let Err(e) = spec.with_bytes(|bs| printf(&mut buf, bs, &format_args[..], chars)) {
         fail!(rt, "unexpected failure during sprintf: {}", e);
     }
     mem::transmute::<Str, U128>(buf.into_str())
@@ -1044,22 +1379,62 @@ pub unsafe extern "C" fn printf_impl_stdout(
     num_args: Int,
 ) {
     let format_args = wrap_args(&mut *(rt as *mut _), args, tys, num_args);
-    let res = (*(rt as *mut Runtime)).core.write_files.printf(
-        None,
-        &*(spec as *mut Str),
-        &format_args[..],
-    );
+    let rt = rt as *mut Runtime;
+    let chars = (*rt).core.chars;
+    let res = (*rt)
+        .core
+        .write_files
+        .printf(None, &*(spec as *mut Str), &format_args[..], chars);
     if res.is_err() {
         exit!(rt);
     }
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn close_file(rt: *mut c_void, file: *mut U128) {
+pub unsafe extern "C" fn close_file(rt: *mut c_void, file: *mut U128) -> Int {
     let rt = &mut *(rt as *mut Runtime);
     let file = &*(file as *mut Str);
-    with_input!(&mut rt.input_data, |(_, read_files)| read_files.close(file));
-    try_abort!(rt, rt.core.write_files.close(file));
+    let res = with_input!(&mut rt.input_data, |(_, read_files)| runtime::close_file(
+        &mut rt.core.write_files,
+        read_files,
+        file
+    ));
+    try_abort!(rt, res)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn run_system(rt: *mut c_void, cmd: *mut U128) -> Int {
+    let rt = &mut *(rt as *mut Runtime);
+    let cmd = &*(cmd as *mut Str);
+    let res = rt.core.write_files.system(cmd);
+    try_abort!(rt, res)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn systime() -> Int {
+    runtime::time::systime()
+}
+
+// strftime and mktime are prefixed to avoid clashing with the libc functions of the same name.
+#[no_mangle]
+pub unsafe extern "C" fn _frawk_strftime(fmt: *mut U128, ts: Int, utc: Int) -> U128 {
+    let fmt = &*(fmt as *mut Str);
+    mem::transmute::<Str, U128>(runtime::time::strftime(fmt, ts, utc != 0))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn _frawk_mktime(spec: *mut U128) -> Int {
+    runtime::time::mktime(&*(spec as *mut Str))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn parse_time(s: *mut U128, fmt: *mut U128) -> Int {
+    runtime::time::parse_time(&*(s as *mut Str), &*(fmt as *mut Str))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn json_path(path: *mut U128, s: *mut U128) -> U128 {
+    mem::transmute::<Str, U128>(runtime::json_path(&*(path as *mut Str), &*(s as *mut Str)))
 }
 
 #[no_mangle]
@@ -1158,8 +1533,8 @@ macro_rules! convert_out {
 }
 
 macro_rules! map_impl_inner {
-    ($alloc:ident, $iter:ident, $lookup:ident, $len:ident,
-     $insert:ident, $delete:ident, $contains:ident, $drop:ident, $k:tt, $v:tt) => {
+    ($alloc:ident, $iter:ident, $lookup:ident, $len:ident, $insert:ident, $delete:ident,
+     $clear:ident, $asort:ident, $asorti:ident, $contains:ident, $drop:ident, $k:tt, $v:tt) => {
         // XXX
         // What's going on with the read_volatile(&false) stuff?
         //
@@ -1177,9 +1552,10 @@ macro_rules! map_impl_inner {
             mem::transmute::<runtime::SharedMap<$k, $v>, *mut c_void>(res)
         }
         #[no_mangle]
-        pub unsafe extern "C" fn $iter(map: *mut c_void) -> iter_ty!($k) {
+        pub unsafe extern "C" fn $iter(runtime: *mut c_void, map: *mut c_void) -> iter_ty!($k) {
+            let order = (*(runtime as *mut Runtime)).core.vars.sort_order;
             let map = mem::transmute::<*mut c_void, runtime::SharedMap<$k, $v>>(map);
-            let iter: Vec<_> = map.to_vec();
+            let iter: Vec<_> = map.to_vec(order);
             mem::forget(map);
             let b = iter.into_boxed_slice();
             Box::into_raw(b) as _
@@ -1226,6 +1602,48 @@ macro_rules! map_impl_inner {
             mem::forget(map);
         }
         #[no_mangle]
+        pub unsafe extern "C" fn $clear(map: *mut c_void) {
+            let map = mem::transmute::<*mut c_void, runtime::SharedMap<$k, $v>>(map);
+            map.clear();
+            mem::forget(map);
+        }
+        // The destination map is keyed by strings if `str_keys` is nonzero, and by integers
+        // otherwise.
+        #[no_mangle]
+        pub unsafe extern "C" fn $asort(src: *mut c_void, dst: *mut c_void, str_keys: Int) -> Int {
+            let src = mem::transmute::<*mut c_void, runtime::SharedMap<$k, $v>>(src);
+            let res = if str_keys != 0 {
+                let dst = mem::transmute::<*mut c_void, runtime::StrMap<$v>>(dst);
+                let res = runtime::sort::asort(&src, &dst);
+                mem::forget(dst);
+                res
+            } else {
+                let dst = mem::transmute::<*mut c_void, runtime::IntMap<$v>>(dst);
+                let res = runtime::sort::asort(&src, &dst);
+                mem::forget(dst);
+                res
+            };
+            mem::forget(src);
+            res
+        }
+        #[no_mangle]
+        pub unsafe extern "C" fn $asorti(src: *mut c_void, dst: *mut c_void, str_keys: Int) -> Int {
+            let src = mem::transmute::<*mut c_void, runtime::SharedMap<$k, $v>>(src);
+            let res = if str_keys != 0 {
+                let dst = mem::transmute::<*mut c_void, runtime::StrMap<$k>>(dst);
+                let res = runtime::sort::asorti(&src, &dst);
+                mem::forget(dst);
+                res
+            } else {
+                let dst = mem::transmute::<*mut c_void, runtime::IntMap<$k>>(dst);
+                let res = runtime::sort::asorti(&src, &dst);
+                mem::forget(dst);
+                res
+            };
+            mem::forget(src);
+            res
+        }
+        #[no_mangle]
         pub unsafe extern "C" fn $drop(map: *mut c_void) {
             if std::ptr::read_volatile(&false) {
                 eprintln!("dropping from {}", stringify!($drop));
@@ -1237,7 +1655,8 @@ macro_rules! map_impl_inner {
 
 macro_rules! map_impl {
     ($($iter:ident, $alloc:ident, $len:ident, $lookup:ident,
-       $insert:ident, $delete:ident, $contains:ident, $drop:ident, < $k:tt, $v:tt >;)*) => {
+       $insert:ident, $delete:ident, $clear:ident, $asort:ident, $asorti:ident,
+       $contains:ident, $drop:ident, < $k:tt, $v:tt >;)*) => {
         $(
             map_impl_inner!(
                 $alloc,
@@ -1246,6 +1665,9 @@ macro_rules! map_impl {
                 $len,
                 $insert,
                 $delete,
+                $clear,
+                $asort,
+                $asorti,
                 $contains,
                 $drop,
                 $k,
@@ -1257,22 +1679,28 @@ macro_rules! map_impl {
 
 map_impl! {
     iter_intint, alloc_intint, len_intint, lookup_intint,
-    insert_intint, delete_intint, contains_intint, drop_intint, <Int, Int>;
+    insert_intint, delete_intint, clear_intint,
+    asort_intint, asorti_intint, contains_intint, drop_intint, <Int, Int>;
 
     iter_intfloat, alloc_intfloat, len_intfloat, lookup_intfloat,
-    insert_intfloat, delete_intfloat, contains_intfloat, drop_intfloat, <Int, Float>;
+    insert_intfloat, delete_intfloat, clear_intfloat,
+    asort_intfloat, asorti_intfloat, contains_intfloat, drop_intfloat, <Int, Float>;
 
     iter_intstr, alloc_intstr, len_intstr, lookup_intstr,
-    insert_intstr, delete_intstr, contains_intstr, drop_intstr, <Int, Str>;
+    insert_intstr, delete_intstr, clear_intstr,
+    asort_intstr, asorti_intstr, contains_intstr, drop_intstr, <Int, Str>;
 
     iter_strint, alloc_strint, len_strint, lookup_strint,
-    insert_strint, delete_strint, contains_strint, drop_strint, <Str, Int>;
+    insert_strint, delete_strint, clear_strint,
+    asort_strint, asorti_strint, contains_strint, drop_strint, <Str, Int>;
 
     iter_strfloat, alloc_strfloat, len_strfloat, lookup_strfloat,
-    insert_strfloat, delete_strfloat, contains_strfloat, drop_strfloat, <Str, Float>;
+    insert_strfloat, delete_strfloat, clear_strfloat,
+    asort_strfloat, asorti_strfloat, contains_strfloat, drop_strfloat, <Str, Float>;
 
     iter_strstr, alloc_strstr, len_strstr, lookup_strstr,
-    insert_strstr, delete_strstr, contains_strstr, drop_strstr, <Str, Str>;
+    insert_strstr, delete_strstr, clear_strstr,
+    asort_strstr, asorti_strstr, contains_strstr, drop_strstr, <Str, Str>;
 }
 
 macro_rules! slot_impl_inner {
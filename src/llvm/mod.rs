@@ -336,13 +336,17 @@ impl<'a, 'b> Generator<'a, 'b> {
         ff: impl runtime::writers::FileFactory,
         used_fields: &FieldSet,
         num_workers: usize,
-    ) -> Result<()> {
+    ) -> Result<i32 /* exit code */> {
         let mut rt = stdin.into_runtime(ff, used_fields);
+        rt.core.chars = self.types.chars;
         let main = self.gen_main()?;
         self.optimize(main.iter().map(|(_, x)| x).cloned())?;
         self.verify()?;
         match main {
-            Stage::Main((main_name, _)) => Ok(self.run_function(&mut rt, main_name)),
+            Stage::Main((main_name, _)) => {
+                self.run_function(&mut rt, main_name);
+                Ok(rt.core.exit_code())
+            }
             Stage::Par {
                 begin,
                 main_loop,
@@ -359,7 +363,7 @@ impl<'a, 'b> Generator<'a, 'b> {
                         for name in begin.into_iter().chain(main_loop).chain(end) {
                             self.run_function(&mut rt, name.0);
                         }
-                        return Ok(());
+                        return Ok(rt.core.exit_code());
                     }
                     #[cfg(not(debug_assertions))]
                     {
@@ -375,7 +379,7 @@ impl<'a, 'b> Generator<'a, 'b> {
                         self.run_function(&mut rt, begin_name);
                     }
                     if let Err(_) = rt.core.write_files.flush_stdout() {
-                        return Ok(());
+                        return Ok(rt.core.exit_code());
                     }
                     let (sender, receiver) = bounded(reads.len());
                     let launch_data: Vec<_> = reads
@@ -427,7 +431,7 @@ impl<'a, 'b> Generator<'a, 'b> {
                         }
                     });
                 });
-                Ok(())
+                Ok(rt.core.exit_code())
             }
         }
     }
@@ -941,7 +945,7 @@ impl<'a> View<'a> {
             _ => return err!("iterating over non-map type: {:?}", arr.1),
         };
 
-        let iter_ptr = self.call(begin_fn, &mut [arrv]);
+        let iter_ptr = self.call(begin_fn, &mut [self.runtime_val(), arrv]);
         let cur_index = self.alloca(Ty::Int);
         let len = self.call(len_fn, &mut [arrv]);
         let _old = self.f.iters.insert(
@@ -1161,6 +1165,22 @@ impl<'a> View<'a> {
         Ok(())
     }
 
+    unsafe fn clear_map(&mut self, map: (NumTy, Ty)) -> Result<()> {
+        use Ty::*;
+        let func = match map.1 {
+            MapIntInt => "clear_intint",
+            MapIntFloat => "clear_intfloat",
+            MapIntStr => "clear_intstr",
+            MapStrInt => "clear_strint",
+            MapStrFloat => "clear_strfloat",
+            MapStrStr => "clear_strstr",
+            _ => unreachable!(),
+        };
+        let mapv = self.get_local(map)?;
+        self.call(func, &mut [mapv]);
+        Ok(())
+    }
+
     unsafe fn contains_map(
         &mut self,
         map: (NumTy, Ty),
@@ -1202,6 +1222,38 @@ impl<'a> View<'a> {
         Ok(())
     }
 
+    unsafe fn sort_map(
+        &mut self,
+        keys: bool,
+        src: (NumTy, Ty),
+        dst: (NumTy, Ty),
+        res: (NumTy, Ty),
+    ) -> Result<()> {
+        use Ty::*;
+        let func = match (keys, src.1) {
+            (false, MapIntInt) => "asort_intint",
+            (false, MapIntFloat) => "asort_intfloat",
+            (false, MapIntStr) => "asort_intstr",
+            (false, MapStrInt) => "asort_strint",
+            (false, MapStrFloat) => "asort_strfloat",
+            (false, MapStrStr) => "asort_strstr",
+            (true, MapIntInt) => "asorti_intint",
+            (true, MapIntFloat) => "asorti_intfloat",
+            (true, MapIntStr) => "asorti_intstr",
+            (true, MapStrInt) => "asorti_strint",
+            (true, MapStrFloat) => "asorti_strfloat",
+            (true, MapStrStr) => "asorti_strstr",
+            _ => unreachable!(),
+        };
+        let srcv = self.get_local(src)?;
+        let dstv = self.get_local(dst)?;
+        let str_keys = (dst.1.key()? == Str) as u64;
+        let str_keys = LLVMConstInt(self.tmap.get_ty(Int), str_keys, /*sign_extend=*/ 0);
+        let resv = self.call(func, &mut [srcv, dstv, str_keys]);
+        self.bind_val(res, resv);
+        Ok(())
+    }
+
     unsafe fn store_map(
         &mut self,
         map: (NumTy, Ty),
@@ -1311,7 +1363,17 @@ impl<'a> View<'a> {
             }
             FloatToStr(sr, fr) => {
                 let arg = self.get_local(fr.reflect())?;
-                let res = self.call("float_to_str", &mut [arg]);
+                let res = self.call("float_to_str", &mut [self.runtime_val(), arg]);
+                self.bind_reg(sr, res);
+            }
+            FloatToOutputStr(sr, fr) => {
+                let arg = self.get_local(fr.reflect())?;
+                let res = self.call("float_to_output_str", &mut [self.runtime_val(), arg]);
+                self.bind_reg(sr, res);
+            }
+            FloatToJSON(sr, fr) => {
+                let arg = self.get_local(fr.reflect())?;
+                let res = self.call("float_to_json", &mut [self.runtime_val(), arg]);
                 self.bind_reg(sr, res);
             }
             StrToInt(ir, sr) => {
@@ -1457,6 +1519,34 @@ impl<'a> View<'a> {
                 let resv = bw.llvm2(self.f.builder, opx, opy);
                 self.bind_reg(dst, resv);
             }
+            Systime(res) => {
+                let resv = self.call("systime", &mut []);
+                self.bind_reg(res, resv);
+            }
+            Strftime(res, fmt, ts, utc) => {
+                let fmtv = self.get_local(fmt.reflect())?;
+                let tsv = self.get_local(ts.reflect())?;
+                let utcv = self.get_local(utc.reflect())?;
+                let resv = self.call("_frawk_strftime", &mut [fmtv, tsv, utcv]);
+                self.bind_reg(res, resv);
+            }
+            Mktime(res, s) => {
+                let sv = self.get_local(s.reflect())?;
+                let resv = self.call("_frawk_mktime", &mut [sv]);
+                self.bind_reg(res, resv);
+            }
+            ParseTime(res, s, fmt) => {
+                let sv = self.get_local(s.reflect())?;
+                let fmtv = self.get_local(fmt.reflect())?;
+                let resv = self.call("parse_time", &mut [sv, fmtv]);
+                self.bind_reg(res, resv);
+            }
+            JSONPath(res, path, s) => {
+                let pathv = self.get_local(path.reflect())?;
+                let sv = self.get_local(s.reflect())?;
+                let resv = self.call("json_path", &mut [pathv, sv]);
+                self.bind_reg(res, resv);
+            }
             Rand(res) => {
                 let resv = self.call("rand_float", &mut [self.runtime_val()]);
                 self.bind_reg(res, resv);
@@ -1483,6 +1573,29 @@ impl<'a> View<'a> {
                 let resv = self.call("match_pat_loc", &mut [rt, lv, rv]);
                 self.bind_reg(res, resv);
             }
+            MatchChars(res, l, r) => {
+                let lv = self.get_local(l.reflect())?;
+                let rv = self.get_local(r.reflect())?;
+                let rt = self.runtime_val();
+                let resv = self.call("match_pat_loc_chars", &mut [rt, lv, rv]);
+                self.bind_reg(res, resv);
+            }
+            MatchCapturesInt(res, l, r, arr) => {
+                let lv = self.get_local(l.reflect())?;
+                let rv = self.get_local(r.reflect())?;
+                let arrv = self.get_local(arr.reflect())?;
+                let rt = self.runtime_val();
+                let resv = self.call("match_captures_int", &mut [rt, lv, rv, arrv]);
+                self.bind_reg(res, resv);
+            }
+            MatchCapturesStr(res, l, r, arr) => {
+                let lv = self.get_local(l.reflect())?;
+                let rv = self.get_local(r.reflect())?;
+                let arrv = self.get_local(arr.reflect())?;
+                let rt = self.runtime_val();
+                let resv = self.call("match_captures_str", &mut [rt, lv, rv, arrv]);
+                self.bind_reg(res, resv);
+            }
             IsMatch(res, l, r) => {
                 let lv = self.get_local(l.reflect())?;
                 let rv = self.get_local(r.reflect())?;
@@ -1496,11 +1609,22 @@ impl<'a> View<'a> {
                 let resv = self.call("substr_index", &mut [sv, tv]);
                 self.bind_reg(res, resv);
             }
+            SubstrIndexChars(res, s, t) => {
+                let sv = self.get_local(s.reflect())?;
+                let tv = self.get_local(t.reflect())?;
+                let resv = self.call("substr_index_chars", &mut [sv, tv]);
+                self.bind_reg(res, resv);
+            }
             LenStr(res, s) => {
                 let sv = self.get_local(s.reflect())?;
                 let lenv = self.call("str_len", &mut [sv]);
                 self.bind_reg(res, lenv);
             }
+            LenStrChars(res, s) => {
+                let sv = self.get_local(s.reflect())?;
+                let lenv = self.call("str_len_chars", &mut [sv]);
+                self.bind_reg(res, lenv);
+            }
             Sub(res, pat, s, in_s) => {
                 let patv = self.get_local(pat.reflect())?;
                 let sv = self.get_local(s.reflect())?;
@@ -1517,6 +1641,15 @@ impl<'a> View<'a> {
                 let resv = self.call("subst_all", &mut [rt, patv, sv, in_sv]);
                 self.bind_reg(res, resv);
             }
+            GenSub(res, pat, s, how, in_s) => {
+                let patv = self.get_local(pat.reflect())?;
+                let sv = self.get_local(s.reflect())?;
+                let howv = self.get_local(how.reflect())?;
+                let in_sv = self.get_local(in_s.reflect())?;
+                let rt = self.runtime_val();
+                let resv = self.call("gensub", &mut [rt, patv, sv, howv, in_sv]);
+                self.bind_reg(res, resv);
+            }
             EscapeCSV(res, s) => {
                 let sv = self.get_local(s.reflect())?;
                 let resv = self.call("escape_csv", &mut [sv]);
@@ -1527,6 +1660,21 @@ impl<'a> View<'a> {
                 let resv = self.call("escape_tsv", &mut [sv]);
                 self.bind_reg(res, resv);
             }
+            EscapeJSON(res, s) => {
+                let sv = self.get_local(s.reflect())?;
+                let resv = self.call("escape_json", &mut [sv]);
+                self.bind_reg(res, resv);
+            }
+            ToUpper(res, s) => {
+                let sv = self.get_local(s.reflect())?;
+                let resv = self.call("to_upper", &mut [sv]);
+                self.bind_reg(res, resv);
+            }
+            ToLower(res, s) => {
+                let sv = self.get_local(s.reflect())?;
+                let resv = self.call("to_lower", &mut [sv]);
+                self.bind_reg(res, resv);
+            }
             Substr(res, base, l, r) => {
                 let basev = self.get_local(base.reflect())?;
                 let lv = self.get_local(l.reflect())?;
@@ -1534,6 +1682,13 @@ impl<'a> View<'a> {
                 let resv = self.call("substr", &mut [basev, lv, rv]);
                 self.bind_reg(res, resv);
             }
+            SubstrChars(res, base, l, r) => {
+                let basev = self.get_local(base.reflect())?;
+                let lv = self.get_local(l.reflect())?;
+                let rv = self.get_local(r.reflect())?;
+                let resv = self.call("substr_chars", &mut [basev, lv, rv]);
+                self.bind_reg(res, resv);
+            }
             LTFloat(res, l, r) => {
                 let lv = self.get_local(l.reflect())?;
                 let rv = self.get_local(r.reflect())?;
@@ -1634,6 +1789,10 @@ impl<'a> View<'a> {
                 let resv = self.call("get_col", &mut [self.runtime_val(), sv]);
                 self.bind_reg(dst, resv);
             }
+            RebuiltRecord(dst) => {
+                let resv = self.call("rebuilt_record", &mut [self.runtime_val()]);
+                self.bind_reg(dst, resv);
+            }
             JoinCSV(dst, start, end) => {
                 let rt = self.runtime_val();
                 let start = self.get_local(start.reflect())?;
@@ -1648,6 +1807,13 @@ impl<'a> View<'a> {
                 let resv = self.call("join_tsv", &mut [rt, start, end]);
                 self.bind_reg(dst, resv);
             }
+            JoinJSON(dst, start, end) => {
+                let rt = self.runtime_val();
+                let start = self.get_local(start.reflect())?;
+                let end = self.get_local(end.reflect())?;
+                let resv = self.call("join_json", &mut [rt, start, end]);
+                self.bind_reg(dst, resv);
+            }
             JoinColumns(dst, start, end, sep) => {
                 let rt = self.runtime_val();
                 let start = self.get_local(start.reflect())?;
@@ -1672,6 +1838,21 @@ impl<'a> View<'a> {
                 let resv = self.call("split_str", &mut [rt, tsv, arrv, patv]);
                 self.bind_reg(flds, resv);
             }
+            PatSplit {
+                flds,
+                to_split,
+                arr,
+                pat,
+                seps,
+            } => {
+                let rt = self.runtime_val();
+                let tsv = self.get_local(to_split.reflect())?;
+                let arrv = self.get_local(arr.reflect())?;
+                let patv = self.get_local(pat.reflect())?;
+                let sepsv = self.get_local(seps.reflect())?;
+                let resv = self.call("patsplit", &mut [rt, tsv, arrv, patv, sepsv]);
+                self.bind_reg(flds, resv);
+            }
             Sprintf { dst, fmt, args } => {
                 let arg_tys: SmallVec<_> = args.iter().map(|x| x.1).collect();
                 let sprintf_fn = self.wrapped_printf((arg_tys, PrintfKind::Sprintf));
@@ -1712,10 +1893,10 @@ impl<'a> View<'a> {
                 for a in args.iter().cloned() {
                     arg_vs.push(self.get_local(a)?);
                 }
-                if let Some((path, append)) = output {
+                if let Some((path, spec)) = output {
                     arg_vs.push(self.get_local(path.reflect())?);
                     let int_ty = self.tmap.get_ty(Ty::Int);
-                    arg_vs.push(LLVMConstInt(int_ty, if *append { 1 } else { 0 }, 0));
+                    arg_vs.push(LLVMConstInt(int_ty, *spec as u64, 0));
                 }
                 LLVMBuildCall(
                     self.f.builder,
@@ -1729,16 +1910,22 @@ impl<'a> View<'a> {
                 let txtv = self.get_local(txt.reflect())?;
                 self.call("print_stdout", &mut [self.runtime_val(), txtv]);
             }
-            Close(file) => {
+            Close(dst, file) => {
                 let filev = self.get_local(file.reflect())?;
-                self.call("close_file", &mut [self.runtime_val(), filev]);
+                let resv = self.call("close_file", &mut [self.runtime_val(), filev]);
+                self.bind_reg(dst, resv);
+            }
+            RunCmd(dst, cmd) => {
+                let cmdv = self.get_local(cmd.reflect())?;
+                let resv = self.call("run_system", &mut [self.runtime_val(), cmdv]);
+                self.bind_reg(dst, resv);
             }
-            Print(txt, out, append) => {
+            Print(txt, out, spec) => {
                 let int_ty = self.tmap.get_ty(Ty::Int);
-                let appv = LLVMConstInt(int_ty, *append as u64, /*sign_extend=*/ 1);
+                let specv = LLVMConstInt(int_ty, *spec as u64, /*sign_extend=*/ 1);
                 let txtv = self.get_local(txt.reflect())?;
                 let outv = self.get_local(out.reflect())?;
-                self.call("print", &mut [self.runtime_val(), txtv, outv, appv]);
+                self.call("print", &mut [self.runtime_val(), txtv, outv, specv]);
             }
 
             ReadErr(dst, file) => {
@@ -1751,6 +1938,16 @@ impl<'a> View<'a> {
                 let resv = self.call("next_line", &mut [self.runtime_val(), filev]);
                 self.bind_reg(dst, resv);
             }
+            ReadErrCmd(dst, cmd) => {
+                let cmdv = self.get_local(cmd.reflect())?;
+                let resv = self.call("read_err_cmd", &mut [self.runtime_val(), cmdv]);
+                self.bind_reg(dst, resv);
+            }
+            NextLineCmd(dst, cmd) => {
+                let cmdv = self.get_local(cmd.reflect())?;
+                let resv = self.call("next_line_cmd", &mut [self.runtime_val(), cmdv]);
+                self.bind_reg(dst, resv);
+            }
             ReadErrStdin(dst) => {
                 let resv = self.call("read_err_stdin", &mut [self.runtime_val()]);
                 self.bind_reg(dst, resv);
@@ -1765,6 +1962,20 @@ impl<'a> View<'a> {
             NextFile() => {
                 self.call("next_file", &mut [self.runtime_val()]);
             }
+            Exit(code) => {
+                let int_ty = self.tmap.get_ty(Ty::Int);
+                let (codev, has_code) = if let Some(code) = code {
+                    (self.get_local(code.reflect())?, 1)
+                } else {
+                    (LLVMConstInt(int_ty, 0, /*sign_extend=*/ 0), 0)
+                };
+                let has_codev = LLVMConstInt(int_ty, has_code, /*sign_extend=*/ 0);
+                self.call("exit_stmt", &mut [self.runtime_val(), codev, has_codev]);
+            }
+            Exiting(dst) => {
+                let resv = self.call("exiting", &mut [self.runtime_val()]);
+                self.bind_reg(dst, resv);
+            }
             Lookup {
                 map_ty,
                 dst,
@@ -1784,7 +1995,32 @@ impl<'a> View<'a> {
             Delete { map_ty, map, key } => {
                 self.delete_map((*map, *map_ty), (*key, map_ty.key()?))?
             }
+            Clear { map_ty, map } => self.clear_map((*map, *map_ty))?,
             Len { map_ty, map, dst } => self.len_map((*map, *map_ty), (*dst, Ty::Int))?,
+            Asort {
+                src_ty,
+                src,
+                dst_ty,
+                dst,
+                res,
+            } => self.sort_map(
+                /*keys=*/ false,
+                (*src, *src_ty),
+                (*dst, *dst_ty),
+                (*res, Ty::Int),
+            )?,
+            Asorti {
+                src_ty,
+                src,
+                dst_ty,
+                dst,
+                res,
+            } => self.sort_map(
+                /*keys=*/ true,
+                (*src, *src_ty),
+                (*dst, *dst_ty),
+                (*res, Ty::Int),
+            )?,
             Store {
                 map_ty,
                 map,
@@ -1840,6 +2076,21 @@ impl<'a> View<'a> {
                 let sv = self.get_local(src.reflect())?;
                 self.call("store_var_intmap", &mut [self.runtime_val(), v, sv]);
             }
+            LoadVarStrMap(dst, var) => {
+                let v = self.var_val(var);
+                let res = self.call("load_var_strmap", &mut [self.runtime_val(), v]);
+                // See the comment in the LoadVarStr case.
+                let dreg = dst.reflect();
+                self.bind_val(dreg, res);
+                if self.is_global(dreg) {
+                    self.drop_reg(dreg)?;
+                }
+            }
+            StoreVarStrMap(var, src) => {
+                let v = self.var_val(var);
+                let sv = self.get_local(src.reflect())?;
+                self.call("store_var_strmap", &mut [self.runtime_val(), v, sv]);
+            }
 
             LoadSlot { ty, dst, slot } => self.load_slot((*dst, *ty), *slot),
             StoreSlot { ty, src, slot } => self.store_slot((*src, *ty), *slot)?,
@@ -1992,7 +2243,7 @@ impl<'a> View<'a> {
         assert_eq!(name_c[name_c.len() - 1], 0);
 
         // The var-arg portion + runtime + format spec
-        //  (+ output + append, if named_output)
+        //  (+ output + output spec, if named_output)
         let mut arg_lltys = smallvec::SmallVec::<[_; 8]>::with_capacity(args.len() + 4);
         arg_lltys.push(self.tmap.runtime_ty);
         arg_lltys.push(self.tmap.get_ptr_ty(Ty::Str)); // spec
@@ -2005,7 +2256,7 @@ impl<'a> View<'a> {
         }));
         if let File = kind {
             arg_lltys.push(self.tmap.get_ptr_ty(Ty::Str)); // output
-            arg_lltys.push(self.tmap.get_ty(Ty::Int)); // append
+            arg_lltys.push(self.tmap.get_ty(Ty::Int)); // output spec
         }
 
         let ret = match kind {
@@ -2066,7 +2317,7 @@ impl<'a> View<'a> {
         match kind {
             File => {
                 let intrinsic = self.intrinsics.get("printf_impl_file");
-                // runtime, spec, args, tys, num_args, output, append
+                // runtime, spec, args, tys, num_args, output, output spec
                 let mut args = [
                     LLVMGetParam(f, 0),
                     LLVMGetParam(f, 1),
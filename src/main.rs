@@ -1,4 +1,4 @@
-#![recursion_limit = "256"]
+#![recursion_limit = "512"]
 #![feature(core_intrinsics)]
 #![feature(test)]
 #![feature(write_all_vectored)]
@@ -58,14 +58,18 @@ use common::{ExecutionStrategy, Stage};
 use llvm::IntoRuntime;
 use runtime::{
     splitter::{
-        batch::{ByteReader, CSVReader, InputFormat},
+        batch::{self, ByteReader, CSVReader, EscapeStyle, InputFormat, OnError},
+        json::JSONReader,
         regex::RegexSplitter,
+        sniff, FieldSplit, FieldSplitReader,
     },
     ChainedReader, LineReader, CHUNK_SIZE,
 };
 use std::fs::File;
-use std::io::{self, BufReader, Write};
+use std::io::{self, BufReader, Read, Write};
 use std::iter::once;
+use std::mem;
+use std::sync::Arc;
 
 #[cfg(feature = "use_jemalloc")]
 #[global_allocator]
@@ -81,18 +85,26 @@ macro_rules! fail {
 struct RawPrelude {
     var_decs: Vec<String>,
     field_sep: Option<String>,
+    field_widths: Option<String>,
     output_sep: Option<&'static str>,
     output_record_sep: Option<&'static str>,
+    header: Option<Vec<String>>,
+    key_paths: bool,
     escaper: Escaper,
+    chars: bool,
     stage: Stage<()>,
 }
 
 struct Prelude<'a> {
     var_decs: Vec<(&'a str, &'a ast::Expr<'a, 'a, &'a str>)>,
     field_sep: Option<&'a str>,
+    field_widths: Option<&'a str>,
     output_sep: Option<&'a str>,
     output_record_sep: Option<&'a str>,
+    header: Option<Vec<&'a str>>,
+    key_paths: bool,
     escaper: Escaper,
+    chars: bool,
     stage: Stage<()>,
 }
 
@@ -103,6 +115,99 @@ fn open_file_read(f: &str) -> io::BufReader<File> {
     }
 }
 
+// Open each input file, or standard input if there are none.
+fn open_inputs(input_files: &[String]) -> Vec<(Box<dyn io::BufRead + Send>, String)> {
+    if input_files.len() == 0 {
+        return vec![(Box::new(BufReader::new(io::stdin())), String::from("-"))];
+    }
+    input_files
+        .iter()
+        .map(|file| {
+            let r: Box<dyn io::BufRead + Send> = Box::new(open_file_read(file.as_str()));
+            (r, file.clone())
+        })
+        .collect()
+}
+
+// Guess the format of the first input from its first chunk, replacing it with a reader that
+// starts again from the beginning.
+fn sniff_inputs(
+    inputs: &mut Vec<(Box<dyn io::BufRead + Send>, String)>,
+    delimiter: Option<u8>,
+) -> InputFormat {
+    let (r, name) = &mut inputs[0];
+    let mut sample = Vec::new();
+    if let Err(e) = r.take(CHUNK_SIZE as u64).read_to_end(&mut sample) {
+        fail!("failed to read {}: {}", name, e);
+    }
+    let ifmt = sniff::sniff(&sample[..], sample.len() < CHUNK_SIZE, delimiter);
+    let rest = mem::replace(r, Box::new(io::empty()));
+    *r = Box::new(io::Cursor::new(sample).chain(rest));
+    ifmt
+}
+
+// Opens an input on the first read; see `batch::SkipHeader`.
+type Opener = Box<dyn FnOnce() -> io::Result<Box<dyn io::BufRead + Send>> + Send>;
+
+// Like open_inputs, but each input file is only opened when it is first read.
+fn lazy_inputs(input_files: &[String]) -> Vec<(Opener, String)> {
+    if input_files.len() == 0 {
+        let open: Opener = Box::new(|| Ok(Box::new(BufReader::new(io::stdin())) as _));
+        return vec![(open, String::from("-"))];
+    }
+    input_files
+        .iter()
+        .map(|file| {
+            let name = file.clone();
+            let open: Opener = Box::new(move || match File::open(name.as_str()) {
+                Ok(f) => Ok(Box::new(BufReader::new(f)) as _),
+                Err(e) => Err(io::Error::new(
+                    e.kind(),
+                    format!("failed to open file {}: {}", name, e),
+                )),
+            });
+            (open, file.clone())
+        })
+        .collect()
+}
+
+// Consume the header row of the first input, returning its column names, the length of the header
+// row of each input, and readers for the remainder of each input. The header rows of the other
+// inputs are consumed when those inputs are first read, failing if they differ from the header of
+// the first input.
+fn read_headers(
+    inputs: Vec<(Opener, String)>,
+    ifmt: InputFormat,
+) -> (
+    Vec<String>,
+    batch::HeaderLens,
+    Vec<(Box<dyn io::Read + Send>, String)>,
+) {
+    let header_lens = batch::HeaderLens::new(inputs.len());
+    let mut header = Arc::new(Vec::new());
+    let mut readers: Vec<(Box<dyn io::Read + Send>, String)> = Vec::new();
+    for (i, (open, name)) in inputs.into_iter().enumerate() {
+        if i > 0 {
+            let r = batch::SkipHeader::new(open, ifmt, header_lens.clone(), i)
+                .expect(header.clone(), name.clone());
+            readers.push((Box::new(r), name));
+            continue;
+        }
+        let mut r = match open() {
+            Ok(r) => r,
+            Err(e) => fail!("{}", e),
+        };
+        let (cur, len) = match batch::read_header(&mut r, ifmt) {
+            Ok(h) => h,
+            Err(e) => fail!("failed to read header of {}: {}", name, e),
+        };
+        header = Arc::new(cur);
+        header_lens.set(0, len);
+        readers.push((Box::new(r), name));
+    }
+    ((*header).clone(), header_lens, readers)
+}
+
 fn chained<LR: LineReader>(lr: LR) -> ChainedReader<LR> {
     ChainedReader::new(std::iter::once(lr))
 }
@@ -143,12 +248,23 @@ fn get_prelude<'a>(a: &'a Arena, raw: &RawPrelude) -> Prelude<'a> {
         .field_sep
         .as_ref()
         .map(|s| lexer::parse_string_literal(s.as_str(), a, &mut buf));
+    let field_widths = raw
+        .field_widths
+        .as_ref()
+        .map(|s| lexer::parse_string_literal(s.as_str(), a, &mut buf));
     Prelude {
         field_sep,
+        field_widths,
         var_decs: get_vars(raw.var_decs.iter().map(|s| s.as_str()), a, &mut buf),
         escaper: raw.escaper,
+        chars: raw.chars,
         output_sep,
         output_record_sep,
+        header: raw
+            .header
+            .as_ref()
+            .map(|h| h.iter().map(|s| a.alloc_str(s.as_str())).collect()),
+        key_paths: raw.key_paths,
         stage: raw.stage.clone(),
     }
 }
@@ -165,9 +281,12 @@ fn get_context<'a>(
     let stmt = match parser.parse(a, &mut buf, &prelude.stage, lexer) {
         Ok(mut program) => {
             program.field_sep = prelude.field_sep;
+            program.field_widths = prelude.field_widths;
             program.prelude_vardecs = prelude.var_decs;
             program.output_sep = prelude.output_sep;
             program.output_record_sep = prelude.output_record_sep;
+            program.header = prelude.header;
+            program.key_paths = prelude.key_paths;
             a.alloc_v(program)
         }
         Err(e) => {
@@ -175,7 +294,10 @@ fn get_context<'a>(
         }
     };
     match cfg::ProgramContext::from_prog(a, stmt, prelude.escaper) {
-        Ok(ctx) => ctx,
+        Ok(mut ctx) => {
+            ctx.chars = prelude.chars;
+            ctx
+        }
         Err(e) => fail!("failed to create program context: {}", e),
     }
 }
@@ -190,8 +312,14 @@ fn run_interp_with_context<'a>(
         Ok(ctx) => ctx,
         Err(e) => fail!("bytecode compilation failure: {}", e),
     };
-    if let Err(e) = interp.run() {
-        fail!("fatal error during execution: {}", e);
+    let code = match interp.run() {
+        Ok(code) => code,
+        Err(e) => fail!("fatal error during execution: {}", e),
+    };
+    // Flush any pending output before exiting with a nonzero exit code.
+    mem::drop(interp);
+    if code != 0 {
+        std::process::exit(code)
     }
 }
 
@@ -203,8 +331,11 @@ cfg_if::cfg_if! {
             ff: impl runtime::writers::FileFactory,
             cfg: llvm::Config,
         ) {
-            if let Err(e) = compile::run_llvm(&mut ctx, stdin, ff, cfg) {
-                fail!("error compiling llvm: {}", e)
+            match compile::run_llvm(&mut ctx, stdin, ff, cfg) {
+                // The runtime (and with it, any buffered output) has been dropped at this point.
+                Ok(0) => {}
+                Ok(code) => std::process::exit(code),
+                Err(e) => fail!("error compiling llvm: {}", e),
             }
         }
 
@@ -233,6 +364,7 @@ fn dump_bytecode(prog: &str, raw: &RawPrelude) -> String {
         chained(CSVReader::new(
             once((fake_inp, String::from("unused"))),
             InputFormat::CSV,
+            Escaper::Identity,
             CHUNK_SIZE,
             /*check_utf8=*/ false,
             ExecutionStrategy::Serial,
@@ -267,14 +399,27 @@ fn main() {
              .about("the optimization level for the program. Positive levels determine the optimization level for LLVM. Level -1 forces bytecode interpretation")
              .possible_values(&["-1", "0", "1", "2", "3"]))
         .arg("--out-file=[FILE] 'the output file used in place of standard input'")
-        .arg("--utf8 'validate all input as UTF-8, returning an error if it is invalid'")
+        .arg("--header 'treat the first row of each csv or tsv input as a header. $\"name\" refers to the column called name in the header of the first input, and FIELDS maps column names to indexes. The headers of all inputs must match'")
+        .arg("--utf8 'validate all input as UTF-8, returning an error if it is invalid. Implies --chars'")
+        .arg("--chars 'length, substr, index and match count UTF-8 characters rather than bytes'")
         .arg("--dump-cfg 'print untyped SSA form for input program'")
         .arg("--dump-bytecode 'print bytecode for input program'")
         .arg(Arg::new("input-format")
              .long("input-format")
              .short('i')
-             .possible_values(&["csv", "tsv"])
-             .about("Input is split according to the rules of (csv|tsv|). $0 contains the unescaped line. Assigning to columns does nothing."))
+             .possible_values(&["csv", "tsv", "auto", "jsonl"])
+             .about("Input is split according to the rules of (csv|tsv|). $0 contains the unescaped line. Assigning to a column or to NF rebuilds $0 from the fields, escaped according to --output-format. With auto, the delimiter and whether there is a header are guessed from the start of the input. With jsonl, each line is a JSON value, and $\"path\" is the value at a dot-separated key path like \"user.id\". Key paths and NF of jsonl input cannot be assigned to."))
+        .arg("--print-dialect 'print the input format chosen by --input-format to standard error'")
+        .arg("--delimiter=[CHAR] 'the field delimiter of csv or tsv input, in place of , or tab'")
+        .arg("--quote=[CHAR] 'the quote character of csv or tsv input, or \"none\". Defaults to \" for csv and none for tsv'")
+        .arg(Arg::new("escape")
+             .long("escape")
+             .takes_value(true)
+             .possible_values(&["doubled", "backslash"])
+             .about("How csv or tsv input is escaped: only by doubling quote characters, or also with backslash sequences like \\t (the default)"))
+        .arg("--comment=[CHAR] 'skip lines of csv or tsv input that begin with CHAR'")
+        .arg("--csv-strict 'report malformed records in csv or tsv input: quoted fields that are never closed, and quotes in the middle of unquoted fields'")
+        .arg("--csv-on-error=[ACTION] 'what --csv-strict does with a malformed record: abort (the default), skip it, or reject:FILE to write it to FILE'")
         .arg(Arg::new("var")
              .long("var")
              .short('v')
@@ -282,12 +427,13 @@ fn main() {
              .takes_value(true)
              .about("Has the form <identifier>=<expr>"))
         .arg("-F, --field-separator=[SEPARATOR] 'Field separator for frawk program.'")
+        .arg("--field-widths=[WIDTHS] 'Split fields into fixed-width columns, as though FIELDWIDTHS were assigned WIDTHS. Widths are counted in characters if --chars is set'")
         .arg("-b, --bytecode 'Execute the program with the bytecode interpreter'")
         .arg(Arg::new("output-format")
              .long("output-format")
              .short('o')
-             .possible_values(&["csv", "tsv"])
-             .about("If set, records output via print are escaped according to the rules of the corresponding format"))
+             .possible_values(&["csv", "tsv", "json", "jsonl"])
+             .about("If set, records output via print are escaped according to the rules of the corresponding format. With json or jsonl, each print emits one JSON object per line, keyed by column name where the --header or a JSON Lines key path names the printed column, and by position otherwise"))
         .arg(Arg::new("program")
              .about("The frawk program to execute")
              .index(1))
@@ -307,12 +453,6 @@ fn main() {
         }
     }
     let matches = app.get_matches();
-    let ifmt = match matches.value_of("input-format") {
-        Some("csv") => Some(InputFormat::CSV),
-        Some("tsv") => Some(InputFormat::TSV),
-        Some(x) => fail!("invalid input format: {}", x),
-        None => None,
-    };
     let exec_strategy = match matches.value_of("parallel-strategy") {
         Some("r") | Some("record") => ExecutionStrategy::ShardPerRecord,
         Some("f") | Some("file") => ExecutionStrategy::ShardPerFile,
@@ -352,17 +492,163 @@ fn main() {
             fail!("must specify program at command line, or in a file via -f");
         }
     };
+    let dialect_char = |flag: &str| -> Option<u8> {
+        let c = matches.value_of(flag)?;
+        match c {
+            "\\t" => Some(b'\t'),
+            c if c.len() == 1 => Some(c.as_bytes()[0]),
+            c => fail!("--{} must be a single character, got {:?}", flag, c),
+        }
+    };
+    // Inputs are opened ahead of time if the first chunk of input is needed to guess its format.
+    let mut opened = None;
+    let mut auto = false;
+    let mut jsonl = false;
+    let mut ifmt = match matches.value_of("input-format") {
+        Some("csv") => Some(InputFormat::CSV),
+        Some("tsv") => Some(InputFormat::TSV),
+        Some("auto") => {
+            let mut inputs = open_inputs(&input_files);
+            // Input with a known delimiter (given with -F, as for input frawk splits itself) still
+            // has the rest of its format guessed.
+            let delimiter = dialect_char("delimiter").or_else(|| dialect_char("field-separator"));
+            let ifmt = sniff_inputs(&mut inputs, delimiter);
+            opened = Some(inputs);
+            auto = true;
+            Some(ifmt)
+        }
+        Some("jsonl") => {
+            jsonl = true;
+            None
+        }
+        Some(x) => fail!("invalid input format: {}", x),
+        None => None,
+    };
+    let dialect_flags = [
+        "delimiter",
+        "quote",
+        "escape",
+        "comment",
+        "header",
+        "csv-strict",
+    ];
+    match &mut ifmt {
+        Some(ifmt) => {
+            if let Some(d) = dialect_char("delimiter") {
+                ifmt.delimiter = d;
+            }
+            match matches.value_of("quote") {
+                Some("none") => ifmt.quote = None,
+                Some(_) => ifmt.quote = dialect_char("quote"),
+                None => {}
+            }
+            match matches.value_of("escape") {
+                Some("doubled") => ifmt.escape = EscapeStyle::Doubled,
+                Some("backslash") => ifmt.escape = EscapeStyle::Backslash,
+                Some(x) => fail!("invalid escape style: {}", x),
+                None => {}
+            }
+            if let Some(c) = dialect_char("comment") {
+                ifmt.comment = Some(c);
+            }
+            ifmt.header |= matches.is_present("header");
+            if let Err(e) = ifmt.validate() {
+                fail!("{}", e);
+            }
+        }
+        None => {
+            if let Some(flag) = dialect_flags.iter().find(|f| matches.is_present(f)) {
+                fail!("--{} requires an input format of csv or tsv", flag);
+            }
+        }
+    }
+    let csv_strict = match (
+        matches.is_present("csv-strict"),
+        matches.value_of("csv-on-error"),
+    ) {
+        (false, None) => None,
+        (false, Some(_)) => fail!("--csv-on-error requires --csv-strict"),
+        (true, None) | (true, Some("abort")) => Some(OnError::Abort),
+        (true, Some("skip")) => Some(OnError::Skip),
+        (true, Some(action)) if action.starts_with("reject:") && action.len() > 7 => {
+            Some(OnError::Reject(String::from(&action[7..])))
+        }
+        (true, Some(action)) => fail!(
+            "invalid --csv-on-error action {:?}; expected abort, skip or reject:FILE",
+            action
+        ),
+    };
+    if let (Some(ifmt), Some(_)) = (&mut ifmt, &csv_strict) {
+        ifmt.strict = true;
+    }
+    // Malformed records are reported by their number within the file, which is not known when
+    // the records of a file are split between workers.
+    if csv_strict.is_some() && matches!(exec_strategy, ExecutionStrategy::ShardPerRecord) {
+        fail!("--csv-strict cannot be used with -pr; use -pf to process files in parallel");
+    }
+    // Input without quotes, escapes or comments can be split more cheaply by a ByteReader.
+    let byte_sep = match ifmt {
+        Some(ifmt)
+            if auto
+                && csv_strict.is_none()
+                && ifmt.quote.is_none()
+                && ifmt.escape == EscapeStyle::Doubled
+                && ifmt.comment.is_none() =>
+        {
+            Some(ifmt.delimiter)
+        }
+        _ => None,
+    };
+    // A header row is not read as a record, so a guess that there is one is always reported.
+    let guessed_header = auto && !matches.is_present("header") && ifmt.map_or(false, |i| i.header);
+    if let (Some(ifmt), true) = (ifmt, matches.is_present("print-dialect") || guessed_header) {
+        let reader = if byte_sep.is_some() { "bytes" } else { "csv" };
+        eprintln!("input format: {} reader={}", ifmt, reader);
+    }
     let (escaper, output_sep, output_record_sep) = match matches.value_of("output-format") {
         Some("csv") => (Escaper::CSV, Some(","), Some("\r\n")),
         Some("tsv") => (Escaper::TSV, Some("\t"), Some("\n")),
+        Some("json") | Some("jsonl") => (Escaper::JSON, None, Some("\n")),
         Some(s) => fail!(
-            "invalid output format {:?}; expected csv or tsv (or the empty string)",
+            "invalid output format {:?}; expected csv, tsv, json or jsonl (or the empty string)",
             s
         ),
         None => (Escaper::Identity, None, None),
     };
+    let (header, header_lens, opened_inputs) = match ifmt {
+        Some(ifmt) if ifmt.header => {
+            let inputs = match opened {
+                Some(opened) => opened
+                    .into_iter()
+                    .map(|(r, name)| (Box::new(move || Ok(r)) as Opener, name))
+                    .collect(),
+                None => lazy_inputs(&input_files),
+            };
+            let (header, header_lens, inputs) = read_headers(inputs, ifmt);
+            (Some(header), header_lens, Some(inputs))
+        }
+        _ => {
+            let inputs = opened.map(|opened| {
+                opened
+                    .into_iter()
+                    .map(|(r, name)| (Box::new(r) as Box<dyn io::Read + Send>, name))
+                    .collect::<Vec<_>>()
+            });
+            (None, batch::HeaderLens::default(), inputs)
+        }
+    };
+    // With JSON output, a rebuilt $0 is keyed by the column names in the header.
+    let names = header.clone().unwrap_or_default();
+    if byte_sep.is_some() {
+        // The CSVReader is not used; only the header was split according to ifmt.
+        ifmt = None;
+    }
     let raw = RawPrelude {
-        field_sep: matches.value_of("field-separator").map(String::from),
+        field_sep: matches
+            .value_of("field-separator")
+            .map(String::from)
+            .or_else(|| byte_sep.map(|b| (b as char).to_string())),
+        field_widths: matches.value_of("field-widths").map(String::from),
         var_decs: matches
             .values_of("var")
             .map(|x| x.map(String::from).collect())
@@ -370,6 +656,9 @@ fn main() {
         output_sep,
         escaper,
         output_record_sep,
+        header,
+        key_paths: jsonl,
+        chars: matches.is_present("chars") || matches.is_present("utf8"),
         stage: exec_strategy.stage(),
     };
     let mut opt_level: i32 = match matches.value_of("opt-level") {
@@ -424,17 +713,59 @@ fn main() {
     // types, making functions hard to write. Still, there must be something to be done to clean
     // this up here.
     macro_rules! with_inp {
-        ($analysis:expr, $inp:ident, $body:expr) => {
-            if input_files.len() == 0 {
+        ($analysis:expr, $field_split:expr, $key_paths:expr, $inp:ident, $body:expr) => {
+            if jsonl {
+                let $inp = JSONReader::new(
+                    open_inputs(&input_files).into_iter(),
+                    $key_paths,
+                    CHUNK_SIZE,
+                    check_utf8,
+                    exec_strategy,
+                );
+                $body
+            } else if let Some(field_sep) = byte_sep {
+                // Inputs are always opened ahead of time by --input-format auto.
+                let $inp = ByteReader::new(
+                    opened_inputs.unwrap().into_iter(),
+                    field_sep,
+                    b'\n',
+                    CHUNK_SIZE,
+                    check_utf8,
+                    exec_strategy,
+                );
+                $body
+            } else if input_files.len() == 0 {
                 let _reader: Box<dyn io::Read + Send> = Box::new(io::stdin());
-                match (ifmt, $analysis) {
-                    (Some(ifmt), _) => {
-                        let $inp = CSVReader::new(
-                            once((_reader, String::from("-"))),
-                            ifmt,
-                            CHUNK_SIZE,
-                            check_utf8,
-                            exec_strategy,
+                match (ifmt, $analysis, $field_split) {
+                    (Some(ifmt), _, _) => {
+                        let $inp = match opened_inputs {
+                            Some(inputs) => CSVReader::new(
+                                inputs.into_iter(),
+                                ifmt,
+                                escaper,
+                                CHUNK_SIZE,
+                                check_utf8,
+                                exec_strategy,
+                            )
+                            .strict(csv_strict.clone(), header_lens.clone())
+                            .header(names.clone()),
+                            None => CSVReader::new(
+                                once((_reader, String::from("-"))),
+                                ifmt,
+                                escaper,
+                                CHUNK_SIZE,
+                                check_utf8,
+                                exec_strategy,
+                            )
+                            .strict(csv_strict.clone(), header_lens.clone())
+                            .header(names.clone()),
+                        };
+                        $body
+                    }
+                    (None, _, Some(field_split)) => {
+                        let $inp = FieldSplitReader::new(
+                            chained(RegexSplitter::new(_reader, CHUNK_SIZE, "-", check_utf8)),
+                            field_split,
                         );
                         $body
                     }
@@ -443,10 +774,14 @@ fn main() {
                         cfg::SepAssign::Potential {
                             field_sep,
                             record_sep,
+                            ..
                         },
+                        None,
                     ) => {
                         let field_sep = field_sep.unwrap_or(" ");
                         let record_sep = record_sep.unwrap_or("\n");
+                        // The byte readers also track RT. Paragraph mode (RS="") and multi-byte
+                        // record separators fall back to RegexSplitter.
                         if field_sep.len() == 1 && record_sep.len() == 1 {
                             if field_sep == " " && record_sep == "\n" {
                                 let $inp = ByteReader::new_whitespace(
@@ -473,32 +808,62 @@ fn main() {
                             $body
                         }
                     }
-                    (None, cfg::SepAssign::Unsure) => {
+                    (None, cfg::SepAssign::Unsure, None) => {
                         let $inp =
                             chained(RegexSplitter::new(_reader, CHUNK_SIZE, "-", check_utf8));
                         $body
                     }
                 }
             } else if let Some(ifmt) = ifmt {
-                let file_handles: Vec<_> = input_files
-                    .iter()
-                    .cloned()
-                    .map(|file| (open_file_read(file.as_str()), file))
-                    .collect();
-                let $inp = CSVReader::new(
-                    file_handles.into_iter(),
-                    ifmt,
-                    CHUNK_SIZE,
-                    check_utf8,
-                    exec_strategy,
-                );
+                let $inp = match opened_inputs {
+                    Some(inputs) => CSVReader::new(
+                        inputs.into_iter(),
+                        ifmt,
+                        escaper,
+                        CHUNK_SIZE,
+                        check_utf8,
+                        exec_strategy,
+                    )
+                    .strict(csv_strict.clone(), header_lens.clone())
+                    .header(names.clone()),
+                    None => {
+                        let file_handles: Vec<_> = input_files
+                            .iter()
+                            .cloned()
+                            .map(|file| (open_file_read(file.as_str()), file))
+                            .collect();
+                        CSVReader::new(
+                            file_handles.into_iter(),
+                            ifmt,
+                            escaper,
+                            CHUNK_SIZE,
+                            check_utf8,
+                            exec_strategy,
+                        )
+                        .strict(csv_strict.clone(), header_lens.clone())
+                        .header(names.clone())
+                    }
+                };
                 $body
             } else {
-                match $analysis {
-                    cfg::SepAssign::Potential {
-                        field_sep,
-                        record_sep,
-                    } => {
+                match ($analysis, $field_split) {
+                    (_, Some(field_split)) => {
+                        let iter = input_files.iter().cloned().map(|file| {
+                            let reader: Box<dyn io::Read + Send> =
+                                Box::new(open_file_read(file.as_str()));
+                            RegexSplitter::new(reader, CHUNK_SIZE, file, check_utf8)
+                        });
+                        let $inp = FieldSplitReader::new(ChainedReader::new(iter), field_split);
+                        $body
+                    }
+                    (
+                        cfg::SepAssign::Potential {
+                            field_sep,
+                            record_sep,
+                            ..
+                        },
+                        None,
+                    ) => {
                         let field_sep = field_sep.unwrap_or(" ");
                         let record_sep = record_sep.unwrap_or("\n");
                         if field_sep.len() == 1 && record_sep.len() == 1 {
@@ -536,7 +901,7 @@ fn main() {
                             $body
                         }
                     }
-                    cfg::SepAssign::Unsure => {
+                    (cfg::SepAssign::Unsure, None) => {
                         let iter = input_files.iter().cloned().map(|file| {
                             let reader: Box<dyn io::Read + Send> =
                                 Box::new(open_file_read(file.as_str()));
@@ -552,7 +917,28 @@ fn main() {
 
     let a = Arena::default();
     let ctx = get_context(program_string.as_str(), &a, get_prelude(&a, &raw));
-    let analysis_result = ctx.analyze_sep_assignments();
+    let analysis_result = match ctx.analyze_sep_assignments() {
+        Ok(res) => res,
+        Err(e) => fail!("{}", e),
+    };
+    // FIELDWIDTHS and FPAT are only honored if they are assigned a constant at the start of the
+    // program (or passed with --field-widths), like the FS and RS analysis above.
+    let field_split = match &analysis_result {
+        cfg::SepAssign::Potential {
+            field_widths,
+            field_pattern,
+            ..
+        } => FieldSplit::new(*field_widths, *field_pattern, raw.chars),
+        cfg::SepAssign::Unsure => FieldSplit::new(raw.field_widths.as_deref(), None, raw.chars),
+    };
+    let field_split = match field_split {
+        Ok(fs) => fs,
+        Err(e) => fail!("{}", e),
+    };
+    if field_split.is_some() && (ifmt.is_some() || jsonl) {
+        fail!("FIELDWIDTHS and FPAT cannot be combined with --input-format");
+    }
+    let key_paths = ctx.key_paths().to_vec();
     let out_file = matches.value_of("out-file");
     macro_rules! with_io {
         (|$inp:ident, $out:ident| $body:expr) => {
@@ -560,11 +946,11 @@ fn main() {
                 Some(oup) => {
                     let $out = runtime::writers::factory_from_file(oup)
                         .unwrap_or_else(|e| fail!("failed to open {}: {}", oup, e));
-                    with_inp!(analysis_result, $inp, $body);
+                    with_inp!(analysis_result, field_split, key_paths, $inp, $body);
                 }
                 None => {
                     let $out = runtime::writers::default_factory();
-                    with_inp!(analysis_result, $inp, $body);
+                    with_inp!(analysis_result, field_split, key_paths, $inp, $body);
                 }
             }
         };
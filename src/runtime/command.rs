@@ -0,0 +1,158 @@
+//! Support for running shell commands, used for `cmd | getline`, `print | cmd` and `system`.
+//!
+//! All commands are run through `/bin/sh -c`, as in other awk implementations.
+use std::io::{self, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, ExitStatus, Stdio};
+use std::thread::JoinHandle;
+
+use crate::runtime::{splitter::regex::RegexSplitter, Int, Str, CHUNK_SIZE};
+
+fn command(cmd: &str) -> Command {
+    let mut res = Command::new("/bin/sh");
+    res.arg("-c").arg(cmd);
+    res
+}
+
+/// Convert the exit status of a child process into the value returned to an awk program: the exit
+/// code if the process exited normally, or 256 plus the signal number if it was killed by a
+/// signal.
+pub(crate) fn exit_status(status: ExitStatus) -> Int {
+    if let Some(code) = status.code() {
+        return code as Int;
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(sig) = status.signal() {
+            return 256 + sig as Int;
+        }
+    }
+    -1
+}
+
+/// Run `cmd` to completion, as in `system(cmd)`, returning its exit status. If `stdout` is None,
+/// the command shares frawk's standard output; otherwise its output is copied to `stdout`.
+pub(crate) fn run(cmd: &str, stdout: Option<&mut dyn Write>) -> io::Result<Int> {
+    let mut c = command(cmd);
+    let status = match stdout {
+        Some(w) => {
+            let mut child = c.stdout(Stdio::piped()).spawn()?;
+            io::copy(child.stdout.as_mut().unwrap(), w)?;
+            w.flush()?;
+            child.wait()?
+        }
+        None => c.status()?,
+    };
+    Ok(exit_status(status))
+}
+
+/// The input end of a pipe to a shell command, as in `print | "sort"`.
+pub struct CommandWriter {
+    child: Child,
+    stdin: Option<ChildStdin>,
+    // If the command's standard output is not inherited from frawk, a thread copies it to its
+    // destination.
+    copier: Option<JoinHandle<io::Result<u64>>>,
+}
+
+impl CommandWriter {
+    /// Spawn `cmd`. If `stdout` is None, the command shares frawk's standard output; otherwise its
+    /// output is copied to `stdout`.
+    pub fn spawn(cmd: &str, stdout: Option<Box<dyn Write + Send>>) -> io::Result<CommandWriter> {
+        let mut c = command(cmd);
+        c.stdin(Stdio::piped());
+        if stdout.is_some() {
+            c.stdout(Stdio::piped());
+        }
+        let mut child = c.spawn()?;
+        let stdin = child.stdin.take();
+        let copier = match (stdout, child.stdout.take()) {
+            (Some(mut w), Some(mut r)) => Some(std::thread::spawn(move || {
+                let res = io::copy(&mut r, &mut w)?;
+                w.flush()?;
+                Ok(res)
+            })),
+            _ => None,
+        };
+        Ok(CommandWriter {
+            child,
+            stdin,
+            copier,
+        })
+    }
+
+    /// Close the command's standard input and wait for it to exit, returning its exit status.
+    pub fn wait(mut self) -> io::Result<Int> {
+        // Dropping stdin sends EOF to the child.
+        self.stdin = None;
+        let status = self.child.wait()?;
+        if let Some(copier) = self.copier.take() {
+            match copier.join() {
+                Ok(res) => {
+                    res?;
+                }
+                Err(_) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Other,
+                        "failed to copy command output",
+                    ))
+                }
+            }
+        }
+        Ok(exit_status(status))
+    }
+
+    fn stdin(&mut self) -> &mut ChildStdin {
+        self.stdin.as_mut().unwrap()
+    }
+}
+
+// The command may exit before reading all of its input (e.g. `head -n1`). Awk programs do not
+// treat that as an error, so we discard any remaining output instead.
+fn ignore_broken_pipe<T>(res: io::Result<T>, default: T) -> io::Result<T> {
+    match res {
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(default),
+        res => res,
+    }
+}
+
+impl Write for CommandWriter {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        ignore_broken_pipe(self.stdin().write(bytes), bytes.len())
+    }
+    fn write_vectored(&mut self, bufs: &[io::IoSlice]) -> io::Result<usize> {
+        let len = bufs.iter().map(|b| b.len()).sum();
+        ignore_broken_pipe(self.stdin().write_vectored(bufs), len)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        ignore_broken_pipe(self.stdin().flush(), ())
+    }
+}
+
+/// The output end of a pipe from a shell command, as in `"date" | getline`.
+pub(crate) struct CommandReader {
+    child: Child,
+    pub(crate) reader: RegexSplitter<ChildStdout>,
+}
+
+impl CommandReader {
+    pub(crate) fn spawn(
+        cmd: &str,
+        name: Str<'static>,
+        check_utf8: bool,
+    ) -> io::Result<CommandReader> {
+        let mut child = command(cmd).stdout(Stdio::piped()).spawn()?;
+        let stdout = child.stdout.take().unwrap();
+        Ok(CommandReader {
+            child,
+            reader: RegexSplitter::new(stdout, CHUNK_SIZE, name, check_utf8),
+        })
+    }
+
+    /// Close the command's standard output and wait for it to exit, returning its exit status.
+    pub(crate) fn wait(self) -> io::Result<Int> {
+        let CommandReader { mut child, reader } = self;
+        std::mem::drop(reader);
+        Ok(exit_status(child.wait()?))
+    }
+}
@@ -1,4 +1,4 @@
-use crate::common::{Either, Result};
+use crate::common::{Either, FileSpec, Result};
 use hashbrown::HashMap;
 use regex::bytes::Regex;
 use std::cell::{Cell, RefCell};
@@ -9,15 +9,19 @@ use std::iter::FromIterator;
 use std::rc::Rc;
 use std::str;
 
+pub mod command;
 pub mod float_parse;
 pub mod printf;
+pub mod sort;
 pub mod splitter;
 pub mod str_impl;
 pub mod string_search;
+pub mod time;
 pub mod utf8;
 pub mod writers;
 
 use crate::pushdown::FieldSet;
+use command::CommandReader;
 use splitter::regex::RegexSplitter;
 
 // TODO: remove the pub use for Variables here.
@@ -26,6 +30,7 @@ pub(crate) use float_parse::{hextoi, strtod, strtoi};
 pub(crate) use printf::FormatArg;
 pub use splitter::{
     batch::{escape_csv, escape_tsv},
+    json::{escape_json, float_to_json, join_json, json_path},
     ChainedReader, Line, LineReader,
 };
 pub use str_impl::{Str, UniqueStr};
@@ -159,10 +164,23 @@ impl<T: Default> LazyVec<T> {
             return;
         };
     }
+    pub(crate) fn resize(&mut self, len: usize) {
+        match self {
+            Either::Left(v) => v.resize_with(len, Default::default),
+            Either::Right(m) => {
+                let mut m = m.0.borrow_mut();
+                m.retain(|ix, _| *ix < len as Int);
+                if len > 0 {
+                    m.entry(len as Int - 1).or_insert_with(Default::default);
+                }
+            }
+        }
+    }
 }
 
+// The second registry holds patterns for `patsplit`, which matches leftmost-longest.
 #[derive(Default)]
-pub struct RegexCache(Registry<Regex>);
+pub struct RegexCache(Registry<Regex>, Registry<splitter::fpat::FieldPattern>);
 
 impl RegexCache {
     pub(crate) fn with_regex<T>(&mut self, pat: &Str, mut f: impl FnMut(&Regex) -> T) -> Result<T> {
@@ -179,38 +197,59 @@ impl RegexCache {
     // TODO: build the same path and implement handling for LLVM (no polymorphism, just do an
     // Either<> of either the CSV or legacy paths).
     // TODO: add tests for the CSV path (including plumbing in harness to get all of that working).
+    //
+    // Each of these functions reads a record separated by RS and updates RT.
     pub(crate) fn get_line<'a, LR: LineReader>(
         &mut self,
         file: &Str<'a>,
-        pat: &Str<'a>,
+        vars: &mut Variables<'a>,
         reg: &mut FileRead<LR>,
     ) -> Result<Str<'a>> {
-        Ok(reg
-            .with_file(file, |reader| {
-                self.with_regex(pat, |re| reader.read_line_regex(re))
-            })?
-            .clone()
-            .upcast())
+        let pat = &vars.rs;
+        let (line, rt): (Str<'static>, Str<'static>) = reg.with_file(file, |reader| {
+            self.with_regex(pat, |re| (reader.read_line_regex(re), reader.last_rt()))
+        })?;
+        vars.rt = rt.upcast();
+        Ok(line.upcast())
+    }
+
+    pub(crate) fn get_line_cmd<'a, LR: LineReader>(
+        &mut self,
+        cmd: &Str<'a>,
+        vars: &mut Variables<'a>,
+        reg: &mut FileRead<LR>,
+    ) -> Result<Str<'a>> {
+        let pat = &vars.rs;
+        let (line, rt): (Str<'static>, Str<'static>) = reg.with_cmd(cmd, |reader| {
+            self.with_regex(pat, |re| (reader.read_line_regex(re), reader.last_rt()))
+        })?;
+        vars.rt = rt.upcast();
+        Ok(line.upcast())
     }
 
     // This only gets used if getline is invoked explicitly without an input file argument.
     pub(crate) fn get_line_stdin<'a, LR: LineReader>(
         &mut self,
-        pat: &Str<'a>,
+        vars: &mut Variables<'a>,
         reg: &mut FileRead<LR>,
     ) -> Result<(/* file changed */ bool, Str<'a>)> {
+        let pat = &vars.rs;
         let (changed, mut line) = reg.stdin.read_line(pat, self)?;
         // NB both of these `pat`s are "wrong" but we are fine because they are only used
         // when the column is nonzero, or someone has overwritten a nonzero column.
-        Ok((changed, line.get_col(0, pat, pat, self)?.clone().upcast()))
+        let res = line.get_col(0, pat, pat, self)?.clone().upcast();
+        vars.rt = reg.stdin.last_rt().upcast();
+        Ok((changed, res))
     }
     pub(crate) fn get_line_stdin_reuse<'a, LR: LineReader>(
         &mut self,
-        pat: &Str<'a>,
+        vars: &mut Variables<'a>,
         reg: &mut FileRead<LR>,
         old_line: &mut LR::Line,
     ) -> Result</*file changed */ bool> {
-        reg.stdin.read_line_reuse(pat, self, old_line)
+        let changed = reg.stdin.read_line_reuse(&vars.rs, self, old_line)?;
+        vars.rt = reg.stdin.last_rt().upcast();
+        Ok(changed)
     }
     fn split_internal<'a>(
         &mut self,
@@ -220,20 +259,7 @@ impl RegexCache {
         mut push: impl FnMut(Str<'a>),
     ) -> Result<()> {
         if pat == &Str::from(" ") {
-            self.with_regex(&Str::from(r#"[ \t]+"#), |re| {
-                s.split(
-                    re,
-                    |s, is_empty| {
-                        if !is_empty {
-                            push(s);
-                            1
-                        } else {
-                            0
-                        }
-                    },
-                    used_fields,
-                )
-            })
+            self.split_skip_empty(&Str::from(r#"[ \t]+"#), s, used_fields, push)
         } else {
             self.with_regex(pat, |re| {
                 s.split(
@@ -247,6 +273,48 @@ impl RegexCache {
             })
         }
     }
+    // Split `s` by `pat`, ignoring empty fields. This is used for the default FS of " ".
+    fn split_skip_empty<'a>(
+        &mut self,
+        pat: &Str,
+        s: &Str<'a>,
+        used_fields: &FieldSet,
+        mut push: impl FnMut(Str<'a>),
+    ) -> Result<()> {
+        self.with_regex(pat, |re| {
+            s.split(
+                re,
+                |s, is_empty| {
+                    if !is_empty {
+                        push(s);
+                        1
+                    } else {
+                        0
+                    }
+                },
+                used_fields,
+            )
+        })
+    }
+    // Split a record read in "paragraph mode" (RS=""). Newlines separate fields in addition to
+    // FS, so long as FS is a single character; as in gawk, regex field separators are left alone.
+    pub(crate) fn split_regex_paragraph<'a>(
+        &mut self,
+        pat: &Str,
+        s: &Str<'a>,
+        used_fields: &FieldSet,
+        v: &mut LazyVec<Str<'a>>,
+    ) -> Result<()> {
+        if pat == &Str::from(" ") {
+            return self.split_skip_empty(&Str::from(r#"[ \t\n]+"#), s, used_fields, |s| v.push(s));
+        }
+        let para_pat = pat.with_bytes(|bs| match str::from_utf8(bs) {
+            Ok(c) if c.chars().count() == 1 => Some(Str::from(format!("[{}\n]", regex::escape(c)))),
+            _ => None,
+        });
+        let pat = para_pat.as_ref().unwrap_or(pat);
+        self.split_internal(pat, s, used_fields, |s| v.push(s))
+    }
     pub(crate) fn split_regex<'a>(
         &mut self,
         pat: &Str,
@@ -283,6 +351,27 @@ impl RegexCache {
         })
     }
 
+    // Store the matches of `pat` in `s` in `arr`, and the text around them in `seps`, returning the
+    // number of matches. Both maps are cleared first.
+    pub(crate) fn patsplit_intmap<'a>(
+        &mut self,
+        pat: &Str<'a>,
+        s: &Str<'a>,
+        arr: &IntMap<Str<'a>>,
+        seps: &IntMap<Str<'a>>,
+    ) -> Result<Int> {
+        arr.clear();
+        seps.clear();
+        self.1.get(pat, splitter::fpat::FieldPattern::new, |re| {
+            splitter::fpat::pattern_split(
+                re,
+                s,
+                |i, f| arr.insert(i, f),
+                |i, sep| seps.insert(i, sep),
+            )
+        })
+    }
+
     pub(crate) fn regex_match_loc(
         &mut self,
         vars: &mut Variables,
@@ -306,6 +395,76 @@ impl RegexCache {
         Ok(start)
     }
 
+    // Like `regex_match_loc`, but also calls `f` with the index, start and length of each capture
+    // group (0 being the entire match) that participates in the leftmost match of `pat`.
+    fn regex_match_groups(
+        &mut self,
+        vars: &mut Variables,
+        pat: &Str,
+        s: &Str,
+        mut f: impl FnMut(Int, Int, Int),
+    ) -> Result<Int> {
+        use crate::builtins::Variable;
+        let (start, len) = self.with_regex(pat, |re| {
+            s.with_bytes(|bs| match re.captures(bs) {
+                Some(caps) => {
+                    for (i, m) in caps.iter().enumerate() {
+                        if let Some(m) = m {
+                            f(i as Int, m.start() as Int + 1, (m.end() - m.start()) as Int);
+                        }
+                    }
+                    let m = caps.get(0).unwrap();
+                    (m.start() as Int + 1, (m.end() - m.start()) as Int)
+                }
+                None => (0, -1),
+            })
+        })?;
+        vars.store_int(Variable::RSTART, start)?;
+        vars.store_int(Variable::RLENGTH, len)?;
+        Ok(start)
+    }
+
+    /// The 3-argument form of `match`: `m` is cleared and then assigned the text of each capture
+    /// group in the match.
+    pub(crate) fn regex_match_loc_intmap<'a>(
+        &mut self,
+        vars: &mut Variables,
+        pat: &Str,
+        s: &Str<'a>,
+        m: &IntMap<Str<'a>>,
+    ) -> Result<Int> {
+        m.clear();
+        self.regex_match_groups(vars, pat, s, |i, start, len| {
+            let start = start as usize - 1;
+            m.insert(i, s.slice(start, start + len as usize));
+        })
+    }
+
+    /// Like `regex_match_loc_intmap`, but with string keys, `m` also records the start and length
+    /// of group `i` under the keys `i SUBSEP "start"` and `i SUBSEP "length"`, as in gawk.
+    pub(crate) fn regex_match_loc_strmap<'a>(
+        &mut self,
+        vars: &mut Variables<'a>,
+        pat: &Str,
+        s: &Str<'a>,
+        m: &StrMap<'a, Str<'a>>,
+    ) -> Result<Int> {
+        m.clear();
+        let subsep = vars.subsep.clone();
+        let key = |i: Int, suffix: &'static str| {
+            Str::concat(
+                Str::concat(convert::<Int, Str>(i), subsep.clone()),
+                suffix.into(),
+            )
+        };
+        self.regex_match_groups(vars, pat, s, |i, start, len| {
+            let off = start as usize - 1;
+            m.insert(convert::<Int, Str>(i), s.slice(off, off + len as usize));
+            m.insert(key(i, "start"), convert::<Int, Str>(start));
+            m.insert(key(i, "length"), convert::<Int, Str>(len));
+        })
+    }
+
     pub(crate) fn is_regex_match(&mut self, pat: &Str, s: &Str) -> Result<bool> {
         self.with_regex(pat, |re| s.with_bytes(|bs| re.is_match(bs)))
     }
@@ -324,8 +483,11 @@ impl FileWrite {
     pub(crate) fn flush_stdout(&mut self) -> Result<()> {
         self.0.get_handle(None)?.flush()
     }
-    pub(crate) fn close(&mut self, path: &Str) -> Result<()> {
-        self.0.get_handle(Some(path))?.close()
+    pub(crate) fn close(&mut self, path: &Str) -> Result<Int> {
+        self.0.close(path)
+    }
+    pub(crate) fn system(&mut self, cmd: &Str) -> Result<Int> {
+        self.0.system(cmd)
     }
     pub(crate) fn new(ff: impl writers::FileFactory) -> FileWrite {
         FileWrite(writers::Registry::from_factory(ff))
@@ -335,19 +497,27 @@ impl FileWrite {
         self.0.destroy_and_flush_all_files()
     }
 
+    fn handle(&mut self, path: &Str, spec: FileSpec) -> Result<&mut writers::FileHandle> {
+        match spec {
+            FileSpec::Cmd => self.0.get_cmd_handle(path),
+            FileSpec::Trunc | FileSpec::Append => self.0.get_handle(Some(path)),
+        }
+    }
+
     pub(crate) fn printf(
         &mut self,
-        path: Option<(&Str, bool)>,
+        path: Option<(&Str, FileSpec)>,
         spec: &Str,
         pa: &[printf::FormatArg],
+        chars: bool,
     ) -> Result<()> {
-        let (handle, append) = if let Some((out_file, append)) = path {
-            (self.0.get_handle(Some(out_file))?, append)
+        let (handle, append) = if let Some((out_file, fspec)) = path {
+            (self.handle(out_file, fspec)?, fspec == FileSpec::Append)
         } else {
             (self.0.get_handle(None)?, true)
         };
         let mut text = str_impl::DynamicBuf::default();
-        spec.with_bytes(|spec| printf::printf(&mut text, spec, pa))?;
+        spec.with_bytes(|spec| printf::printf(&mut text, spec, pa, chars))?;
         let s = unsafe { text.into_str() };
         handle.write(&s, append)
     }
@@ -356,15 +526,28 @@ impl FileWrite {
         self.0.get_handle(None)?.write(s, /*append=*/ true)
     }
 
-    pub(crate) fn write_str(&mut self, path: &Str, s: &Str, append: bool) -> Result<()> {
-        self.0.get_handle(Some(path))?.write(s, append)
+    pub(crate) fn write_str(&mut self, path: &Str, s: &Str, spec: FileSpec) -> Result<()> {
+        self.handle(path, spec)?.write(s, spec == FileSpec::Append)
     }
 }
 
+/// Close `path` as both an input and an output, returning the value of a call to `close(path)`:
+/// the exit status of a command, 0 for a file, or -1 if nothing named `path` was open.
+pub(crate) fn close_file<LR: LineReader>(
+    write: &mut FileWrite,
+    read: &mut FileRead<LR>,
+    path: &Str,
+) -> Result<Int> {
+    let w = write.close(path)?;
+    let r = read.close(path)?;
+    Ok(if w != -1 { w } else { r })
+}
+
 pub const CHUNK_SIZE: usize = 8 << 10;
 
 pub(crate) struct FileRead<LR = RegexSplitter<Box<dyn io::Read + Send>>> {
     pub(crate) files: Registry<RegexSplitter<File>>,
+    cmds: Registry<CommandReader>,
     stdin: LR,
 }
 
@@ -376,22 +559,33 @@ impl<LR: LineReader> FileRead<LR> {
             .map(|x| {
                 move || FileRead {
                     files: Default::default(),
+                    cmds: Default::default(),
                     stdin: x(),
                 }
             })
             .collect()
     }
 
-    pub(crate) fn close(&mut self, path: &Str) {
-        self.files.remove(path);
+    pub(crate) fn close(&mut self, path: &Str) -> Result<Int> {
+        let file = self.files.remove(path).map(|_| 0);
+        let cmd = match self.cmds.remove(path) {
+            Some(c) => match c.wait() {
+                Ok(status) => Some(status),
+                Err(e) => return err!("failed to close command: {}", e),
+            },
+            None => None,
+        };
+        Ok(cmd.or(file).unwrap_or(-1))
     }
 
-    pub(crate) fn new(stdin: LR, used_fields: &FieldSet) -> FileRead<LR> {
+    pub(crate) fn new(stdin: LR, used_fields: &FieldSet, writers: &FileWrite) -> FileRead<LR> {
         let mut res = FileRead {
             files: Default::default(),
+            cmds: Default::default(),
             stdin,
         };
         res.stdin.set_used_fields(used_fields);
+        res.stdin.set_writers(&writers.0);
         res
     }
 
@@ -407,6 +601,10 @@ impl<LR: LineReader> FileRead<LR> {
         self.with_file(path, |reader| Ok(reader.read_state()))
     }
 
+    pub(crate) fn read_err_cmd<'a>(&mut self, cmd: &Str<'a>) -> Result<Int> {
+        self.with_cmd(cmd, |reader| Ok(reader.read_state()))
+    }
+
     pub(crate) fn next_file(&mut self) -> Result<()> {
         let _ = self.stdin.next_file()?;
         Ok(())
@@ -432,6 +630,22 @@ impl<LR: LineReader> FileRead<LR> {
             f,
         )
     }
+
+    fn with_cmd<'a, R>(
+        &mut self,
+        cmd: &Str<'a>,
+        mut f: impl FnMut(&mut RegexSplitter<std::process::ChildStdout>) -> Result<R>,
+    ) -> Result<R> {
+        let check_utf8 = self.stdin.check_utf8();
+        self.cmds.get_fallible(
+            cmd,
+            |s| match CommandReader::spawn(s, cmd.clone().unmoor(), check_utf8) {
+                Ok(c) => Ok(c),
+                Err(e) => err!("failed to run command '{}': {}", s, e),
+            },
+            |c| f(&mut c.reader),
+        )
+    }
 }
 
 pub(crate) struct Registry<T> {
@@ -452,8 +666,8 @@ impl<T> Default for Registry<T> {
 }
 
 impl<T> Registry<T> {
-    fn remove(&mut self, s: &Str) {
-        self.cached.remove(&s.clone().unmoor());
+    fn remove(&mut self, s: &Str) -> Option<T> {
+        self.cached.remove(&s.clone().unmoor())
     }
     fn get<R>(
         &mut self,
@@ -518,6 +732,24 @@ impl<'a> Convert<Float, Str<'a>> for _Carrier {
         f.into()
     }
 }
+/// The default value of `CONVFMT` and `OFMT`.
+pub(crate) const DEFAULT_NUM_FMT: &str = "%.6g";
+
+/// Convert `f` to a string according to `fmt`, the value of `CONVFMT` or `OFMT`. Integral values
+/// are converted as integers. If `fmt` has its default value, we fall back to the faster
+/// conversion used by `convert`.
+pub(crate) fn float_to_str_fmt<'a>(f: Float, fmt: &Str) -> Result<Str<'a>> {
+    if fmt.with_bytes(|bs| bs == DEFAULT_NUM_FMT.as_bytes()) {
+        return Ok(convert::<_, Str>(f));
+    }
+    if f.fract() == 0.0 && f.abs() < (1u64 << 53) as Float {
+        return Ok(convert::<_, Str>(f as Int));
+    }
+    let mut text = str_impl::DynamicBuf::default();
+    fmt.with_bytes(|fmt| printf::printf(&mut text, fmt, &[printf::FormatArg::F(f)], false))?;
+    Ok(unsafe { text.into_str() })
+}
+
 impl<'a> Convert<Str<'a>, Float> for _Carrier {
     fn convert(s: Str<'a>) -> Float {
         s.with_bytes(strtod)
@@ -574,6 +806,9 @@ impl<K: Hash + Eq, V> SharedMap<K, V> {
     pub(crate) fn delete(&self, k: &K) {
         self.0.borrow_mut().remove(k);
     }
+    pub(crate) fn clear(&self) {
+        self.0.borrow_mut().clear();
+    }
     pub(crate) fn iter<'a, F, R>(&'a self, f: F) -> R
     where
         F: FnOnce(hashbrown::hash_map::Iter<K, V>) -> R,
@@ -587,13 +822,14 @@ impl<K: Hash + Eq, V: Clone> SharedMap<K, V> {
         self.0.borrow().get(k).cloned()
     }
 }
-impl<K: Hash + Eq + Clone, V> SharedMap<K, V> {
-    pub(crate) fn to_iter(&self) -> Iter<K> {
-        self.0.borrow().keys().cloned().collect()
+impl<K: Hash + Eq + sort::SortKey, V> SharedMap<K, V> {
+    pub(crate) fn to_iter(&self, order: sort::SortOrder) -> Iter<K> {
+        self.to_vec(order).into_iter().collect()
     }
-    #[cfg(feature = "llvm_backend")]
-    pub(crate) fn to_vec(&self) -> Vec<K> {
-        self.0.borrow().keys().cloned().collect()
+    pub(crate) fn to_vec(&self, order: sort::SortOrder) -> Vec<K> {
+        let mut keys: Vec<K> = self.0.borrow().keys().cloned().collect();
+        order.sort(&mut keys);
+        keys
     }
 }
 
@@ -3,14 +3,13 @@
 //! We lean heavily on ryu and the std::fmt machinery; as such, most of the work is parsing
 //! awk-style format strings and translating them to individual calls to write!.
 //!
-//! TODO: Originally, frawk enforced that all Strs contained valid UTF-8. We have since allowed
-//! strings to contain arbitrary byte sequences, but this module will eagerly replace invalid UTF8
-//! byte sequences with REPLACEMENT CHARACTER using String's from_utf8_lossy function. This means
-//! that users hoping to output raw bytes using `printf` (as may be necessary, given that print
-//! appends a newline) may find some bytes replaced inadvertently. We could solve this by adding a
-//! new print function that does not append a newline.
+//! Strings may contain arbitrary bytes, so `%s` writes its argument out unchanged, and `%s` and `%c`
+//! (which writes a character code as UTF-8) handle padding themselves. Widths and precisions for
+//! these conversions count bytes, unless `--chars` is set, in which case they count characters in
+//! valid UTF-8 strings. Precisions never split a character of a valid UTF-8 string.
 use crate::common::Result;
-use crate::runtime::{convert, strtoi, Float, Int, Str};
+use crate::runtime::{convert, utf8, Float, Int, Str};
+use memchr::memchr;
 
 use std::convert::TryFrom;
 use std::fmt;
@@ -38,13 +37,6 @@ impl Write for StackWriter {
     }
 }
 
-struct DisplayBytes<'a>(&'a [u8]);
-impl<'a> fmt::Display for DisplayBytes<'a> {
-    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-        fmt::Display::fmt(&*std::string::String::from_utf8_lossy(self.0), fmt)
-    }
-}
-
 #[derive(Clone, Debug)]
 pub(crate) enum FormatArg<'a> {
     S(Str<'a>),
@@ -114,14 +106,24 @@ impl<'a> FormatArg<'a> {
 struct FormatSpec {
     // leading '-' ? -- left justification.
     minus: bool,
-    // number to the left of '.', if any
+    // pad with zeros rather than spaces.
     leading_zeros: bool,
+    // '+': always print a sign for signed conversions.
+    plus: bool,
+    // ' ': print a space in place of a '+' sign.
+    space: bool,
+    // '#': "alternate form"; prefix octal numbers with 0 and hex numbers with 0x.
+    alt: bool,
+    // '\'': group the integer part of decimal conversions into thousands.
+    group: bool,
     // padding
     lnum: usize,
     // maximum string width, or floating point precision.
     rnum: usize,
     // format specifier: e.g. c, d, s, x.
     spec: u8,
+    // whether widths and precisions for %s and %c count characters rather than bytes.
+    chars: bool,
 }
 
 impl Default for FormatSpec {
@@ -129,111 +131,291 @@ impl Default for FormatSpec {
         FormatSpec {
             minus: false,
             leading_zeros: false,
+            plus: false,
+            space: false,
+            alt: false,
+            group: false,
             lnum: 0,
             rnum: usize::max_value(),
             spec: b'z', /* invalid */
+            chars: false,
         }
     }
 }
 
+impl FormatSpec {
+    fn has_precision(&self) -> bool {
+        self.rnum != usize::max_value()
+    }
+}
+
 fn is_spec(c: u8) -> bool {
     match c {
-        b'f' | b'c' | b'd' | b'e' | b'g' | b'o' | b's' | b'x' => true,
+        b'd' | b'i' | b'o' | b'u' | b'x' | b'X' | b'e' | b'E' | b'f' | b'F' | b'g' | b'G'
+        | b'c' | b's' => true,
         _ => false,
     }
 }
 
-fn process_spec(mut w: impl Write, fspec: &mut FormatSpec, arg: &FormatArg) -> Result<()> {
-    macro_rules! match_for_spec {
-        ($s:expr, $arg:expr) => {
-            match (
-                fspec.minus,
-                fspec.leading_zeros,
-                fspec.lnum,
-                fspec.rnum == usize::max_value(),
-            ) {
-                (true, true, lnum, true) => write!(w, concat!("{:0<l$", $s, "}"), $arg, l = lnum),
-                (true, false, lnum, true) => write!(w, concat!("{:<l$", $s, "}"), $arg, l = lnum),
-                (true, true, lnum, false) => write!(
-                    w,
-                    concat!("{:0<l$.r$", $s, "}"),
-                    $arg,
-                    l = lnum,
-                    r = fspec.rnum
-                ),
-                (true, false, lnum, false) => write!(
-                    w,
-                    concat!("{:<l$.r$", $s, "}"),
-                    $arg,
-                    l = lnum,
-                    r = fspec.rnum
-                ),
-                (false, true, lnum, true) => write!(w, concat!("{:0>l$", $s, "}"), $arg, l = lnum),
-                (false, false, lnum, true) => write!(w, concat!("{:>l$", $s, "}"), $arg, l = lnum),
-                (false, true, lnum, false) => write!(
-                    w,
-                    concat!("{:0>l$.r$", $s, "}"),
-                    $arg,
-                    l = lnum,
-                    r = fspec.rnum
-                ),
-                (false, false, lnum, false) => write!(
-                    w,
-                    concat!("{:>l$.r$", $s, "}"),
-                    $arg,
-                    l = lnum,
-                    r = fspec.rnum
-                ),
-            }
-        };
+const SPACES: &'static [u8] = b"                                ";
+const ZEROS: &'static [u8] = b"00000000000000000000000000000000";
+
+fn write_fill(mut w: impl Write, fill: &'static [u8], mut n: usize) -> Result<()> {
+    while n > 0 {
+        let m = std::cmp::min(n, fill.len());
+        write_bytes(&mut w, &fill[..m])?;
+        n -= m;
+    }
+    Ok(())
+}
+
+// Write `prefix` (a sign or "0x") followed by `body`, padded out to the width in `fspec`. `len`
+// is the width of the output without padding. Zero padding goes between the prefix and the body.
+fn write_padded(
+    mut w: impl Write,
+    fspec: &FormatSpec,
+    prefix: &[u8],
+    body: &[u8],
+    len: usize,
+    zero_pad: bool,
+) -> Result<()> {
+    let pad = fspec.lnum.saturating_sub(len);
+    if fspec.minus {
+        write_bytes(&mut w, prefix)?;
+        write_bytes(&mut w, body)?;
+        write_fill(&mut w, SPACES, pad)
+    } else if zero_pad && fspec.leading_zeros {
+        write_bytes(&mut w, prefix)?;
+        write_fill(&mut w, ZEROS, pad)?;
+        write_bytes(&mut w, body)
+    } else {
+        write_fill(&mut w, SPACES, pad)?;
+        write_bytes(&mut w, prefix)?;
+        write_bytes(&mut w, body)
+    }
+}
+
+// Insert commas between each group of three digits in the leading run of digits in `body`.
+fn group_thousands(body: &mut SmallVec<u8>) {
+    let ndigits = body.iter().take_while(|b| b.is_ascii_digit()).count();
+    if ndigits <= 3 {
+        return;
+    }
+    let mut res = SmallVec::with_capacity(body.len() + ndigits / 3);
+    for (i, b) in body[..ndigits].iter().enumerate() {
+        if i > 0 && (ndigits - i) % 3 == 0 {
+            res.push(b',');
+        }
+        res.push(*b);
+    }
+    res.extend_from_slice(&body[ndigits..]);
+    *body = res;
+}
+
+// Write `f` in scientific notation with `prec` digits after the decimal point, returning the
+// exponent. Like C, the exponent has a sign and at least two digits.
+fn write_exp(w: &mut StackWriter, f: Float, prec: usize, alt: bool) -> Result<i32> {
+    let mut buf = StackWriter::default();
+    wrap_result(write!(&mut buf, "{:.*e}", prec, f))?;
+    // Rust writes the exponent without padding, and only includes a sign if it is negative.
+    let e_ix = buf.0.iter().position(|b| *b == b'e').unwrap_or(buf.len());
+    let exp: i32 = str::from_utf8(&buf.0[e_ix + 1..])
+        .ok()
+        .and_then(|e| e.parse().ok())
+        .unwrap_or(0);
+    write_bytes(&mut *w, &buf.0[..e_ix])?;
+    if alt && prec == 0 {
+        write_bytes(&mut *w, b".")?;
+    }
+    let sign = if exp < 0 { '-' } else { '+' };
+    wrap_result(write!(w, "e{}{:02}", sign, exp.unsigned_abs()))?;
+    Ok(exp)
+}
+
+// Remove trailing zeros from the fractional part of the number in `body`, along with the decimal
+// point if no digits follow it. Any exponent is kept.
+fn strip_zeros(body: &mut SmallVec<u8>) {
+    let end = body.iter().position(|b| *b == b'e').unwrap_or(body.len());
+    if !body[..end].contains(&b'.') {
+        return;
+    }
+    let mut last = end;
+    while body[last - 1] == b'0' {
+        last -= 1;
+    }
+    if body[last - 1] == b'.' {
+        last -= 1;
     }
-    let res = match fspec.spec {
-        b'f' => {
-            if !fspec.leading_zeros && fspec.lnum == 0 && fspec.rnum == usize::max_value() {
+    let exp: SmallVec<u8> = body[end..].iter().cloned().collect();
+    body.truncate(last);
+    body.extend_from_slice(&exp[..]);
+}
+
+// Write the digits of a (nonnegative) floating point number according to `spec`, which is one of
+// 'f', 'e' or 'g'. `%e` and `%g` follow C; `%f` without a precision prints the shortest
+// representation of the number.
+fn float_body(w: &mut StackWriter, fspec: &FormatSpec, spec: u8, f: Float) -> Result<()> {
+    if !f.is_finite() {
+        return write_bytes(w, if f.is_nan() { b"nan" } else { b"inf" });
+    }
+    let prec = fspec.rnum;
+    let res = match (spec, fspec.has_precision()) {
+        (b'f', false) => {
+            if !fspec.leading_zeros && fspec.lnum == 0 {
                 // Fast path: use Ryu, which today is more efficient than the standard library.
                 // NB Ryu prints some things a bit differently than most awk implementations.
                 // `write!(w, "{}", arg.to_float())` is a bit closer.
                 let mut buf = ryu::Buffer::new();
-                write!(w, "{}", buf.format(arg.to_float()))
+                write!(w, "{}", buf.format(f))
             } else {
-                match_for_spec!("", arg.to_float())
+                write!(w, "{}", f)
             }
         }
-        b'e' => match_for_spec!("e", arg.to_float()),
-        b'g' => {
+        (b'f', true) => write!(w, "{:.*}", prec, f),
+        (b'e', _) => {
+            let prec = if fspec.has_precision() { prec } else { 6 };
+            return write_exp(w, f, prec, fspec.alt).map(|_| ());
+        }
+        (b'g', _) => {
+            // %g uses scientific notation if the exponent is less than -4, or at least the
+            // precision (the number of significant digits), and standard notation otherwise.
+            // Trailing zeros are removed unless the '#' flag is given.
+            let p = match prec {
+                0 => 1,
+                p if fspec.has_precision() => p,
+                _ => 6,
+            };
             let mut buf = StackWriter::default();
-            // %g means "pick the shorter of standard and scientific notation". We do the obvious
-            // thing of computing both and writing out the smaller one.
-            fspec.spec = b'f';
-            process_spec(&mut buf, fspec, arg)?;
-            let l1 = buf.len();
-            fspec.spec = b'e';
-            process_spec(&mut buf, fspec, arg)?;
-            let l2 = buf.len() - l1;
-            let bytes = if l1 < l2 {
-                &buf.0[0..l1]
+            let exp = write_exp(&mut buf, f, p - 1, fspec.alt)?;
+            if exp < -4 || exp >= p as i32 {
+                if !fspec.alt {
+                    strip_zeros(&mut buf.0);
+                }
             } else {
-                &buf.0[l1..(l1 + l2)]
+                buf.0.clear();
+                let prec = (p as i32 - 1 - exp) as usize;
+                wrap_result(write!(&mut buf, "{:.*}", prec, f))?;
+                if fspec.alt && prec == 0 {
+                    write_bytes(&mut buf, b".")?;
+                } else if !fspec.alt {
+                    strip_zeros(&mut buf.0);
+                }
+            }
+            return write_bytes(w, &buf.0[..]);
+        }
+        _ => unreachable!(),
+    };
+    wrap_result(res)
+}
+
+fn process_spec(mut w: impl Write, fspec: &FormatSpec, arg: &FormatArg) -> Result<()> {
+    let mut body = StackWriter::default();
+    let mut prefix: &[u8] = b"";
+    let sign = |neg: bool| -> &'static [u8] {
+        if neg {
+            b"-"
+        } else if fspec.plus {
+            b"+"
+        } else if fspec.space {
+            b" "
+        } else {
+            b""
+        }
+    };
+    let zero_pad = match fspec.spec {
+        b'd' | b'i' | b'u' | b'o' | b'x' | b'X' => {
+            let i = arg.to_int();
+            let (neg, u) = match fspec.spec {
+                b'd' | b'i' => (i < 0, i.unsigned_abs()),
+                // Like C, treat the argument as unsigned.
+                _ => (false, i as u64),
             };
-            return write_bytes(&mut w, bytes);
+            // The precision is the minimum number of digits to print.
+            if !(fspec.rnum == 0 && u == 0) {
+                let mut digits = StackWriter::default();
+                wrap_result(match fspec.spec {
+                    b'o' => write!(&mut digits, "{:o}", u),
+                    b'x' => write!(&mut digits, "{:x}", u),
+                    b'X' => write!(&mut digits, "{:X}", u),
+                    _ => write!(&mut digits, "{}", u),
+                })?;
+                if fspec.has_precision() {
+                    write_fill(&mut body, ZEROS, fspec.rnum.saturating_sub(digits.len()))?;
+                }
+                write_bytes(&mut body, &digits.0[..])?;
+            }
+            match fspec.spec {
+                b'd' | b'i' => prefix = sign(neg),
+                b'o' if fspec.alt && body.0.first() != Some(&b'0') => prefix = b"0",
+                b'x' if fspec.alt && u != 0 => prefix = b"0x",
+                b'X' if fspec.alt && u != 0 => prefix = b"0X",
+                _ => {}
+            }
+            if fspec.group && matches!(fspec.spec, b'd' | b'i' | b'u') {
+                group_thousands(&mut body.0);
+            }
+            // Like C, ignore the '0' flag for integers if a precision is given.
+            !fspec.has_precision()
+        }
+        b'e' | b'E' | b'f' | b'F' | b'g' | b'G' => {
+            let f = arg.to_float();
+            let lower = fspec.spec.to_ascii_lowercase();
+            float_body(&mut body, fspec, lower, f.abs())?;
+            if lower != fspec.spec {
+                body.0.make_ascii_uppercase();
+            }
+            if fspec.group && lower != b'e' {
+                group_thousands(&mut body.0);
+            }
+            prefix = sign(f.is_sign_negative() && !f.is_nan());
+            f.is_finite()
         }
-        b'd' => match_for_spec!("", arg.to_int()),
-        b'o' => match_for_spec!("o", arg.to_int()),
-        b'x' => match_for_spec!("x", arg.to_int()),
         b'c' => {
-            // First, see if we have something ascii/UTF8 here
-            match char::try_from(arg.to_int() as u32) {
-                Ok(ch) => match_for_spec!("", ch),
-                // TODO: Unclear what we should do here, write out the raw bytes? write out the
-                // character code? Awk may just write the raw bytes out, but it's hard to say
-                // (different behavior across implementations)
-                _ => match_for_spec!("", "?"),
+            if let FormatArg::S(_) = arg {
+                // Like other Awks, a string argument writes its first character: its first byte,
+                // or its first UTF-8 character with `chars` set.
+                return arg.with_bytes(|bs| {
+                    let (end, len) = if fspec.chars {
+                        let end = utf8::byte_offset(bs, 1);
+                        (end, std::cmp::min(end, 1))
+                    } else {
+                        let end = std::cmp::min(bs.len(), 1);
+                        (end, end)
+                    };
+                    write_padded(&mut w, fspec, b"", &bs[..end], len, true)
+                });
             }
+            let code = arg.to_int();
+            let mut buf = [0u8; 4];
+            let bs: &[u8] = match char::try_from(code as u32) {
+                Ok(ch) => ch.encode_utf8(&mut buf).as_bytes(),
+                _ => b"?",
+            };
+            let len = if fspec.chars { 1 } else { bs.len() };
+            return write_padded(w, fspec, b"", bs, len, true);
+        }
+        b's' => {
+            return arg.with_bytes(|bs| {
+                let (end, len) = if fspec.chars {
+                    let end = utf8::byte_offset(bs, fspec.rnum);
+                    (end, utf8::char_offset(bs, end))
+                } else {
+                    let mut end = std::cmp::min(bs.len(), fspec.rnum);
+                    // Don't cut a multi-byte character in half in valid UTF-8.
+                    if end < bs.len() && utf8::has_multibyte_chars(bs) {
+                        while !utf8::is_char_boundary(bs[end]) {
+                            end -= 1;
+                        }
+                    }
+                    (end, end)
+                };
+                write_padded(&mut w, fspec, b"", &bs[..end], len, true)
+            });
         }
-        b's' => arg.with_bytes(|bs| match_for_spec!("", DisplayBytes(bs))),
         x => return err!("unsupported format specifier: {}", x),
     };
-    wrap_result(res)
+    let len = prefix.len() + body.len();
+    write_padded(w, fspec, prefix, &body.0[..], len, zero_pad)
 }
 
 fn wrap_result<T>(r: std::result::Result<T, impl fmt::Display>) -> Result<()> {
@@ -244,155 +426,163 @@ fn wrap_result<T>(r: std::result::Result<T, impl fmt::Display>) -> Result<()> {
 }
 
 fn write_bytes(mut w: impl Write, bs: &[u8]) -> Result<()> {
-    wrap_result(w.write(bs))
+    wrap_result(w.write_all(bs))
 }
 
-pub(crate) fn printf(mut w: impl Write, spec: &[u8], mut args: &[FormatArg]) -> Result<()> {
-    #[derive(Copy, Clone)]
-    enum State {
-        // Byte index of start of string
-        Raw(usize),
-        // Byte index of percent sign
-        Format(usize),
-    }
-
-    use State::*;
-    let mut iter = spec.iter().cloned().enumerate();
-    macro_rules! next_state {
-        ($e:expr) => {
-            match $e {
-                Some((_, b'%')) => Format(0),
-                Some(_) => Raw(0),
-                None => return Ok(()),
-            }
-        };
+// Where to find a width or precision: in the format string itself, or in the argument list.
+#[derive(Copy, Clone, Debug)]
+enum Count {
+    Lit(usize),
+    // `*`: the next argument.
+    Next,
+    // `*n$`: the nth argument (0-indexed).
+    Pos(usize),
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+struct Directive {
+    fs: FormatSpec,
+    // The argument to format, if given as `%n$`.
+    pos: Option<usize>,
+    width: Option<Count>,
+    prec: Option<Count>,
+}
+
+// Parse a decimal number starting at `spec[*i]`.
+fn parse_num(spec: &[u8], i: &mut usize) -> Option<usize> {
+    let start = *i;
+    let mut res: usize = 0;
+    while let Some(d) = spec.get(*i).filter(|d| d.is_ascii_digit()) {
+        res = res.checked_mul(10)?.checked_add((d - b'0') as usize)?;
+        *i += 1;
+    }
+    if *i == start {
+        None
+    } else {
+        Some(res)
+    }
+}
+
+// Parse a `n$` positional argument starting at `spec[*i]`, leaving `i` unchanged if there is none.
+fn parse_pos(spec: &[u8], i: &mut usize) -> Option<usize> {
+    let mut j = *i;
+    match parse_num(spec, &mut j) {
+        Some(n) if n > 0 && spec.get(j) == Some(&b'$') => {
+            *i = j + 1;
+            Some(n - 1)
+        }
+        _ => None,
     }
-    let mut state = next_state!(iter.next());
+}
+
+fn parse_count(spec: &[u8], i: &mut usize) -> Option<Count> {
+    if spec.get(*i) == Some(&b'*') {
+        *i += 1;
+        Some(parse_pos(spec, i).map(Count::Pos).unwrap_or(Count::Next))
+    } else {
+        parse_num(spec, i).map(Count::Lit)
+    }
+}
+
+// Parse the directive following a '%' at `spec[i-1]`, returning it along with the index of the
+// first byte after it. Returns None if the directive is malformed.
+fn parse_directive(spec: &[u8], mut i: usize) -> Option<(Directive, usize)> {
+    let mut d = Directive::default();
+    if spec.get(i) == Some(&b'%') {
+        d.fs.spec = b'%';
+        return Some((d, i + 1));
+    }
+    d.pos = parse_pos(spec, &mut i);
+    loop {
+        match spec.get(i)? {
+            b'-' => d.fs.minus = true,
+            b'0' => d.fs.leading_zeros = true,
+            b'+' => d.fs.plus = true,
+            b' ' => d.fs.space = true,
+            b'#' => d.fs.alt = true,
+            b'\'' => d.fs.group = true,
+            _ => break,
+        }
+        i += 1;
+    }
+    d.width = parse_count(spec, &mut i);
+    if spec.get(i) == Some(&b'.') {
+        i += 1;
+        d.prec = Some(parse_count(spec, &mut i).unwrap_or(Count::Lit(0)));
+    }
+    // Length modifiers are meaningless in awk; skip them like gawk does.
+    while matches!(spec.get(i), Some(b'h') | Some(b'l') | Some(b'L')) {
+        i += 1;
+    }
+    let c = *spec.get(i)?;
+    // Like glibc, ignore any flags, width or precision given to `%%`.
+    if !is_spec(c) && c != b'%' {
+        return None;
+    }
+    d.fs.spec = c;
+    Some((d, i + 1))
+}
+
+/// Write `args` to `w` formatted according to `spec`. If `chars` is set, the widths and
+/// precisions of `%s` and `%c` conversions count characters rather than bytes.
+pub(crate) fn printf(
+    mut w: impl Write,
+    spec: &[u8],
+    args: &[FormatArg],
+    chars: bool,
+) -> Result<()> {
     let default = FormatArg::S(Default::default());
+    let get_arg = |i: usize| args.get(i).unwrap_or(&default);
+    // The index of the next argument to be consumed by a directive without a `n$` position.
+    let mut next = 0;
     let mut next_arg = || {
-        if args.len() == 0 {
-            &default
-        } else {
-            let res = &args[0];
-            args = &args[1..];
-            res
-        }
+        next += 1;
+        get_arg(next - 1)
     };
-    let mut buf = SmallVec::new();
-    'outer: loop {
-        match state {
-            Raw(start) => {
-                while let Some((ix, ch)) = iter.next() {
-                    if ch == b'%' {
-                        write_bytes(&mut w, &spec[start..ix])?;
-                        state = Format(ix);
-                        continue 'outer;
-                    }
-                }
-                write_bytes(&mut w, &spec[start..])?;
-                break 'outer;
+    let mut pos = 0;
+    while let Some(off) = memchr(b'%', &spec[pos..]) {
+        let start = pos + off;
+        write_bytes(&mut w, &spec[pos..start])?;
+        // AWK is, as usual, rather permissive when it comes to invalid format specifiers: If
+        // something is formatted incorrectly, it is simply treated like a normal string.
+        let (d, end) = match parse_directive(spec, start + 1) {
+            Some(x) => x,
+            None => {
+                write_bytes(&mut w, b"%")?;
+                pos = start + 1;
+                continue;
             }
-            Format(start) => {
-                let mut fs = FormatSpec::default();
-                #[derive(Copy, Clone)]
-                enum Stage {
-                    Begin,
-                    Lnum,
-                    Rnum,
-                }
-                use Stage::*;
-                let mut stage = Begin;
-                let mut next = iter.next();
-                // AWK is, as usual, rather permissive when it comes to invalid format specifiers:
-                // If something is formatted incorrectly, it is simply treated like a normal
-                // string. We implement by checking for error conditions and `break`ing out of the
-                // inner loop, which will change state to Raw(start).
-                while let Some((ix, ch)) = next {
-                    if !ch.is_ascii() {
-                        // We cast characters to bytes in what follows.
-                        break;
-                    }
-                    match (ch, stage) {
-                        (b'%', Begin) => {
-                            fs.spec = b'%';
-                            process_spec(&mut w, &mut fs, next_arg())?;
-                            state = Raw(ix + 1);
-                            continue 'outer;
-                        }
-                        (ch, _) if is_spec(ch) => {
-                            fs.spec = ch as u8;
-                            process_spec(&mut w, &mut fs, next_arg())?;
-                            state = Raw(ix + 1);
-                            continue 'outer;
-                        }
-                        (b'-', Begin) => {
-                            stage = Lnum;
-                            fs.minus = true;
-                        }
-                        (b'-', _) | (b'%', _) => break,
-                        (ch, Lnum) | (ch, Begin) => {
-                            if fs.lnum != 0 {
-                                break;
-                            }
-                            buf.clear();
-                            if ch == b'0' {
-                                fs.leading_zeros = true;
-                            } else if ch == b'.' {
-                                stage = Rnum;
-                                continue;
-                            } else {
-                                buf.push(ch);
-                            };
-                            next = None;
-                            while let Some((ix, ch)) = iter.next() {
-                                if !matches!(ch, b'0'..=b'9') {
-                                    next = Some((ix, ch));
-                                    break;
-                                }
-                                buf.push(ch);
-                            }
-                            let num = strtoi(&buf[..]);
-                            if num < 0 {
-                                break;
-                            }
-                            fs.lnum = num as usize;
-                            stage = Rnum;
-                            continue;
-                        }
-                        (ch, Rnum) => {
-                            if fs.rnum != usize::max_value() {
-                                break;
-                            }
-                            if ch != b'.' {
-                                break;
-                            }
-                            buf.clear();
-                            next = None;
-                            while let Some((ix, ch)) = iter.next() {
-                                if !matches!(ch, b'0'..=b'9') {
-                                    next = Some((ix, ch));
-                                    break;
-                                }
-                                buf.push(ch);
-                            }
-                            let num = strtoi(&buf[..]);
-                            if num < 0 {
-                                break;
-                            }
-                            fs.rnum = num as usize;
-                            continue;
-                        }
-                    };
-                    next = iter.next();
-                }
-                // We do not have a complete format specifier, and we have exhausted the string.
-                // Just print it out.
-                state = Raw(start);
-                continue 'outer;
+        };
+        pos = end;
+        if d.fs.spec == b'%' {
+            write_bytes(&mut w, b"%")?;
+            continue;
+        }
+        let mut fs = d.fs;
+        fs.chars = chars;
+        let mut count = |c: Count| match c {
+            Count::Lit(n) => n as Int,
+            Count::Next => next_arg().to_int(),
+            Count::Pos(i) => get_arg(i).to_int(),
+        };
+        if let Some(width) = d.width.map(&mut count) {
+            // A negative width taken from the arguments means left justification.
+            fs.minus |= width < 0;
+            fs.lnum = width.unsigned_abs() as usize;
+        }
+        if let Some(prec) = d.prec.map(&mut count) {
+            // ... and a negative precision is ignored.
+            if prec >= 0 {
+                fs.rnum = prec as usize;
             }
         }
+        let arg = match d.pos {
+            Some(i) => get_arg(i),
+            None => next_arg(),
+        };
+        process_spec(&mut w, &fs, arg)?;
     }
-    Ok(())
+    write_bytes(&mut w, &spec[pos..])
 }
 
 #[cfg(test)]
@@ -405,7 +595,7 @@ mod tests {
         ($fmt:expr $(, $e:expr)*) => {{
             let mut v = Vec::<u8>::new();
             let w = Cursor::new(&mut v);
-            printf(w, $fmt, &[$( $e.into() ),*]).expect("printf failure");
+            printf(w, $fmt, &[$( $e.into() ),*], false).expect("printf failure");
             String::from_utf8(v).expect("printf should produce valid utf8")
         }}
     }
@@ -420,16 +610,17 @@ mod tests {
             w,
             b"Hi %s, to my %d friends %f percent of the time: %g!",
             &[S("there".into()), F(2.5), I(1), F(1.25369E23)],
+            /*chars=*/ false,
         )
         .expect("printf failed");
         let s = str::from_utf8(&v[..]).unwrap();
         assert_eq!(
             s,
-            "Hi there, to my 2 friends 1.0 percent of the time: 1.25369e23!"
+            "Hi there, to my 2 friends 1.0 percent of the time: 1.25369e+23!"
         );
 
         let s2 = sprintf!(b"%e %d ~~ %s", 12535, 3, "hi");
-        assert_eq!(s2.as_str(), "1.2535e4 3 ~~ hi");
+        assert_eq!(s2.as_str(), "1.253500e+04 3 ~~ hi");
     }
 
     #[test]
@@ -447,4 +638,108 @@ mod tests {
         let s2 = sprintf!(b"%.2f", 2.375);
         assert_eq!(s2.as_str(), "2.38");
     }
+
+    #[test]
+    fn flags_and_conversions() {
+        assert_eq!(
+            sprintf!(b"%i|%u|%X|%#o", 42, -1, 255, 8),
+            "42|18446744073709551615|FF|010"
+        );
+        assert_eq!(
+            sprintf!(b"%+d|% d|%05d|%-5d|", 5, 7, -42, 3),
+            "+5| 7|-0042|3    |"
+        );
+        assert_eq!(sprintf!(b"%.3d|%08.3f", 7, -3.5), "007|-003.500");
+        assert_eq!(
+            sprintf!(b"%'d %'.2f", 1234567, 9876543.21),
+            "1,234,567 9,876,543.21"
+        );
+        assert_eq!(sprintf!(b"%E %G", 12345.5, 0.0001), "1.234550E+04 0.0001");
+        assert_eq!(sprintf!(b"100%% [%5%]"), "100% [%]");
+    }
+
+    #[test]
+    fn c_float_conversions() {
+        // Expected outputs are from glibc's printf.
+        assert_eq!(
+            sprintf!(b"%e|%g|%g|%g", 0.0, 100.0, 123456789.0, 0.0),
+            "0.000000e+00|100|1.23457e+08|0"
+        );
+        assert_eq!(
+            sprintf!(
+                b"%.0e|%#.0e|%.3g|%#g|%g|%g|%g",
+                12345.0,
+                12345.0,
+                0.0001234,
+                1.5,
+                1e-5,
+                123456.0,
+                1234567.0
+            ),
+            "1e+04|1.e+04|0.000123|1.50000|1e-05|123456|1.23457e+06"
+        );
+        assert_eq!(
+            sprintf!(
+                b"%.0g|%g|%10.3e|%-10g|%+g|%010.2e|%G",
+                0.5,
+                -2.5,
+                3.14159,
+                1.5,
+                2.0,
+                -1.5,
+                1e100
+            ),
+            "0.5|-2.5| 3.142e+00|1.5       |+2|-01.50e+00|1E+100"
+        );
+        assert_eq!(
+            sprintf!(b"%.10g|%g|%g", 1.0 / 3.0, 0.00001234, 9.9999999),
+            "0.3333333333|1.234e-05|10"
+        );
+        assert_eq!(
+            sprintf!(b"%g|%G", Float::INFINITY, Float::INFINITY),
+            "inf|INF"
+        );
+    }
+
+    #[test]
+    fn dynamic_and_positional() {
+        assert_eq!(sprintf!(b"[%*d][%.*f]", -4, 7, 2, 3.14159), "[7   ][3.14]");
+        assert_eq!(
+            sprintf!(b"%2$s %1$s %2$s", "world", "hello"),
+            "hello world hello"
+        );
+        assert_eq!(sprintf!(b"%*2$d|", 42, 5), "   42|");
+    }
+
+    #[test]
+    fn raw_bytes() {
+        let fmt = |spec: &[u8], arg: FormatArg, chars: bool| {
+            let mut v = Vec::<u8>::new();
+            printf(Cursor::new(&mut v), spec, &[arg], chars).expect("printf failure");
+            v
+        };
+        let latin1: &[u8] = b"caf\xe9";
+        assert_eq!(fmt(b"[%6s]", latin1.into(), false), b"[  caf\xe9]");
+        assert_eq!(fmt(b"[%-5.4s]", latin1.into(), true), b"[caf\xe9 ]");
+        assert_eq!(fmt(b"%c", FormatArg::I(0xe9), false), "é".as_bytes());
+        assert_eq!(fmt(b"%c", FormatArg::I(0xe9), true), "é".as_bytes());
+
+        // Widths and precisions count bytes by default, and characters with `chars` set.
+        assert_eq!(fmt(b"[%6s]", "café".into(), false), "[ café]".as_bytes());
+        assert_eq!(fmt(b"[%6s]", "café".into(), true), "[  café]".as_bytes());
+        assert_eq!(fmt(b"[%.4s]", "éé".into(), false), "[éé]".as_bytes());
+        assert_eq!(fmt(b"[%.3s]", "éé".into(), false), "[é]".as_bytes());
+        assert_eq!(fmt(b"[%.1s]", "éé".into(), false), b"[]");
+        let invalid: &[u8] = b"\xe9\xe9";
+        assert_eq!(fmt(b"[%.1s]", invalid.into(), false), b"[\xe9]");
+        assert_eq!(fmt(b"[%.1s]", "éé".into(), true), "[é]".as_bytes());
+        assert_eq!(fmt(b"[%-3c]", FormatArg::I(0xe9), true), "[é  ]".as_bytes());
+
+        // A string argument to `%c` writes its first byte, or its first character with `chars`.
+        assert_eq!(fmt(b"[%3c]", "65".into(), false), b"[  6]");
+        assert_eq!(fmt(b"[%3c]", "éa".into(), false), b"[  \xc3]");
+        assert_eq!(fmt(b"[%-3c]", "éa".into(), true), "[é  ]".as_bytes());
+        assert_eq!(fmt(b"[%2c]", latin1.into(), true), b"[ c]");
+        assert_eq!(fmt(b"[%2c]", "".into(), true), b"[  ]");
+    }
 }
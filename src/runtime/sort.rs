@@ -0,0 +1,173 @@
+//! Sorting support for `asort`, `asorti` and ordered `for (k in m)` loops.
+use std::cmp::Ordering;
+use std::hash::Hash;
+
+use crate::common::Result;
+use crate::runtime::{strtod, Float, Int, SharedMap, Str};
+
+/// The order in which `for (k in m)` loops visit the keys of `m`, set by assigning to `SORTED_IN`.
+/// The names match the corresponding values of gawk's `PROCINFO["sorted_in"]`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum SortOrder {
+    Unsorted,
+    NumAsc,
+    NumDesc,
+    StrAsc,
+    StrDesc,
+}
+
+impl Default for SortOrder {
+    fn default() -> SortOrder {
+        SortOrder::Unsorted
+    }
+}
+
+impl SortOrder {
+    pub(crate) fn parse(s: &Str) -> Result<SortOrder> {
+        use SortOrder::*;
+        s.with_bytes(|bs| {
+            Ok(match bs {
+                b"" | b"@unsorted" => Unsorted,
+                b"@ind_num_asc" => NumAsc,
+                b"@ind_num_desc" => NumDesc,
+                b"@ind_str_asc" => StrAsc,
+                b"@ind_str_desc" => StrDesc,
+                _ => {
+                    return err!(
+                        "invalid value for SORTED_IN: {:?} (expected one of \"@unsorted\", \
+                         \"@ind_num_asc\", \"@ind_num_desc\", \"@ind_str_asc\" or \
+                         \"@ind_str_desc\")",
+                        String::from_utf8_lossy(bs)
+                    )
+                }
+            })
+        })
+    }
+
+    pub(crate) fn sort<K: SortKey>(self, keys: &mut Vec<K>) {
+        use SortOrder::*;
+        match self {
+            Unsorted => {}
+            NumAsc => keys.sort_by(|x, y| x.cmp_num(y)),
+            NumDesc => keys.sort_by(|x, y| y.cmp_num(x)),
+            StrAsc => keys.sort_by(|x, y| x.cmp_str(y)),
+            StrDesc => keys.sort_by(|x, y| y.cmp_str(x)),
+        }
+    }
+}
+
+/// Comparisons between map keys and values.
+pub(crate) trait SortKey: Clone {
+    /// Compare as numbers, breaking ties between strings with the same numeric value by
+    /// comparing them as strings.
+    fn cmp_num(&self, other: &Self) -> Ordering;
+    /// Compare the string representations of both values.
+    fn cmp_str(&self, other: &Self) -> Ordering;
+    /// The order used by `asort` and `asorti`: numbers are compared numerically and strings are
+    /// compared as strings.
+    fn cmp_natural(&self, other: &Self) -> Ordering;
+}
+
+impl SortKey for Int {
+    fn cmp_num(&self, other: &Int) -> Ordering {
+        self.cmp(other)
+    }
+    fn cmp_str(&self, other: &Int) -> Ordering {
+        self.to_string().cmp(&other.to_string())
+    }
+    fn cmp_natural(&self, other: &Int) -> Ordering {
+        self.cmp_num(other)
+    }
+}
+
+impl SortKey for Float {
+    fn cmp_num(&self, other: &Float) -> Ordering {
+        self.partial_cmp(other).unwrap_or(Ordering::Equal)
+    }
+    fn cmp_str(&self, other: &Float) -> Ordering {
+        self.to_string().cmp(&other.to_string())
+    }
+    fn cmp_natural(&self, other: &Float) -> Ordering {
+        self.cmp_num(other)
+    }
+}
+
+impl<'a> SortKey for Str<'a> {
+    fn cmp_num(&self, other: &Str<'a>) -> Ordering {
+        let x = self.with_bytes(strtod);
+        let y = other.with_bytes(strtod);
+        x.cmp_num(&y).then_with(|| self.cmp_str(other))
+    }
+    fn cmp_str(&self, other: &Str<'a>) -> Ordering {
+        self.with_bytes(|x| other.with_bytes(|y| x.cmp(y)))
+    }
+    fn cmp_natural(&self, other: &Str<'a>) -> Ordering {
+        self.cmp_str(other)
+    }
+}
+
+// Replace the contents of `dst` with `items` in sorted order, stored under the keys 1 through
+// `items.len()`. Maps with string keys get the string representations of those integers.
+fn fill_sorted<D: Hash + Eq + From<Int>, T: SortKey>(
+    mut items: Vec<T>,
+    dst: &SharedMap<D, T>,
+) -> Int {
+    items.sort_by(|x, y| x.cmp_natural(y));
+    dst.clear();
+    let n = items.len() as Int;
+    for (i, item) in items.into_iter().enumerate() {
+        dst.insert(D::from(i as Int + 1), item);
+    }
+    n
+}
+
+/// `asort(src, dst)`: store the values of `src` in `dst` in sorted order, returning the number of
+/// elements. `src` and `dst` may be the same map.
+pub(crate) fn asort<K, V, D>(src: &SharedMap<K, V>, dst: &SharedMap<D, V>) -> Int
+where
+    K: Hash + Eq,
+    V: SortKey,
+    D: Hash + Eq + From<Int>,
+{
+    let vals = src.iter(|i| i.map(|(_, v)| v.clone()).collect());
+    fill_sorted(vals, dst)
+}
+
+/// `asorti(src, dst)`: store the keys of `src` in `dst` in sorted order, returning the number of
+/// elements. `src` and `dst` may be the same map.
+pub(crate) fn asorti<K, V, D>(src: &SharedMap<K, V>, dst: &SharedMap<D, K>) -> Int
+where
+    K: Hash + Eq + SortKey,
+    D: Hash + Eq + From<Int>,
+{
+    let keys = src.iter(|i| i.map(|(k, _)| k.clone()).collect());
+    fill_sorted(keys, dst)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sort_orders() {
+        let strs = |ss: &[&'static str]| ss.iter().map(|s| Str::from(*s)).collect::<Vec<_>>();
+        let mut ints = vec![10, 9, -1, 100];
+        SortOrder::StrAsc.sort(&mut ints);
+        assert_eq!(ints, vec![-1, 10, 100, 9]);
+        SortOrder::NumDesc.sort(&mut ints);
+        assert_eq!(ints, vec![100, 10, 9, -1]);
+
+        let mut keys = strs(&["b", "10", "9", "a", "1e1"]);
+        SortOrder::NumAsc.sort(&mut keys);
+        assert_eq!(keys, strs(&["a", "b", "9", "10", "1e1"]));
+        SortOrder::StrDesc.sort(&mut keys);
+        assert_eq!(keys, strs(&["b", "a", "9", "1e1", "10"]));
+
+        assert_eq!(
+            SortOrder::parse(&"@ind_num_desc".into()).unwrap(),
+            SortOrder::NumDesc
+        );
+        assert_eq!(SortOrder::parse(&"".into()).unwrap(), SortOrder::Unsorted);
+        assert!(SortOrder::parse(&"@val_num_asc".into()).is_err());
+    }
+}
@@ -20,22 +20,30 @@
 /// auxiliary vector at the cost of 2x steady-state memory usage, or more complex offset management
 /// in the `Offsets` type.
 /// NB the changes to fix this issue will now be in the chunk module.
-use std::io::Read;
+use std::fmt;
+use std::io::{self, BufRead, Read};
 use std::mem;
 use std::str;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
 
 use lazy_static::lazy_static;
 use regex::{bytes, bytes::Regex};
 
+use crate::cfg::Escaper;
 use crate::common::{ExecutionStrategy, Result};
 use crate::pushdown::FieldSet;
 use crate::runtime::{
     str_impl::{Buf, Str, UniqueBuf},
-    Int, LazyVec, RegexCache,
+    writers::Registry,
+    Int, LazyVec, RegexCache, CHUNK_SIZE,
 };
 
 use super::{
     chunk::{self, Chunk, ChunkProducer, OffsetChunk, ParallelChunkProducer, ShardedChunkProducer},
+    json::json_record,
     normalize_join_indexes, DefaultLine, LineReader, ReaderState,
 };
 
@@ -48,10 +56,24 @@ pub struct CSVReader<P> {
     last_len: usize,
     // Used to trigger updating FILENAME on the first read.
     ifmt: InputFormat,
+    // Used to rebuild $0 after a field is assigned to: with JSON output, columns are keyed by the
+    // `names` in the header row, if there is one.
+    escaper: Escaper,
+    names: Arc<Vec<String>>,
     field_set: FieldSet,
 
     empty_buf: Buf,
     check_utf8: bool,
+
+    // Set by `strict`: what to do with malformed records. Rejected records are written through
+    // the program's output files, set by `set_writers`.
+    strict: Option<OnError>,
+    rejects: Option<Registry>,
+    // The number of records read from the current file, used when reporting malformed records.
+    records: u64,
+    // The length of any header row consumed before the start of each input file, so that byte
+    // offsets in reports are relative to the start of the file.
+    header_lens: HeaderLens,
 }
 
 impl LineReader for CSVReader<Box<dyn ChunkProducer<Chunk = OffsetChunk>>> {
@@ -66,9 +88,14 @@ impl LineReader for CSVReader<Box<dyn ChunkProducer<Chunk = OffsetChunk>>> {
         let producers = self.prod.try_dyn_resize(size);
         let mut res = Vec::with_capacity(producers.len());
         let ifmt = self.ifmt;
+        let escaper = self.escaper;
         for p_factory in producers.into_iter() {
+            let names = self.names.clone();
             let field_set = self.field_set.clone();
             let check_utf8 = self.check_utf8;
+            let strict = self.strict.clone();
+            let rejects = self.rejects.clone();
+            let header_lens = self.header_lens.clone();
             res.push(Box::new(move || {
                 let empty_buf = UniqueBuf::new(0).into_buf();
                 let cur_buf = empty_buf.clone();
@@ -81,8 +108,14 @@ impl LineReader for CSVReader<Box<dyn ChunkProducer<Chunk = OffsetChunk>>> {
                     prev_ix: 0,
                     last_len: 0,
                     ifmt,
+                    escaper,
+                    names,
                     field_set,
                     check_utf8,
+                    strict,
+                    rejects,
+                    records: 0,
+                    header_lens,
                 }
             }) as _)
         }
@@ -116,7 +149,15 @@ impl LineReader for CSVReader<Box<dyn ChunkProducer<Chunk = OffsetChunk>>> {
         self.prod.next_file()
     }
     fn set_used_fields(&mut self, field_set: &FieldSet) {
-        self.field_set = field_set.clone();
+        // Unused fields are skipped without looking for malformed quotes.
+        if self.strict.is_none() {
+            self.field_set = field_set.clone();
+        }
+    }
+    fn set_writers(&mut self, writers: &Registry) {
+        if let Some(OnError::Reject(_)) = self.strict {
+            self.rejects = Some(writers.clone());
+        }
     }
 }
 
@@ -124,6 +165,7 @@ impl CSVReader<Box<dyn ChunkProducer<Chunk = OffsetChunk>>> {
     pub fn new<I, S>(
         rs: I,
         ifmt: InputFormat,
+        escaper: Escaper,
         chunk_size: usize,
         check_utf8: bool,
         exec_strategy: ExecutionStrategy,
@@ -173,10 +215,174 @@ impl CSVReader<Box<dyn ChunkProducer<Chunk = OffsetChunk>>> {
             last_len: 0,
             field_set: FieldSet::all(),
             ifmt,
+            escaper,
+            names: Default::default(),
             empty_buf,
             check_utf8,
+            strict: None,
+            rejects: None,
+            records: 0,
+            header_lens: Default::default(),
+        }
+    }
+
+    /// Check each record for unbalanced quotes and for quotes in the middle of unquoted fields,
+    /// handling malformed records according to `on_error`. `header_lens` holds the length of the
+    /// header row that was read from each input before it was passed to the reader, if any. The
+    /// reader's input format must be `strict` when `on_error` is set.
+    pub fn strict(mut self, on_error: Option<OnError>, header_lens: HeaderLens) -> Self {
+        debug_assert!(on_error.is_none() || self.ifmt.strict);
+        self.header_lens = header_lens;
+        if on_error.is_some() {
+            self.field_set = FieldSet::all();
+        }
+        self.strict = on_error;
+        self
+    }
+
+    /// Key the columns of $0 by `names`, the column names in the header row, when it is rebuilt
+    /// with JSON output.
+    pub fn header(mut self, names: Vec<String>) -> Self {
+        self.names = Arc::new(names);
+        self
+    }
+}
+
+/// The length of the header row consumed from the start of each input file, indexed by file
+/// version - 1. Lengths are filled in by `SkipHeader` as each file is opened.
+#[derive(Clone, Default)]
+pub struct HeaderLens(Arc<Vec<AtomicU64>>);
+
+impl HeaderLens {
+    pub fn new(inputs: usize) -> HeaderLens {
+        HeaderLens(Arc::new((0..inputs).map(|_| AtomicU64::new(0)).collect()))
+    }
+    pub fn set(&self, i: usize, len: u64) {
+        if let Some(l) = self.0.get(i) {
+            l.store(len, Ordering::Relaxed);
+        }
+    }
+    fn get(&self, i: usize) -> u64 {
+        self.0.get(i).map_or(0, |l| l.load(Ordering::Relaxed))
+    }
+}
+
+/// A reader for an input file that consumes the file's header row before returning any data. The
+/// file is opened by calling `open` on the first read, so inputs are not opened (and their headers
+/// are not read) until the program reaches them.
+pub struct SkipHeader<R, F> {
+    open: Option<F>,
+    inner: Option<R>,
+    ifmt: InputFormat,
+    lens: HeaderLens,
+    index: usize,
+    expected: Option<(Arc<Vec<String>>, String)>,
+}
+
+impl<R, F> SkipHeader<R, F> {
+    pub fn new(open: F, ifmt: InputFormat, lens: HeaderLens, index: usize) -> Self {
+        SkipHeader {
+            open: Some(open),
+            inner: None,
+            ifmt,
+            lens,
+            index,
+            expected: None,
         }
     }
+
+    /// Fail the first read if the header row of the input (named `name` in errors) differs from
+    /// `header`, the header row of the first input. Column names are resolved against the first
+    /// header, so a reordered header would otherwise silently read the wrong columns.
+    pub fn expect(mut self, header: Arc<Vec<String>>, name: String) -> Self {
+        self.expected = Some((header, name));
+        self
+    }
+}
+
+impl<R: BufRead, F: FnOnce() -> io::Result<R>> Read for SkipHeader<R, F> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(open) = self.open.take() {
+            let mut r = open()?;
+            let (names, len) = match read_header(&mut r, self.ifmt) {
+                Ok(h) => h,
+                Err(e) => return Err(io::Error::new(io::ErrorKind::Other, e.to_string())),
+            };
+            // An empty input has no header, but it has no records either.
+            match &self.expected {
+                Some((header, name)) if len > 0 && names != **header => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "header of {} ({}) does not match the header of the first input ({})",
+                            name,
+                            names.join(","),
+                            header.join(","),
+                        ),
+                    ));
+                }
+                _ => {}
+            }
+            self.lens.set(self.index, len);
+            self.inner = Some(r);
+        }
+        match &mut self.inner {
+            Some(r) => r.read(buf),
+            None => Ok(0),
+        }
+    }
+}
+
+/// Consume the first row of `r`, returning the names of its columns and the number of bytes read.
+/// This is used to read the header row of each input file before the rest of the file is handed
+/// to a `CSVReader`.
+pub fn read_header(r: &mut impl BufRead, ifmt: InputFormat) -> Result<(Vec<String>, u64)> {
+    let mut row = Vec::new();
+    let mut consumed = 0;
+    loop {
+        match r.read_until(b'\n', &mut row) {
+            Ok(0) => break,
+            Ok(n) => consumed += n as u64,
+            Err(e) => return err!("failed to read header: {}", e),
+        }
+        if ifmt.comment.is_some() && row.first() == ifmt.comment.as_ref() {
+            row.clear();
+            continue;
+        }
+        // Newlines inside of a quoted CSV field do not end the row.
+        let in_quote = match ifmt.quote {
+            Some(q) => row.iter().filter(|b| **b == q).count() % 2 == 1,
+            None => false,
+        };
+        if !in_quote {
+            break;
+        }
+    }
+    let names = split_record(row, ifmt)?
+        .iter()
+        .map(|f| f.to_string())
+        .collect();
+    Ok((names, consumed))
+}
+
+// Split a single CSV or TSV record into its fields.
+fn split_record(record: Vec<u8>, ifmt: InputFormat) -> Result<Vec<Str<'static>>> {
+    if record.is_empty() {
+        return Ok(vec![]);
+    }
+    let mut reader = CSVReader::new(
+        std::iter::once((io::Cursor::new(record), String::from("record"))),
+        InputFormat {
+            comment: None,
+            ..ifmt
+        },
+        Escaper::Identity,
+        CHUNK_SIZE,
+        /*check_utf8=*/ false,
+        ExecutionStrategy::Serial,
+    );
+    let (_, line) = reader.read_line(&Str::default(), &mut RegexCache::default())?;
+    Ok(line.fields)
 }
 
 // TODO rename as it handles CSV and TSV
@@ -211,38 +417,142 @@ impl<P: ChunkProducer<Chunk = OffsetChunk>> CSVReader<P> {
             field_set: self.field_set.clone(),
             line,
             st,
+            strict: self.strict.is_some(),
+            malformed: None,
+        }
+    }
+    // Move past any comment lines at the start of the current record, returning true if that
+    // uses up the offsets in the current chunk, in which case the caller should refresh it.
+    //
+    // The offsets in a chunk always end at a newline, so comments are never split across chunks.
+    // Only the newline ending a comment is recorded in the offsets (see
+    // find_indexes_csv_scalar).
+    fn skip_comments(&mut self) -> bool {
+        let comment = match self.ifmt.comment {
+            Some(c) => c,
+            None => return false,
+        };
+        let bs = &self.cur_buf.as_bytes()[0..self.buf_len];
+        let off = &mut self.cur_chunk.off;
+        while self.prev_ix < bs.len() && bs[self.prev_ix] == comment {
+            loop {
+                if off.start == off.fields.len() {
+                    self.prev_ix = bs.len();
+                    return true;
+                }
+                let ix = off.fields[off.start] as usize;
+                off.start += 1;
+                if bs[ix] == b'\n' {
+                    self.prev_ix = ix + 1;
+                    break;
+                }
+            }
         }
+        off.start == off.fields.len()
     }
+
+    // The position of the current chunk within its file.
+    fn file_offset(&self) -> u64 {
+        let header_len = (self.cur_chunk.version as usize)
+            .checked_sub(1)
+            .map_or(0, |i| self.header_lens.get(i));
+        header_len + self.cur_chunk.file_offset
+    }
+
+    // Report a malformed record starting at byte `start` of the current buffer, and then abort or
+    // write the record's text to the reject file.
+    fn malformed(&mut self, start: usize, record: &Str, reason: &str) -> Result<()> {
+        let msg = format!(
+            "{}: record {} at byte {}: {}",
+            self.cur_chunk.get_name(),
+            self.records,
+            self.file_offset() + start as u64,
+            reason
+        );
+        let path = match &self.strict {
+            Some(OnError::Abort) | None => return err!("malformed CSV: {}", msg),
+            Some(OnError::Skip) => {
+                eprintln!("skipping malformed record: {}", msg);
+                return Ok(());
+            }
+            Some(OnError::Reject(path)) => Str::from(path.as_str()).unmoor(),
+        };
+        eprintln!("rejecting malformed record: {}", msg);
+        let fh = match &mut self.rejects {
+            Some(rejects) => rejects.get_handle(Some(&path))?,
+            None => return err!("no output files to write rejected records to"),
+        };
+        fh.write(record, /*append=*/ false)?;
+        // The reader is not told when the program exits, so rejected records are not buffered.
+        fh.flush()
+    }
+
     pub fn read_line_inner<'a, 'b: 'a>(
         &'b mut self,
         line: &'a mut Line,
     ) -> Result</*file changed*/ bool> {
-        line.clear();
         let mut changed = false;
-        if self.cur_chunk.off.start == self.cur_chunk.off.fields.len() {
-            // NB: see comment on corresponding condition in ByteReader.
-            let (is_eof, has_changed) = self.refresh_buf()?;
-            changed = has_changed;
-            // NB: >= because the `push_past` logic in stepper can result in prev_ix pointing two
-            // past the end of the buffer.
-            if is_eof && self.prev_ix >= self.buf_len {
-                self.last_len = 0;
-                debug_assert!(!changed);
-                return Ok(false);
+        loop {
+            line.clear();
+            line.ifmt = self.ifmt;
+            line.escaper = self.escaper;
+            line.names = self.names.clone();
+            line.used = self.field_set.clone();
+            loop {
+                if self.cur_chunk.off.start == self.cur_chunk.off.fields.len() {
+                    // NB: see comment on corresponding condition in ByteReader.
+                    let (is_eof, has_changed) = self.refresh_buf()?;
+                    if has_changed {
+                        changed = true;
+                        self.records = 0;
+                    }
+                    // NB: >= because the `push_past` logic in stepper can result in prev_ix
+                    // pointing two past the end of the buffer.
+                    if is_eof && self.prev_ix >= self.buf_len {
+                        self.last_len = 0;
+                        return Ok(changed);
+                    }
+                }
+                if !self.skip_comments() {
+                    break;
+                }
             }
-        }
 
-        let (prev_ix, st) = {
-            let mut stepper = self.stepper(State::Init, line);
-            (unsafe { stepper.step() }, stepper.st)
-        };
-        let consumed = prev_ix - self.prev_ix;
-        self.prev_ix = prev_ix;
-        self.last_len = consumed;
-        if st != State::Done {
-            line.promote();
+            let start = self.prev_ix;
+            let (prev_ix, st, malformed) = {
+                let mut stepper = self.stepper(State::Init, line);
+                (unsafe { stepper.step() }, stepper.st, stepper.malformed)
+            };
+            let consumed = prev_ix - self.prev_ix;
+            self.prev_ix = prev_ix;
+            self.last_len = consumed;
+            if st != State::Done {
+                line.promote();
+            }
+            if self.strict.is_none() {
+                return Ok(changed);
+            }
+            self.records += 1;
+            // The input ended inside of a quoted field.
+            let unterminated = st == State::Quote || (st == State::BS && self.ifmt.quote.is_some());
+            match malformed.or(if unterminated {
+                Some("unterminated quoted field")
+            } else {
+                None
+            }) {
+                Some(reason) => {
+                    // The stepper keeps the text of every record in strict mode, leaving out the
+                    // newline that ended it, if any.
+                    let record = if st == State::Done {
+                        Str::concat(line.raw.clone(), "\n".into())
+                    } else {
+                        line.raw.clone()
+                    };
+                    self.malformed(start, &record, reason)?
+                }
+                None => return Ok(changed),
+            }
         }
-        Ok(changed)
     }
 }
 
@@ -272,7 +582,7 @@ pub struct Offsets {
 }
 
 impl Offsets {
-    fn clear(&mut self) {
+    pub(crate) fn clear(&mut self) {
         self.start = 0;
         self.fields.clear();
     }
@@ -286,12 +596,39 @@ pub struct Line {
     len: usize,
     fields: Vec<Str<'static>>,
     partial: Str<'static>,
+    // Has a field been assigned since `raw` was last computed? If so, we rebuild `raw` from
+    // `fields` the next time $0 is read.
+    diverged: bool,
+    // Has a field been assigned since $0 was last set, or has $0 been read with CSV or TSV output?
+    // If so, `raw` is (or will be) rebuilt from `fields`.
+    rebuilt: bool,
+    // The CSVReader that produced this line sets these: `ifmt` is used to split a new value of
+    // $0, `escaper` and `names` to escape each field when rebuilding $0, and `used` holds the
+    // fields that were split out of `raw`.
+    ifmt: InputFormat,
+    escaper: Escaper,
+    names: Arc<Vec<String>>,
+    used: FieldSet,
 }
 
 impl Line {
     pub fn len(&self) -> usize {
         self.len
     }
+
+    // Fields that the program never reads are not split out of the record. Before one of the
+    // fields is assigned, split out the rest of them so that $0 can be rebuilt from the fields.
+    fn split_all(&mut self) -> Result<()> {
+        // If $0 is never read, it is never rebuilt.
+        if self.used == FieldSet::all() || !self.used.get(0) {
+            return Ok(());
+        }
+        self.fields = self
+            .raw
+            .with_bytes(|bs| split_record(bs.to_vec(), self.ifmt))?;
+        self.used = FieldSet::all();
+        Ok(())
+    }
 }
 
 impl<'a> super::Line<'a> for Line {
@@ -321,10 +658,28 @@ impl<'a> super::Line<'a> for Line {
         &mut self,
         col: super::Int,
         _pat: &Str,
-        _ofs: &Str,
+        ofs: &Str,
         _rc: &mut super::RegexCache,
     ) -> Result<Str<'a>> {
         if col == 0 {
+            if !self.rebuilt && self.escapes_fields() {
+                // With CSV or TSV output, $0 is always rebuilt, so that printing it escapes each
+                // field rather than the record as a whole.
+                self.split_all()?;
+                self.diverged = true;
+                self.rebuilt = true;
+            }
+            if self.diverged {
+                let ofs = ofs.clone().unmoor();
+                let fields = self.fields.iter();
+                self.raw = match self.escaper {
+                    Escaper::CSV => ofs.join(fields.map(escape_csv)),
+                    Escaper::TSV => ofs.join(fields.map(escape_tsv)),
+                    Escaper::JSON => json_record(&self.fields, &self.names),
+                    Escaper::Identity => ofs.join(fields.cloned()),
+                };
+                self.diverged = false;
+            }
             return Ok(self.raw.clone().upcast());
         }
         if col < 0 {
@@ -338,19 +693,59 @@ impl<'a> super::Line<'a> for Line {
             .upcast())
     }
 
-    // Setting columns for CSV doesn't work. We refuse it outright.
     fn set_col(
         &mut self,
-        _col: super::Int,
-        _s: &Str<'a>,
+        col: super::Int,
+        s: &Str<'a>,
         _pat: &Str,
         _rc: &mut super::RegexCache,
     ) -> Result<()> {
+        if col == 0 {
+            self.raw = s.clone().unmoor();
+            self.fields = s.with_bytes(|bs| split_record(bs.to_vec(), self.ifmt))?;
+            self.used = FieldSet::all();
+            self.diverged = false;
+            self.rebuilt = false;
+            return Ok(());
+        }
+        if col < 0 {
+            return err!("attempt to access negative index {}", col);
+        }
+        self.split_all()?;
+        let col = col as usize;
+        if col > self.fields.len() {
+            self.fields.resize_with(col, Str::default);
+        }
+        self.fields[col - 1] = s.clone().unmoor();
+        self.diverged = true;
+        self.rebuilt = true;
         Ok(())
     }
+
+    fn set_nf(&mut self, nf: super::Int, _pat: &Str, _rc: &mut super::RegexCache) -> Result<()> {
+        if nf < 0 {
+            return err!("attempt to set NF to negative value {}", nf);
+        }
+        self.split_all()?;
+        self.fields.resize_with(nf as usize, Str::default);
+        self.diverged = true;
+        self.rebuilt = true;
+        Ok(())
+    }
+
+    fn rebuilt(&self) -> bool {
+        self.rebuilt || self.escapes_fields()
+    }
+
+    fn names(&self) -> Arc<Vec<String>> {
+        self.names.clone()
+    }
 }
 
 impl Line {
+    fn escapes_fields(&self) -> bool {
+        matches!(self.escaper, Escaper::CSV | Escaper::TSV)
+    }
     pub fn promote(&mut self) {
         let partial = mem::replace(&mut self.partial, Str::default());
         self.fields.push(partial);
@@ -364,6 +759,8 @@ impl Line {
         self.partial = Str::default();
         self.raw = Str::default();
         self.len = 0;
+        self.diverged = false;
+        self.rebuilt = false;
     }
 }
 
@@ -388,6 +785,10 @@ pub struct Stepper<'a> {
     pub st: State,
     pub line: &'a mut Line,
     pub field_set: FieldSet,
+    // If `strict` is set, the stepper records the first reason that the current record is
+    // malformed in `malformed`. Malformed records are still split as usual.
+    pub strict: bool,
+    pub malformed: Option<&'static str>,
 }
 
 impl<'a> Stepper<'a> {
@@ -412,6 +813,12 @@ impl<'a> Stepper<'a> {
         self.line.promote();
     }
 
+    fn flag(&mut self, reason: &'static str) {
+        if self.strict && self.malformed.is_none() {
+            self.malformed = Some(reason);
+        }
+    }
+
     fn get(&mut self, line_start: usize, j: usize, cur: usize) -> usize {
         self.off.start = cur;
         if self.field_set.get(0) {
@@ -423,15 +830,19 @@ impl<'a> Stepper<'a> {
 
     pub unsafe fn step(&mut self) -> usize {
         let sep = self.ifmt.sep();
+        // NB: the quote and escape characters are distinct from the delimiter and from '\n', so
+        // these sentinels never match a byte that was recorded in the offsets.
+        let quote = self.ifmt.quote.unwrap_or(b'\n');
+        let escape = self.ifmt.escape_byte().unwrap_or(b'\n');
         let line_start = self.prev_ix;
         let bs = &self.buf.as_bytes()[0..self.buf_len];
         let mut cur = self.off.start;
-        let bs_transition = match self.ifmt {
+        let bs_transition = match self.ifmt.quote {
             // Escape sequences only occur within quotes for CSV-formatted data.
-            InputFormat::CSV => State::Quote,
+            Some(_) => State::Quote,
             // There are no "quoted fields" in TSV, and escape sequences simply occur at any point
             // in a field.
-            InputFormat::TSV => State::Init,
+            None => State::Init,
         };
         macro_rules! get_next {
             () => {
@@ -459,19 +870,19 @@ impl<'a> Stepper<'a> {
                             let ix = *self.off.fields.get_unchecked(cur) as usize;
                             cur += 1;
                             match *bs.get_unchecked(ix) {
-                                b'\r' | b'"' | b'\\' => {}
                                 b'\n' => {
                                     self.prev_ix = ix + 1;
                                     self.promote_null();
                                     self.st = State::Done;
                                     return self.get(line_start, ix, cur);
                                 }
-                                _x => {
-                                    debug_assert_eq!(_x, sep);
+                                x if x == sep => {
                                     self.prev_ix = ix + 1;
                                     self.promote_null();
                                     continue 'init;
                                 }
+                                // '\r', quotes and escapes.
+                                _ => {}
                             }
                         }
                     }
@@ -488,13 +899,20 @@ impl<'a> Stepper<'a> {
                             self.st = State::Done;
                             return self.get(line_start, ix, cur);
                         }
-                        b'"' => {
+                        x if x == quote => {
+                            if ix > self.prev_ix || !self.line.partial.is_empty() {
+                                self.flag("stray quote in an unquoted field");
+                                if self.ifmt.strict {
+                                    // The quote is part of the field; see find_indexes_csv_scalar.
+                                    continue;
+                                }
+                            }
                             self.push_past(ix);
                             self.st = State::Quote;
                             continue 'outer;
                         }
                         // Only happens in TSV mode
-                        b'\\' => {
+                        x if x == escape => {
                             self.push_past(ix);
                             self.st = State::BS;
                             continue 'outer;
@@ -511,7 +929,7 @@ impl<'a> Stepper<'a> {
                     // Parse a quoted field; this will only happen in CSV mode.
                     let ix = get_next!();
                     match *bs.get_unchecked(ix) {
-                        b'"' => {
+                        x if x == quote => {
                             // We have found a quote, time to figure out if the next character is a
                             // quote, or if it is the end of the quoted portion of the field.
                             //
@@ -522,7 +940,7 @@ impl<'a> Stepper<'a> {
                             self.st = State::QuoteInQuote;
                             continue;
                         }
-                        b'\\' => {
+                        x if x == escape => {
                             // A similar lookahead case: handling escaped sequences.
                             self.push_past(ix);
                             self.st = State::BS;
@@ -540,8 +958,8 @@ impl<'a> Stepper<'a> {
                         debug_assert_eq!(self.off.fields.len(), cur);
                         return self.get(line_start, bs.len(), cur);
                     }
-                    if *bs.get_unchecked(self.prev_ix) == b'"' {
-                        self.append("\"".into());
+                    if *bs.get_unchecked(self.prev_ix) == quote {
+                        self.append_slice(self.prev_ix, self.prev_ix + 1);
                         self.st = State::Quote;
                         // burn the next entry. It should be a quote. Using get_next here is a
                         // convenience: if we hit the branch that returns early within the macro,
@@ -549,9 +967,14 @@ impl<'a> Stepper<'a> {
                         // should appear in the offsets vector, and we know that there is more
                         // space in `bs`.
                         let _q = get_next!();
-                        debug_assert_eq!(bs[_q], b'"');
+                        debug_assert_eq!(bs[_q], quote);
                         self.prev_ix += 1;
                     } else {
+                        match *bs.get_unchecked(self.prev_ix) {
+                            b'\n' | b'\r' => {}
+                            x if x == sep => {}
+                            _ => self.flag("text after the closing quote of a field"),
+                        }
                         self.st = State::Init;
                     }
                 }
@@ -582,17 +1005,180 @@ impl<'a> Stepper<'a> {
     }
 }
 
-#[derive(Copy, Clone)]
-pub enum InputFormat {
-    CSV,
-    TSV,
+/// How escape sequences are written within CSV or TSV fields.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EscapeStyle {
+    /// The only escape is a doubled quote character inside of a quoted field, as in RFC 4180.
+    Doubled,
+    /// Backslash sequences like `\t`, `\n` and `\\` are also recognized. They only appear inside
+    /// of quoted fields if the dialect has a quote character, and anywhere in a field otherwise.
+    Backslash,
+}
+
+/// What a `CSVReader` in strict mode does with a malformed record.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OnError {
+    /// Stop reading input, returning an error.
+    Abort,
+    /// Report the record on standard error, and move on to the next one.
+    Skip,
+    /// Like `Skip`, but the raw text of the record is also written to the named file.
+    Reject(String),
+}
+
+/// A CSV "dialect": the rules used to split input into records and fields.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct InputFormat {
+    pub delimiter: u8,
+    /// Fields containing the delimiter or a newline are surrounded by the quote character, if
+    /// there is one.
+    pub quote: Option<u8>,
+    pub escape: EscapeStyle,
+    /// Records beginning with this character are skipped.
+    pub comment: Option<u8>,
+    /// Is the first row of each input a header, rather than a record?
+    pub header: bool,
+    /// Is a quote only special at the start of a field? This is set when malformed records are
+    /// reported, so that a stray quote in the middle of a field does not run on into the records
+    /// after it.
+    pub strict: bool,
+}
+
+impl Default for InputFormat {
+    fn default() -> InputFormat {
+        InputFormat::CSV
+    }
 }
 
 impl InputFormat {
+    pub const CSV: InputFormat = InputFormat {
+        delimiter: b',',
+        quote: Some(b'"'),
+        escape: EscapeStyle::Backslash,
+        comment: None,
+        header: false,
+        strict: false,
+    };
+
+    pub const TSV: InputFormat = InputFormat {
+        delimiter: b'\t',
+        quote: None,
+        escape: EscapeStyle::Backslash,
+        comment: None,
+        header: false,
+        strict: false,
+    };
+
     fn sep(self) -> u8 {
-        match self {
-            InputFormat::CSV => b',',
-            InputFormat::TSV => b'\t',
+        self.delimiter
+    }
+
+    fn escape_byte(self) -> Option<u8> {
+        match self.escape {
+            EscapeStyle::Doubled => None,
+            EscapeStyle::Backslash => Some(b'\\'),
+        }
+    }
+
+    /// Check that the special characters of this dialect can be told apart. They must be
+    /// distinct ASCII characters, and none of them can end a line.
+    pub fn validate(&self) -> Result<()> {
+        let mut specials = vec![("delimiter", self.delimiter)];
+        specials.extend(self.quote.map(|q| ("quote", q)));
+        specials.extend(self.escape_byte().map(|e| ("escape", e)));
+        specials.extend(self.comment.map(|c| ("comment", c)));
+        for (i, (name, c)) in specials.iter().enumerate() {
+            if !c.is_ascii() || *c == b'\n' || *c == b'\r' {
+                return err!("invalid {} character {:?}", name, *c as char);
+            }
+            for (other, d) in &specials[..i] {
+                if c == d {
+                    return err!(
+                        "{} and {} characters must differ, both are {:?}",
+                        other,
+                        name,
+                        *c as char
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for InputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let opt = |c: Option<u8>| c.map_or(String::from("none"), |c| format!("{:?}", c as char));
+        let escape = match self.escape {
+            EscapeStyle::Doubled => "doubled",
+            EscapeStyle::Backslash => "backslash",
+        };
+        write!(
+            f,
+            "delimiter={:?} quote={} escape={} comment={} header={}",
+            self.delimiter as char,
+            opt(self.quote),
+            escape,
+            opt(self.comment),
+            self.header
+        )
+    }
+}
+
+/// A scalar variant of `find_indexes_csv` for the dialects that the vectorized version cannot
+/// handle, because it tracks quotes before it knows anything else about the input:
+///
+/// * Quotes in a comment line are not special. For these lines we record only the newline ending
+///   the line, which is all that `CSVReader::skip_comments` looks for.
+/// * In strict dialects, a quote only starts a quoted field at the start of the field. Quotes
+///   later in a field are still recorded, so that the record can be reported as malformed, but
+///   they do not change whether the following delimiters and newlines are quoted.
+///
+/// Like `find_indexes_csv`, this assumes `buf` starts at the beginning of a record.
+pub fn find_indexes_csv_scalar(buf: &[u8], offsets: &mut Offsets, ifmt: InputFormat) {
+    offsets.clear();
+    let quote = ifmt.quote;
+    let escape = ifmt.escape_byte();
+    let mut in_quote = false;
+    let mut in_comment = false;
+    let mut line_start = true;
+    // Has the current field no text outside of quotes yet, and was the previous byte a closing
+    // quote? A quote in any other position is in the middle of a field.
+    let mut field_start = true;
+    let mut after_quote = false;
+    for (i, b) in buf.iter().cloned().enumerate() {
+        if in_comment {
+            if b == b'\n' {
+                offsets.fields.push(i as u64);
+                in_comment = false;
+                line_start = true;
+            }
+            continue;
+        }
+        if line_start && Some(b) == ifmt.comment {
+            in_comment = true;
+            line_start = false;
+            continue;
+        }
+        line_start = false;
+        let closed = mem::replace(&mut after_quote, false);
+        if Some(b) == quote {
+            offsets.fields.push(i as u64);
+            if in_quote || field_start || closed || !ifmt.strict {
+                after_quote = in_quote;
+                in_quote = !in_quote;
+                field_start = false;
+            }
+        } else if in_quote {
+            if Some(b) == escape {
+                offsets.fields.push(i as u64);
+            }
+        } else if b == ifmt.delimiter || b == b'\r' || b == b'\n' {
+            offsets.fields.push(i as u64);
+            line_start = b == b'\n';
+            field_start = b != b'\r' || field_start;
+        } else {
+            field_start = false;
         }
     }
 }
@@ -605,29 +1191,18 @@ impl InputFormat {
 // will instead store a function pointer that is computed at startup based on the dynamically
 // available CPU features.
 
-pub fn get_find_indexes(
-    ifmt: InputFormat,
-) -> unsafe fn(&[u8], &mut Offsets, u64, u64) -> (u64, u64) {
+pub fn get_find_indexes() -> unsafe fn(&[u8], &mut Offsets, InputFormat, u64, u64) -> (u64, u64) {
     #[cfg(feature = "allow_avx2")]
     const ALLOW_AVX2: bool = true;
     #[cfg(not(feature = "allow_avx2"))]
     const ALLOW_AVX2: bool = false;
 
     if ALLOW_AVX2 && is_x86_feature_detected!("avx2") && is_x86_feature_detected!("pclmulqdq") {
-        match ifmt {
-            InputFormat::CSV => generic::find_indexes_csv::<avx2::Impl>,
-            InputFormat::TSV => generic::find_indexes_tsv::<avx2::Impl>,
-        }
+        generic::find_indexes_csv::<avx2::Impl>
     } else if is_x86_feature_detected!("sse2") && is_x86_feature_detected!("pclmulqdq") {
-        match ifmt {
-            InputFormat::CSV => generic::find_indexes_csv::<sse2::Impl>,
-            InputFormat::TSV => generic::find_indexes_tsv::<sse2::Impl>,
-        }
+        generic::find_indexes_csv::<sse2::Impl>
     } else {
-        match ifmt {
-            InputFormat::CSV => generic::find_indexes_csv::<generic::Impl>,
-            InputFormat::TSV => generic::find_indexes_tsv::<generic::Impl>,
-        }
+        generic::find_indexes_csv::<generic::Impl>
     }
 }
 
@@ -661,6 +1236,21 @@ pub fn get_find_indexes_ascii_whitespace() -> unsafe fn(&[u8], &mut WhitespaceOf
     }
 }
 
+pub fn get_find_indexes_json() -> unsafe fn(&[u8], &mut Offsets) -> (u64, u64) {
+    #[cfg(feature = "allow_avx2")]
+    const ALLOW_AVX2: bool = true;
+    #[cfg(not(feature = "allow_avx2"))]
+    const ALLOW_AVX2: bool = false;
+
+    if ALLOW_AVX2 && is_x86_feature_detected!("avx2") {
+        generic::find_indexes_json::<avx2::Impl>
+    } else if is_x86_feature_detected!("sse2") {
+        generic::find_indexes_json::<sse2::Impl>
+    } else {
+        generic::find_indexes_json::<generic::Impl>
+    }
+}
+
 // TODO: consider putting these into the runtime struct to avoid the extra indirection.
 lazy_static! {
     static ref QUOTE: Regex = Regex::new(r#"""#).unwrap();
@@ -740,7 +1330,7 @@ mod escape_tests {
 }
 
 mod generic {
-    use super::{Offsets, WhitespaceOffsets};
+    use super::{InputFormat, Offsets, WhitespaceOffsets};
     const MAX_INPUT_SIZE: usize = 64;
 
     pub trait Vector: Copy {
@@ -763,6 +1353,7 @@ mod generic {
 
         unsafe fn find_quote_mask(
             self,
+            quote: u8,
             prev_iter_inside_quote: &mut u64,
         ) -> (/*inside quotes*/ u64, /*quote locations*/ u64);
 
@@ -876,8 +1467,8 @@ mod generic {
             foreach_impl!(ix, if self.0[ix] == m { 1u8 } else { 0u8 })
         }
 
-        unsafe fn find_quote_mask(self, prev_iter_inside_quote: &mut u64) -> (u64, u64) {
-            let quote_mask = self.cmp_against_input(b'"');
+        unsafe fn find_quote_mask(self, quote: u8, prev_iter_inside_quote: &mut u64) -> (u64, u64) {
+            let quote_mask = self.cmp_against_input(quote);
             let mut in_quote = false;
             let mut in_quotes = Impl([0; 32]);
             for ix in 0..Self::VEC_BYTES {
@@ -894,6 +1485,7 @@ mod generic {
     #[cfg(target_arch = "x86_64")]
     pub unsafe fn default_x86_find_quote_mask<V: Vector>(
         inp: V,
+        quote: u8,
         prev_iter_inside_quote: &mut u64,
     ) -> (/*inside quotes*/ u64, /*quote locations*/ u64) {
         use std::arch::x86_64::*;
@@ -904,7 +1496,7 @@ mod generic {
         // [000000000000001111111111110]
         // We will use this mask to avoid splitting on commas that are inside a quoted field. We
         // start by generating a mask for all the quote characters appearing in the string.
-        let quote_bits = inp.cmp_mask_against_input(b'"');
+        let quote_bits = inp.cmp_mask_against_input(quote);
         // Then we pull this trick from the simdjson paper. Lets use the example from the comments
         // above:
         // [unquoted text "quoted text"]
@@ -982,9 +1574,16 @@ mod generic {
     pub unsafe fn find_indexes_csv<V: Vector>(
         buf: &[u8],
         offsets: &mut Offsets,
+        ifmt: InputFormat,
         mut prev_iter_inside_quote: u64, /*start at 0*/
         mut prev_iter_cr_end: u64,       /*start at 0*/
     ) -> (u64, u64) {
+        let quote = match ifmt.quote {
+            Some(q) => q,
+            None => return find_indexes_tsv::<V>(buf, offsets, ifmt),
+        };
+        let delimiter = ifmt.delimiter;
+        let escape = ifmt.escape_byte();
         offsets.clear();
         // This may cause us to overuse memory, but it's a safe upper bound and the plan is to
         // reuse this across different chunks.
@@ -1003,9 +1602,13 @@ mod generic {
                 std::intrinsics::prefetch_read_data($buf.offset(128), 3);
                 // find commas not inside quotes
                 let inp = V::fill_input($buf);
-                let (quote_mask, quote_locs) = inp.find_quote_mask(&mut prev_iter_inside_quote);
-                let sep = inp.cmp_mask_against_input(b',');
-                let esc = inp.cmp_mask_against_input(b'\\');
+                let (quote_mask, quote_locs) =
+                    inp.find_quote_mask(quote, &mut prev_iter_inside_quote);
+                let sep = inp.cmp_mask_against_input(delimiter);
+                let esc = match escape {
+                    Some(e) => inp.cmp_mask_against_input(e),
+                    None => 0,
+                };
 
                 let cr = inp.cmp_mask_against_input(0x0d);
                 let cr_adjusted = cr.wrapping_shl(1) | prev_iter_cr_end;
@@ -1111,6 +1714,128 @@ mod generic {
         (0, 0)
     }
 
+    // Compute the prefix XOR of `bits`: bit i of the result is the parity of bits 0 through i.
+    // Like the carryless multiplication in default_x86_find_quote_mask, this fills in the bits
+    // between pairs of quotes.
+    #[inline(always)]
+    fn prefix_xor(mut bits: u64) -> u64 {
+        bits ^= bits << 1;
+        bits ^= bits << 2;
+        bits ^= bits << 4;
+        bits ^= bits << 8;
+        bits ^= bits << 16;
+        bits ^= bits << 32;
+        bits
+    }
+
+    // JSON Lines splitting finds the "structural characters" of each line, as in the first stage
+    // of simdjson: braces, brackets, colons, commas and newlines outside of strings, along with the
+    // quotes that begin and end each string. Unlike CSV, quotes are escaped with backslashes, so
+    // we first find the characters that are preceded by an odd number of backslashes.
+    //
+    // Like find_indexes_csv, this returns whether the buffer ended inside of a string, along with
+    // whether the byte after the buffer would be escaped.
+    pub unsafe fn find_indexes_json<V: Vector>(
+        buf: &[u8],
+        offsets: &mut Offsets,
+    ) -> (/*inside string*/ u64, /*escaped*/ u64) {
+        const ODD_BITS: u64 = 0xAAAA_AAAA_AAAA_AAAA;
+        offsets.clear();
+        // This may cause us to overuse memory, but it's a safe upper bound and the plan is to
+        // reuse this across different chunks.
+        offsets.fields.reserve(buf.len());
+        let buf_ptr = buf.as_ptr();
+        let len = buf.len();
+        let len_minus_64 = len.saturating_sub(V::INPUT_SIZE);
+        let mut ix = 0;
+        let base_ptr: *mut u64 = offsets.fields.get_unchecked_mut(0);
+        let mut base = 0;
+        // Is the first character of the next block escaped?
+        let mut prev_iter_escaped = 0u64;
+        // All 1s if the next block starts inside of a string, all 0s otherwise.
+        let mut prev_iter_inside_quote = 0u64;
+        let last_bit = V::INPUT_SIZE as u32 - 1;
+        let block_mask = !0u64 >> (64 - V::INPUT_SIZE);
+
+        const BUFFER_SIZE: usize = 4;
+        macro_rules! iterate {
+            ($buf:expr) => {{
+                std::intrinsics::prefetch_read_data($buf.offset(128), 3);
+                let inp = V::fill_input($buf);
+                // This is simdjson's escape scanner. Subtracting the start of each run of
+                // backslashes from the odd bits carries through the run, flipping the parity of
+                // the bit just past the run depending on whether the run started on an even or an
+                // odd index; the result tells us which runs have an odd length.
+                let backslash = inp.cmp_mask_against_input(b'\\');
+                let potential_escape = backslash & !prev_iter_escaped;
+                let codes =
+                    ((potential_escape << 1) | ODD_BITS).wrapping_sub(potential_escape) ^ ODD_BITS;
+                let escaped = (codes ^ (backslash | prev_iter_escaped)) & block_mask;
+                prev_iter_escaped = (codes & backslash).wrapping_shr(last_bit) & 1;
+
+                let quotes = inp.cmp_mask_against_input(b'"') & !escaped;
+                let lf = inp.cmp_mask_against_input(b'\n');
+                let mut toggles = quotes;
+                let mut inside_quote = prefix_xor(toggles) ^ prev_iter_inside_quote;
+                // Strings cannot contain newlines, so a newline inside of a string means that the
+                // string was never closed. End the string at the newline, so that the malformed
+                // record does not swallow the ones after it.
+                loop {
+                    let unclosed = lf & inside_quote;
+                    if unclosed == 0 {
+                        break;
+                    }
+                    toggles |= unclosed & unclosed.wrapping_neg();
+                    inside_quote = prefix_xor(toggles) ^ prev_iter_inside_quote;
+                }
+                prev_iter_inside_quote = (inside_quote.wrapping_shr(last_bit) & 1).wrapping_neg();
+                let structural = inp
+                    .cmp_against_input(b'{')
+                    .or(inp.cmp_against_input(b'}'))
+                    .or(inp.cmp_against_input(b'['))
+                    .or(inp.cmp_against_input(b']'))
+                    .or(inp.cmp_against_input(b':'))
+                    .or(inp.cmp_against_input(b','))
+                    .mask();
+                ((structural | lf) & !inside_quote) | quotes
+            }};
+        }
+        if len_minus_64 > V::INPUT_SIZE * BUFFER_SIZE {
+            let mut fields = [0u64; BUFFER_SIZE];
+            while ix < len_minus_64 - V::INPUT_SIZE * BUFFER_SIZE + 1 {
+                for b in 0..BUFFER_SIZE {
+                    fields[b] = iterate!(buf_ptr.offset((V::INPUT_SIZE * b + ix) as isize));
+                }
+                for b in 0..BUFFER_SIZE {
+                    let internal_ix = V::INPUT_SIZE * b + ix;
+                    flatten_bits(base_ptr, &mut base, internal_ix as u64, fields[b]);
+                }
+                ix += V::INPUT_SIZE * BUFFER_SIZE;
+            }
+        }
+        // Do an unbuffered version for the remaining data
+        while ix < len_minus_64 {
+            let field_sep = iterate!(buf_ptr.offset(ix as isize));
+            flatten_bits(base_ptr, &mut base, ix as u64, field_sep);
+            ix += V::INPUT_SIZE;
+        }
+        // For any text that remains, just copy the results to the stack with some padding and do
+        // one more iteration.
+        let remaining = len - ix;
+        if remaining > 0 {
+            let mut rest = [0u8; MAX_INPUT_SIZE];
+            std::ptr::copy_nonoverlapping(
+                buf_ptr.offset(ix as isize),
+                rest.as_mut_ptr(),
+                remaining,
+            );
+            let field_sep = iterate!(rest.as_mut_ptr());
+            flatten_bits(base_ptr, &mut base, ix as u64, field_sep);
+        }
+        offsets.fields.set_len(base as usize);
+        (prev_iter_inside_quote, prev_iter_escaped)
+    }
+
     // Unlike the other find_indexes methods, splitting by ASCII whitespace involves emitting two
     // streams of offsets: once for newlines and one for field boundaries (encapsulated in
     // WhitespaceOffsets). Other than that, the basic structure is the same.
@@ -1199,19 +1924,24 @@ mod generic {
         start_ws
     }
 
-    pub unsafe fn find_indexes_tsv<V: Vector>(
+    // TSV, and other dialects without a quote character, have no quoting state to track: escape
+    // sequences can appear anywhere in a field.
+    unsafe fn find_indexes_tsv<V: Vector>(
         buf: &[u8],
         offsets: &mut Offsets,
-        // These two are ignored for TSV
-        _prev_iter_inside_quote: u64,
-        _prev_iter_cr_end: u64,
+        ifmt: InputFormat,
     ) -> (u64, u64) {
+        let delimiter = ifmt.delimiter;
+        let escape = ifmt.escape_byte();
         find_indexes_unquoted::<V, _>(buf, offsets, |ptr| {
             let inp = V::fill_input(ptr);
-            let sep = inp.cmp_against_input(b'\t');
-            let esc = inp.cmp_against_input(b'\\');
+            let sep = inp.cmp_against_input(delimiter);
             let lf = inp.cmp_against_input(b'\n');
-            sep.or(esc).or(lf).mask()
+            let res = sep.or(lf).mask();
+            match escape {
+                Some(e) => res | inp.cmp_mask_against_input(e),
+                None => res,
+            }
         });
         (0, 0)
     }
@@ -1285,9 +2015,10 @@ mod sse2 {
 
         unsafe fn find_quote_mask(
             self,
+            quote: u8,
             prev_iter_inside_quote: &mut u64,
         ) -> (/*inside quotes*/ u64, /*quote locations*/ u64) {
-            default_x86_find_quote_mask::<Self>(self, prev_iter_inside_quote)
+            default_x86_find_quote_mask::<Self>(self, quote, prev_iter_inside_quote)
         }
     }
 }
@@ -1346,9 +2077,10 @@ mod avx2 {
 
         unsafe fn find_quote_mask(
             self,
+            quote: u8,
             prev_iter_inside_quote: &mut u64,
         ) -> (/*inside quotes*/ u64, /*quote locations*/ u64) {
-            default_x86_find_quote_mask::<Self>(self, prev_iter_inside_quote)
+            default_x86_find_quote_mask::<Self>(self, quote, prev_iter_inside_quote)
         }
     }
 }
@@ -1362,6 +2094,8 @@ pub struct ByteReader<P: ChunkProducer> {
     // Progress in the current buffer.
     progress: usize,
     record_sep: u8,
+    // Did the last record end with `record_sep`, rather than the end of the input?
+    terminated: bool,
 
     last_len: usize,
     check_utf8: bool,
@@ -1418,6 +2152,7 @@ impl ByteReader<Box<dyn ChunkProducer<Chunk = OffsetChunk>>> {
             buf_len: 0,
             progress: 0,
             record_sep,
+            terminated: false,
             used_fields: FieldSet::all(),
             last_len: usize::max_value(),
             check_utf8,
@@ -1474,7 +2209,9 @@ impl ByteReader<Box<dyn ChunkProducer<Chunk = OffsetChunk<WhitespaceOffsets>>>>
             cur_buf: UniqueBuf::new(0).into_buf(),
             buf_len: 0,
             progress: 0,
-            record_sep: 0, // unused
+            // Only used for RT; records are always split on newlines.
+            record_sep: b'\n',
+            terminated: false,
             used_fields: FieldSet::all(),
             last_len: usize::max_value(),
             check_utf8,
@@ -1507,6 +2244,7 @@ where
                 buf_len: 0,
                 progress: 0,
                 record_sep,
+                terminated: false,
                 last_len: usize::max_value(),
                 used_fields,
                 check_utf8,
@@ -1557,6 +2295,14 @@ where
     fn set_used_fields(&mut self, field_set: &FieldSet) {
         self.used_fields = field_set.clone();
     }
+
+    fn last_rt(&self) -> Str<'static> {
+        if self.terminated {
+            Str::from(&[self.record_sep][..]).unmoor()
+        } else {
+            Str::default()
+        }
+    }
 }
 
 // Most of the implementation for splitting by whitespace and splitting by a single byte are
@@ -1619,6 +2365,7 @@ where
         if is_eof && br.progress == br.buf_len {
             *line = Str::default();
             br.last_len = 0;
+            br.terminated = false;
             debug_assert!(!changed);
             return Ok(false);
         }
@@ -1626,6 +2373,8 @@ where
     let (next_line, consumed) = unsafe { br.consume_line(fields) };
     *line = next_line;
     br.last_len = consumed;
+    br.terminated =
+        consumed > 0 && br.cur_buf.as_bytes().get(br.progress - 1).cloned() == Some(br.record_sep);
     Ok(changed)
 }
 
@@ -1796,8 +2545,9 @@ unquoted,commas,"as well, including some long ones", and there we have it."#;
         let mut mem: Vec<u8> = text.as_bytes().iter().cloned().collect();
         mem.reserve(32);
         let mut offsets: Offsets = Default::default();
-        let (in_quote, in_cr) =
-            unsafe { generic::find_indexes_csv::<V>(&mem[..], &mut offsets, 0, 0) };
+        let (in_quote, in_cr) = unsafe {
+            generic::find_indexes_csv::<V>(&mem[..], &mut offsets, InputFormat::CSV, 0, 0)
+        };
         assert_eq!(in_quote, 0);
         assert_eq!(in_cr, 0);
         assert_eq!(
@@ -1824,6 +2574,46 @@ unquoted,commas,"as well, including some long ones", and there we have it."#;
     fn sse2_smoke_test() {
         smoke_test::<sse2::Impl>();
     }
+
+    fn json_structural_test<V: generic::Vector>() {
+        fn structural<V: generic::Vector>(s: &str) -> String {
+            let mut offsets: Offsets = Default::default();
+            unsafe { generic::find_indexes_json::<V>(s.as_bytes(), &mut offsets) };
+            offsets
+                .fields
+                .iter()
+                .map(|i| s.as_bytes()[*i as usize] as char)
+                .collect()
+        }
+        assert_eq!(
+            structural::<V>(r#"{"a": [1, 2], "b": "x,y"}"#),
+            r#"{"":[,],"":""}"#
+        );
+        // Escaped quotes do not end a string, but a quote after an escaped backslash does.
+        assert_eq!(
+            structural::<V>(r#"{"a\"b": "c\\", "d": 1}"#),
+            r#"{"":"","":}"#
+        );
+        assert_eq!(structural::<V>("[\"\\\\\\\"{\"]\n[]\n"), "[\"\"]\n[]\n");
+        // Strings and runs of backslashes that cross the boundaries of blocks.
+        for pad in 0..150 {
+            let s = format!(
+                "{{\"{}\": \"{}\\\\\\\"{{}}\", \"z\": [1]}}\n",
+                "k".repeat(pad),
+                "\\\\".repeat(pad % 40)
+            );
+            assert_eq!(structural::<V>(&s), "{\"\":\"\",\"\":[]}\n", "pad={}", pad);
+        }
+    }
+
+    #[test]
+    fn json_structural() {
+        if is_x86_feature_detected!("avx2") {
+            json_structural_test::<avx2::Impl>();
+        }
+        json_structural_test::<sse2::Impl>();
+        json_structural_test::<generic::Impl>();
+    }
     fn read_to_vec<T: Clone + Default>(lv: &LazyVec<T>) -> Vec<T> {
         let mut res = Vec::with_capacity(lv.len());
         for i in 0..lv.len() {
@@ -1858,6 +2648,7 @@ unquoted,commas,"as well, including some long ones", and there we have it."#;
         let mut reader = CSVReader::new(
             once((reader, String::from("fake-stdin"))),
             InputFormat::TSV,
+            Escaper::Identity,
             /*chunk_size=*/ 512,
             /*check_utf8=*/ true,
             ExecutionStrategy::Serial,
@@ -1897,6 +2688,248 @@ unquoted,commas,"as well, including some long ones", and there we have it."#;
         tsv_split(crate::test_string_constants::PRIDE_PREJUDICE_CH2);
     }
 
+    fn strs(ss: &[&str]) -> Vec<String> {
+        ss.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn read_header_basic() {
+        use std::io::BufRead;
+        let mut csv = std::io::Cursor::new("name,\"city,\nstate\",age\r\nalice,\"a,\nb\",30\n");
+        assert_eq!(
+            read_header(&mut csv, InputFormat::CSV).unwrap(),
+            (strs(&["name", "city,\nstate", "age"]), 24)
+        );
+        let mut rest = String::new();
+        csv.read_line(&mut rest).unwrap();
+        assert_eq!(rest, "alice,\"a,\n");
+
+        let mut tsv = std::io::Cursor::new("a\tb\n1\t2\n");
+        assert_eq!(
+            read_header(&mut tsv, InputFormat::TSV).unwrap(),
+            (strs(&["a", "b"]), 4)
+        );
+        let mut empty = std::io::Cursor::new("");
+        assert!(read_header(&mut empty, InputFormat::CSV)
+            .unwrap()
+            .0
+            .is_empty());
+    }
+
+    #[test]
+    fn skip_header_lazy() {
+        use std::sync::atomic::AtomicBool;
+        let opened = Arc::new(AtomicBool::new(false));
+        let lens = HeaderLens::new(2);
+        let flag = opened.clone();
+        let second = SkipHeader::new(
+            move || {
+                flag.store(true, Ordering::Relaxed);
+                Ok(std::io::Cursor::new("x,y,z\n3,4\n"))
+            },
+            InputFormat::CSV,
+            lens.clone(),
+            1,
+        );
+        let mut first = std::io::Cursor::new("a,b\n1,2\n");
+        let (_, len) = read_header(&mut first, InputFormat::CSV).unwrap();
+        lens.set(0, len);
+        let first: Box<dyn Read + Send> = Box::new(first);
+        let second: Box<dyn Read + Send> = Box::new(second);
+        let mut reader = CSVReader::new(
+            vec![(first, String::from("a")), (second, String::from("b"))].into_iter(),
+            InputFormat::CSV,
+            Escaper::Identity,
+            CHUNK_SIZE,
+            /*check_utf8=*/ true,
+            ExecutionStrategy::Serial,
+        );
+        assert!(!opened.load(Ordering::Relaxed));
+        let mut got: Vec<Vec<String>> = Vec::new();
+        loop {
+            let (_, line) = reader
+                .read_line(&Str::default(), &mut RegexCache::default())
+                .expect("failed to read line");
+            if reader.read_state() != 1 {
+                break;
+            }
+            got.push(line.fields.iter().map(|f| f.to_string()).collect());
+        }
+        assert_eq!(got, vec![strs(&["1", "2"]), strs(&["3", "4"])]);
+        assert!(opened.load(Ordering::Relaxed));
+        assert_eq!((lens.get(0), lens.get(1)), (4, 6));
+    }
+
+    #[test]
+    fn skip_header_mismatch() {
+        let header = Arc::new(strs(&["name", "age", "city"]));
+        let read = |input: &'static str| {
+            let mut r = SkipHeader::new(
+                move || Ok(std::io::Cursor::new(input)),
+                InputFormat::CSV,
+                HeaderLens::new(2),
+                1,
+            )
+            .expect(header.clone(), String::from("b.csv"));
+            let mut out = String::new();
+            r.read_to_string(&mut out).map(|_| out)
+        };
+        assert_eq!(
+            read(
+                "name,age,city
+bob,30,LA
+"
+            )
+            .unwrap(),
+            "bob,30,LA
+"
+        );
+        assert_eq!(read("").unwrap(), "");
+        let e = read(
+            "age,name
+30,bob
+",
+        )
+        .unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "header of b.csv (age,name) does not match the header of the first input (name,age,city)"
+        );
+    }
+
+    #[test]
+    fn csv_dialect() {
+        let ifmt = InputFormat {
+            delimiter: b';',
+            quote: Some(b'\''),
+            escape: EscapeStyle::Doubled,
+            comment: Some(b'#'),
+            header: false,
+            strict: false,
+        };
+        let mut corpus = String::new();
+        let mut expected = Vec::new();
+        for i in 0..100 {
+            if i % 3 == 0 {
+                corpus.push_str("# a comment, with an 'unbalanced quote\n");
+            }
+            corpus.push_str(&format!("{};'it''s;\n{}';a\\tb\n", i, i));
+            expected.push(vec![
+                i.to_string(),
+                format!("it's;\n{}", i),
+                String::from("a\\tb"),
+            ]);
+        }
+        for strat in &[ExecutionStrategy::Serial, ExecutionStrategy::ShardPerRecord] {
+            // Use a small chunk size so that records and comments straddle chunk boundaries.
+            let mut reader = CSVReader::new(
+                once((
+                    std::io::Cursor::new(corpus.clone()),
+                    String::from("fake-stdin"),
+                )),
+                ifmt,
+                Escaper::Identity,
+                /*chunk_size=*/ 64,
+                /*check_utf8=*/ true,
+                *strat,
+            );
+            let mut got = Vec::new();
+            loop {
+                let (_, line) = reader
+                    .read_line(&Str::default(), &mut RegexCache::default())
+                    .expect("failed to read line");
+                if reader.read_state() != 1 {
+                    break;
+                }
+                got.push(
+                    line.fields
+                        .iter()
+                        .map(|f| f.to_string())
+                        .collect::<Vec<_>>(),
+                );
+            }
+            assert_eq!(got, expected);
+        }
+
+        let mut csv = std::io::Cursor::new("# comment\n'a;b';c\n1;2\n");
+        assert_eq!(
+            read_header(&mut csv, ifmt).unwrap(),
+            (strs(&["a;b", "c"]), 18)
+        );
+
+        let mut bad = ifmt;
+        bad.delimiter = b'\'';
+        assert!(bad.validate().is_err());
+        bad = ifmt;
+        bad.quote = Some(b'\n');
+        assert!(bad.validate().is_err());
+        // Backslashes can only be delimiters if they are not also escape characters.
+        let mut tsv = InputFormat::TSV;
+        tsv.delimiter = b'\\';
+        assert!(tsv.validate().is_err());
+        tsv.escape = EscapeStyle::Doubled;
+        assert!(tsv.validate().is_ok());
+    }
+
+    #[test]
+    fn csv_strict() {
+        use crate::runtime::writers::testing::FakeFs;
+        let corpus = "a,b\n1,\"x\"y\nc\"d,e\"\n\"f\",g\n\"open,h\n";
+        let read = |corpus: &'static str, on_error: OnError, fs: FakeFs| -> Result<Vec<String>> {
+            let mut reader = CSVReader::new(
+                once((std::io::Cursor::new(corpus), String::from("fake-stdin"))),
+                InputFormat {
+                    strict: true,
+                    ..InputFormat::CSV
+                },
+                Escaper::Identity,
+                CHUNK_SIZE,
+                /*check_utf8=*/ true,
+                ExecutionStrategy::Serial,
+            )
+            .strict(Some(on_error), HeaderLens::default());
+            reader.set_writers(&Registry::from_factory(fs));
+            let mut got = Vec::new();
+            loop {
+                let (_, line) = reader.read_line(&Str::default(), &mut RegexCache::default())?;
+                if reader.read_state() != 1 {
+                    break;
+                }
+                got.push(line.fields.iter().map(|f| f.to_string()).collect());
+            }
+            Ok(got)
+        };
+        let fs = FakeFs::default();
+        let got = read(corpus, OnError::Skip, fs.clone()).unwrap();
+        assert_eq!(got, vec!["ab", "fg"]);
+        assert!(fs.get_handle("rejects").is_none());
+
+        let got = read(corpus, OnError::Reject("rejects".into()), fs.clone()).unwrap();
+        assert_eq!(got, vec!["ab", "fg"]);
+        let rejected = fs.get_handle("rejects").unwrap().read_data();
+        assert_eq!(
+            String::from_utf8(rejected).unwrap(),
+            "1,\"x\"y\nc\"d,e\"\n\"open,h\n"
+        );
+
+        let err = read(corpus, OnError::Abort, fs).unwrap_err();
+        let msg = format!("{}", err);
+        assert!(
+            msg.contains("fake-stdin: record 2 at byte 4: text after the closing quote"),
+            "{}",
+            msg
+        );
+
+        // A stray quote is part of its field, so the record still ends at the newline and the
+        // records after it are read as usual.
+        let corpus = "a,b\nc\"d,e\nf,g\n\"h\"i,j\nk,l\n";
+        let fs = FakeFs::default();
+        let got = read(corpus, OnError::Reject("rejects".into()), fs.clone()).unwrap();
+        assert_eq!(got, vec!["ab", "fg", "kl"]);
+        let rejected = fs.get_handle("rejects").unwrap().read_data();
+        assert_eq!(String::from_utf8(rejected).unwrap(), "c\"d,e\n\"h\"i,j\n");
+    }
+
     fn bytes_split(fs: u8, rs: u8, corpus: &'static str) {
         let mut _cache = RegexCache::default();
         let _pat = Str::default();
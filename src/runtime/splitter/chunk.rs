@@ -9,8 +9,8 @@ use crate::common::Result;
 use crate::runtime::{
     splitter::{
         batch::{
-            get_find_indexes, get_find_indexes_ascii_whitespace, get_find_indexes_bytes,
-            InputFormat, Offsets, WhitespaceOffsets,
+            find_indexes_csv_scalar, get_find_indexes, get_find_indexes_ascii_whitespace,
+            get_find_indexes_bytes, get_find_indexes_json, InputFormat, Offsets, WhitespaceOffsets,
         },
         Reader,
     },
@@ -63,6 +63,8 @@ pub struct OffsetChunkProducer<R, F> {
     find_indexes: F,
     record_sep: u8,
     state: ChunkState,
+    // The number of bytes of the file that were yielded in previous chunks.
+    file_offset: u64,
 }
 
 pub fn new_offset_chunk_producer_csv<R: Read>(
@@ -73,16 +75,21 @@ pub fn new_offset_chunk_producer_csv<R: Read>(
     start_version: u32,
     check_utf8: bool,
 ) -> OffsetChunkProducer<R, impl FnMut(&[u8], &mut Offsets)> {
-    let find_indexes = get_find_indexes(ifmt);
+    let find_indexes = get_find_indexes();
     OffsetChunkProducer {
         name: name.into(),
         inner: Reader::new(r, chunk_size, /*padding=*/ 128, check_utf8),
         find_indexes: move |bs: &[u8], offs: &mut Offsets| {
-            unsafe { find_indexes(bs, offs, 0, 0) };
+            if ifmt.quote.is_some() && (ifmt.comment.is_some() || ifmt.strict) {
+                find_indexes_csv_scalar(bs, offs, ifmt)
+            } else {
+                unsafe { find_indexes(bs, offs, ifmt, 0, 0) };
+            }
         },
         record_sep: b'\n',
         cur_file_version: start_version,
         state: ChunkState::Init,
+        file_offset: 0,
     }
 }
 
@@ -105,6 +112,28 @@ pub fn new_offset_chunk_producer_bytes<R: Read>(
         cur_file_version: start_version,
         record_sep,
         state: ChunkState::Init,
+        file_offset: 0,
+    }
+}
+
+pub fn new_offset_chunk_producer_json<R: Read>(
+    r: R,
+    chunk_size: usize,
+    name: &str,
+    start_version: u32,
+    check_utf8: bool,
+) -> OffsetChunkProducer<R, impl FnMut(&[u8], &mut Offsets)> {
+    let find_indexes = get_find_indexes_json();
+    OffsetChunkProducer {
+        name: name.into(),
+        inner: Reader::new(r, chunk_size, /*padding=*/ 128, check_utf8),
+        find_indexes: move |bs: &[u8], offs: &mut Offsets| {
+            unsafe { find_indexes(bs, offs) };
+        },
+        cur_file_version: start_version,
+        record_sep: b'\n',
+        state: ChunkState::Init,
+        file_offset: 0,
     }
 }
 
@@ -126,6 +155,7 @@ pub fn new_offset_chunk_producer_ascii_whitespace<R: Read>(
             cur_file_version: start_version,
             record_sep: 0u8, // unused
             state: ChunkState::Init,
+            file_offset: 0,
         },
         1,
     )
@@ -186,6 +216,30 @@ pub fn new_chained_offset_chunk_producer_bytes<
     )
 }
 
+pub fn new_chained_offset_chunk_producer_json<
+    R: Read,
+    N: Borrow<str>,
+    I: Iterator<Item = (R, N)>,
+>(
+    r: I,
+    chunk_size: usize,
+    check_utf8: bool,
+) -> ChainedChunkProducer<OffsetChunkProducer<R, impl FnMut(&[u8], &mut Offsets)>> {
+    ChainedChunkProducer::new(
+        r.enumerate()
+            .map(|(i, (r, name))| {
+                new_offset_chunk_producer_json(
+                    r,
+                    chunk_size,
+                    name.borrow(),
+                    /*start_version=*/ (i as u32).wrapping_add(1),
+                    check_utf8,
+                )
+            })
+            .collect(),
+    )
+}
+
 pub fn new_chained_offset_chunk_producer_ascii_whitespace<
     R: Read,
     N: Borrow<str>,
@@ -234,6 +288,9 @@ pub struct OffsetChunk<Off = Offsets> {
     pub buf: Option<UniqueBuf>,
     pub len: usize,
     pub off: Off,
+    // The position of the start of `buf` in the file `name`. This is only tracked for chunks of
+    // CSV and single-byte-separated input.
+    pub file_offset: u64,
 }
 
 impl<Off: Default> Default for OffsetChunk<Off> {
@@ -244,6 +301,7 @@ impl<Off: Default> Default for OffsetChunk<Off> {
             buf: None,
             len: 0,
             off: Default::default(),
+            file_offset: 0,
         }
     }
 }
@@ -274,6 +332,7 @@ impl<R: Read, F: FnMut(&[u8], &mut Offsets)> ChunkProducer for OffsetChunkProduc
                 ChunkState::Main => {
                     chunk.version = self.cur_file_version;
                     chunk.name = self.name.clone();
+                    chunk.file_offset = self.file_offset;
                     let buf = self.inner.buf.clone();
                     let bs = buf.as_bytes();
                     (self.find_indexes)(bs, &mut chunk.off);
@@ -296,6 +355,7 @@ impl<R: Read, F: FnMut(&[u8], &mut Offsets)> ChunkProducer for OffsetChunkProduc
                     debug_assert!(new_len <= always_truncate);
                     let is_partial = if let Some(chunk_end) = target {
                         self.inner.start = chunk_end;
+                        self.file_offset += chunk_end as u64;
                         false
                     } else {
                         debug_assert_eq!(new_len, 0);
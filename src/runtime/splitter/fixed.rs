@@ -0,0 +1,179 @@
+//! Fixed-width field splitting, driven by the FIELDWIDTHS variable.
+//!
+//! FIELDWIDTHS is a whitespace-separated list of column widths. Each entry can be prefixed by a
+//! number of bytes (or characters) to skip before the column starts, as in "2:5", and the final
+//! entry can be "*" to take the rest of the record. Records are still read according to RS; only
+//! field splitting changes.
+use crate::common::Result;
+use crate::pushdown::FieldSet;
+use crate::runtime::utf8::{has_multibyte_chars, is_char_boundary};
+use crate::runtime::{LazyVec, Str};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct Column {
+    skip: usize,
+    // None means "the rest of the record".
+    width: Option<usize>,
+}
+
+/// A parsed FIELDWIDTHS specification.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FieldWidths {
+    cols: Vec<Column>,
+    // Count widths in UTF-8 characters rather than bytes.
+    chars: bool,
+}
+
+impl FieldWidths {
+    pub fn parse(spec: &str, chars: bool) -> Result<FieldWidths> {
+        fn parse_num(spec: &str, s: &str) -> Result<usize> {
+            match s.parse::<usize>() {
+                Ok(n) => Ok(n),
+                Err(_) => err!("invalid FIELDWIDTHS {:?}: {:?} is not a width", spec, s),
+            }
+        }
+        let mut cols = Vec::new();
+        let mut entries = spec.split_whitespace().peekable();
+        while let Some(entry) = entries.next() {
+            let (skip, width) = match entry.find(':') {
+                Some(ix) => (parse_num(spec, &entry[..ix])?, &entry[ix + 1..]),
+                None => (0, entry),
+            };
+            let width = if width == "*" {
+                if entries.peek().is_some() {
+                    return err!("invalid FIELDWIDTHS {:?}: * must be the last entry", spec);
+                }
+                None
+            } else {
+                Some(parse_num(spec, width)?)
+            };
+            cols.push(Column { skip, width });
+        }
+        if cols.len() == 0 {
+            return err!("FIELDWIDTHS must contain at least one width");
+        }
+        Ok(FieldWidths { cols, chars })
+    }
+
+    /// Split `line` into columns, pushing them onto `fields`. Columns not in `used_fields` are
+    /// pushed as empty strings. A record that ends partway through the specification yields fewer
+    /// fields, the last of which may be shorter than its width.
+    pub(crate) fn split<'a>(
+        &self,
+        line: &Str<'a>,
+        used_fields: &FieldSet,
+        fields: &mut LazyVec<Str<'a>>,
+    ) {
+        line.with_bytes(|bs| {
+            let chars = self.chars && has_multibyte_chars(bs);
+            let mut pos = 0;
+            for (i, col) in self.cols.iter().enumerate() {
+                let start = advance(bs, pos, col.skip, chars);
+                if start == bs.len() {
+                    break;
+                }
+                let end = match col.width {
+                    Some(w) => advance(bs, start, w, chars),
+                    None => bs.len(),
+                };
+                if used_fields.get(i + 1) {
+                    fields.push(line.slice(start, end));
+                } else {
+                    fields.push(Str::default());
+                }
+                pos = end;
+            }
+        })
+    }
+}
+
+// The offset `n` bytes (or characters) after `start`, clamped to the length of `bs`.
+fn advance(bs: &[u8], start: usize, n: usize, chars: bool) -> usize {
+    if !chars {
+        return std::cmp::min(start.saturating_add(n), bs.len());
+    }
+    let mut ix = start;
+    for _ in 0..n {
+        if ix == bs.len() {
+            break;
+        }
+        ix += 1;
+        while ix < bs.len() && !is_char_boundary(bs[ix]) {
+            ix += 1;
+        }
+    }
+    ix
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(spec: &str, chars: bool, used: &FieldSet, line: &str) -> Vec<String> {
+        let widths = FieldWidths::parse(spec, chars).unwrap();
+        let line = Str::from(line);
+        let mut fields = LazyVec::new();
+        widths.split(&line, used, &mut fields);
+        (0..fields.len())
+            .map(|i| {
+                fields
+                    .get(i)
+                    .unwrap()
+                    .with_bytes(|bs| String::from_utf8_lossy(bs).into_owned())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parse_spec() {
+        assert!(FieldWidths::parse("", false).is_err());
+        assert!(FieldWidths::parse("1 x 2", false).is_err());
+        assert!(FieldWidths::parse("1 * 2", false).is_err());
+        assert!(FieldWidths::parse("-1", false).is_err());
+        let w = FieldWidths::parse(" 3 2:4\t* ", false).unwrap();
+        assert_eq!(
+            w.cols,
+            vec![
+                Column {
+                    skip: 0,
+                    width: Some(3)
+                },
+                Column {
+                    skip: 2,
+                    width: Some(4)
+                },
+                Column {
+                    skip: 0,
+                    width: None
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn split_widths() {
+        let all = FieldSet::all();
+        assert_eq!(
+            split("3 2:4 *", false, &all, "abcdefghijklmn"),
+            vec!["abc", "fghi", "jklmn"]
+        );
+        // Short records yield fewer fields, with a truncated final field.
+        assert_eq!(split("3 4 5", false, &all, "abcde"), vec!["abc", "de"]);
+        assert_eq!(split("3 2:4", false, &all, "abcd"), vec!["abc"]);
+        assert!(split("3 4", false, &all, "").is_empty());
+        // Projected-out fields are never materialized.
+        let mut used = FieldSet::empty();
+        used.set(2);
+        assert_eq!(split("2 2 2", false, &used, "aabbcc"), vec!["", "bb", ""]);
+    }
+
+    #[test]
+    fn split_chars() {
+        let all = FieldSet::all();
+        assert_eq!(split("2 1:2", true, &all, "héllo wörld"), vec!["hé", "lo"]);
+        assert_eq!(
+            split("2 1:2", false, &all, "héllo"),
+            vec!["h\u{fffd}", "ll"]
+        );
+    }
+}
@@ -0,0 +1,203 @@
+//! Content-defined field splitting, driven by the FPAT variable.
+//!
+//! Where FS describes the text between fields, FPAT describes the fields themselves: each
+//! non-overlapping match of FPAT in a record is a field. This is also the splitting rule used by
+//! the `patsplit` builtin.
+//!
+//! Unlike the rest of frawk's regex support, matches here are leftmost-longest, as in gawk:
+//! patterns like `([^,]*)|("[^"]+")` rely on a longer alternative winning over an earlier one.
+use regex::bytes::Regex;
+use regex_automata::{
+    hybrid::dfa::{Cache, DFA},
+    nfa::thompson,
+    util::{pool::Pool, syntax},
+    Anchored, Input, MatchKind,
+};
+
+use crate::common::Result;
+use crate::pushdown::FieldSet;
+use crate::runtime::{LazyVec, Str};
+
+type CacheFn = Box<dyn Fn() -> Cache + Send + Sync>;
+
+/// A compiled FPAT pattern.
+pub struct FieldPattern {
+    // Finds where the next match starts. The leftmost starting position is the same whether or
+    // not we prefer longer matches.
+    re: Regex,
+    // Finds the longest match anchored at a given position. `None` if the pattern uses a feature
+    // the lazy DFA does not support, in which case we keep the ends that `re` finds.
+    longest: Option<(DFA, Pool<Cache, CacheFn>)>,
+}
+
+impl FieldPattern {
+    pub fn new(pat: &str) -> Result<FieldPattern> {
+        let re = match Regex::new(pat) {
+            Ok(re) => re,
+            Err(e) => return err!("{}", e),
+        };
+        // Mirror the syntax of regex::bytes::Regex, which may match invalid UTF-8.
+        let longest = DFA::builder()
+            .configure(
+                DFA::config()
+                    .match_kind(MatchKind::All)
+                    .unicode_word_boundary(true),
+            )
+            .syntax(syntax::Config::new().utf8(false))
+            .thompson(thompson::Config::new().utf8(false))
+            .build(pat)
+            .ok()
+            .map(|dfa| {
+                let for_cache = dfa.clone();
+                let create: CacheFn = Box::new(move || for_cache.create_cache());
+                (dfa, Pool::new(create))
+            });
+        Ok(FieldPattern { re, longest })
+    }
+
+    /// Call `f` with the start and end of each match in `bs`. As with the regex crate's
+    /// `find_iter`, an empty match directly after another match is skipped.
+    fn for_each_match(&self, bs: &[u8], mut f: impl FnMut(usize, usize)) {
+        let mut cache = self.longest.as_ref().map(|(_, pool)| pool.get());
+        let mut pos = 0;
+        let mut last_end = None;
+        while pos <= bs.len() {
+            let m = match self.re.find_at(bs, pos) {
+                Some(m) => m,
+                None => break,
+            };
+            let (start, mut end) = (m.start(), m.end());
+            if let (Some((dfa, _)), Some(cache)) = (&self.longest, &mut cache) {
+                let input = Input::new(bs).range(start..).anchored(Anchored::Yes);
+                // The search only fails for Unicode word boundaries in non-ASCII text; keep the
+                // leftmost-first match in that case.
+                if let Ok(Some(hm)) = dfa.try_search_fwd(cache, &input) {
+                    end = end.max(hm.offset());
+                }
+            }
+            if start == end && last_end == Some(start) {
+                pos = start + 1;
+                continue;
+            }
+            f(start, end);
+            last_end = Some(end);
+            pos = if start == end { end + 1 } else { end };
+        }
+    }
+
+    /// Push each match of the pattern in `line` onto `fields`. Fields not in `used_fields` are
+    /// pushed as empty strings.
+    pub(crate) fn split<'a>(
+        &self,
+        line: &Str<'a>,
+        used_fields: &FieldSet,
+        fields: &mut LazyVec<Str<'a>>,
+    ) {
+        line.with_bytes(|bs| {
+            let mut i = 0;
+            self.for_each_match(bs, |start, end| {
+                i += 1;
+                if used_fields.get(i) {
+                    fields.push(line.slice(start, end));
+                } else {
+                    fields.push(Str::default());
+                }
+            })
+        })
+    }
+}
+
+/// Call `field` with each match of `pat` in `s`, numbered from 1, and `sep` with the text between
+/// them: `sep(i, ..)` is called with the text following field `i`. The text before the first
+/// field and after the last one is passed as separators 0 and n, if it is nonempty. Returns the
+/// number of fields.
+pub(crate) fn pattern_split<'a>(
+    pat: &FieldPattern,
+    s: &Str<'a>,
+    mut field: impl FnMut(i64, Str<'a>),
+    mut sep: impl FnMut(i64, Str<'a>),
+) -> i64 {
+    s.with_bytes(|bs| {
+        let mut n = 0;
+        let mut prev = 0;
+        pat.for_each_match(bs, |start, end| {
+            if n > 0 || start > 0 {
+                sep(n, s.slice(prev, start));
+            }
+            n += 1;
+            field(n, s.slice(start, end));
+            prev = end;
+        });
+        if prev < bs.len() {
+            sep(n, s.slice(prev, bs.len()));
+        }
+        n
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_string(s: &Str) -> String {
+        s.with_bytes(|bs| String::from_utf8_lossy(bs).into_owned())
+    }
+
+    fn split(pat: &str, used: &FieldSet, line: &str) -> Vec<String> {
+        let fpat = FieldPattern::new(pat).unwrap();
+        let line = Str::from(line);
+        let mut fields = LazyVec::new();
+        fpat.split(&line, used, &mut fields);
+        (0..fields.len())
+            .map(|i| to_string(&fields.get(i).unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn split_fields() {
+        let all = FieldSet::all();
+        // Alternatives match leftmost-longest, so their order does not matter.
+        for csv in &[r#"([^,]*)|("[^"]+")"#, r#"("[^"]+")|([^,]*)"#] {
+            assert_eq!(split(csv, &all, r#"a,"b,c",d"#), vec!["a", r#""b,c""#, "d"]);
+            assert_eq!(
+                split(csv, &all, r#"a,"b,c",,d"#),
+                vec!["a", r#""b,c""#, "", "d"]
+            );
+        }
+        assert_eq!(split("a|ab|abc", &all, "abcab"), vec!["abc", "ab"]);
+        assert_eq!(split("[0-9]+", &all, "ab 12 c 345"), vec!["12", "345"]);
+        assert!(split("[0-9]+", &all, "abc").is_empty());
+        let mut used = FieldSet::empty();
+        used.set(2);
+        assert_eq!(split("[a-z]+", &used, "ab cd ef"), vec!["", "cd", ""]);
+        assert!(FieldPattern::new("(").is_err());
+    }
+
+    #[test]
+    fn split_with_seps() {
+        let re = FieldPattern::new("[0-9]+").unwrap();
+        let split = |s: &str| {
+            let mut fields = Vec::new();
+            let mut seps = Vec::new();
+            let n = pattern_split(
+                &re,
+                &Str::from(s),
+                |i, f| fields.push((i, to_string(&f))),
+                |i, s| seps.push((i, to_string(&s))),
+            );
+            (n, fields, seps)
+        };
+        let (n, fields, seps) = split("a1b22c");
+        assert_eq!(n, 2);
+        assert_eq!(fields, vec![(1, "1".into()), (2, "22".into())]);
+        assert_eq!(
+            seps,
+            vec![(0, "a".into()), (1, "b".into()), (2, "c".into())]
+        );
+        let (n, fields, seps) = split("1 2");
+        assert_eq!(n, 2);
+        assert_eq!(fields, vec![(1, "1".into()), (2, "2".into())]);
+        assert_eq!(seps, vec![(1, " ".into())]);
+        assert_eq!(split(""), (0, vec![], vec![]));
+    }
+}
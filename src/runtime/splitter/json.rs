@@ -0,0 +1,964 @@
+//! Splitting for JSON Lines input (`--input-format jsonl`).
+//!
+//! Each line of the input holds a JSON value. Rather than splitting records into positional
+//! fields, programs name the values they need by key path: `$"user.id"` is the `id` member of the
+//! object stored under the `user` key, and numeric path components index into arrays, as in
+//! `$"tags.0"`. Each distinct path in the program is assigned a column, and the reader only fills
+//! in those columns.
+//!
+//! Lines are scanned by `batch::find_indexes_json`, which finds the structural characters of each
+//! line using the same vectorized approach as the CSV reader. The parser below then walks those
+//! offsets rather than the bytes of the line, skipping over any value that no column refers to
+//! without decoding it.
+use std::io::Read;
+use std::mem;
+use std::str;
+use std::sync::Arc;
+
+use crate::common::{ExecutionStrategy, Result};
+use crate::pushdown::FieldSet;
+use crate::runtime::{
+    float_to_str_fmt,
+    str_impl::{Buf, Str, UniqueBuf},
+    Float, Int, RegexCache,
+};
+
+use super::{
+    batch::{get_find_indexes_json, Offsets},
+    chunk::{self, Chunk, ChunkProducer, OffsetChunk, ParallelChunkProducer, ShardedChunkProducer},
+    normalize_join_indexes, LineReader, ReaderState,
+};
+
+/// The key paths read from each record, stored as a trie. The value at the `i`th path is stored
+/// in column `i + 1`.
+#[derive(Clone, Debug, Default)]
+pub struct KeyPaths {
+    root: Node,
+    columns: usize,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Node {
+    // The column holding the value at this path, if a column refers to it directly.
+    column: Option<usize>,
+    children: Vec<(Vec<u8>, Node)>,
+}
+
+impl Node {
+    fn member(&self, key: &[u8]) -> Option<&Node> {
+        self.children
+            .iter()
+            .find(|(k, _)| &k[..] == key)
+            .map(|(_, n)| n)
+    }
+
+    fn element(&self, i: usize) -> Option<&Node> {
+        self.children
+            .iter()
+            .find(|(k, _)| str::from_utf8(k).ok().and_then(|k| k.parse().ok()) == Some(i))
+            .map(|(_, n)| n)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.column.is_none() && self.children.is_empty()
+    }
+}
+
+impl KeyPaths {
+    pub fn new(paths: &[impl AsRef<str>]) -> KeyPaths {
+        KeyPaths::with_used(paths, &FieldSet::all())
+    }
+
+    /// Like `new`, but the paths of columns that are not in `used` are left out of the trie.
+    /// Their columns are always empty.
+    pub fn with_used(paths: &[impl AsRef<str>], used: &FieldSet) -> KeyPaths {
+        let mut root = Node::default();
+        for (i, path) in paths.iter().enumerate() {
+            if !used.get(i + 1) {
+                continue;
+            }
+            let path = path.as_ref();
+            let mut node = &mut root;
+            // The empty path refers to the entire value.
+            if path.len() > 0 {
+                for key in path.split('.').map(str::as_bytes) {
+                    let pos = match node.children.iter().position(|(k, _)| &k[..] == key) {
+                        Some(pos) => pos,
+                        None => {
+                            node.children.push((key.to_vec(), Node::default()));
+                            node.children.len() - 1
+                        }
+                    };
+                    node = &mut node.children[pos].1;
+                }
+            }
+            node.column = Some(i + 1);
+        }
+        KeyPaths {
+            root,
+            columns: paths.len(),
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum Value {
+    // The contents of a string, which may contain escape sequences.
+    Str,
+    Null,
+    // Numbers, booleans, objects and arrays are stored as they appear in the input.
+    Raw,
+}
+
+fn is_ws(b: u8) -> bool {
+    matches!(b, b' ' | b'\t' | b'\r' | b'\n')
+}
+
+// A parser for a single JSON value, reading from the offsets of its structural characters. It
+// calls `emit` with the column, bounds and kind of each value that a column refers to. Methods
+// return None if the value is malformed.
+struct Parser<'a, F> {
+    bs: &'a [u8],
+    offs: &'a [u64],
+    // The index into `offs` of the next structural character.
+    cur: usize,
+    // The next byte of `bs` to read.
+    ix: usize,
+    emit: F,
+}
+
+impl<'a, F: FnMut(usize, usize, usize, Value)> Parser<'a, F> {
+    fn skip_ws(&mut self) {
+        while self.ix < self.bs.len() && is_ws(self.bs[self.ix]) {
+            self.ix += 1;
+        }
+    }
+
+    // The next structural character, if it is the next byte to be read.
+    fn peek(&self) -> Option<u8> {
+        match self.offs.get(self.cur) {
+            Some(off) if *off as usize == self.ix => Some(self.bs[self.ix]),
+            _ => None,
+        }
+    }
+
+    fn advance(&mut self) {
+        self.cur += 1;
+        self.ix += 1;
+    }
+
+    // Parse a string starting at the current (quote) character, returning the bounds of its
+    // contents. The closing quote is always the next structural character.
+    fn string(&mut self) -> Option<(usize, usize)> {
+        let start = self.ix + 1;
+        let close = *self.offs.get(self.cur + 1)? as usize;
+        if self.bs[close] != b'"' {
+            return None;
+        }
+        self.cur += 2;
+        self.ix = close + 1;
+        Some((start, close))
+    }
+
+    // Parse a number, boolean or null: everything up to the next structural character.
+    fn scalar(&mut self) -> Option<(usize, usize)> {
+        let start = self.ix;
+        let mut end = self
+            .offs
+            .get(self.cur)
+            .map_or(self.bs.len(), |off| *off as usize);
+        while end > start && is_ws(self.bs[end - 1]) {
+            end -= 1;
+        }
+        if end == start || self.bs[start..end].iter().any(|b| is_ws(*b)) {
+            return None;
+        }
+        self.ix = end;
+        Some((start, end))
+    }
+
+    fn value(&mut self, node: Option<&Node>) -> Option<()> {
+        self.skip_ws();
+        let node = match node {
+            Some(node) => node,
+            None => return self.skip(),
+        };
+        let start = self.ix;
+        match self.peek() {
+            Some(b'"') => {
+                let (s, e) = self.string()?;
+                if let Some(col) = node.column {
+                    (self.emit)(col, s, e, Value::Str);
+                }
+                return Some(());
+            }
+            Some(b'{') => self.object(node)?,
+            Some(b'[') => self.array(node)?,
+            Some(_) => return None,
+            None => {
+                let (s, e) = self.scalar()?;
+                if let Some(col) = node.column {
+                    let kind = if &self.bs[s..e] == b"null" {
+                        Value::Null
+                    } else {
+                        Value::Raw
+                    };
+                    (self.emit)(col, s, e, kind);
+                }
+                return Some(());
+            }
+        }
+        if let Some(col) = node.column {
+            (self.emit)(col, start, self.ix, Value::Raw);
+        }
+        Some(())
+    }
+
+    // Skip a value that no column refers to. Objects and arrays are skipped by matching up
+    // brackets, without looking at their contents.
+    fn skip(&mut self) -> Option<()> {
+        match self.peek() {
+            Some(b'"') => {
+                self.string()?;
+            }
+            Some(b'{') | Some(b'[') => {
+                let mut depth = 0;
+                loop {
+                    let off = *self.offs.get(self.cur)? as usize;
+                    self.cur += 1;
+                    match self.bs[off] {
+                        b'{' | b'[' => depth += 1,
+                        b'}' | b']' => {
+                            depth -= 1;
+                            if depth == 0 {
+                                self.ix = off + 1;
+                                break;
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            Some(_) => return None,
+            None => {
+                self.scalar()?;
+            }
+        }
+        Some(())
+    }
+
+    fn object(&mut self, node: &Node) -> Option<()> {
+        let bs = self.bs;
+        self.advance();
+        self.skip_ws();
+        if self.peek() == Some(b'}') {
+            self.advance();
+            return Some(());
+        }
+        loop {
+            self.skip_ws();
+            if self.peek()? != b'"' {
+                return None;
+            }
+            let (s, e) = self.string()?;
+            let key = &bs[s..e];
+            let child = if key.contains(&b'\\') {
+                node.member(&unescape(key))
+            } else {
+                node.member(key)
+            };
+            self.skip_ws();
+            if self.peek()? != b':' {
+                return None;
+            }
+            self.advance();
+            self.value(child)?;
+            self.skip_ws();
+            match self.peek()? {
+                b',' => self.advance(),
+                b'}' => {
+                    self.advance();
+                    return Some(());
+                }
+                _ => return None,
+            }
+        }
+    }
+
+    fn array(&mut self, node: &Node) -> Option<()> {
+        self.advance();
+        self.skip_ws();
+        if self.peek() == Some(b']') {
+            self.advance();
+            return Some(());
+        }
+        let mut i = 0;
+        loop {
+            self.value(node.element(i))?;
+            self.skip_ws();
+            match self.peek()? {
+                b',' => self.advance(),
+                b']' => {
+                    self.advance();
+                    return Some(());
+                }
+                _ => return None,
+            }
+            i += 1;
+        }
+    }
+}
+
+// Decode the escape sequences in the contents of a JSON string. Invalid escapes are left as they
+// are, and unpaired surrogates are replaced with U+FFFD.
+fn unescape(bs: &[u8]) -> Vec<u8> {
+    fn hex4(bs: &[u8]) -> Option<u32> {
+        let digits = str::from_utf8(bs.get(0..4)?).ok()?;
+        u32::from_str_radix(digits, 16).ok()
+    }
+    let mut res = Vec::with_capacity(bs.len());
+    let mut i = 0;
+    while i < bs.len() {
+        if bs[i] != b'\\' || i + 1 == bs.len() {
+            res.push(bs[i]);
+            i += 1;
+            continue;
+        }
+        i += 2;
+        let c = match bs[i - 1] {
+            b'"' => b'"',
+            b'\\' => b'\\',
+            b'/' => b'/',
+            b'b' => 8,
+            b'f' => 12,
+            b'n' => b'\n',
+            b'r' => b'\r',
+            b't' => b'\t',
+            b'u' => {
+                let hi = match hex4(&bs[i..]) {
+                    Some(hi) => hi,
+                    None => {
+                        res.extend_from_slice(b"\\u");
+                        continue;
+                    }
+                };
+                i += 4;
+                let mut code = hi;
+                if (0xD800..0xDC00).contains(&hi) && bs[i..].starts_with(b"\\u") {
+                    if let Some(lo @ 0xDC00..=0xDFFF) = hex4(&bs[i + 2..]) {
+                        code = 0x10000 + ((hi - 0xD800) << 10) + (lo - 0xDC00);
+                        i += 6;
+                    }
+                }
+                let c = std::char::from_u32(code).unwrap_or('\u{FFFD}');
+                res.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                continue;
+            }
+            c => {
+                res.push(b'\\');
+                c
+            }
+        };
+        res.push(c);
+    }
+    res
+}
+
+// Store the values of the columns in `keys` for the JSON value starting at `start` in `bs` into
+// `fields`, given the offsets of the value's structural characters. `slice` returns a range of
+// `bs` as a string. If the value is malformed, every column is empty.
+fn extract<'a>(
+    bs: &[u8],
+    offs: &[u64],
+    start: usize,
+    keys: &KeyPaths,
+    fields: &mut Vec<Str<'a>>,
+    slice: impl Fn(usize, usize) -> Str<'a>,
+) {
+    fields.clear();
+    fields.resize_with(keys.columns, Str::default);
+    if keys.root.is_empty() {
+        return;
+    }
+    let mut parser = Parser {
+        bs,
+        offs,
+        cur: 0,
+        ix: start,
+        emit: |col: usize, s: usize, e: usize, kind: Value| {
+            fields[col - 1] = match kind {
+                Value::Null => Str::default(),
+                Value::Str if bs[s..e].contains(&b'\\') => {
+                    Buf::read_from_bytes(&unescape(&bs[s..e])).into_str()
+                }
+                Value::Str | Value::Raw => slice(s, e),
+            }
+        },
+    };
+    let ok = parser.value(Some(&keys.root)).is_some() && {
+        parser.skip_ws();
+        parser.ix == bs.len() && parser.cur == offs.len()
+    };
+    if !ok {
+        for f in fields.iter_mut() {
+            *f = Str::default();
+        }
+    }
+}
+
+fn extract_str<'a>(s: &Str<'a>, keys: &KeyPaths, fields: &mut Vec<Str<'a>>) {
+    s.with_bytes(|bs| {
+        let mut offs = Offsets::default();
+        unsafe { get_find_indexes_json()(bs, &mut offs) };
+        extract(bs, &offs.fields[..], 0, keys, fields, |i, j| s.slice(i, j))
+    })
+}
+
+/// The value at `path` in the JSON text `s`, as it would appear in a column of JSON Lines input.
+/// This implements the `json` builtin.
+pub fn json_path<'a>(path: &Str<'a>, s: &Str<'a>) -> Str<'a> {
+    let keys = path.with_bytes(|p| KeyPaths::new(&[String::from_utf8_lossy(p)]));
+    let mut fields = Vec::with_capacity(1);
+    extract_str(s, &keys, &mut fields);
+    fields.pop().unwrap_or_default()
+}
+
+// Append `bs` to `out` as a JSON string. Quotes, backslashes and control characters are escaped
+// as RFC 8259 requires; all other bytes are copied as they are.
+fn push_escaped(out: &mut Vec<u8>, bs: &[u8]) {
+    out.push(b'"');
+    for &b in bs {
+        match b {
+            b'"' => out.extend_from_slice(br#"\""#),
+            b'\\' => out.extend_from_slice(br"\\"),
+            b'\n' => out.extend_from_slice(br"\n"),
+            b'\r' => out.extend_from_slice(br"\r"),
+            b'\t' => out.extend_from_slice(br"\t"),
+            8 => out.extend_from_slice(br"\b"),
+            12 => out.extend_from_slice(br"\f"),
+            0..=0x1f => out.extend_from_slice(format!("\\u{:04x}", b).as_bytes()),
+            b => out.push(b),
+        }
+    }
+    out.push(b'"');
+}
+
+/// Escape `s` as a JSON string, including the enclosing quotes. This implements the
+/// `escape_json` builtin.
+pub fn escape_json<'a>(s: &Str<'a>) -> Str<'a> {
+    s.with_bytes(|bs| {
+        let mut out = Vec::with_capacity(bs.len() + 2);
+        push_escaped(&mut out, bs);
+        Buf::read_from_bytes(&out).into_str()
+    })
+}
+
+/// Convert `f` to a JSON number according to `fmt`, the value of `OFMT`. JSON has no way to write
+/// infinities or NaN, so they are converted to `null`.
+pub fn float_to_json<'a>(f: Float, fmt: &Str) -> Result<Str<'a>> {
+    if f.is_finite() {
+        float_to_str_fmt(f, fmt)
+    } else {
+        Ok(Str::from("null"))
+    }
+}
+
+// A JSON object with the given keys and values, all of which are written as strings.
+pub(crate) fn json_object<'a, 'b, K: AsRef<[u8]>>(
+    members: impl Iterator<Item = (K, &'b Str<'a>)>,
+) -> Str<'static>
+where
+    'a: 'b,
+{
+    let mut out = vec![b'{'];
+    for (i, (k, v)) in members.enumerate() {
+        if i > 0 {
+            out.push(b',');
+        }
+        push_escaped(&mut out, k.as_ref());
+        out.push(b':');
+        v.with_bytes(|bs| push_escaped(&mut out, bs));
+    }
+    out.push(b'}');
+    Buf::read_from_bytes(&out).into_str()
+}
+
+// A JSON object holding `fields`, keyed by the corresponding entry of `names` or, past the end of
+// `names`, by column index. This is how $0 is rebuilt with JSON output.
+pub(crate) fn json_record<'a>(fields: &[Str<'a>], names: &[String]) -> Str<'static> {
+    json_object(fields.iter().enumerate().map(|(i, f)| match names.get(i) {
+        Some(name) => (name.clone(), f),
+        None => ((i + 1).to_string(), f),
+    }))
+}
+
+/// Columns `start` through `end` of `line` as a JSON object keyed in the same way as `json_record`.
+/// This implements the `join_json` builtin.
+pub fn join_json<'a, L: super::Line<'a>>(
+    line: &mut L,
+    start: Int,
+    end: Int,
+    nf: usize,
+) -> Result<Str<'a>> {
+    if nf == 0 && start > 0 && end == 0 {
+        // Columns 1 through NF of an empty record, as printed by `print` with no arguments.
+        return Ok(Str::from("{}"));
+    }
+    let names = line.names();
+    let mut col = start;
+    let members = line.join_cols(start, end, &Str::from(","), nf, |s| {
+        let mut out = Vec::new();
+        match names.get(col as usize - 1) {
+            Some(name) => push_escaped(&mut out, name.as_bytes()),
+            None => push_escaped(&mut out, col.to_string().as_bytes()),
+        }
+        out.push(b':');
+        s.with_bytes(|bs| push_escaped(&mut out, bs));
+        col += 1;
+        Buf::read_from_bytes(&out).into_str()
+    })?;
+    Ok(Str::concat(
+        Str::concat(Str::from("{"), members),
+        Str::from("}"),
+    ))
+}
+
+pub struct JSONReader<P> {
+    prod: P,
+    cur_chunk: OffsetChunk,
+    cur_buf: Buf,
+    buf_len: usize,
+    prev_ix: usize,
+    last_len: usize,
+    // The key path of each column, and a trie of the paths of the columns that are used.
+    paths: Arc<Vec<String>>,
+    keys: Arc<KeyPaths>,
+    empty_buf: Buf,
+    check_utf8: bool,
+}
+
+impl LineReader for JSONReader<Box<dyn ChunkProducer<Chunk = OffsetChunk>>> {
+    type Line = Line;
+    fn filename(&self) -> Str<'static> {
+        Str::from(self.cur_chunk.get_name()).unmoor()
+    }
+    fn check_utf8(&self) -> bool {
+        self.check_utf8
+    }
+    fn request_handles(&self, size: usize) -> Vec<Box<dyn FnOnce() -> Self + Send>> {
+        let producers = self.prod.try_dyn_resize(size);
+        let mut res = Vec::with_capacity(producers.len());
+        for p_factory in producers.into_iter() {
+            let paths = self.paths.clone();
+            let keys = self.keys.clone();
+            let check_utf8 = self.check_utf8;
+            res.push(Box::new(move || {
+                let empty_buf = UniqueBuf::new(0).into_buf();
+                let cur_buf = empty_buf.clone();
+                JSONReader {
+                    prod: p_factory(),
+                    cur_chunk: OffsetChunk::default(),
+                    cur_buf,
+                    empty_buf,
+                    buf_len: 0,
+                    prev_ix: 0,
+                    last_len: 0,
+                    paths,
+                    keys,
+                    check_utf8,
+                }
+            }) as _)
+        }
+        res
+    }
+    fn read_line(&mut self, _pat: &Str, _rc: &mut RegexCache) -> Result<(bool, Line)> {
+        let mut line = Line::default();
+        let changed = self.read_line_inner(&mut line)?;
+        Ok((changed, line))
+    }
+    fn read_line_reuse<'a, 'b: 'a>(
+        &'b mut self,
+        _pat: &Str,
+        _rc: &mut RegexCache,
+        old: &'a mut Line,
+    ) -> Result<bool> {
+        self.read_line_inner(old)
+    }
+    fn read_state(&self) -> i64 {
+        if self.cur_chunk.version != 0 && self.last_len == 0 {
+            ReaderState::EOF as i64
+        } else {
+            ReaderState::OK as i64
+        }
+    }
+    fn next_file(&mut self) -> Result<bool> {
+        self.cur_chunk.off.clear();
+        self.cur_buf = UniqueBuf::new(0).into_buf();
+        self.buf_len = 0;
+        self.prev_ix = 0;
+        self.prod.next_file()
+    }
+    fn set_used_fields(&mut self, field_set: &FieldSet) {
+        self.keys = Arc::new(KeyPaths::with_used(&self.paths[..], field_set));
+    }
+}
+
+impl JSONReader<Box<dyn ChunkProducer<Chunk = OffsetChunk>>> {
+    /// Read JSON Lines from `rs`, where column `i + 1` holds the value at `paths[i]`.
+    pub fn new<I, S>(
+        rs: I,
+        paths: Vec<String>,
+        chunk_size: usize,
+        check_utf8: bool,
+        exec_strategy: ExecutionStrategy,
+    ) -> Self
+    where
+        I: Iterator<Item = (S, String)> + Send + 'static,
+        S: Read + Send + 'static,
+    {
+        let prod: Box<dyn ChunkProducer<Chunk = OffsetChunk>> = match exec_strategy {
+            ExecutionStrategy::Serial => Box::new(chunk::new_chained_offset_chunk_producer_json(
+                rs, chunk_size, check_utf8,
+            )),
+            x @ ExecutionStrategy::ShardPerRecord => Box::new(ParallelChunkProducer::new(
+                move || chunk::new_chained_offset_chunk_producer_json(rs, chunk_size, check_utf8),
+                /*channel_size*/ x.num_workers() * 2,
+            )),
+            ExecutionStrategy::ShardPerFile => {
+                let iter = rs.enumerate().map(move |(i, (r, name))| {
+                    move || {
+                        chunk::new_offset_chunk_producer_json(
+                            r,
+                            chunk_size,
+                            name.as_str(),
+                            i as u32 + 1,
+                            check_utf8,
+                        )
+                    }
+                });
+                Box::new(ShardedChunkProducer::new(iter))
+            }
+        };
+        let empty_buf = UniqueBuf::new(0).into_buf();
+        let cur_buf = empty_buf.clone();
+        let keys = Arc::new(KeyPaths::new(&paths[..]));
+        JSONReader {
+            prod,
+            cur_buf,
+            buf_len: 0,
+            cur_chunk: OffsetChunk::default(),
+            prev_ix: 0,
+            last_len: 0,
+            paths: Arc::new(paths),
+            keys,
+            empty_buf,
+            check_utf8,
+        }
+    }
+}
+
+impl<P: ChunkProducer<Chunk = OffsetChunk>> JSONReader<P> {
+    fn refresh_buf(&mut self) -> Result<(/*is eof*/ bool, /* file changed */ bool)> {
+        let prev_version = self.cur_chunk.version;
+        let placeholder = self.empty_buf.clone();
+        let old_buf = mem::replace(&mut self.cur_buf, placeholder);
+        self.buf_len = 0;
+
+        // Send the chunks back, if possible, so they get freed in the same thread.
+        self.cur_chunk.buf = old_buf.try_unique().ok();
+        if self.prod.get_chunk(&mut self.cur_chunk)? {
+            self.cur_chunk.version = std::cmp::max(prev_version, 1);
+            return Ok((true, false));
+        }
+        self.cur_buf = self.cur_chunk.buf.take().unwrap().into_buf();
+        self.buf_len = self.cur_chunk.len;
+        self.prev_ix = 0;
+        Ok((false, prev_version != self.cur_chunk.version))
+    }
+
+    fn read_line_inner(&mut self, line: &mut Line) -> Result</*file changed*/ bool> {
+        let mut changed = false;
+        line.clear();
+        line.keys = self.keys.clone();
+        line.paths = self.paths.clone();
+        if self.cur_chunk.off.start == self.cur_chunk.off.fields.len() {
+            let (is_eof, has_changed) = self.refresh_buf()?;
+            changed = has_changed;
+            if is_eof && self.prev_ix >= self.buf_len {
+                self.last_len = 0;
+                return Ok(changed);
+            }
+        }
+        // Chunks end at a newline, so the structural characters of the record are all in the
+        // current chunk. Only the last record of the input may not end with a newline.
+        let bs = &self.cur_buf.as_bytes()[0..self.buf_len];
+        let off = &mut self.cur_chunk.off;
+        let first = off.start;
+        let mut last = off.fields.len();
+        let mut end = bs.len();
+        while off.start < off.fields.len() {
+            let ix = off.fields[off.start] as usize;
+            off.start += 1;
+            if bs[ix] == b'\n' {
+                last = off.start - 1;
+                end = ix;
+                break;
+            }
+        }
+        let start = self.prev_ix;
+        self.prev_ix = std::cmp::min(end + 1, bs.len());
+        self.last_len = self.prev_ix - start;
+        if end > start && bs[end - 1] == b'\r' {
+            end -= 1;
+        }
+        let buf = &self.cur_buf;
+        line.raw = buf.slice_to_str(start, end);
+        line.len = end - start;
+        extract(
+            &bs[0..end],
+            &off.fields[first..last],
+            start,
+            &self.keys,
+            &mut line.fields,
+            |i, j| buf.slice_to_str(i, j),
+        );
+        Ok(changed)
+    }
+}
+
+#[derive(Default, Clone, Debug)]
+pub struct Line {
+    raw: Str<'static>,
+    len: usize,
+    fields: Vec<Str<'static>>,
+    // The JSONReader that produced this line sets these: `keys` is used to extract the fields of
+    // a new value of $0, and `paths` names the columns.
+    keys: Arc<KeyPaths>,
+    paths: Arc<Vec<String>>,
+}
+
+impl Line {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    fn clear(&mut self) {
+        self.fields.clear();
+        self.raw = Str::default();
+        self.len = 0;
+    }
+}
+
+impl<'a> super::Line<'a> for Line {
+    fn join_cols<F>(
+        &mut self,
+        start: Int,
+        end: Int,
+        sep: &Str<'a>,
+        nf: usize,
+        trans: F,
+    ) -> Result<Str<'a>>
+    where
+        F: FnMut(Str<'static>) -> Str<'static>,
+    {
+        debug_assert_eq!(self.fields.len(), nf);
+        let (start, end) = normalize_join_indexes(start, end, nf)?;
+        let sep = sep.clone().unmoor();
+        Ok(sep
+            .join(self.fields[start..end].iter().cloned().map(trans))
+            .upcast())
+    }
+    fn nf(&mut self, _pat: &Str, _rc: &mut RegexCache) -> Result<usize> {
+        Ok(self.fields.len())
+    }
+
+    fn get_col(
+        &mut self,
+        col: Int,
+        _pat: &Str,
+        _ofs: &Str,
+        _rc: &mut RegexCache,
+    ) -> Result<Str<'a>> {
+        if col == 0 {
+            return Ok(self.raw.clone().upcast());
+        }
+        if col < 0 {
+            return err!("attempt to access negative index {}", col);
+        }
+        Ok(self
+            .fields
+            .get(col as usize - 1)
+            .cloned()
+            .unwrap_or_else(Str::default)
+            .upcast())
+    }
+
+    // Programs that assign to a key path or to NF are rejected when they are compiled, as there is
+    // no way to rebuild $0 from the paths that the program reads.
+    fn set_col(&mut self, col: Int, s: &Str<'a>, _pat: &Str, _rc: &mut RegexCache) -> Result<()> {
+        if col != 0 {
+            return err!("cannot assign to column {} of JSON Lines input", col);
+        }
+        self.raw = s.clone().unmoor();
+        extract_str(&self.raw, &self.keys, &mut self.fields);
+        self.len = self.raw.len();
+        Ok(())
+    }
+
+    fn set_nf(&mut self, _nf: Int, _pat: &Str, _rc: &mut RegexCache) -> Result<()> {
+        err!("cannot assign to NF of JSON Lines input")
+    }
+
+    fn names(&self) -> Arc<Vec<String>> {
+        self.paths.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn extract_all(paths: &[&str], s: &str) -> Vec<String> {
+        let keys = KeyPaths::new(paths);
+        let mut fields = Vec::new();
+        extract_str(&Str::from(s), &keys, &mut fields);
+        fields.iter().map(|f| f.to_string()).collect()
+    }
+
+    #[test]
+    fn json_extract_paths() {
+        let rec = r#"{"user": {"id": 7, "name": "Jörg \"J\"", "tags": ["a", "b"]}, "ok": true, "n": null}"#;
+        assert_eq!(
+            extract_all(
+                &[
+                    "user.id",
+                    "user.name",
+                    "user.tags.1",
+                    "ok",
+                    "n",
+                    "missing",
+                    "user.tags"
+                ],
+                rec
+            ),
+            vec!["7", "Jörg \"J\"", "b", "true", "", "", r#"["a", "b"]"#]
+        );
+        assert_eq!(
+            extract_all(&["", "0.x"], r#"[{"x": 1}] "#),
+            vec![r#"[{"x": 1}]"#, "1"]
+        );
+        assert_eq!(extract_all(&["a"], r#"{"a": "😀\n"}"#), vec!["😀\n"]);
+        // Malformed records have no fields.
+        assert_eq!(extract_all(&["a"], r#"{"a": 1"#), vec![""]);
+        assert_eq!(extract_all(&["a"], r#"{"a": 1} x"#), vec![""]);
+        assert_eq!(extract_all(&["a"], r#"{"a" 1}"#), vec![""]);
+        assert_eq!(extract_all(&["a"], ""), vec![""]);
+    }
+
+    #[test]
+    fn json_unused_paths() {
+        let paths = ["a", "b.c"];
+        let mut used = FieldSet::empty();
+        used.set(2);
+        let keys = KeyPaths::with_used(&paths, &used);
+        let mut fields = Vec::new();
+        extract_str(
+            &Str::from(r#"{"a": "x", "b": {"c": [1, {"d": "}"}]}}"#),
+            &keys,
+            &mut fields,
+        );
+        assert_eq!(
+            fields,
+            vec![Str::default(), Str::from(r#"[1, {"d": "}"}]"#)]
+        );
+    }
+
+    #[test]
+    fn json_escaping() {
+        assert_eq!(escape_json(&Str::from("plain")), Str::from(r#""plain""#));
+        assert_eq!(
+            escape_json(&Str::from("a \"b\"\\\n\t\r\u{8}\u{c}\u{1}é")),
+            Str::from(r#""a \"b\"\\\n\t\r\b\f\u0001é""#)
+        );
+        let (a, b) = (Str::from("x"), Str::from("\"y\""));
+        assert_eq!(
+            json_object(vec![("1", &a), ("k\"", &b)].into_iter()),
+            Str::from(r#"{"1":"x","k\"":"\"y\""}"#)
+        );
+        assert_eq!(
+            json_object(std::iter::empty::<(&str, &Str)>()),
+            Str::from("{}")
+        );
+    }
+
+    #[test]
+    fn json_reader() {
+        use crate::runtime::splitter::Line as _;
+        let mut input = String::new();
+        for i in 0..1000 {
+            input.push_str(&format!(
+                "{{\"id\": {}, \"s\": \"line\\n{}\", \"pad\": \"{}\"}}\r\n",
+                i,
+                i,
+                "x".repeat(i % 37)
+            ));
+        }
+        input.push_str(r#"{"id": "last"}"#);
+        let mut reader = JSONReader::new(
+            std::iter::once((std::io::Cursor::new(input), String::from("fake-stdin"))),
+            vec![String::from("s"), String::from("id")],
+            /*chunk_size=*/ 512,
+            /*check_utf8=*/ false,
+            ExecutionStrategy::Serial,
+        );
+        let mut rc = RegexCache::default();
+        let pat = Str::default();
+        for i in 0..1000 {
+            let (_, mut line) = reader
+                .read_line(&pat, &mut rc)
+                .expect("failed to read line");
+            assert_eq!(
+                line.fields,
+                vec![Str::from(format!("line\n{}", i)), Str::from(i as Int)]
+            );
+            let raw = line.get_col(0, &pat, &pat, &mut rc).unwrap();
+            assert!(raw.to_string().ends_with('}'));
+        }
+        let (_, line) = reader.read_line(&pat, &mut rc).unwrap();
+        assert_eq!(line.fields, vec![Str::default(), Str::from("last")]);
+        let (_, line) = reader.read_line(&pat, &mut rc).unwrap();
+        assert_eq!(line.len(), 0);
+        assert_eq!(reader.read_state(), ReaderState::EOF as i64);
+    }
+    #[test]
+    fn json_reader_unclosed_string() {
+        // A string that is never closed ends at the newline, along with its record.
+        let mut input = String::from("{\"a\":\"x}\n{\"a\":\"y\"}\n");
+        input.push_str(&format!("{{\"a\":\"{}\n", "p".repeat(100)));
+        input.push_str("{\"a\":\"z\"}\n");
+        let mut reader = JSONReader::new(
+            std::iter::once((std::io::Cursor::new(input), String::from("fake-stdin"))),
+            vec![String::from("a")],
+            /*chunk_size=*/ 64,
+            /*check_utf8=*/ false,
+            ExecutionStrategy::Serial,
+        );
+        let mut rc = RegexCache::default();
+        let pat = Str::default();
+        let mut got = Vec::new();
+        loop {
+            let (_, line) = reader.read_line(&pat, &mut rc).unwrap();
+            if reader.read_state() != ReaderState::OK as i64 {
+                break;
+            }
+            got.push((line.raw.to_string(), line.fields[0].to_string()));
+        }
+        let long = format!("{{\"a\":\"{}", "p".repeat(100));
+        let expected = vec![
+            (String::from("{\"a\":\"x}"), String::new()),
+            (String::from("{\"a\":\"y\"}"), String::from("y")),
+            (long, String::new()),
+            (String::from("{\"a\":\"z\"}"), String::from("z")),
+        ];
+        assert_eq!(got, expected);
+    }
+}
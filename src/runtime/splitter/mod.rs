@@ -7,15 +7,22 @@
 // TODO: add padding to the linereader trait
 pub mod batch;
 pub mod chunk;
+pub mod fixed;
+pub mod fpat;
+pub mod json;
 pub mod regex;
+pub mod sniff;
 
 use super::str_impl::{Buf, Str, UniqueBuf};
 use super::utf8::{is_utf8, validate_utf8_clipped};
-use super::{Int, LazyVec, RegexCache};
+use super::{writers::Registry, Int, LazyVec, RegexCache};
 use crate::common::Result;
 use crate::pushdown::FieldSet;
+use fixed::FieldWidths;
+use fpat::FieldPattern;
 
 use std::io::{ErrorKind, Read};
+use std::sync::Arc;
 
 // We have several implementations of "read and split a line"; they are governed by the LineReader
 // and Line traits.
@@ -34,6 +41,18 @@ pub trait Line<'a>: Default {
     fn nf(&mut self, pat: &Str, rc: &mut RegexCache) -> Result<usize>;
     fn get_col(&mut self, col: Int, pat: &Str, ofs: &Str, rc: &mut RegexCache) -> Result<Str<'a>>;
     fn set_col(&mut self, col: Int, s: &Str<'a>, pat: &Str, rc: &mut RegexCache) -> Result<()>;
+    // Assign to NF, truncating the line or padding it with empty fields.
+    fn set_nf(&mut self, nf: Int, pat: &Str, rc: &mut RegexCache) -> Result<()>;
+    // Is $0 rebuilt from the fields using the output escaper, because a field or NF was assigned
+    // after $0 was last set? If so, print does not escape it again.
+    fn rebuilt(&self) -> bool {
+        false
+    }
+    // The names of the columns (the header row, or the key paths of JSON Lines input), if known.
+    // JSON objects built from the line are keyed by these, and by column index past their end.
+    fn names(&self) -> Arc<Vec<String>> {
+        Default::default()
+    }
 }
 
 pub trait LineReader: Sized {
@@ -61,6 +80,15 @@ pub trait LineReader: Sized {
     fn read_state(&self) -> i64;
     fn next_file(&mut self) -> Result<bool>;
     fn set_used_fields(&mut self, _used_fields: &FieldSet);
+    // Called with the program's output files before any input is read. Readers that write out
+    // records themselves (like CSVReader with --csv-on-error=reject) go through these, so that
+    // the program's redirections and calls to close apply to them.
+    fn set_writers(&mut self, _writers: &Registry) {}
+    // The text that terminated the most recently read record, used to set RT. It is empty if the
+    // record ended at the end of the input.
+    fn last_rt(&self) -> Str<'static> {
+        Str::default()
+    }
     // Whether or not this LineReader is configured to check for valid UTF-8. This is used to
     // propagate consistent options across multiple LineReader instances.
     fn check_utf8(&self) -> bool;
@@ -95,6 +123,10 @@ pub struct DefaultLine {
     // After that first line, we set diverged to true, so we know to regenerate $0 when $0 is asked
     // for. This speeds up cases where multiple fields are assigned in a row.
     diverged: bool,
+    // Was this line read in "paragraph mode" (RS="")? If so, newlines also separate fields.
+    paragraph: bool,
+    // If set, fields are split according to FIELDWIDTHS or FPAT, and FS is ignored.
+    field_split: Option<Arc<FieldSplit>>,
 }
 
 impl Default for DefaultLine {
@@ -104,6 +136,8 @@ impl Default for DefaultLine {
             used_fields: FieldSet::all(),
             fields: LazyVec::new(),
             diverged: false,
+            paragraph: false,
+            field_split: None,
         }
     }
 }
@@ -111,10 +145,52 @@ impl Default for DefaultLine {
 impl DefaultLine {
     fn split_if_needed(&mut self, pat: &Str, rc: &mut RegexCache) -> Result<()> {
         if self.fields.len() == 0 {
-            rc.split_regex(pat, &self.line, &self.used_fields, &mut self.fields)?;
+            split_line(
+                pat,
+                &self.line,
+                &self.used_fields,
+                &mut self.fields,
+                self.paragraph,
+                self.field_split.as_deref(),
+                rc,
+            )?;
         }
         Ok(())
     }
+
+    fn split_all(&mut self, pat: &Str, rc: &mut RegexCache) -> Result<()> {
+        if self.used_fields == FieldSet::all() {
+            return self.split_if_needed(pat, rc);
+        }
+        // We projected out fields, but now we are setting one of them and will need all of them
+        // to regenerate $0. That means we have to split $0 in its entirety.
+        //
+        // This is strictly more work than just reading all of the fields in the first place; so
+        // once we hit this condition we overwrite the used fields with all() so this doesn't
+        // happen again for a while.
+        self.used_fields = FieldSet::all();
+        self.fields.clear();
+        self.split_if_needed(pat, rc)
+    }
+}
+
+fn split_line(
+    pat: &Str,
+    line: &Str<'static>,
+    used_fields: &FieldSet,
+    fields: &mut LazyVec<Str<'static>>,
+    paragraph: bool,
+    field_split: Option<&FieldSplit>,
+    rc: &mut RegexCache,
+) -> Result<()> {
+    if let Some(field_split) = field_split {
+        field_split.split(line, used_fields, fields);
+        Ok(())
+    } else if paragraph {
+        rc.split_regex_paragraph(pat, line, used_fields, fields)
+    } else {
+        rc.split_regex(pat, line, used_fields, fields)
+    }
 }
 
 impl<'a> Line<'a> for DefaultLine {
@@ -148,24 +224,6 @@ impl<'a> Line<'a> for DefaultLine {
         let res = if col == 0 && !self.diverged {
             self.line.clone()
         } else if col == 0 && self.diverged {
-            if self.used_fields != FieldSet::all() {
-                // We projected out fields, but now we have set one of the interior fields and need
-                // to print out $0. That means we have to split $0 in its entirety and then copy
-                // over the fields that were already set.
-                //
-                // This is strictly more work than just reading all of the fields in the first
-                // place; so once we hit this condition we overwrite the used fields with all() so
-                // this doesn't happen again for a while.
-                let old_set = std::mem::replace(&mut self.used_fields, FieldSet::all());
-                let mut new_vec = LazyVec::new();
-                rc.split_regex(pat, &self.line, &self.used_fields, &mut new_vec)?;
-                for i in 0..new_vec.len() {
-                    if old_set.get(i + 1) {
-                        new_vec.insert(i, self.fields.get(i).unwrap_or_else(Str::default));
-                    }
-                }
-                self.fields = new_vec;
-            }
             let res = self.fields.join_all(&ofs.clone().unmoor());
             self.line = res.clone();
             self.diverged = false;
@@ -187,11 +245,127 @@ impl<'a> Line<'a> for DefaultLine {
         if col < 0 {
             return err!("attempt to access field {}; field must be nonnegative", col);
         }
-        self.split_if_needed(pat, rc)?;
+        self.split_all(pat, rc)?;
         self.fields.insert(col as usize - 1, s.clone().unmoor());
         self.diverged = true;
         Ok(())
     }
+    fn set_nf(&mut self, nf: Int, pat: &Str, rc: &mut RegexCache) -> Result<()> {
+        if nf < 0 {
+            return err!("attempt to set NF to negative value {}", nf);
+        }
+        self.split_all(pat, rc)?;
+        self.fields.resize(nf as usize);
+        self.diverged = true;
+        Ok(())
+    }
+}
+
+/// A rule for splitting fields that takes the place of FS for an entire input: fixed-width columns
+/// (FIELDWIDTHS) or fields matching a pattern (FPAT).
+pub enum FieldSplit {
+    Widths(FieldWidths),
+    Pattern(FieldPattern),
+}
+
+impl FieldSplit {
+    /// Build a FieldSplit from the values of FIELDWIDTHS and FPAT, if either is set.
+    pub fn new(
+        widths: Option<&str>,
+        pattern: Option<&str>,
+        chars: bool,
+    ) -> Result<Option<FieldSplit>> {
+        match (widths, pattern) {
+            (Some(_), Some(_)) => err!("FIELDWIDTHS and FPAT cannot both be set"),
+            (Some(w), None) => Ok(Some(FieldSplit::Widths(FieldWidths::parse(w, chars)?))),
+            (None, Some(p)) => match FieldPattern::new(p) {
+                Ok(p) => Ok(Some(FieldSplit::Pattern(p))),
+                Err(e) => err!("invalid FPAT {:?}: {}", p, e),
+            },
+            (None, None) => Ok(None),
+        }
+    }
+
+    fn split<'a>(&self, line: &Str<'a>, used_fields: &FieldSet, fields: &mut LazyVec<Str<'a>>) {
+        match self {
+            FieldSplit::Widths(w) => w.split(line, used_fields, fields),
+            FieldSplit::Pattern(p) => p.split(line, used_fields, fields),
+        }
+    }
+}
+
+/// A LineReader that reads records from an underlying reader and splits them according to a
+/// FieldSplit rather than FS. The underlying reader should split its lines lazily (e.g.
+/// RegexSplitter); any fields it splits eagerly are discarded.
+pub struct FieldSplitReader<R> {
+    inner: R,
+    field_split: Arc<FieldSplit>,
+}
+
+impl<R: LineReader<Line = DefaultLine>> FieldSplitReader<R> {
+    pub fn new(inner: R, field_split: FieldSplit) -> Self {
+        FieldSplitReader {
+            inner,
+            field_split: Arc::new(field_split),
+        }
+    }
+
+    fn prepare(&self, line: &mut DefaultLine) {
+        line.fields.clear();
+        if line.field_split.is_none() {
+            line.field_split = Some(self.field_split.clone());
+        }
+    }
+}
+
+impl<R: LineReader<Line = DefaultLine> + 'static> LineReader for FieldSplitReader<R> {
+    type Line = DefaultLine;
+    fn filename(&self) -> Str<'static> {
+        self.inner.filename()
+    }
+    fn request_handles(&self, size: usize) -> Vec<Box<dyn FnOnce() -> Self + Send>> {
+        self.inner
+            .request_handles(size)
+            .into_iter()
+            .map(|h| {
+                let field_split = self.field_split.clone();
+                Box::new(move || FieldSplitReader {
+                    inner: h(),
+                    field_split,
+                }) as Box<_>
+            })
+            .collect()
+    }
+    fn read_line(&mut self, pat: &Str, rc: &mut RegexCache) -> Result<(bool, DefaultLine)> {
+        let (changed, mut line) = self.inner.read_line(pat, rc)?;
+        self.prepare(&mut line);
+        Ok((changed, line))
+    }
+    fn read_line_reuse<'a, 'b: 'a>(
+        &'b mut self,
+        pat: &Str,
+        rc: &mut RegexCache,
+        old: &'a mut DefaultLine,
+    ) -> Result<bool> {
+        let changed = self.inner.read_line_reuse(pat, rc, old)?;
+        self.prepare(old);
+        Ok(changed)
+    }
+    fn read_state(&self) -> i64 {
+        self.inner.read_state()
+    }
+    fn next_file(&mut self) -> Result<bool> {
+        self.inner.next_file()
+    }
+    fn set_used_fields(&mut self, used_fields: &FieldSet) {
+        self.inner.set_used_fields(used_fields)
+    }
+    fn last_rt(&self) -> Str<'static> {
+        self.inner.last_rt()
+    }
+    fn check_utf8(&self) -> bool {
+        self.inner.check_utf8()
+    }
 }
 
 pub struct ChainedReader<R>(Vec<R>, /*check_utf8=*/ bool);
@@ -271,6 +445,12 @@ where
             i.set_used_fields(used_fields);
         }
     }
+    fn last_rt(&self) -> Str<'static> {
+        self.0
+            .last()
+            .map(LineReader::last_rt)
+            .unwrap_or_else(Str::default)
+    }
 }
 
 // Buffer management and io
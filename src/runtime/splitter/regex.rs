@@ -4,10 +4,21 @@ use std::io::Read;
 use crate::common::Result;
 use crate::pushdown::FieldSet;
 use crate::runtime::{LazyVec, Str};
+use lazy_static::lazy_static;
 use regex::bytes::Regex;
 
 use super::{DefaultLine, LineReader, Reader, ReaderState};
 
+lazy_static! {
+    // The record separator used in "paragraph mode", when RS is the empty string.
+    static ref PARAGRAPH_SEP: Regex = Regex::new("\n\n+").unwrap();
+}
+
+// RS="" is compiled to the empty regex.
+fn is_paragraph_mode(pat: &Regex) -> bool {
+    pat.as_str().is_empty()
+}
+
 // TODO: this can probably just be "Splitter"
 pub struct RegexSplitter<R> {
     reader: Reader<R>,
@@ -15,6 +26,8 @@ pub struct RegexSplitter<R> {
     used_fields: FieldSet,
     // Used to trigger updating FILENAME on the first read.
     start: bool,
+    // The separator matched at the end of the last record.
+    last_rt: Str<'static>,
 }
 
 impl<R: Read> LineReader for RegexSplitter<R> {
@@ -44,6 +57,7 @@ impl<R: Read> LineReader for RegexSplitter<R> {
         old.fields.clear();
         rc.with_regex(pat, |re| {
             old.line = self.read_line_regex(re);
+            old.paragraph = is_paragraph_mode(re);
         })?;
         Ok(/* file changed */ start)
     }
@@ -56,6 +70,8 @@ impl<R: Read> LineReader for RegexSplitter<R> {
             fields: LazyVec::new(),
             used_fields: self.used_fields.clone(),
             diverged: false,
+            paragraph: is_paragraph_mode(re),
+            field_split: None,
         })?;
         Ok((/* file changed */ start, line))
     }
@@ -70,6 +86,9 @@ impl<R: Read> LineReader for RegexSplitter<R> {
     fn set_used_fields(&mut self, used_fields: &FieldSet) {
         self.used_fields = used_fields.clone();
     }
+    fn last_rt(&self) -> Str<'static> {
+        self.last_rt.clone()
+    }
 }
 
 impl<R: Read> RegexSplitter<R> {
@@ -79,17 +98,61 @@ impl<R: Read> RegexSplitter<R> {
             name: name.into(),
             used_fields: FieldSet::all(),
             start: true,
+            last_rt: Str::default(),
         }
     }
 
     pub fn read_line_regex(&mut self, pat: &Regex) -> Str<'static> {
         // We keep this as a separate method because it helps in writing tests.
-        let (res, consumed) = self.read_line_inner(pat);
+        let (res, consumed) = if is_paragraph_mode(pat) {
+            self.read_paragraph()
+        } else {
+            self.read_line_inner(pat)
+        };
         self.reader.last_len = consumed;
         res
     }
 
+    // In paragraph mode, records are separated by runs of blank lines, and newlines at the start
+    // and end of the input are ignored.
+    fn read_paragraph(&mut self) -> (Str<'static>, usize) {
+        // A separator consumes all adjacent newlines, so there is only something to skip at the
+        // start of the input.
+        let mut skipped = 0;
+        loop {
+            if self.reader.is_eof() {
+                self.last_rt = Str::default();
+                return (Str::default(), 0);
+            }
+            let bs = &self.reader.buf.as_bytes()[self.reader.start..self.reader.end];
+            let n = bs.iter().take_while(|b| **b == b'\n').count();
+            self.reader.start += n;
+            skipped += n;
+            if self.reader.start < self.reader.end {
+                break;
+            }
+            if let Err(_) = self.reader.reset() {
+                self.reader.state = ReaderState::ERROR;
+                self.last_rt = Str::default();
+                return (Str::default(), 0);
+            }
+        }
+        let (mut line, consumed) = self.read_line_inner(&*PARAGRAPH_SEP);
+        if self.last_rt.is_empty() {
+            // The last record in the input: trailing newlines become RT.
+            let len = line.len();
+            let trimmed =
+                line.with_bytes(|bs| bs.iter().rev().take_while(|b| **b == b'\n').count());
+            if trimmed > 0 {
+                self.last_rt = line.slice(len - trimmed, len);
+                line = line.slice(0, len - trimmed);
+            }
+        }
+        (line, consumed + skipped)
+    }
+
     fn read_line_inner(&mut self, pat: &Regex) -> (Str<'static>, usize) {
+        self.last_rt = Str::default();
         if self.reader.is_eof() {
             return (Str::default(), 0);
         }
@@ -106,6 +169,10 @@ impl<R: Read> RegexSplitter<R> {
                         .reader
                         .buf
                         .slice_to_str(self.reader.start, self.reader.start + start);
+                    self.last_rt = self
+                        .reader
+                        .buf
+                        .slice_to_str(self.reader.start + start, self.reader.start + end);
                     self.reader.start += end;
                     return (res, end);
                 }
@@ -155,6 +222,10 @@ impl<R: Read> RegexSplitter<R> {
                                 .reader
                                 .buf
                                 .slice_to_str(self.reader.start, self.reader.start + start);
+                            self.last_rt = self
+                                .reader
+                                .buf
+                                .slice_to_str(self.reader.start + start, self.reader.start + end);
                             self.reader.start += end;
                             (res, end)
                         }
@@ -252,6 +323,35 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_paragraph_split() {
+        let mut bs = String::from("\n\n\n");
+        let mut expected = Vec::new();
+        for i in 0..200 {
+            let para = format!("paragraph {}\nsecond line", i);
+            bs.push_str(&para);
+            bs.push_str(&"\n".repeat(2 + i % 3));
+            expected.push(para);
+        }
+        let c = Cursor::new(bs);
+        let empty = Regex::new("").unwrap();
+        let mut rdr =
+            RegexSplitter::new(c, /*chunk_size=*/ 64, "", /*check_utf8=*/ false);
+        let mut lines = Vec::new();
+        let mut rts = Vec::new();
+        loop {
+            let line = rdr.read_line_regex(&empty);
+            if rdr.read_state() == 0 {
+                break;
+            }
+            lines.push(String::from_utf8(line.with_bytes(|bs| bs.to_vec())).unwrap());
+            rts.push(rdr.last_rt().with_bytes(|bs| bs.len()));
+        }
+        assert_eq!(lines, expected);
+        let expected_rts: Vec<_> = (0..200).map(|i| 2 + i % 3).collect();
+        assert_eq!(rts, expected_rts);
+    }
+
     #[test]
     fn test_clipped_chunk_split_pp() {
         // _random is more thorough, but this works as a sort of smoke test.
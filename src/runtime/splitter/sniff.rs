@@ -0,0 +1,197 @@
+//! Guess the dialect of CSV-like input from a sample of it, for `--input-format auto`.
+//!
+//! Each candidate delimiter is used to split the sample into records, and it is scored by how
+//! consistently it splits those records into the same number of columns. The header heuristic is
+//! similar to that of Python's `csv.Sniffer`: a first row that looks different from the rows
+//! beneath it (text above a numeric column, or a value of a different length above a column of
+//! fixed-width values) is probably a header.
+use super::batch::{EscapeStyle, InputFormat};
+
+const CANDIDATES: &[u8] = b",\t;|";
+
+/// Guess the format of the input beginning with `sample`. If `complete` is false, the input
+/// continues past the end of `sample`, and its last record may be truncated. If `delimiter` is
+/// set, only the other parts of the format are guessed.
+pub fn sniff(sample: &[u8], complete: bool, delimiter: Option<u8>) -> InputFormat {
+    let quoted = sample.contains(&b'"');
+    // Only dialects with a quote character strip the '\r' from "\r\n" line endings.
+    let crlf = sample.windows(2).any(|w| w == b"\r\n");
+    let given;
+    let candidates = match delimiter {
+        Some(d) => {
+            given = [d];
+            &given[..]
+        }
+        None => CANDIDATES,
+    };
+    let mut best: Option<(u8, Vec<Vec<&[u8]>>, (usize, usize))> = None;
+    for &delim in candidates {
+        let mut records = split_sample(sample, delim, quoted);
+        if !complete && records.len() > 1 {
+            records.pop();
+        }
+        let score = score(&records);
+        // The first candidate wins ties.
+        if best.as_ref().map_or(true, |(_, _, best)| score > *best) {
+            best = Some((delim, records, score));
+        }
+    }
+    let (best_delim, records, (consistent, columns)) = best.unwrap();
+    let delimiter = match delimiter {
+        Some(d) => d,
+        // Every candidate splits the sample into a single column.
+        None if consistent == 0 || columns < 2 => b',',
+        None => best_delim,
+    };
+    InputFormat {
+        delimiter,
+        quote: if quoted || crlf { Some(b'"') } else { None },
+        // Backslashes are only special in input that already uses quotes.
+        escape: if quoted {
+            EscapeStyle::Backslash
+        } else {
+            EscapeStyle::Doubled
+        },
+        comment: None,
+        header: has_header(&records),
+        strict: false,
+    }
+}
+
+// Split `sample` into records of fields, ignoring delimiters and newlines inside of quotes if
+// `quoted` is set. Empty lines are skipped, and quotes are not removed from fields.
+fn split_sample(sample: &[u8], delim: u8, quoted: bool) -> Vec<Vec<&[u8]>> {
+    let mut records = Vec::new();
+    let mut cur = Vec::new();
+    let mut in_quote = false;
+    let mut start = 0;
+    for (i, b) in sample.iter().cloned().enumerate() {
+        if quoted && b == b'"' {
+            in_quote = !in_quote;
+        } else if !in_quote && b == delim {
+            cur.push(&sample[start..i]);
+            start = i + 1;
+        } else if !in_quote && b == b'\n' {
+            let mut end = i;
+            if end > start && sample[end - 1] == b'\r' {
+                end -= 1;
+            }
+            if cur.len() > 0 || end > start {
+                cur.push(&sample[start..end]);
+                records.push(std::mem::replace(&mut cur, Vec::new()));
+            }
+            start = i + 1;
+        }
+    }
+    if start < sample.len() {
+        cur.push(&sample[start..]);
+    }
+    if cur.len() > 0 {
+        records.push(cur);
+    }
+    records
+}
+
+// Score a split of the sample: the number of records with the most common column count, followed
+// by that column count. Records with a single column do not count as consistent.
+fn score(records: &[Vec<&[u8]>]) -> (usize, usize) {
+    use hashbrown::HashMap;
+    let mut counts = HashMap::new();
+    for r in records.iter() {
+        if r.len() > 1 {
+            *counts.entry(r.len()).or_insert(0usize) += 1;
+        }
+    }
+    counts
+        .into_iter()
+        .map(|(columns, n)| (n, columns))
+        .max()
+        .unwrap_or((0, 1))
+}
+
+fn has_header(records: &[Vec<&[u8]>]) -> bool {
+    fn unquote(f: &[u8]) -> &[u8] {
+        if f.len() >= 2 && f[0] == b'"' && f[f.len() - 1] == b'"' {
+            &f[1..f.len() - 1]
+        } else {
+            f
+        }
+    }
+    fn is_numeric(f: &[u8]) -> bool {
+        std::str::from_utf8(f).map_or(false, |s| s.trim().parse::<f64>().is_ok())
+    }
+    // A single column gives too little to go on: a word followed by longer or shorter words is
+    // about as likely to be data as a header.
+    let (first, rest) = match records.split_first() {
+        Some((first, rest)) if rest.len() > 0 && first.len() > 1 => (first, rest),
+        _ => return false,
+    };
+    let mut votes = 0i64;
+    for (i, name) in first.iter().cloned().map(unquote).enumerate() {
+        let mut column = rest
+            .iter()
+            .filter(|r| r.len() == first.len())
+            .map(|r| unquote(r[i]));
+        let head = match column.next() {
+            Some(head) => head,
+            None => return false,
+        };
+        if is_numeric(head) && column.clone().all(is_numeric) {
+            votes += if is_numeric(name) { -1 } else { 1 };
+        } else if column.clone().next().is_some() && column.all(|f| f.len() == head.len()) {
+            // Any single row has fields of a consistent length, so this needs two or more.
+            votes += if name.len() == head.len() { -1 } else { 1 };
+        }
+    }
+    votes > 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn delim_header(sample: &str) -> (char, bool) {
+        let ifmt = sniff(sample.as_bytes(), true, None);
+        (ifmt.delimiter as char, ifmt.header)
+    }
+
+    #[test]
+    fn sniff_delimiters() {
+        assert_eq!(delim_header("a,b,c\n1,2,3\n4,5,6\n"), (',', true));
+        assert_eq!(delim_header("a\tb\n1\t2 3\n4\t5 6\n"), ('\t', true));
+        assert_eq!(delim_header("x;1,5;y\nz;2,5;w\n"), (';', false));
+        assert_eq!(delim_header("a|b|c\n1|2,5|3\n"), ('|', true));
+        assert_eq!(delim_header("abc\ndef\nxyz\n"), (',', false));
+        // Quoted delimiters do not count.
+        let ifmt = sniff(b"name;note\n\"a\";\"x,y,z\"\n\"b\";\"p,q\"\n", true, None);
+        assert_eq!(ifmt.delimiter, b';');
+        assert_eq!(ifmt.quote, Some(b'"'));
+        assert_eq!(sniff(b"a;b\n1;2\n", true, None).quote, None);
+        // Incomplete samples drop their last (possibly truncated) record.
+        assert_eq!(
+            sniff(b"a;b\n1;2\n3;4\n5,6,7,8", false, None).delimiter,
+            b';'
+        );
+        assert_eq!(sniff(b"a,b\r\n1,2\r\n", true, None).quote, Some(b'"'));
+        // A given delimiter is used even if another one splits the sample more consistently.
+        let sample = b"ab,c;name\n1,2;3\n4,5;6\n";
+        assert_eq!(sniff(sample, true, None).delimiter, b',');
+        let ifmt = sniff(sample, true, Some(b';'));
+        assert_eq!((ifmt.delimiter, ifmt.header), (b';', true));
+    }
+
+    #[test]
+    fn sniff_header() {
+        assert!(!sniff(b"1,2\n3,4\n", true, None).header);
+        assert!(sniff(b"id,code\n1,ab\n2,cd\n", true, None).header);
+        assert!(!sniff(b"ab,1\ncd,2\n", true, None).header);
+        // A single row is never a header.
+        assert!(!sniff(b"name,age\n", true, None).header);
+        // Neither is the first row of single-column input, and field lengths are only compared
+        // with two or more data rows.
+        assert!(!sniff(b"justone\nline\n", true, None).header);
+        assert!(!sniff(b"id\n1\n2\n", true, None).header);
+        assert!(!sniff(b"name,city\nbob,LA\n", true, None).header);
+        assert!(sniff(b"name,city\nbob,LAX\nann,SFO\n", true, None).header);
+    }
+}
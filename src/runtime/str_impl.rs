@@ -7,7 +7,7 @@
 ///
 /// TODO explain more about what is going on here.
 use crate::pushdown::FieldSet;
-use crate::runtime::{Float, Int};
+use crate::runtime::{utf8, Float, Int};
 
 use regex::bytes::Regex;
 use smallvec::SmallVec;
@@ -481,10 +481,113 @@ impl<'a> Str<'a> {
         })
     }
 
+    /// Substitute matches of `pat` in `self` with `subst`, following gawk's `gensub`.
+    ///
+    /// Unlike `subst_first` and `subst_all`, `subst` may refer to capture groups: `\0` (or `&`) is
+    /// the entire match and `\1` through `\9` are the corresponding groups; `\&` and `\\` produce
+    /// a literal `&` and `\`. If `how` starts with "g" or "G", all matches are replaced, otherwise
+    /// it is interpreted as a number n and only the nth match is replaced.
+    pub fn gensub(&self, pat: &Regex, subst: &Str<'a>, how: &Str<'a>) -> Str<'a> {
+        let nth = how.with_bytes(|how| match how.first() {
+            Some(b'g') | Some(b'G') => None,
+            // gawk treats a non-positive occurrence as 1 (after printing a warning).
+            _ => Some(std::cmp::max(crate::runtime::strtoi(how), 1)),
+        });
+        self.with_bytes(|s| {
+            subst.with_bytes(|subst| {
+                let mut buf = DynamicBuf::new(0);
+                let mut prev = 0;
+                let mut replaced = false;
+                for (i, caps) in pat.captures_iter(s).enumerate() {
+                    if let Some(n) = nth {
+                        if i as Int + 1 != n {
+                            continue;
+                        }
+                    }
+                    let m = caps.get(0).unwrap();
+                    buf.write(&s[prev..m.start()]).unwrap();
+                    expand_gensub(&caps, subst, &mut buf);
+                    prev = m.end();
+                    replaced = true;
+                    if nth.is_some() {
+                        break;
+                    }
+                }
+                if !replaced {
+                    self.clone()
+                } else {
+                    buf.write(&s[prev..s.len()]).unwrap();
+                    unsafe { buf.into_str() }
+                }
+            })
+        })
+    }
+
+    pub fn to_upper(&self) -> Str<'a> {
+        self.convert_case(/*upper=*/ true)
+    }
+
+    pub fn to_lower(&self) -> Str<'a> {
+        self.convert_case(/*upper=*/ false)
+    }
+
+    // ASCII strings are converted byte-by-byte, while other valid UTF-8 strings get the full
+    // Unicode case mapping. For strings that are not valid UTF-8, we convert ASCII letters and
+    // pass all other bytes through unchanged.
+    fn convert_case(&self, upper: bool) -> Str<'a> {
+        self.with_bytes(|bs| {
+            if !bs.is_ascii() {
+                if let Ok(s) = str::from_utf8(bs) {
+                    return if upper {
+                        s.to_uppercase().into()
+                    } else {
+                        s.to_lowercase().into()
+                    };
+                }
+            }
+            let needs_conversion = |b: &u8| {
+                if upper {
+                    b.is_ascii_lowercase()
+                } else {
+                    b.is_ascii_uppercase()
+                }
+            };
+            if !bs.iter().any(needs_conversion) {
+                return self.clone();
+            }
+            let mut buf = UniqueBuf::new(bs.len());
+            let res = buf.as_mut_bytes();
+            res.copy_from_slice(bs);
+            if upper {
+                res.make_ascii_uppercase();
+            } else {
+                res.make_ascii_lowercase();
+            }
+            buf.into_buf().into_str()
+        })
+    }
+
     pub fn len(&self) -> usize {
         unsafe { self.rep_mut() }.len()
     }
 
+    /// The length of the string in characters, as used by `--chars`. Strings that are ASCII or
+    /// that are not valid UTF-8 are measured in bytes.
+    pub fn char_len(&self) -> usize {
+        self.with_bytes(|bs| utf8::char_offset(bs, bs.len()))
+    }
+
+    /// Like `slice`, but `from` and `to` are character offsets, clamped to the length of the
+    /// string.
+    pub fn char_slice(&self, from: usize, to: usize) -> Str<'a> {
+        let (from, to) =
+            self.with_bytes(|bs| (utf8::byte_offset(bs, from), utf8::byte_offset(bs, to)));
+        if from >= to {
+            return Str::default();
+        }
+        self.slice(from, to)
+    }
+
     pub fn concat(left: Str<'a>, right: Str<'a>) -> Str<'a> {
         if left.is_empty() {
             mem::forget(left);
@@ -754,6 +857,37 @@ impl<'a> Str<'a> {
     }
 }
 
+// Write the replacement text for a single `gensub` match to `buf`. Groups that did not
+// participate in the match expand to the empty string, and unrecognized escapes are kept as-is.
+fn expand_gensub(caps: &regex::bytes::Captures, subst: &[u8], buf: &mut DynamicBuf) {
+    let group = |n: usize, buf: &mut DynamicBuf| {
+        if let Some(m) = caps.get(n) {
+            buf.write(m.as_bytes()).unwrap();
+        }
+    };
+    let mut i = 0;
+    while i < subst.len() {
+        match (subst[i], subst.get(i + 1)) {
+            (b'\\', Some(d)) if d.is_ascii_digit() => {
+                group((d - b'0') as usize, buf);
+                i += 2;
+            }
+            (b'\\', Some(c @ b'&')) | (b'\\', Some(c @ b'\\')) => {
+                buf.write(&[*c]).unwrap();
+                i += 2;
+            }
+            (b'&', _) => {
+                group(0, buf);
+                i += 1;
+            }
+            (b, _) => {
+                buf.write(&[b]).unwrap();
+                i += 1;
+            }
+        }
+    }
+}
+
 impl<'a> Clone for Str<'a> {
     fn clone(&self) -> Str<'a> {
         let rep = unsafe { self.rep_mut() };
@@ -1311,6 +1445,47 @@ And this is the second part"#
         assert!(subbed);
     }
 
+    #[test]
+    fn gensub() {
+        let s: Str = "one two three".into();
+        let re = Regex::new("(t)(w|h)").unwrap();
+        let all: Str = "g".into();
+        let second: Str = "2".into();
+        let subst: Str = r"<\2\1|&|\&|\\>".into();
+        s.gensub(&re, &subst, &all)
+            .with_bytes(|bs| assert_eq!(bs, br"one <wt|tw|&|\>o <ht|th|&|\>ree"));
+        s.gensub(&re, &subst, &second)
+            .with_bytes(|bs| assert_eq!(bs, br"one two <ht|th|&|\>ree"));
+
+        let none: Str = "3".into();
+        assert_eq!(s.gensub(&re, &subst, &none), s);
+    }
+
+    #[test]
+    fn convert_case() {
+        let s1: Str = "Hello, World! This string is not inlined".into();
+        s1.to_upper()
+            .with_bytes(|bs| assert_eq!(bs, b"HELLO, WORLD! THIS STRING IS NOT INLINED"));
+        s1.to_lower()
+            .with_bytes(|bs| assert_eq!(bs, b"hello, world! this string is not inlined"));
+
+        let s2: Str = "straße ÉCOLE".into();
+        s2.to_upper()
+            .with_bytes(|bs| assert_eq!(bs, "STRASSE ÉCOLE".as_bytes()));
+        s2.to_lower()
+            .with_bytes(|bs| assert_eq!(bs, "straße école".as_bytes()));
+
+        let invalid: &[u8] = b"ab\xffCD\xc3";
+        let s3: Str = invalid.into();
+        s3.to_upper()
+            .with_bytes(|bs| assert_eq!(bs, b"AB\xffCD\xc3"));
+        s3.to_lower()
+            .with_bytes(|bs| assert_eq!(bs, b"ab\xffcd\xc3"));
+
+        let empty = Str::default();
+        assert_eq!(empty.to_upper(), empty);
+    }
+
     #[bench]
     fn bench_get_bytes_drop_empty(b: &mut Bencher) {
         b.iter(|| {
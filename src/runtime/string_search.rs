@@ -4,7 +4,7 @@
 //! library. Now, we implement string searching in terms of memchr. For extremely long strings,
 //! something like Teddy would probably work better, but this has fewer runtime requirements and
 //! won't have much overhead for the common case use-caes of small strings.
-use super::{Int, Str};
+use super::{utf8, Int, Str};
 use memchr::{memchr, memchr_iter};
 
 // 1-indexed, 0 on failure
@@ -15,6 +15,14 @@ pub fn index_substr<'a, 'b>(needle: &Str<'a>, haystack: &Str<'a>) -> Int {
         .unwrap_or(0)
 }
 
+// Like index_substr, but the index is a character offset.
+pub fn index_substr_chars<'a>(needle: &Str<'a>, haystack: &Str<'a>) -> Int {
+    needle
+        .with_bytes(|n| haystack.with_bytes(|h| index(n, h).map(|x| utf8::char_offset(h, x))))
+        .map(|x| x as Int + 1)
+        .unwrap_or(0)
+}
+
 fn index(needle: &[u8], haystack: &[u8]) -> Option<usize> {
     let (needle, first) = match needle.len() {
         0 => return Some(0),
@@ -0,0 +1,323 @@
+//! Support for the time functions `systime`, `strftime`, `mktime` and `parse_time`.
+//!
+//! Conversions to and from local time go through the C library, so they respect the `TZ`
+//! environment variable. Setting `TZ` (e.g. to `UTC`) pins the timezone used by frawk.
+use std::ffi::CString;
+use std::mem;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::runtime::{Int, Str};
+
+/// The format used by `strftime` when none is provided; it matches the output of `date`.
+pub const DEFAULT_FORMAT: &'static str = "%a %b %e %H:%M:%S %Z %Y";
+
+extern "C" {
+    // Not exposed by the libc crate.
+    fn tzset();
+}
+
+/// The current time, in seconds since the Unix epoch.
+pub fn systime() -> Int {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs() as Int,
+        Err(e) => -(e.duration().as_secs() as Int),
+    }
+}
+
+/// Format the timestamp `ts` according to `fmt`, using the same conversions as strftime(3). The
+/// time is formatted in UTC if `utc` is set, and in the local timezone otherwise.
+pub fn strftime(fmt: &Str, ts: Int, utc: bool) -> Str<'static> {
+    let fmt = match fmt.with_bytes(|bs| CString::new(bs)) {
+        Ok(fmt) => fmt,
+        Err(_) => return Str::default(),
+    };
+    let t = ts as libc::time_t;
+    let mut tm: libc::tm = unsafe { mem::zeroed() };
+    let res = unsafe {
+        if utc {
+            libc::gmtime_r(&t, &mut tm)
+        } else {
+            tzset();
+            libc::localtime_r(&t, &mut tm)
+        }
+    };
+    if res.is_null() || fmt.as_bytes().is_empty() {
+        return Str::default();
+    }
+    // strftime returns 0 if the output does not fit in the buffer, but also for formats that
+    // expand to the empty string (like "%p" in some locales), so we give up after a few tries.
+    let mut buf = vec![0u8; 64 + fmt.as_bytes().len() * 4];
+    loop {
+        let len = unsafe {
+            libc::strftime(
+                buf.as_mut_ptr() as *mut libc::c_char,
+                buf.len(),
+                fmt.as_ptr(),
+                &tm,
+            )
+        };
+        if len > 0 || buf.len() >= 1 << 16 {
+            return Str::from(&buf[..len]).unmoor();
+        }
+        buf.resize(buf.len() * 2, 0);
+    }
+}
+
+/// Convert a local time of the form "YYYY MM DD HH MM SS [DST]" to seconds since the epoch, as in
+/// gawk's `mktime`. Out-of-range values are normalized (so month 13 is January of the next year);
+/// -1 is returned if the specification is malformed.
+pub fn mktime(spec: &Str) -> Int {
+    let mut fields = [0 as Int; 7];
+    // A missing DST flag means "determine it from the timezone".
+    fields[6] = -1;
+    let nfields = spec.with_bytes(|bs| {
+        let mut n = 0;
+        for tok in bs
+            .split(|b| b.is_ascii_whitespace())
+            .filter(|tok| !tok.is_empty())
+        {
+            if n == fields.len() {
+                return None;
+            }
+            fields[n] = std::str::from_utf8(tok).ok()?.parse().ok()?;
+            n += 1;
+        }
+        Some(n)
+    });
+    match nfields {
+        Some(n) if n >= 6 => {}
+        _ => return -1,
+    }
+    let mut tm: libc::tm = unsafe { mem::zeroed() };
+    tm.tm_year = (fields[0] - 1900) as libc::c_int;
+    tm.tm_mon = (fields[1] - 1) as libc::c_int;
+    tm.tm_mday = fields[2] as libc::c_int;
+    tm.tm_hour = fields[3] as libc::c_int;
+    tm.tm_min = fields[4] as libc::c_int;
+    tm.tm_sec = fields[5] as libc::c_int;
+    tm.tm_isdst = fields[6] as libc::c_int;
+    local_to_epoch(&mut tm)
+}
+
+/// Parse a timestamp into seconds since the epoch, returning -1 if `s` is not a valid time.
+///
+/// If `fmt` is empty, `s` is parsed as an ISO-8601/RFC-3339 timestamp like
+/// "2020-06-01T12:30:00.25+02:00"; the time, seconds, fractional seconds and UTC offset are
+/// optional, and timestamps without an offset are interpreted in the local timezone. Otherwise,
+/// `s` is parsed with strptime(3) using `fmt`, also in the local timezone.
+pub fn parse_time(s: &Str, fmt: &Str) -> Int {
+    s.with_bytes(|bs| {
+        let bs = trim_ascii(bs);
+        if fmt.with_bytes(|fmt| fmt.is_empty()) {
+            parse_iso8601(bs).unwrap_or(-1)
+        } else {
+            fmt.with_bytes(|fmt| parse_strptime(bs, fmt)).unwrap_or(-1)
+        }
+    })
+}
+
+fn local_to_epoch(tm: &mut libc::tm) -> Int {
+    unsafe {
+        tzset();
+        libc::mktime(tm) as Int
+    }
+}
+
+fn trim_ascii(mut bs: &[u8]) -> &[u8] {
+    while let Some((b, rest)) = bs.split_first() {
+        if !b.is_ascii_whitespace() {
+            break;
+        }
+        bs = rest;
+    }
+    while let Some((b, rest)) = bs.split_last() {
+        if !b.is_ascii_whitespace() {
+            break;
+        }
+        bs = rest;
+    }
+    bs
+}
+
+fn parse_strptime(s: &[u8], fmt: &[u8]) -> Option<Int> {
+    let s = CString::new(s).ok()?;
+    let fmt = CString::new(fmt).ok()?;
+    let mut tm: libc::tm = unsafe { mem::zeroed() };
+    tm.tm_mday = 1;
+    let end = unsafe { libc::strptime(s.as_ptr(), fmt.as_ptr(), &mut tm) };
+    // Reject partial matches.
+    if end.is_null() || unsafe { *end } != 0 {
+        return None;
+    }
+    tm.tm_isdst = -1;
+    Some(local_to_epoch(&mut tm))
+}
+
+// A cursor over the bytes of an ISO-8601 timestamp.
+struct Cursor<'a> {
+    bs: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.bs.get(self.pos).cloned()
+    }
+    fn eat(&mut self, b: u8) -> bool {
+        if self.peek() == Some(b) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+    // Parse exactly `n` decimal digits.
+    fn digits(&mut self, n: usize) -> Option<Int> {
+        let ds = self.bs.get(self.pos..self.pos + n)?;
+        let mut res = 0;
+        for d in ds {
+            if !d.is_ascii_digit() {
+                return None;
+            }
+            res = res * 10 + (d - b'0') as Int;
+        }
+        self.pos += n;
+        Some(res)
+    }
+    fn done(&self) -> bool {
+        self.pos == self.bs.len()
+    }
+}
+
+fn parse_iso8601(bs: &[u8]) -> Option<Int> {
+    let mut c = Cursor { bs, pos: 0 };
+    let year = c.digits(4)?;
+    if !c.eat(b'-') {
+        return None;
+    }
+    let month = c.digits(2)?;
+    if !c.eat(b'-') {
+        return None;
+    }
+    let day = c.digits(2)?;
+    if month < 1 || month > 12 || day < 1 || day > days_in_month(year, month) {
+        return None;
+    }
+    let (mut hour, mut min, mut sec) = (0, 0, 0);
+    if c.eat(b'T') || c.eat(b't') || c.eat(b' ') {
+        hour = c.digits(2)?;
+        if !c.eat(b':') {
+            return None;
+        }
+        min = c.digits(2)?;
+        if c.eat(b':') {
+            sec = c.digits(2)?;
+            // Fractional seconds are accepted, but truncated.
+            if c.eat(b'.') || c.eat(b',') {
+                let start = c.pos;
+                while c.peek().map_or(false, |b| b.is_ascii_digit()) {
+                    c.pos += 1;
+                }
+                if c.pos == start {
+                    return None;
+                }
+            }
+        }
+        // Allow for leap seconds.
+        if hour > 23 || min > 59 || sec > 60 {
+            return None;
+        }
+    }
+    let offset = match c.peek() {
+        None => None,
+        Some(b'Z') | Some(b'z') => {
+            c.pos += 1;
+            Some(0)
+        }
+        Some(sign @ b'+') | Some(sign @ b'-') => {
+            c.pos += 1;
+            let hours = c.digits(2)?;
+            c.eat(b':');
+            let mins = if c.done() { 0 } else { c.digits(2)? };
+            if hours > 23 || mins > 59 {
+                return None;
+            }
+            let off = hours * 3600 + mins * 60;
+            Some(if sign == b'-' { -off } else { off })
+        }
+        Some(_) => return None,
+    };
+    if !c.done() {
+        return None;
+    }
+    Some(match offset {
+        Some(off) => days_from_civil(year, month, day) * 86400 + hour * 3600 + min * 60 + sec - off,
+        None => {
+            let mut tm: libc::tm = unsafe { mem::zeroed() };
+            tm.tm_year = (year - 1900) as libc::c_int;
+            tm.tm_mon = (month - 1) as libc::c_int;
+            tm.tm_mday = day as libc::c_int;
+            tm.tm_hour = hour as libc::c_int;
+            tm.tm_min = min as libc::c_int;
+            tm.tm_sec = sec as libc::c_int;
+            tm.tm_isdst = -1;
+            local_to_epoch(&mut tm)
+        }
+    })
+}
+
+fn is_leap_year(year: Int) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+fn days_in_month(year: Int, month: Int) -> Int {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// The number of days between 1970-01-01 and the given date in the proleptic Gregorian calendar,
+// following Howard Hinnant's `days_from_civil` algorithm.
+fn days_from_civil(year: Int, month: Int, day: Int) -> Int {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = if y >= 0 { y } else { y - 399 } / 400;
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn iso8601() {
+        let parse = |s: &str| parse_iso8601(s.as_bytes());
+        assert_eq!(parse("1970-01-01T00:00:00Z"), Some(0));
+        assert_eq!(parse("2000-02-29T12:34:56Z"), Some(951827696));
+        assert_eq!(parse("2000-02-29 12:34:56.789z"), Some(951827696));
+        assert_eq!(parse("2020-06-01T12:30:00+02:00"), Some(1591007400));
+        assert_eq!(parse("2020-06-01T12:30-0230"), Some(1591023600));
+        assert_eq!(parse("1969-12-31T23:59:59Z"), Some(-1));
+        assert_eq!(parse("2021-02-29T00:00:00Z"), None);
+        assert_eq!(parse("2021-01-01T24:00:00Z"), None);
+        assert_eq!(parse("2021-01-01T10Z"), None);
+        assert_eq!(parse("2021-01-01Tfoo"), None);
+        assert_eq!(parse("not a date"), None);
+    }
+
+    #[test]
+    fn utc_strftime() {
+        let fmt: Str = "%Y-%m-%d %H:%M:%S".into();
+        assert_eq!(
+            strftime(&fmt, 951827696, /*utc=*/ true),
+            Str::from("2000-02-29 12:34:56")
+        );
+        assert_eq!(strftime(&"".into(), 0, true), Str::default());
+    }
+}
@@ -131,6 +131,54 @@ pub(crate) fn is_utf8(mut bs: &[u8]) -> bool {
     }
 }
 
+pub(crate) fn is_ascii(bs: &[u8]) -> bool {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        if is_x86_feature_detected!("sse2") {
+            unsafe { x86::validate_ascii(bs) }
+        } else {
+            bs.is_ascii()
+        }
+    }
+
+    #[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+    {
+        bs.is_ascii()
+    }
+}
+
+/// Whether `bs` is valid UTF-8 containing at least one multi-byte character. Functions that count
+/// characters rather than bytes only need to do any extra work for these strings: ASCII strings
+/// and strings that are not valid UTF-8 are indexed by byte.
+pub(crate) fn has_multibyte_chars(bs: &[u8]) -> bool {
+    !is_ascii(bs) && is_utf8(bs)
+}
+
+/// The number of characters in `bs[..off]`, if `bs` has multi-byte characters. `off` is clamped
+/// to the length of `bs`.
+pub(crate) fn char_offset(bs: &[u8], off: usize) -> usize {
+    let off = std::cmp::min(off, bs.len());
+    if has_multibyte_chars(bs) {
+        bs[..off].iter().filter(|b| is_char_boundary(**b)).count()
+    } else {
+        off
+    }
+}
+
+/// The inverse of [char_offset]: the byte offset of the character with index `off` in `bs`, or
+/// the length of `bs` if it has `off` or fewer characters.
+pub(crate) fn byte_offset(bs: &[u8], off: usize) -> usize {
+    if has_multibyte_chars(bs) {
+        let s = unsafe { str::from_utf8_unchecked(bs) };
+        s.char_indices()
+            .nth(off)
+            .map(|(i, _)| i)
+            .unwrap_or(bs.len())
+    } else {
+        std::cmp::min(off, bs.len())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     extern crate test;
@@ -173,6 +221,21 @@ mod tests {
         assert!(!super::is_utf8(&bs[..]));
         assert!(std::str::from_utf8(&bs[..]).is_err());
     }
+    #[test]
+    fn char_offsets() {
+        use super::{byte_offset, char_offset};
+        let s = "héllo wörld".as_bytes();
+        assert_eq!(char_offset(s, s.len()), 11);
+        assert_eq!(char_offset(s, 3), 2);
+        assert_eq!(byte_offset(s, 2), 3);
+        assert_eq!(byte_offset(s, 8), 10);
+        assert_eq!(byte_offset(s, 20), s.len());
+        // ASCII and invalid UTF-8 are indexed by byte.
+        assert_eq!(char_offset(b"hello", 3), 3);
+        assert_eq!(byte_offset(&[b'h', 0xff, b'i'], 2), 2);
+        assert_eq!(char_offset(&[b'h', 0xff, b'i'], 10), 3);
+    }
+
     #[test]
     fn ascii_valid() {
         assert!(std::str::from_utf8(ASCII.as_bytes()).is_ok());
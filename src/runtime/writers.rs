@@ -34,11 +34,15 @@
 //! To facilitate easier testing, the functionality of the file system that we use is abstracted in
 //! the `FileFactory` trait. The `testing` module contains an implementation of this trait that
 //! writes all data in memory.
+//!
+//! Output to commands (as in `print | "sort"`) goes through the same machinery: each command gets
+//! its own handle and thread, which writes to the command's standard input. The only difference
+//! is that closing a command waits for it to exit, so that `close` can report its exit status.
 
 use std::collections::VecDeque;
 use std::io::{self, Write};
 use std::sync::{
-    atomic::{AtomicBool, AtomicUsize, Ordering},
+    atomic::{AtomicBool, AtomicI64, AtomicUsize, Ordering},
     Arc, Mutex,
 };
 
@@ -50,7 +54,10 @@ use crossbeam_channel::{bounded, Receiver, Sender};
 use hashbrown::HashMap;
 
 use crate::common::{CompileError, Notification, Result};
-use crate::runtime::Str;
+use crate::runtime::{
+    command::{self, CommandWriter},
+    Int, Str,
+};
 
 /// The maximum number of pending requests in the per-file channels.
 const IO_CHAN_SIZE: usize = 16;
@@ -70,6 +77,16 @@ pub trait FileFactory: Clone + 'static + Send + Sync {
     fn build(&self, path: &str, append: bool) -> io::Result<Self::Output>;
     // TODO maybe we shold support this returning an error.
     fn stdout(&self) -> Self::Stdout;
+    /// Start a command for `print | cmd`. By default, the command writes directly to frawk's
+    /// standard output.
+    fn cmd(&self, cmd: &str) -> io::Result<CommandWriter> {
+        CommandWriter::spawn(cmd, None)
+    }
+    /// Run a command for `system(cmd)`, returning its exit status. By default, the command writes
+    /// directly to frawk's standard output.
+    fn system(&self, cmd: &str) -> io::Result<Int> {
+        command::run(cmd, None)
+    }
 }
 
 impl<W: io::Write, T: Fn(&str, bool) -> io::Result<W> + Clone + 'static + Send + Sync> FileFactory
@@ -115,26 +132,70 @@ pub fn factory_from_file(fname: &str) -> io::Result<impl FileFactory> {
         fn stdout(&self) -> Self::Stdout {
             open_file(self.0.as_str(), /*append=*/ true).expect("failed to open stdout")
         }
+        fn cmd(&self, cmd: &str) -> io::Result<CommandWriter> {
+            let out = open_file(self.0.as_str(), /*append=*/ true)?;
+            CommandWriter::spawn(cmd, Some(Box::new(out)))
+        }
+        fn system(&self, cmd: &str) -> io::Result<Int> {
+            let mut out = open_file(self.0.as_str(), /*append=*/ true)?;
+            command::run(cmd, Some(&mut out))
+        }
     }
     Ok(FileStdout(fname.into()))
 }
 
-fn build_handle<W: io::Write, F: Fn(bool) -> io::Result<W> + Send + 'static>(f: F) -> RawHandle {
+/// Build a handle writing to the output produced by `open`. `close` is called when the output is
+/// closed, and returns the status reported to the caller of `close`.
+fn build_handle<W, F, C>(open: F, close: C) -> RawHandle
+where
+    W: io::Write,
+    F: Fn(bool) -> io::Result<W> + Send + 'static,
+    C: Fn(W) -> io::Result<Int> + Send + 'static,
+{
     let (sender, receiver) = bounded(IO_CHAN_SIZE);
     let error = Arc::new(Mutex::new(None));
     let receiver_error = error.clone();
-    std::thread::spawn(move || receive_thread(receiver, receiver_error, f));
+    std::thread::spawn(move || receive_thread(receiver, receiver_error, open, close));
     RawHandle { error, sender }
 }
 
-/// Registry is a thread-local handle on all files we have ever interacted with.
+fn close_file<W>(_w: W) -> io::Result<Int> {
+    Ok(0)
+}
+
+/// Registry is a thread-local handle on all files (and commands) we have ever interacted with.
 ///
 /// Note that handles are never removed, even after a file is closed. The single thread continues
 /// to run and listen for new requests that might trigger a reopen.
 pub struct Registry {
     global: Arc<dyn Root>,
     local: HashMap<Str<'static>, FileHandle>,
+    cmds: HashMap<Str<'static>, FileHandle>,
     stdout: FileHandle,
+    // Whether this registry was created with `from_factory`, rather than cloned from another
+    // registry. Only the original registry waits for any open commands on shutdown, as clones may
+    // be shut down while other threads are still writing to those commands.
+    is_root: bool,
+}
+
+/// Look up `name` in a thread-local cache of handles, falling back to `get_global` if it is not
+/// present.
+fn get_local<'a, 'b>(
+    local: &'a mut HashMap<Str<'static>, FileHandle>,
+    name: &Str<'b>,
+    get_global: impl FnOnce(&str) -> Option<RawHandle>,
+) -> Result<Option<&'a mut FileHandle>> {
+    use hashbrown::hash_map::Entry;
+    match local.entry(name.clone().unmoor()) {
+        Entry::Occupied(o) => Ok(Some(o.into_mut())),
+        Entry::Vacant(v) => {
+            let raw = name.with_bytes(|bs| match std::str::from_utf8(bs) {
+                Ok(s) => Ok(get_global(s)),
+                Err(e) => err!("invalid UTF8 in filename: {}", e),
+            })?;
+            Ok(raw.map(|raw| v.insert(raw.into_handle())))
+        }
+    }
 }
 
 impl Registry {
@@ -144,39 +205,87 @@ impl Registry {
         Registry {
             global: Arc::new(root_impl),
             local: Default::default(),
+            cmds: Default::default(),
             stdout,
+            is_root: true,
         }
     }
 
     pub fn get_handle<'a>(&mut self, name: Option<&Str<'a>>) -> Result<&mut FileHandle> {
         match name {
             Some(path) => {
-                use hashbrown::hash_map::Entry;
-                // borrowed by with_str closure.
+                // borrowed by get_local closure.
                 let global = &self.global;
-                match self.local.entry(path.clone().unmoor()) {
-                    Entry::Occupied(o) => Ok(o.into_mut()),
-                    Entry::Vacant(v) => {
-                        let raw = path.with_bytes(|bs| match std::str::from_utf8(bs) {
-                            Ok(s) => Ok(global.get_handle(s)),
-                            Err(e) => err!("invalid UTF8 in filename: {}", e),
-                        })?;
-                        Ok(v.insert(raw.into_handle()))
-                    }
-                }
+                Ok(get_local(&mut self.local, path, |s| Some(global.get_handle(s)))?.unwrap())
             }
             None => Ok(&mut self.stdout),
         }
     }
 
+    pub fn get_cmd_handle<'a>(&mut self, cmd: &Str<'a>) -> Result<&mut FileHandle> {
+        if !self.cmds.contains_key(&cmd.clone().unmoor()) {
+            // The command may write to standard output as well; make sure that anything we have
+            // printed so far appears first.
+            self.stdout.flush()?;
+        }
+        let global = &self.global;
+        Ok(get_local(&mut self.cmds, cmd, |s| Some(global.get_cmd_handle(s)))?.unwrap())
+    }
+
+    /// Close any file or command named `name`, returning the status for the awk `close` function:
+    /// the exit status of a command, 0 for a file, or -1 if `name` was never opened.
+    pub fn close<'a>(&mut self, name: &Str<'a>) -> Result<Int> {
+        let global = &self.global;
+        let cmd_status = match get_local(&mut self.cmds, name, |s| global.find_cmd_handle(s))? {
+            Some(handle) => handle.close()?,
+            None => -1,
+        };
+        // NB this may create an unused entry for a file. It would not be terribly difficult to
+        // optimize the close path to include an existence check first, as we do for commands.
+        let file_status = self.get_handle(Some(name))?.close()?;
+        Ok(if cmd_status != -1 {
+            cmd_status
+        } else {
+            file_status
+        })
+    }
+
+    /// Flush all pending output, including output to commands, and then run `cmd`, returning
+    /// its exit status for the awk `system` function.
+    pub fn system<'a>(&mut self, cmd: &Str<'a>) -> Result<Int> {
+        for fh in self.local.values_mut().chain(self.cmds.values_mut()) {
+            fh.flush()?;
+        }
+        self.stdout.flush()?;
+        cmd.with_bytes(|bs| match std::str::from_utf8(bs) {
+            Ok(s) => match self.global.system(s) {
+                Ok(status) => Ok(status),
+                Err(e) => err!("failed to run command '{}': {}", s, e),
+            },
+            Err(e) => err!("invalid UTF8 in command: {}", e),
+        })
+    }
+
     pub fn destroy_and_flush_all_files(&mut self) -> Result<()> {
         let mut last_error = Ok(());
-        for (_, mut fh) in self.local.drain() {
+        for (_, mut fh) in self.local.drain().chain(self.cmds.drain()) {
             let res = fh.flush();
             if res.is_err() {
                 last_error = res;
             }
         }
+        if self.is_root {
+            // Wait for all commands to exit, so that their output is not cut off when frawk exits.
+            let res = self.stdout.flush();
+            if res.is_err() {
+                last_error = res;
+            }
+            for raw in self.global.get_cmd_handles() {
+                if let Err(e) = raw.into_handle().close() {
+                    last_error = Err(e);
+                }
+            }
+        }
         last_error
     }
 }
@@ -186,7 +295,9 @@ impl Clone for Registry {
         Registry {
             global: self.global.clone(),
             local: HashMap::new(),
+            cmds: HashMap::new(),
             stdout: self.stdout.raw().into_handle(),
+            is_root: false,
         }
     }
 }
@@ -195,11 +306,16 @@ impl Clone for Registry {
 // receiver threads, while still avoiding an extra type parameter all the way up the stack.
 trait Root: 'static + Send + Sync {
     fn get_handle(&self, fname: &str) -> RawHandle;
+    fn get_cmd_handle(&self, cmd: &str) -> RawHandle;
+    fn find_cmd_handle(&self, cmd: &str) -> Option<RawHandle>;
+    fn get_cmd_handles(&self) -> Vec<RawHandle>;
+    fn system(&self, cmd: &str) -> io::Result<Int>;
     fn get_stdout(&self) -> RawHandle;
 }
 
 struct RootImpl<F> {
     handles: Mutex<HashMap<String, RawHandle>>,
+    cmd_handles: Mutex<HashMap<String, RawHandle>>,
     stdout_raw: RawHandle,
     file_factory: F,
 }
@@ -207,9 +323,10 @@ struct RootImpl<F> {
 impl<F: FileFactory> RootImpl<F> {
     fn from_factory(file_factory: F) -> RootImpl<F> {
         let local_factory = file_factory.clone();
-        let stdout_raw = build_handle(move |_append| Ok(local_factory.stdout()));
+        let stdout_raw = build_handle(move |_append| Ok(local_factory.stdout()), close_file);
         RootImpl {
             handles: Default::default(),
+            cmd_handles: Default::default(),
             stdout_raw,
             file_factory,
         }
@@ -225,10 +342,37 @@ impl<F: FileFactory> Root for RootImpl<F> {
         let local_factory = self.file_factory.clone();
         let local_name = String::from(fname);
         let global_name = local_name.clone();
-        let handle = build_handle(move |append| local_factory.build(local_name.as_str(), append));
+        let handle = build_handle(
+            move |append| local_factory.build(local_name.as_str(), append),
+            close_file,
+        );
         handles.insert(global_name, handle.clone());
         handle
     }
+    fn get_cmd_handle(&self, cmd: &str) -> RawHandle {
+        let mut handles = self.cmd_handles.lock().unwrap();
+        if let Some(h) = handles.get(cmd) {
+            return h.clone();
+        }
+        let local_factory = self.file_factory.clone();
+        let local_cmd = String::from(cmd);
+        let global_cmd = local_cmd.clone();
+        let handle = build_handle(
+            move |_append| local_factory.cmd(local_cmd.as_str()),
+            CommandWriter::wait,
+        );
+        handles.insert(global_cmd, handle.clone());
+        handle
+    }
+    fn find_cmd_handle(&self, cmd: &str) -> Option<RawHandle> {
+        self.cmd_handles.lock().unwrap().get(cmd).cloned()
+    }
+    fn get_cmd_handles(&self) -> Vec<RawHandle> {
+        self.cmd_handles.lock().unwrap().values().cloned().collect()
+    }
+    fn system(&self, cmd: &str) -> io::Result<Int> {
+        self.file_factory.system(cmd)
+    }
     fn get_stdout(&self) -> RawHandle {
         self.stdout_raw.clone()
     }
@@ -333,10 +477,19 @@ impl FileHandle {
         }
     }
 
-    pub fn close(&mut self) -> Result<()> {
+    /// Close the underlying file or command, returning its exit status (0 for files), or -1 if
+    /// it was not open.
+    pub fn close(&mut self) -> Result<Int> {
         self.clear_batch()?;
-        self.raw.sender.send(Request::Close).unwrap();
-        Ok(())
+        let (n, req) = Request::close();
+        self.raw.sender.send(req).unwrap();
+        n.2.wait();
+        self.guards.clear();
+        if let RequestStatus::ERROR = n.0.read() {
+            Err(self.read_error())
+        } else {
+            Ok(n.1.load(Ordering::Acquire))
+        }
     }
 }
 
@@ -396,7 +549,8 @@ enum Request {
         append: bool,
     },
     Flush(Arc<(ErrorCode, Notification)>),
-    Close,
+    // Close requests also carry the status of the closed file or command.
+    Close(Arc<(ErrorCode, AtomicI64, Notification)>),
 }
 
 // This isn't implemented automatically because of the raw pointers in Write. Those pointers are
@@ -410,13 +564,22 @@ impl Request {
         let req = Request::Flush(notify.clone());
         (notify, req)
     }
+    fn close() -> (Arc<(ErrorCode, AtomicI64, Notification)>, Request) {
+        let notify = Arc::new((
+            ErrorCode::default(),
+            AtomicI64::new(-1),
+            Notification::default(),
+        ));
+        let req = Request::Close(notify.clone());
+        (notify, req)
+    }
     fn size(&self) -> usize {
         match self {
             // NB, aside from the invariants we maintain about the validity of `data`, grabbing the
             // length here should _always_ be safe. This is tracked by the {const_}slice_ptr_len
             // feature.
             Request::Write { data, .. } => unsafe { &**data }.len(),
-            Request::Flush(_) | Request::Close => 0,
+            Request::Flush(_) | Request::Close(_) => 0,
         }
     }
     fn set_code(&self, mut f: impl FnMut(&ErrorCode)) {
@@ -426,7 +589,15 @@ impl Request {
                 f(&n.0);
                 n.1.notify();
             }
-            Request::Close => {}
+            Request::Close(n) => {
+                f(&n.0);
+                n.2.notify();
+            }
+        }
+    }
+    fn set_close_status(&self, status: Int) {
+        if let Request::Close(n) = self {
+            n.1.store(status, Ordering::Release);
         }
     }
 }
@@ -442,7 +613,9 @@ impl Drop for Request {
             Request::Flush(n) => {
                 assert!(n.1.has_been_notified());
             }
-            Request::Close => {}
+            Request::Close(n) => {
+                assert!(n.2.has_been_notified());
+            }
         }
     }
 }
@@ -527,9 +700,7 @@ impl WriteBatch {
         if self.flush || self.close {
             w.flush()?;
         }
-        let close = self.close;
-        self.clear();
-        Ok(close)
+        Ok(self.close)
     }
     fn is_append(&self) -> bool {
         for req in self.requests.iter() {
@@ -548,7 +719,7 @@ impl WriteBatch {
                 self.n_writes += 1;
             }
             Request::Flush(_) => self.flush = true,
-            Request::Close => self.close = true,
+            Request::Close(_) => self.close = true,
         };
         self.requests.push(req);
         self.flush || self.close
@@ -562,6 +733,11 @@ impl WriteBatch {
         self.flush = false;
         self.n_writes = 0;
     }
+    fn set_close_status(&mut self, status: Int) {
+        for req in self.requests.iter() {
+            req.set_close_status(status);
+        }
+    }
     fn clear_error(&mut self) {
         self.clear_batch(ErrorCode::set_error)
     }
@@ -573,10 +749,11 @@ impl WriteBatch {
 fn receive_thread<W: io::Write>(
     receiver: Receiver<Request>,
     error: Arc<Mutex<Option<CompileError>>>,
-    f: impl Fn(bool) -> io::Result<W>,
+    open: impl Fn(bool) -> io::Result<W>,
+    close: impl Fn(W) -> io::Result<Int>,
 ) {
     let mut batch = WriteBatch::default();
-    if let Err(e) = receive_loop(&receiver, &mut batch, f) {
+    if let Err(e) = receive_loop(&receiver, &mut batch, open, close) {
         // We got an error! install it in the `error` mutex.
         {
             let mut err = error.lock().unwrap();
@@ -594,12 +771,13 @@ fn receive_thread<W: io::Write>(
 fn receive_loop<W: io::Write>(
     receiver: &Receiver<Request>,
     batch: &mut WriteBatch,
-    f: impl Fn(bool) -> io::Result<W>,
+    open: impl Fn(bool) -> io::Result<W>,
+    close: impl Fn(W) -> io::Result<Int>,
 ) -> io::Result<()> {
     const MAX_BATCH_BYTES: usize = 1 << 20;
     const MAX_BATCH_SIZE: usize = 1 << 10;
 
-    // Writer starts off closed. We use `f` to open it if a write appears.
+    // Writer starts off closed. We use `open` to open it if a write appears.
     let mut writer = None;
 
     while let Ok(req) = receiver.recv() {
@@ -623,17 +801,20 @@ fn receive_loop<W: io::Write>(
         if writer.is_none() {
             if batch.n_writes() == 0 {
                 // check for a "flush/close-only batch", which we treat as a noop if the file is
-                // closed.
+                // closed. Close requests for a closed file report a status of -1, which is what
+                // they are initialized to.
                 batch.clear();
                 continue;
             }
             // We need to (re)open the file, the first write request will tell us whether or not
             // this is an append request.
-            writer = Some(f(batch.is_append())?);
+            writer = Some(open(batch.is_append())?);
         }
         if batch.issue(writer.as_mut().unwrap())? {
-            writer = None;
+            let status = close(writer.take().unwrap())?;
+            batch.set_close_status(status);
         }
+        batch.clear();
     }
     Ok(())
 }
@@ -670,6 +851,12 @@ pub mod testing {
         fn stdout(&self) -> Self::Stdout {
             self.stdout.clone()
         }
+        fn cmd(&self, cmd: &str) -> io::Result<CommandWriter> {
+            CommandWriter::spawn(cmd, Some(Box::new(self.stdout.clone())))
+        }
+        fn system(&self, cmd: &str) -> io::Result<Int> {
+            command::run(cmd, Some(&mut self.stdout.clone()))
+        }
     }
 
     #[derive(Default)]
@@ -787,6 +974,28 @@ mod tests {
         assert_eq!(&data[..], "hello there".as_bytes());
     }
 
+    #[test]
+    fn close_status() {
+        let fname = Str::from("/fake");
+        let cmd = Str::from("cat; exit 7");
+        let fs = FakeFs::default();
+        let mut reg = Registry::from_factory(fs.clone());
+        assert_eq!(reg.close(&fname).unwrap(), -1);
+        reg.get_handle(Some(&fname))
+            .unwrap()
+            .write(&Str::from("file"), /*append=*/ false)
+            .unwrap();
+        assert_eq!(reg.close(&fname).unwrap(), 0);
+        reg.get_cmd_handle(&cmd)
+            .unwrap()
+            .write(&Str::from("cmd"), /*append=*/ false)
+            .unwrap();
+        assert_eq!(reg.close(&cmd).unwrap(), 7);
+        assert_eq!(reg.close(&cmd).unwrap(), -1);
+        assert_eq!(fs.get_handle("/fake").unwrap().read_data(), b"file");
+        assert_eq!(fs.stdout.read_data(), b"cmd");
+    }
+
     #[test]
     fn multithreaded_write() {
         const N_THREADS: usize = 100;
@@ -805,6 +805,9 @@ impl<'b, 'c> TypeContext<'b, 'c> {
             }
         }
     }
+    pub(crate) fn fresh_var(&mut self) -> NodeIx {
+        self.nw.add_rule(Rule::Var)
+    }
     pub(crate) fn constrain_as_map(&mut self, ix: NodeIx) {
         // To be completely explicit, this function assigns a unique `Flows` constaint into a map
         // from the constant node that "just specifies the node is a Map".
@@ -958,7 +961,7 @@ impl<'b, 'c, 'd> View<'b, 'c, 'd> {
                     self.nw
                         .add_dep(scalar_node, arg_node, Constraint::Flows(()));
                 }
-                if let Some((out, _append)) = out {
+                if let Some((out, _spec)) = out {
                     let out_node = self.val_node(out);
                     self.nw
                         .add_dep(scalar_node, out_node, Constraint::Flows(()));